@@ -0,0 +1,142 @@
+/*
+ * The C interface of the Onion interpreter.
+ *
+ * A native plugin is a shared library exporting the two functions at the bottom of this
+ * file. `(load_plugin "libmyext.so")` checks the plugin's ABI version, then calls
+ * `onion_register`, which defines functions and values with the `OnionApi` it is given.
+ *
+ * A program embeds Onion by linking the `onion` shared library and using the functions
+ * under "Embedding" below.
+ */
+#ifndef ONION_H
+#define ONION_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Bumped whenever a type or function below changes. */
+#define ONION_PLUGIN_ABI_VERSION 1
+
+typedef enum OnionTag {
+    ONION_NIL = 0,
+    ONION_INT = 1,
+    ONION_FLOAT = 2,
+    ONION_STRING = 3,
+    ONION_SYMBOL = 4,
+    ONION_KEYWORD = 5,
+    ONION_BYTES = 6,
+    ONION_LIST = 7,
+    ONION_MAP = 8,
+    /* Anything else, such as a function or a big integer, as its printed form. */
+    ONION_OTHER = 9
+} OnionTag;
+
+/*
+ * An Onion value. `int_value` is set for ONION_INT and `float_value` for ONION_FLOAT.
+ * For the others `data` points at:
+ *   ONION_STRING, ONION_SYMBOL, ONION_KEYWORD, ONION_OTHER: `len` bytes of UTF-8 text,
+ *       followed by a NUL;
+ *   ONION_BYTES: `len` bytes;
+ *   ONION_LIST: `len` values;
+ *   ONION_MAP: `len` entries, each a key followed by its value, so 2 * `len` values.
+ */
+typedef struct OnionValue {
+    uint32_t tag;
+    int64_t int_value;
+    double float_value;
+    const void *data;
+    size_t len;
+} OnionValue;
+
+/* The arguments and result of one call of a native function. */
+typedef struct OnionCall OnionCall;
+
+typedef struct OnionApi OnionApi;
+
+/*
+ * A native function. It reads its arguments and sets its result through `api`, and
+ * returns 0, or anything else to fail with a generic error.
+ */
+typedef int (*OnionFn)(const OnionApi *api, OnionCall *call, void *user_data);
+
+struct OnionApi {
+    uint32_t abi_version;
+    /* The interpreter being registered into; only `define` and `define_value` use it. */
+    void *host;
+
+    /*
+     * Define a function as `module.name`, creating the module if needed, or as a global
+     * when `module` is NULL. `user_data` is passed to every call. Returns 0 on success.
+     */
+    int (*define)(const OnionApi *api, const char *module, const char *name,
+                  const char *doc, OnionFn func, void *user_data);
+    /* Define a value the same way. The value is copied. */
+    int (*define_value)(const OnionApi *api, const char *module, const char *name,
+                        const OnionValue *value);
+
+    /* How many arguments were passed. */
+    size_t (*arg_count)(const OnionCall *call);
+    /*
+     * Read argument `index`, already evaluated. What `out` points to stays valid until the
+     * function returns. Returns 0, or -1 if there is no such argument.
+     */
+    int (*arg)(OnionCall *call, size_t index, OnionValue *out);
+    /* Set the result, copying the value. The result is nil unless this is called. */
+    void (*ret)(OnionCall *call, const OnionValue *value);
+    /* Fail with a runtime error, which `try` can catch, once the function returns. */
+    void (*raise)(OnionCall *call, const char *message);
+};
+
+/* Exported by every plugin: the ONION_PLUGIN_ABI_VERSION it was built against. */
+uint32_t onion_plugin_abi_version(void);
+
+/* Exported by every plugin: define its functions. Returns 0 on success. */
+int onion_register(const OnionApi *api);
+
+/* ---- Embedding ---- */
+
+typedef struct OnionInterpreter OnionInterpreter;
+
+/*
+ * The functions below that return an int return 0 on success. On failure they return
+ * nonzero and `onion_error` says why; -1 means `onion` itself was null. Values and
+ * strings they hand out stay valid until the next call on the same interpreter.
+ */
+
+/* A new interpreter with the whole standard library, or NULL if it couldn't be made. */
+OnionInterpreter *onion_new(void);
+
+/* Free an interpreter and everything it handed out. NULL is ignored. */
+void onion_free(OnionInterpreter *onion);
+
+/* Run every form in `source`, setting `result`, unless NULL, to the value of the last. */
+int onion_eval(OnionInterpreter *onion, const char *source, OnionValue *result);
+
+/* Call the global function `name` with `nargs` arguments, which are copied. */
+int onion_call(OnionInterpreter *onion, const char *name, const OnionValue *args,
+               size_t nargs, OnionValue *result);
+
+/*
+ * The result of the last successful `onion_eval` or `onion_call` as JSON. Symbols and
+ * keywords become strings, and maps objects keyed by their keys' printed form.
+ */
+const char *onion_result_json(OnionInterpreter *onion);
+
+/* Why the last call failed, or NULL if it didn't. */
+const char *onion_error(const OnionInterpreter *onion);
+
+/* Define a C function or a value, as `OnionApi.define` and `define_value` do. */
+int onion_define(OnionInterpreter *onion, const char *module, const char *name,
+                 const char *doc, OnionFn func, void *user_data);
+int onion_define_value(OnionInterpreter *onion, const char *module, const char *name,
+                       const OnionValue *value);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
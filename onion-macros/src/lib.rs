@@ -0,0 +1,193 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{FnArg, ItemFn, LitStr, Pat, ReturnType, Type, parse_macro_input};
+
+/// Turn a Rust function into an Onion native function.
+///
+/// ```ignore
+/// /// Clamp value between min and max
+/// #[onion_fn(module = "Math")]
+/// fn clamp(x: f64, lo: f64, hi: f64) -> f64 {
+///     x.max(lo).min(hi)
+/// }
+/// ```
+///
+/// The function is left as it is, and `clamp_native()` is added next to it, returning an
+/// `onion::native::Native` that evaluates and converts the arguments, checks their number
+/// and types, and converts the result. Options:
+///
+/// - `module = "Math"`: install the function as `Math.clamp` instead of a global.
+/// - `name = "..."`: the name in Onion, if it differs from the Rust one.
+/// - `doc = "..."`: the description, if not the function's doc comment.
+#[proc_macro_attribute]
+pub fn onion_fn(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut options = Options::default();
+    let parser = syn::meta::parser(|meta| {
+        let slot = if meta.path.is_ident("module") {
+            &mut options.module
+        } else if meta.path.is_ident("name") {
+            &mut options.name
+        } else if meta.path.is_ident("doc") {
+            &mut options.doc
+        } else {
+            return Err(meta.error("expected `module`, `name` or `doc`"));
+        };
+        *slot = Some(meta.value()?.parse()?);
+        Ok(())
+    });
+    parse_macro_input!(attr with parser);
+    let func = parse_macro_input!(item as ItemFn);
+    match expand(func, options) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+#[derive(Default)]
+struct Options {
+    module: Option<LitStr>,
+    name: Option<LitStr>,
+    doc: Option<LitStr>,
+}
+
+fn expand(func: ItemFn, options: Options) -> syn::Result<TokenStream2> {
+    let sig = &func.sig;
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &sig.generics,
+            "onion_fn functions can't be generic",
+        ));
+    }
+    let fn_ident = &sig.ident;
+    let native_ident = format_ident!("{}_native", fn_ident);
+    let vis = &func.vis;
+
+    let name = options
+        .name
+        .map_or_else(|| fn_ident.to_string(), |n| n.value());
+    let full_name = match &options.module {
+        Some(module) => format!("{}.{}", module.value(), name),
+        None => name.clone(),
+    };
+    let module = match &options.module {
+        Some(module) => quote!(::core::option::Option::Some(#module)),
+        None => quote!(::core::option::Option::None),
+    };
+    let doc = options
+        .doc
+        .map(|d| d.value())
+        .or_else(|| doc_comment(&func))
+        .unwrap_or_else(|| name.clone());
+
+    let mut lets = Vec::new();
+    let mut call_args = Vec::new();
+    let mut param_types = Vec::new();
+    let mut required = 0;
+    for input in &sig.inputs {
+        let FnArg::Typed(pat_type) = input else {
+            return Err(syn::Error::new_spanned(
+                input,
+                "onion_fn can't be used on methods",
+            ));
+        };
+        let ty = &*pat_type.ty;
+        if is_context(ty) {
+            call_args.push(quote!(__onion_ctx));
+            continue;
+        }
+        let index = param_types.len();
+        let param = match &*pat_type.pat {
+            Pat::Ident(p) => p.ident.to_string(),
+            _ => format!("{}", index + 1),
+        };
+        let var = format_ident!("__onion_arg{}", index);
+        lets.push(quote! {
+            let #var: #ty = ::onion::native::arg(
+                #full_name, #param, __onion_args, #index, __onion_ctx,
+            );
+        });
+        call_args.push(quote!(#var));
+        if !is_option(ty) {
+            required = index + 1;
+        }
+        param_types.push(ty.clone());
+    }
+    let max = param_types.len();
+    let ret = match &sig.output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ty) => quote!(#ty),
+    };
+
+    Ok(quote! {
+        #func
+
+        #[doc = concat!("The Onion native for [`", stringify!(#fn_ident), "`].")]
+        #vis fn #native_ident() -> ::onion::native::Native {
+            let func = ::onion::Expr::extern_ref(
+                |__onion_args: &[::onion::Expr], __onion_ctx: &mut ::onion::Context| {
+                    ::onion::native::check_arity(#full_name, __onion_args, #required, #max);
+                    #(#lets)*
+                    ::onion::native::NativeReturn::into_return(
+                        #fn_ident(#(#call_args),*),
+                        #full_name,
+                    )
+                },
+                #name,
+                #doc,
+            )
+            .with_signature(
+                &[#(<#param_types as ::onion::FromExpr>::TYPE),*],
+                <#ret as ::onion::native::NativeReturn>::TYPE,
+            );
+            ::onion::native::Native {
+                name: #name,
+                module: #module,
+                func,
+            }
+        }
+    })
+}
+
+/// The function's `///` comment, joined into one line.
+fn doc_comment(func: &ItemFn) -> Option<String> {
+    let lines: Vec<String> = func
+        .attrs
+        .iter()
+        .filter(|a| a.path().is_ident("doc"))
+        .filter_map(|a| match &a.meta {
+            syn::Meta::NameValue(nv) => match &nv.value {
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(s),
+                    ..
+                }) => Some(s.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .filter(|line| !line.is_empty())
+        .collect();
+    (!lines.is_empty()).then(|| lines.join(" "))
+}
+
+/// The last segment of a type path, like `Option` in `std::option::Option<T>`.
+fn last_segment(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(p) => p.path.segments.last().map(|s| s.ident.to_string()),
+        _ => None,
+    }
+}
+
+fn is_option(ty: &Type) -> bool {
+    last_segment(ty).as_deref() == Some("Option")
+}
+
+/// `&mut Context`, which is passed the caller's context instead of an argument.
+fn is_context(ty: &Type) -> bool {
+    match ty {
+        Type::Reference(r) => {
+            r.mutability.is_some() && last_segment(&r.elem).as_deref() == Some("Context")
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,226 @@
+//! Standalone executables: `onion bundle main.onion -o game` copies the interpreter and
+//! appends the script, and the files it reads, to the copy. When the copy starts it finds
+//! them at its end and runs the script, serving its reads from the bundle instead of disk.
+//!
+//! Onion has no import form, so what a script reads is found by scanning it for calls of
+//! `Game.load_image`, `Game.load_sound`, `IO.read_file` and `IO.read_bytes` with a literal
+//! path. Files named at runtime are added with `--include`.
+//!
+//! The appended data is the file table followed by a trailer: the table's length as a
+//! little-endian `u64` and the 8 bytes of [`MAGIC`]. The table is the main script's name,
+//! preceded by its length, then the files as an [`Archive`].
+
+use crate::context::Context;
+use crate::expr::Expr;
+use crate::vfs::{Archive, Table, invalid, key, put};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Marks the end of an executable carrying a bundle.
+pub const MAGIC: &[u8; 8] = b"ONIONBDL";
+
+/// The calls whose literal path argument names a file the script needs.
+const READERS: [(&str, &str); 4] = [
+    ("Game", "load_image"),
+    ("Game", "load_sound"),
+    ("IO", "read_file"),
+    ("IO", "read_bytes"),
+];
+
+/// A script and the files it reads, by the names it reads them by.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Bundle {
+    main: String,
+    files: Archive,
+}
+
+impl Bundle {
+    /// A bundle that runs the file added as `main`.
+    pub fn new(main: &str) -> Self {
+        Self {
+            main: key(Path::new(main)),
+            files: Archive::new(),
+        }
+    }
+
+    pub fn add(&mut self, name: &str, contents: Vec<u8>) {
+        self.files.insert(name, contents);
+    }
+
+    /// The contents of the file a script names `name`.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.files.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.files.names()
+    }
+
+    /// The files, to be read through an [`Overlay`](crate::vfs::Overlay) mounted over the
+    /// disk.
+    pub fn into_archive(self) -> Archive {
+        self.files
+    }
+
+    /// The source of the main script.
+    pub fn main_source(&self) -> io::Result<String> {
+        let source = self
+            .get(&self.main)
+            .ok_or_else(|| invalid(format!("the bundle has no {}", self.main)))?;
+        String::from_utf8(source.to_vec()).map_err(|e| invalid(e.to_string()))
+    }
+
+    /// Bundle `script` with every file it reads with a literal path, and everything in
+    /// `include`. Literal paths are looked up next to the script, then in the current
+    /// directory; ones found in neither are skipped with a warning, as the script may
+    /// create them.
+    pub fn collect(script: &Path, include: &[PathBuf], ctx: &Context) -> Result<Self, String> {
+        let source = std::fs::read_to_string(script)
+            .map_err(|e| format!("can't read {}: {}", script.display(), e))?;
+        let name = script.file_name().unwrap_or_default().to_string_lossy();
+        let mut bundle = Bundle::new(&name);
+        bundle.add(&name, source.clone().into_bytes());
+
+        let mut paths = Vec::new();
+        crate::check::for_each_form(&source, ctx, |_, expr| literal_paths(&expr, &mut paths))?;
+        let dir = script.parent().unwrap_or(Path::new(""));
+        for path in paths {
+            let found = [dir.join(&path), PathBuf::from(&path)]
+                .into_iter()
+                .find(|p| p.is_file());
+            match found {
+                Some(file) => {
+                    let contents = std::fs::read(&file)
+                        .map_err(|e| format!("can't read {}: {}", file.display(), e))?;
+                    bundle.add(&path, contents);
+                }
+                None => eprintln!("warning: {} not found, so not bundled", path),
+            }
+        }
+        for path in include {
+            bundle.include(path)?;
+        }
+        Ok(bundle)
+    }
+
+    /// Add a file, or a directory and everything in it, by the path given.
+    fn include(&mut self, path: &Path) -> Result<(), String> {
+        let error = |e: io::Error| format!("can't read {}: {}", path.display(), e);
+        if path.is_dir() {
+            for entry in std::fs::read_dir(path).map_err(error)? {
+                self.include(&entry.map_err(error)?.path())?;
+            }
+        } else {
+            self.add(&path.to_string_lossy(), std::fs::read(path).map_err(error)?);
+        }
+        Ok(())
+    }
+
+    /// The file table, as appended to an executable without its trailer.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        put(&mut out, self.main.as_bytes());
+        out.extend_from_slice(&self.files.to_bytes());
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let mut table = Table(bytes);
+        let main = table.text()?;
+        let files = Archive::read_from(&mut table)?;
+        Ok(Self { main, files })
+    }
+
+    /// Write a copy of the executable `exe` with this bundle appended to `out`, and make
+    /// it executable.
+    pub fn write_executable(&self, exe: &Path, out: &Path) -> io::Result<()> {
+        let mut program = std::fs::read(exe)?;
+        // Bundling from a bundled executable replaces its bundle
+        if let Some(start) = find(&mut File::open(exe)?)?.map(|(start, _)| start) {
+            program.truncate(start as usize);
+        }
+        let table = self.to_bytes();
+        program.extend_from_slice(&table);
+        program.extend_from_slice(&(table.len() as u64).to_le_bytes());
+        program.extend_from_slice(MAGIC);
+        std::fs::write(out, program)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(out, std::fs::Permissions::from_mode(0o755))?;
+        }
+        Ok(())
+    }
+
+    /// The bundle appended to the executable at `exe`, if it has one.
+    pub fn read_executable(exe: &Path) -> io::Result<Option<Self>> {
+        let mut file = File::open(exe)?;
+        let Some((start, len)) = find(&mut file)? else {
+            return Ok(None);
+        };
+        file.seek(SeekFrom::Start(start))?;
+        let mut table = vec![0; len as usize];
+        file.read_exact(&mut table)?;
+        Self::from_bytes(&table).map(Some)
+    }
+
+    /// The bundle appended to the running executable, if it has one.
+    pub fn current() -> Option<Self> {
+        let exe = std::env::current_exe().ok()?;
+        Self::read_executable(&exe).ok().flatten()
+    }
+}
+
+/// Where the file table of a bundled executable starts, and its length, reading only the
+/// trailer.
+fn find(file: &mut File) -> io::Result<Option<(u64, u64)>> {
+    let size = file.metadata()?.len();
+    if size < 16 {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::Start(size - 16))?;
+    let mut trailer = [0; 16];
+    file.read_exact(&mut trailer)?;
+    if &trailer[8..] != MAGIC {
+        return Ok(None);
+    }
+    let len = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+    match (size - 16).checked_sub(len) {
+        Some(start) => Ok(Some((start, len))),
+        None => Err(invalid("the bundle is truncated".to_string())),
+    }
+}
+
+/// Collect the literal paths passed to the calls in [`READERS`].
+fn literal_paths(expr: &Expr, out: &mut Vec<String>) {
+    match expr {
+        Expr::List(items) => {
+            if let (Some(Expr::List(head)), Some(Expr::Str(path))) = (items.get(0), items.get(1))
+                && is_reader(head)
+            {
+                out.push(path.clone());
+            }
+            for item in items {
+                literal_paths(item, out);
+            }
+        }
+        Expr::Quoted(inner) => literal_paths(inner, out),
+        Expr::Map(m) => m.values().for_each(|v| literal_paths(v, out)),
+        Expr::HashMap(m) => m.values().for_each(|v| literal_paths(v, out)),
+        _ => {}
+    }
+}
+
+/// Whether `head`, the parsed `Module.func`, is one of the [`READERS`].
+fn is_reader(head: &imbl::Vector<Expr>) -> bool {
+    let name = |i: usize| match head.get(i) {
+        Some(Expr::Sym(s)) => s.as_str(),
+        _ => "",
+    };
+    head.len() == 3
+        && name(0) == "."
+        && READERS
+            .iter()
+            .any(|&(module, func)| name(1) == module && name(2) == func)
+}
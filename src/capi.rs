@@ -0,0 +1,285 @@
+//! The C interface for embedding Onion in a program that isn't written in Rust, built into
+//! the `cdylib` and declared in `include/onion.h`.
+//!
+//! ```c
+//! OnionInterpreter *onion = onion_new();
+//! OnionValue result;
+//! if (onion_eval(onion, "(1 + 2)", &result) == 0)
+//!     printf("%lld\n", (long long)result.int_value);
+//! else
+//!     fprintf(stderr, "%s\n", onion_error(onion));
+//! onion_free(onion);
+//! ```
+//!
+//! Values are handed out as the [`OnionValue`]s plugins see, or as JSON, and stay valid
+//! until the next call on the same interpreter. C functions are registered with the same
+//! [`OnionApi`] plugins use. Every function returns 0 on success; on failure
+//! `onion_error` describes the problem. No panic crosses into C.
+
+use crate::error::RwLockExt;
+use crate::expr::Expr;
+use crate::interpreter::Interpreter;
+use crate::plugin::{Arena, OnionApi, OnionFn, OnionValue, to_expr};
+use serde_json::{Map, Number, Value};
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+/// An interpreter and what it last handed to C.
+pub struct OnionInterpreter {
+    onion: Interpreter,
+    result: Expr,
+    arena: Arena,
+    json: Option<CString>,
+    error: Option<CString>,
+}
+
+impl OnionInterpreter {
+    /// Run `f`, keeping its error, or a panic's, for `onion_error`.
+    fn guard(&mut self, f: impl FnOnce(&mut Self) -> Result<(), String>) -> c_int {
+        self.arena = Arena::default();
+        self.json = None;
+        self.error = None;
+        let outcome = catch_unwind(AssertUnwindSafe(|| f(self))).unwrap_or_else(|panic| {
+            let message = panic
+                .downcast_ref::<String>()
+                .map(String::as_str)
+                .or_else(|| panic.downcast_ref::<&str>().copied())
+                .unwrap_or("unknown panic");
+            Err(format!("Internal error: {}", message))
+        });
+        match outcome {
+            Ok(()) => 0,
+            Err(message) => {
+                self.error = Some(c_string(message));
+                1
+            }
+        }
+    }
+
+    /// Keep `value` as the result, and write its view to `out` unless that is null.
+    fn set_result(&mut self, value: Expr, out: *mut OnionValue) {
+        if let Some(out) = unsafe { out.as_mut() } {
+            *out = self.arena.view(&value);
+        }
+        self.result = value;
+    }
+}
+
+fn c_string(text: String) -> CString {
+    CString::new(text.replace('\0', "\\0")).unwrap_or_default()
+}
+
+/// A C string argument, which must not be null.
+unsafe fn arg<'a>(s: *const c_char, what: &str) -> Result<std::borrow::Cow<'a, str>, String> {
+    if s.is_null() {
+        return Err(format!("{} is null", what));
+    }
+    Ok(unsafe { CStr::from_ptr(s) }.to_string_lossy())
+}
+
+/// `expr` as JSON. Keywords and symbols become strings, maps become objects keyed by
+/// their keys' printed form, and anything without a JSON equivalent its printed form.
+pub fn to_json(expr: &Expr) -> Value {
+    match expr {
+        Expr::Ref(r) => to_json(&r.read_unpoisoned()),
+        Expr::Quoted(inner) => to_json(inner),
+        Expr::Nil => Value::Null,
+        Expr::Int(n) => Value::from(*n),
+        Expr::Float(f) => Number::from_f64(*f).map_or(Value::Null, Value::Number),
+        Expr::Str(s) => Value::from(s.as_str()),
+        Expr::Sym(s) | Expr::Keyword(s) => Value::from(s.as_str()),
+        Expr::Bytes(b) => Value::from(b.as_slice()),
+        Expr::List(l) => l.iter().map(to_json).collect(),
+        Expr::Map(m) => object(m.iter()),
+        Expr::HashMap(m) => object(m.iter()),
+        Expr::Tagged { tag, value } => {
+            Value::Object(Map::from_iter([(tag.to_string(), to_json(value))]))
+        }
+        other => Value::from(other.to_string()),
+    }
+}
+
+fn object<'a>(entries: impl Iterator<Item = (&'a Expr, &'a Expr)>) -> Value {
+    entries
+        .map(|(k, v)| {
+            let key = match k {
+                Expr::Str(s) => s.clone(),
+                Expr::Sym(s) | Expr::Keyword(s) => s.to_string(),
+                other => other.to_string(),
+            };
+            (key, to_json(v))
+        })
+        .collect::<Map<_, _>>()
+        .into()
+}
+
+/// A new interpreter with the whole standard library and every capability.
+#[unsafe(no_mangle)]
+pub extern "C" fn onion_new() -> *mut OnionInterpreter {
+    catch_unwind(|| {
+        Box::into_raw(Box::new(OnionInterpreter {
+            onion: Interpreter::new(),
+            result: Expr::Nil,
+            arena: Arena::default(),
+            json: None,
+            error: None,
+        }))
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+/// Free an interpreter from `onion_new`, and everything it handed out.
+///
+/// # Safety
+///
+/// `onion` must come from `onion_new`, or be null, and not be used again.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn onion_free(onion: *mut OnionInterpreter) {
+    if !onion.is_null() {
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(unsafe { Box::from_raw(onion) })));
+    }
+}
+
+/// Run every form in `source`, writing the value of the last one to `result` if it isn't
+/// null.
+///
+/// # Safety
+///
+/// `onion` must come from `onion_new`, `source` be a NUL-terminated string, and `result`
+/// be null or point to an `OnionValue`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn onion_eval(
+    onion: *mut OnionInterpreter,
+    source: *const c_char,
+    result: *mut OnionValue,
+) -> c_int {
+    let Some(onion) = (unsafe { onion.as_mut() }) else {
+        return -1;
+    };
+    onion.guard(|onion| {
+        let source = unsafe { arg(source, "source") }?;
+        let value = onion.onion.eval_str(&source).map_err(|e| e.to_string())?;
+        onion.set_result(value, result);
+        Ok(())
+    })
+}
+
+/// Call the global function `name` with `nargs` values from `args`.
+///
+/// # Safety
+///
+/// As for `onion_eval`, and `args` must point to `nargs` values, or be null if `nargs` is
+/// 0.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn onion_call(
+    onion: *mut OnionInterpreter,
+    name: *const c_char,
+    args: *const OnionValue,
+    nargs: usize,
+    result: *mut OnionValue,
+) -> c_int {
+    let Some(onion) = (unsafe { onion.as_mut() }) else {
+        return -1;
+    };
+    onion.guard(|onion| {
+        let name = unsafe { arg(name, "name") }?;
+        let args = match nargs {
+            0 => &[][..],
+            _ if args.is_null() => return Err("args is null".to_string()),
+            _ => unsafe { std::slice::from_raw_parts(args, nargs) },
+        };
+        let args = args
+            .iter()
+            .map(|value| unsafe { to_expr(value) })
+            .collect::<Result<Vec<_>, _>>()?;
+        let value = onion.onion.call(&name, &args).map_err(|e| e.to_string())?;
+        onion.set_result(value, result);
+        Ok(())
+    })
+}
+
+/// The result of the last successful `onion_eval` or `onion_call` as JSON, valid until the
+/// next call.
+///
+/// # Safety
+///
+/// `onion` must come from `onion_new`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn onion_result_json(onion: *mut OnionInterpreter) -> *const c_char {
+    let Some(onion) = (unsafe { onion.as_mut() }) else {
+        return std::ptr::null();
+    };
+    if onion.json.is_none() {
+        let json = catch_unwind(AssertUnwindSafe(|| to_json(&onion.result).to_string()));
+        onion.json = Some(c_string(json.unwrap_or_else(|_| "null".to_string())));
+    }
+    onion
+        .json
+        .as_ref()
+        .map_or(std::ptr::null(), |json| json.as_ptr())
+}
+
+/// Why the last call failed, or null if it didn't.
+///
+/// # Safety
+///
+/// `onion` must come from `onion_new`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn onion_error(onion: *const OnionInterpreter) -> *const c_char {
+    unsafe { onion.as_ref() }
+        .and_then(|onion| onion.error.as_ref())
+        .map_or(std::ptr::null(), |error| error.as_ptr())
+}
+
+/// Define the C function `func` as `module.name`, or as a global when `module` is null,
+/// as `OnionApi.define` does for plugins.
+///
+/// # Safety
+///
+/// `onion` must come from `onion_new`, the strings be NUL-terminated or null where
+/// allowed, and `user_data` stay valid as long as the interpreter.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn onion_define(
+    onion: *mut OnionInterpreter,
+    module: *const c_char,
+    name: *const c_char,
+    doc: *const c_char,
+    func: Option<OnionFn>,
+    user_data: *mut c_void,
+) -> c_int {
+    let Some(onion) = (unsafe { onion.as_mut() }) else {
+        return -1;
+    };
+    onion.guard(|onion| {
+        let api = OnionApi::new(onion.onion.context());
+        match unsafe { (api.define)(&api, module, name, doc, func, user_data) } {
+            0 => Ok(()),
+            _ => Err("onion_define: name and func are required".to_string()),
+        }
+    })
+}
+
+/// Define a value as `module.name`, or as a global when `module` is null. The value is
+/// copied.
+///
+/// # Safety
+///
+/// As for `onion_define`, and `value` must point to a valid `OnionValue`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn onion_define_value(
+    onion: *mut OnionInterpreter,
+    module: *const c_char,
+    name: *const c_char,
+    value: *const OnionValue,
+) -> c_int {
+    let Some(onion) = (unsafe { onion.as_mut() }) else {
+        return -1;
+    };
+    onion.guard(|onion| {
+        let api = OnionApi::new(onion.onion.context());
+        match unsafe { (api.define_value)(&api, module, name, value) } {
+            0 => Ok(()),
+            _ => Err("onion_define_value: invalid name or value".to_string()),
+        }
+    })
+}
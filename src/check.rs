@@ -0,0 +1,618 @@
+use crate::context::Context;
+use crate::error::RwLockExt;
+use crate::expr::Expr;
+use crate::parser::{convert_error_to_string, parse_expr};
+use crate::stdlib::contracts;
+use crate::symbol::Symbol;
+use std::collections::HashMap;
+
+/// A likely error found by `onion check --types` before the program runs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    /// Line of the top-level form the problem was found in, starting at 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// What the checker knows about the type of an expression.
+#[derive(Clone, Debug, PartialEq)]
+enum Ty {
+    Unknown,
+    /// A value with a `Type.of` name or a struct name.
+    Value(Symbol),
+    /// A function whose parameter types are known.
+    Func(FnSig),
+    /// A struct method looked up with `.` that hasn't been called.
+    Method(FnSig),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct FnSig {
+    name: String,
+    params: Vec<Option<Symbol>>,
+    ret: Option<Symbol>,
+}
+
+struct StructInfo {
+    parent: Option<Symbol>,
+    interfaces: Vec<Symbol>,
+    fields: Vec<(Symbol, Option<Symbol>)>,
+    methods: HashMap<Symbol, FnSig>,
+}
+
+/// Parse every top-level form in `source`, returning the parse error if it doesn't parse.
+pub fn check_syntax(source: &str, ctx: &Context) -> Result<(), String> {
+    for_each_form(source, ctx, |_, _| {})
+}
+
+/// Parse `source` and report likely type errors in it. Types are inferred from literals,
+/// the signatures of stdlib externs, struct definitions and any annotations in the source.
+/// Returns the parse error if the source doesn't parse.
+pub fn check_types(source: &str, ctx: &Context) -> Result<Vec<Diagnostic>, String> {
+    let mut checker = Checker {
+        ctx,
+        structs: HashMap::new(),
+        scopes: vec![HashMap::new()],
+        line: 1,
+        diagnostics: Vec::new(),
+    };
+    for_each_form(source, ctx, |line, expr| {
+        checker.line = line;
+        checker.infer(&expr);
+    })?;
+    Ok(checker.diagnostics)
+}
+
+/// Call `f` with the line number and parsed expression of each top-level form.
+pub(crate) fn for_each_form(
+    source: &str,
+    ctx: &Context,
+    mut f: impl FnMut(usize, Expr),
+) -> Result<(), String> {
+    let mut input = source.trim_start();
+    while !input.is_empty() {
+        let line = source[..source.len() - input.len()].matches('\n').count() + 1;
+        match parse_expr(input, ctx) {
+            Ok((rest, expr)) => {
+                f(line, expr);
+                input = rest.trim_start();
+            }
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+                return Err(convert_error_to_string(input, e));
+            }
+            Err(nom::Err::Incomplete(_)) => return Err("Parse incomplete".to_string()),
+        }
+    }
+    Ok(())
+}
+
+struct Checker<'a> {
+    ctx: &'a Context,
+    structs: HashMap<Symbol, StructInfo>,
+    scopes: Vec<HashMap<Symbol, Ty>>,
+    line: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, message: String) {
+        self.diagnostics.push(Diagnostic {
+            line: self.line,
+            message,
+        });
+    }
+
+    fn bind(&mut self, name: &Symbol, ty: Ty) {
+        self.scopes.last_mut().unwrap().insert(name.clone(), ty);
+    }
+
+    /// Record an assignment to an existing variable. If the new value has a different type,
+    /// the variable's type is no longer known.
+    fn assign(&mut self, name: &Symbol, ty: &Ty) {
+        if let Some(existing) = self.scopes.iter_mut().rev().find_map(|s| s.get_mut(name))
+            && existing != ty
+        {
+            *existing = Ty::Unknown;
+        }
+    }
+
+    fn is_bound(&self, name: &Symbol) -> bool {
+        self.scopes.iter().any(|s| s.contains_key(name))
+    }
+
+    fn lookup(&self, name: &Symbol) -> Ty {
+        for scope in self.scopes.iter().rev() {
+            if let Some(ty) = scope.get(name) {
+                return ty.clone();
+            }
+        }
+        match self.ctx.resolve(name) {
+            Some(Expr::Extern(f)) => extern_type(name.as_str(), &f),
+            _ => Ty::Unknown,
+        }
+    }
+
+    fn with_scope<T>(&mut self, bindings: Vec<(Symbol, Ty)>, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.scopes.push(bindings.into_iter().collect());
+        let result = f(self);
+        self.scopes.pop();
+        result
+    }
+
+    fn infer_all(&mut self, exprs: &[Expr]) -> Ty {
+        let mut last = Ty::Value(Symbol::new("nil"));
+        for e in exprs {
+            last = self.infer(e);
+        }
+        last
+    }
+
+    fn infer(&mut self, expr: &Expr) -> Ty {
+        match expr {
+            Expr::Nil => value("nil"),
+            Expr::Int(_) | Expr::BigInt(_) => value("int"),
+            Expr::Float(_) => value("float"),
+            Expr::Str(_) => value("string"),
+            Expr::Sym(s) => self.lookup(s),
+            Expr::Keyword(_) => value("keyword"),
+            Expr::Bytes(_) => value("bytes"),
+            Expr::Map(m) => {
+                for (k, v) in m {
+                    self.infer(k);
+                    self.infer(v);
+                }
+                value("map")
+            }
+            Expr::HashMap(m) => {
+                for (k, v) in m {
+                    self.infer(k);
+                    self.infer(v);
+                }
+                value("map")
+            }
+            Expr::List(l) if l.is_empty() => value("list"),
+            Expr::List(l) => {
+                let args: Vec<Expr> = l.iter().skip(1).cloned().collect();
+                self.infer_form(&l[0], &args)
+            }
+            _ => Ty::Unknown,
+        }
+    }
+
+    fn infer_form(&mut self, head: &Expr, args: &[Expr]) -> Ty {
+        if let Expr::Sym(s) = head
+            && !self.is_bound(s)
+        {
+            match s.as_str() {
+                "def" | "=" if args.len() == 2 => {
+                    let ty = self.infer(&args[1]);
+                    match &args[0] {
+                        Expr::Sym(name) if s.as_str() == "def" || !self.is_bound(name) => {
+                            self.bind(name, ty.clone())
+                        }
+                        Expr::Sym(name) => self.assign(name, &ty),
+                        target => {
+                            self.infer(target);
+                        }
+                    }
+                    return ty;
+                }
+                "defun" => return self.infer_defun(args),
+                "fun" if !args.is_empty() => {
+                    return self.infer_function(None, &args[0], &args[1..]);
+                }
+                "struct" => {
+                    self.infer_struct(args);
+                    return value("nil");
+                }
+                "defmethod" => {
+                    self.infer_defmethod(args);
+                    return value("nil");
+                }
+                "interface" | "defgeneric" | "quote" => return Ty::Unknown,
+                "module" if !args.is_empty() => {
+                    self.with_scope(vec![], |c| c.infer_all(&args[1..]));
+                    return Ty::Unknown;
+                }
+                "do" => return self.infer_all(args),
+                "if" => {
+                    let branches: Vec<Ty> = args.iter().map(|a| self.infer(a)).collect();
+                    return match &branches[..] {
+                        [_, then, otherwise] if then == otherwise => then.clone(),
+                        _ => Ty::Unknown,
+                    };
+                }
+                "for" if args.len() >= 2 => {
+                    self.infer(&args[1]);
+                    if let Expr::Sym(var) = &args[0] {
+                        let var = var.clone();
+                        self.with_scope(vec![(var, Ty::Unknown)], |c| c.infer_all(&args[2..]));
+                    }
+                    return Ty::Unknown;
+                }
+                "." if args.len() >= 2 => return self.infer_dot(args),
+                "+" | "-" | "*" | "/" | "%" => return self.infer_arithmetic(s, args),
+                "&" | "|" | "^" | "<<" | ">>" | "~" => return self.infer_bitwise(s, args),
+                "==" | "!=" | "<" | ">" | "<=" | ">=" => {
+                    let tys: Vec<Ty> = args.iter().map(|a| self.infer(a)).collect();
+                    for ty in tys {
+                        if let Ty::Method(sig) = ty {
+                            self.report(format!(
+                                "comparing the method {} itself with {}; did you mean to call it?",
+                                sig.name, s
+                            ));
+                        }
+                    }
+                    return Ty::Unknown;
+                }
+                _ => {}
+            }
+        }
+
+        match self.infer(head) {
+            // Named arguments can't be matched up without parameter names, so skip the checks
+            Ty::Func(_) | Ty::Method(_) if args.iter().any(Expr::is_keyword) => {
+                for a in args {
+                    self.infer(a);
+                }
+                Ty::Unknown
+            }
+            Ty::Func(sig) | Ty::Method(sig) => self.check_call(&sig, args),
+            // Calling a keyword looks it up in a map
+            Ty::Value(v) if v.as_str() == "keyword" => {
+                for a in args {
+                    self.infer(a);
+                }
+                Ty::Unknown
+            }
+            // A list whose head isn't callable, such as `(x + 1)`, evaluates to its head
+            other => {
+                for a in args {
+                    self.infer(a);
+                }
+                other
+            }
+        }
+    }
+
+    fn check_call(&mut self, sig: &FnSig, args: &[Expr]) -> Ty {
+        if args.len() != sig.params.len() {
+            self.report(format!(
+                "{} expects {} arguments, got {}",
+                sig.name,
+                sig.params.len(),
+                args.len()
+            ));
+        }
+        for (i, arg) in args.iter().enumerate() {
+            let actual = self.infer(arg);
+            if let Some(Some(expected)) = sig.params.get(i)
+                && !self.compatible(&actual, expected)
+            {
+                self.report(format!(
+                    "argument {} of {} expects {}, got {}",
+                    i + 1,
+                    sig.name,
+                    expected,
+                    describe(&actual)
+                ));
+            }
+        }
+        match &sig.ret {
+            Some(ret) => Ty::Value(ret.clone()),
+            None => Ty::Unknown,
+        }
+    }
+
+    fn infer_dot(&mut self, args: &[Expr]) -> Ty {
+        if let Some(val) = args.get(2) {
+            self.infer(val);
+        }
+        let key = match &args[1] {
+            Expr::Sym(k) => k,
+            _ => return Ty::Unknown,
+        };
+        if matches!(&args[0], Expr::Sym(s) if s.as_str() == "super") {
+            return Ty::Unknown;
+        }
+
+        // Stdlib modules such as `Math.sqrt`
+        if let Expr::Sym(module) = &args[0]
+            && !self.is_bound(module)
+            && let Some(Expr::Ref(r)) = self.ctx.resolve(module)
+        {
+            return match &*r.read_unpoisoned() {
+                Expr::Map(m) => match m.get(&Expr::Sym(key.clone())) {
+                    Some(Expr::Extern(f)) => extern_type(&format!("{}.{}", module, key), f),
+                    _ => Ty::Unknown,
+                },
+                _ => Ty::Unknown,
+            };
+        }
+
+        match self.infer(&args[0]) {
+            Ty::Value(type_name) if self.structs.contains_key(&type_name) => {
+                let mut current = Some(type_name);
+                while let Some(name) = current {
+                    let info = &self.structs[&name];
+                    if let Some((_, ty)) = info.fields.iter().find(|(f, _)| f == key) {
+                        return ty.clone().map(Ty::Value).unwrap_or(Ty::Unknown);
+                    }
+                    if let Some(sig) = info.methods.get(key) {
+                        return Ty::Method(sig.clone());
+                    }
+                    current = info.parent.clone().filter(|p| self.structs.contains_key(p));
+                }
+                Ty::Unknown
+            }
+            _ => Ty::Unknown,
+        }
+    }
+
+    fn infer_arithmetic(&mut self, op: &Symbol, args: &[Expr]) -> Ty {
+        let tys: Vec<Ty> = args.iter().map(|a| self.infer(a)).collect();
+        let mut result = "int";
+        for ty in &tys {
+            match ty {
+                Ty::Method(sig) => {
+                    self.report(format!(
+                        "using the method {} itself in {}; did you mean to call it?",
+                        sig.name, op
+                    ));
+                    return Ty::Unknown;
+                }
+                Ty::Value(v) if v.as_str() == "int" => {}
+                Ty::Value(v) if v.as_str() == "float" => result = "float",
+                _ => return Ty::Unknown,
+            }
+        }
+        value(result)
+    }
+
+    fn infer_bitwise(&mut self, op: &Symbol, args: &[Expr]) -> Ty {
+        let tys: Vec<Ty> = args.iter().map(|a| self.infer(a)).collect();
+        for ty in &tys {
+            match ty {
+                Ty::Value(v) if v.as_str() == "int" => {}
+                // Structs may overload the operator with a magic method
+                Ty::Value(v) if !self.structs.contains_key(v) => {
+                    self.report(format!("{} expects int, got {}", op, v));
+                    return Ty::Unknown;
+                }
+                _ => return Ty::Unknown,
+            }
+        }
+        value("int")
+    }
+
+    fn infer_defun(&mut self, args: &[Expr]) -> Ty {
+        match args.first() {
+            Some(Expr::Sym(name)) if args.len() >= 3 => {
+                let name = name.clone();
+                self.infer_function(Some(name), &args[1], &args[2..])
+            }
+            _ => Ty::Unknown,
+        }
+    }
+
+    /// Check a function body against its annotations and return the function's type.
+    /// Named functions are bound before their body is checked, so recursive calls are checked too.
+    fn infer_function(&mut self, name: Option<Symbol>, params: &Expr, body: &[Expr]) -> Ty {
+        let (names, types) = match params {
+            Expr::List(l) => match contracts::parse_params(l) {
+                Some(parsed) => parsed,
+                None => return Ty::Unknown,
+            },
+            Expr::Sym(s) => (vec![s.clone()], vec![None]),
+            _ => (vec![], vec![]),
+        };
+        let (ret, body) = contracts::parse_return(body);
+        let sig = FnSig {
+            name: name.as_ref().map_or("fun".to_string(), |n| n.to_string()),
+            params: types.clone(),
+            ret,
+        };
+        if let Some(name) = &name {
+            self.bind(name, Ty::Func(sig.clone()));
+        }
+
+        let bindings = names
+            .into_iter()
+            .zip(types)
+            .map(|(n, t)| (n, type_of(t)))
+            .collect();
+        let actual = self.with_scope(bindings, |c| c.infer_all(body));
+        self.check_return(&sig, &actual);
+        Ty::Func(sig)
+    }
+
+    fn check_return(&mut self, sig: &FnSig, actual: &Ty) {
+        if let Some(ret) = &sig.ret
+            && !self.compatible(actual, ret)
+        {
+            self.report(format!(
+                "{} is declared to return {}, but returns {}",
+                sig.name,
+                ret,
+                describe(actual)
+            ));
+        }
+    }
+
+    fn infer_struct(&mut self, args: &[Expr]) {
+        let name = match args.first() {
+            Some(Expr::Sym(s)) => s.clone(),
+            _ => return,
+        };
+        let mut parent = None;
+        let mut interfaces = Vec::new();
+        let mut i = 1;
+        while let Some(Expr::Sym(s)) = args.get(i) {
+            match (s.as_str(), args.get(i + 1)) {
+                ("extends", Some(Expr::Sym(p))) => {
+                    parent = Some(p.clone());
+                    i += 2;
+                }
+                ("implements", _) => {
+                    i += 1;
+                    while let Some(Expr::Sym(iface)) = args.get(i) {
+                        if matches!(iface.as_str(), "extends" | "implements") {
+                            break;
+                        }
+                        interfaces.push(iface.clone());
+                        i += 1;
+                    }
+                }
+                _ => return,
+            }
+        }
+
+        let mut fields = parent
+            .as_ref()
+            .and_then(|p| self.structs.get(p))
+            .map(|p| p.fields.clone())
+            .unwrap_or_default();
+        if let Some(Expr::List(l)) = args.get(i)
+            && let Some((names, types)) = contracts::parse_params(l)
+        {
+            fields.extend(names.into_iter().zip(types));
+        }
+
+        let mut methods = HashMap::new();
+        let mut bodies = Vec::new();
+        for def in args.iter().skip(i + 1) {
+            let l = match def {
+                Expr::List(l) if l.len() >= 3 => l,
+                _ => continue,
+            };
+            let (m_name, (names, types)) = match (&l[0], &l[1]) {
+                (Expr::Sym(m), Expr::List(p)) => match contracts::parse_params(p) {
+                    Some(parsed) => (m.clone(), parsed),
+                    None => continue,
+                },
+                (Expr::Sym(m), Expr::Nil) => (m.clone(), (vec![], vec![])),
+                _ => continue,
+            };
+            let rest: Vec<Expr> = l.iter().skip(2).cloned().collect();
+            let (ret, body) = contracts::parse_return(&rest);
+            let sig = FnSig {
+                name: format!("{}.{}", name, m_name),
+                params: types.clone(),
+                ret,
+            };
+            methods.insert(m_name, sig.clone());
+            let body = body.to_vec();
+            bodies.push((sig, names.into_iter().zip(types).collect::<Vec<_>>(), body));
+        }
+
+        let ctor = FnSig {
+            name: name.to_string(),
+            params: fields.iter().map(|(_, t)| t.clone()).collect(),
+            ret: Some(name.clone()),
+        };
+        self.structs.insert(
+            name.clone(),
+            StructInfo {
+                parent,
+                interfaces,
+                fields,
+                methods,
+            },
+        );
+        self.bind(&name, Ty::Func(ctor));
+
+        for (sig, params, body) in bodies {
+            let mut bindings: Vec<(Symbol, Ty)> =
+                params.into_iter().map(|(n, t)| (n, type_of(t))).collect();
+            bindings.push((Symbol::SELF, Ty::Value(name.clone())));
+            let actual = self.with_scope(bindings, |c| c.infer_all(&body));
+            self.check_return(&sig, &actual);
+        }
+    }
+
+    fn infer_defmethod(&mut self, args: &[Expr]) {
+        let params = match args.get(1) {
+            Some(Expr::List(l)) => l,
+            _ => return,
+        };
+        let bindings = params
+            .iter()
+            .filter_map(|p| match p {
+                Expr::Sym(s) => Some((s.clone(), Ty::Unknown)),
+                Expr::List(l) => match l.iter().collect::<Vec<_>>()[..] {
+                    [Expr::Sym(s), Expr::Sym(t)] => Some((s.clone(), type_of(Some(t.clone())))),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        self.with_scope(bindings, |c| c.infer_all(&args[2..]));
+    }
+
+    /// Whether a value of type `actual` can be passed where `expected` is wanted.
+    fn compatible(&self, actual: &Ty, expected: &Symbol) -> bool {
+        let expected_name = expected.as_str();
+        match actual {
+            _ if expected_name == contracts::ANY => true,
+            Ty::Unknown => true,
+            Ty::Func(_) | Ty::Method(_) => expected_name == "fun",
+            Ty::Value(actual) => {
+                let actual_name = actual.as_str();
+                actual == expected
+                    || (expected_name == "number" && matches!(actual_name, "int" | "float"))
+                    || (actual_name == "number" && matches!(expected_name, "int" | "float"))
+                    || (expected_name == "ref" && self.structs.contains_key(actual))
+                    || self.is_subtype(actual, expected)
+            }
+        }
+    }
+
+    fn is_subtype(&self, name: &Symbol, ancestor: &Symbol) -> bool {
+        let mut current = Some(name.clone());
+        while let Some(n) = current {
+            let info = match self.structs.get(&n) {
+                Some(info) => info,
+                None => return false,
+            };
+            if &n == ancestor || info.interfaces.contains(ancestor) {
+                return true;
+            }
+            current = info.parent.clone();
+        }
+        false
+    }
+}
+
+fn value(name: &str) -> Ty {
+    Ty::Value(Symbol::new(name))
+}
+
+fn type_of(annotation: Option<Symbol>) -> Ty {
+    annotation.map(Ty::Value).unwrap_or(Ty::Unknown)
+}
+
+fn extern_type(name: &str, f: &crate::expr::ExternFunc) -> Ty {
+    match f.signature() {
+        Some(sig) => Ty::Func(FnSig {
+            name: name.to_string(),
+            params: sig.params.iter().cloned().map(Some).collect(),
+            ret: Some(sig.ret.clone()).filter(|r| r.as_str() != contracts::ANY),
+        }),
+        None => Ty::Unknown,
+    }
+}
+
+fn describe(ty: &Ty) -> String {
+    match ty {
+        Ty::Unknown => "a value of unknown type".to_string(),
+        Ty::Value(v) => v.to_string(),
+        Ty::Func(_) => "fun".to_string(),
+        Ty::Method(sig) => format!("the method {} without calling it", sig.name),
+    }
+}
@@ -1,7 +1,17 @@
 use super::*;
+use crate::error::RwLockExt;
 use crate::expr::Expr;
+use crate::limits::Limits;
+use crate::settings::Settings;
+use crate::stdlib::capabilities::Capabilities;
+use crate::stdlib::ffi::Libraries;
+use crate::stdlib::game::GameState;
+use crate::stdlib::io::Streams;
+use crate::vfs::{RealFs, Vfs};
+use imbl::{OrdMap, Vector};
+use std::borrow::Cow;
 use std::collections::BTreeMap;
-use std::sync::RwLock;
+use std::sync::{Mutex, RwLock};
 use std::{collections::HashMap, sync::Arc};
 
 /// Associativity of an operator.
@@ -30,12 +40,66 @@ impl ParsingContext {
     }
 }
 
+/// A struct type: its fields and the method table shared by all of its instances.
+#[derive(Clone, Debug)]
+pub struct StructDef {
+    pub name: Symbol,
+    pub parent: Option<Symbol>,
+    /// Every field of an instance, inherited fields first.
+    pub fields: Vec<Symbol>,
+    /// The annotated type of each field in `fields`, if any.
+    pub field_types: Vec<Option<Symbol>>,
+    /// Methods defined on this struct; inherited ones are found through `parent`.
+    pub methods: BTreeMap<Symbol, Expr>,
+    pub interfaces: Vec<Symbol>,
+}
+
+/// An interface: the methods a struct must provide and how many parameters each takes.
+#[derive(Clone, Debug)]
+pub struct InterfaceDef {
+    pub name: Symbol,
+    pub methods: Vec<(Symbol, usize)>,
+}
+
+/// One method of a generic function. `specializers` holds the type each parameter must
+/// have, or `None` for a parameter that accepts anything.
+#[derive(Clone, Debug)]
+pub struct MethodDef {
+    pub specializers: Vec<Option<Symbol>>,
+    pub func: Expr,
+}
+
+/// A generic function created by `defgeneric`, and the methods `defmethod` has added to it.
+#[derive(Debug)]
+pub struct GenericDef {
+    pub name: Symbol,
+    pub params: Vec<Symbol>,
+    pub methods: RwLock<Vec<MethodDef>>,
+}
+
+#[derive(Clone, Debug)]
+pub enum TypeDef {
+    Struct(Arc<StructDef>),
+    Interface(Arc<InterfaceDef>),
+    Generic(Arc<GenericDef>),
+}
+
 #[derive(Debug, Default)]
 pub struct Scope {
-    pub vars: RwLock<HashMap<Expr, Expr>>,
+    pub vars: RwLock<HashMap<Symbol, Expr>>,
+    /// Set for the scope of a function call, whose parameters are stored by position.
+    pub frame: Option<Frame>,
     pub parent: Option<Arc<Scope>>,
 }
 
+/// The parameters of one function call. The resolver turns references to them into
+/// `Expr::Local` slots, found by counting frames up the scope chain instead of by name.
+#[derive(Debug)]
+pub struct Frame {
+    pub params: Arc<[Symbol]>,
+    pub slots: RwLock<Vec<Expr>>,
+}
+
 impl Drop for Scope {
     fn drop(&mut self) {
         let mut current = self.parent.take();
@@ -58,6 +122,23 @@ pub struct Context {
     pub parsing: Arc<RwLock<ParsingContext>>,
     /// Current scope.
     pub scope: Arc<Scope>,
+    /// Struct, interface and generic function definitions, shared by every scope.
+    pub types: Arc<RwLock<HashMap<Symbol, TypeDef>>>,
+    /// The window, images and sounds of the `Game` module. Each interpreter has its own,
+    /// shared by every scope, and they are released when the last scope is dropped.
+    pub game: Arc<RwLock<GameState>>,
+    /// The C libraries `FFI.open` has opened, which like `game` belong to the interpreter.
+    pub libraries: Arc<Mutex<Libraries>>,
+    /// The output and input streams of `print`, `eprint` and `IO.read_line`.
+    pub streams: Arc<Streams>,
+    /// What the stdlib may do outside the interpreter.
+    pub caps: Arc<Capabilities>,
+    /// The fuel, time and size limits, and the interrupt flag, of the interpreter.
+    pub limits: Arc<Limits>,
+    /// Strict integers, contract checking and the like, for this interpreter only.
+    pub settings: Arc<Settings>,
+    /// The filesystem `IO` and the `Game` asset loaders use.
+    pub vfs: Arc<dyn Vfs>,
 }
 
 impl Context {
@@ -67,282 +148,415 @@ impl Context {
                 operators: HashMap::new(),
             })),
             scope: Arc::new(Scope::default()),
+            types: Arc::new(RwLock::new(HashMap::new())),
+            game: Arc::new(RwLock::new(GameState::new())),
+            libraries: Arc::new(Mutex::new(Libraries::default())),
+            streams: Arc::new(Streams::new()),
+            caps: Arc::new(Capabilities::all()),
+            limits: Arc::new(Limits::new()),
+            settings: Arc::new(Settings::new()),
+            vfs: Arc::new(RealFs::new()),
         }
     }
 
     pub fn fork(&self) -> Self {
+        self.with_scope(None)
+    }
+
+    /// A new scope for a call to a function, holding its arguments.
+    pub fn fork_frame(&self, params: Arc<[Symbol]>, args: Vec<Expr>) -> Self {
+        self.with_scope(Some(Frame {
+            params,
+            slots: RwLock::new(args),
+        }))
+    }
+
+    fn with_scope(&self, frame: Option<Frame>) -> Self {
         Self {
             parsing: self.parsing.clone(),
             scope: Arc::new(Scope {
                 vars: RwLock::new(HashMap::new()),
+                frame,
                 parent: Some(self.scope.clone()),
             }),
+            types: self.types.clone(),
+            game: self.game.clone(),
+            libraries: self.libraries.clone(),
+            streams: self.streams.clone(),
+            caps: self.caps.clone(),
+            limits: self.limits.clone(),
+            settings: self.settings.clone(),
+            vfs: self.vfs.clone(),
         }
     }
 
     pub fn define_op(&mut self, symbol: impl ToString, info: OpInfo, e: Expr) {
         let symbol = Symbol::new(&symbol.to_string());
         {
-            let mut parsing = self.parsing.write().unwrap();
+            let mut parsing = self.parsing.write_unpoisoned();
             parsing.define_op(&symbol, info);
         }
-        self.define(symbol.into(), e);
+        self.define(symbol, e);
     }
 
     pub fn get_op(&self, symbol: &str) -> Option<OpInfo> {
-        let parsing = self.parsing.read().unwrap();
+        let parsing = self.parsing.read_unpoisoned();
         parsing.operators.get(symbol).cloned()
     }
 
     pub fn get_operator_keys(&self) -> Vec<String> {
-        let parsing = self.parsing.read().unwrap();
+        let parsing = self.parsing.read_unpoisoned();
         parsing.operators.keys().cloned().collect()
     }
 
-    pub fn define(&self, key: Expr, value: Expr) {
-        self.scope.vars.write().unwrap().insert(key, value);
+    /// Define a variable in the current scope. Defining a parameter of the current function
+    /// call replaces its argument.
+    pub fn define(&self, name: impl Into<Symbol>, value: Expr) {
+        let name = name.into();
+        if let Some(frame) = &self.scope.frame
+            && let Some(i) = frame.params.iter().position(|p| *p == name)
+        {
+            frame.slots.write_unpoisoned()[i] = value;
+            return;
+        }
+        self.scope.vars.write_unpoisoned().insert(name, value);
     }
 
-    pub fn resolve(&self, key: &Expr) -> Option<Expr> {
+    /// Look a variable up through the enclosing scopes. `name` is usually a `&Symbol`, but
+    /// a `&str` or an `&Expr` symbol works too.
+    pub fn resolve<K>(&self, name: &K) -> Option<Expr>
+    where
+        K: ?Sized,
+        for<'a> &'a K: Into<Symbol>,
+    {
+        let name = &name.into();
         let mut current = Some(&self.scope);
         while let Some(scope) = current {
-            if let Some(val) = scope.vars.read().unwrap().get(key) {
+            if let Some(frame) = &scope.frame
+                && let Some(i) = frame.params.iter().position(|p| p == name)
+            {
+                return Some(frame.slots.read_unpoisoned()[i].clone());
+            }
+            if let Some(val) = scope.vars.read_unpoisoned().get(name) {
                 return Some(val.clone());
             }
             current = scope.parent.as_ref();
         }
         None
     }
+
+    /// Read parameter `index` of the function call `depth` frames up from this scope.
+    pub fn local(&self, depth: usize, index: usize) -> Expr {
+        let mut depth = depth;
+        let mut current = Some(&self.scope);
+        while let Some(scope) = current {
+            if let Some(frame) = &scope.frame {
+                if depth == 0 {
+                    return frame.slots.read_unpoisoned()[index].clone();
+                }
+                depth -= 1;
+            }
+            current = scope.parent.as_ref();
+        }
+        crate::stop!("Local variable refers to a function call that isn't running")
+    }
+
+    pub fn define_type(&self, name: Symbol, def: TypeDef) {
+        self.types.write_unpoisoned().insert(name, def);
+    }
+
+    pub fn get_type(&self, name: &Symbol) -> Option<TypeDef> {
+        self.types.read_unpoisoned().get(name).cloned()
+    }
+
+    pub fn get_struct(&self, name: &Symbol) -> Option<Arc<StructDef>> {
+        match self.get_type(name) {
+            Some(TypeDef::Struct(def)) => Some(def),
+            _ => None,
+        }
+    }
+
+    pub fn get_generic(&self, name: &Symbol) -> Option<Arc<GenericDef>> {
+        match self.get_type(name) {
+            Some(TypeDef::Generic(def)) => Some(def),
+            _ => None,
+        }
+    }
+
+    /// Look up a method on a struct type, walking up through its parents.
+    /// Returns the struct that defines the method along with the method itself.
+    pub fn find_method(&self, type_name: &Symbol, method: &Symbol) -> Option<(Symbol, Expr)> {
+        let mut current = self.get_struct(type_name);
+        while let Some(def) = current {
+            if let Some(m) = def.methods.get(method) {
+                return Some((def.name.clone(), m.clone()));
+            }
+            current = def.parent.as_ref().and_then(|p| self.get_struct(p));
+        }
+        None
+    }
+}
+
+pub fn eval(expr: Expr, ctx: &mut Context) -> Expr {
+    eval_ref(&expr, ctx)
 }
 
-pub fn eval(mut expr: Expr, ctx: &mut Context) -> Expr {
+/// Evaluate an expression without taking ownership of it. Function bodies and call forms
+/// are only read, never copied.
+pub fn eval_ref(expr: &Expr, ctx: &mut Context) -> Expr {
     let saved_ctx = ctx.clone();
     let mut is_in_new_env = false;
-    loop {
-        if let Some(replacement) = ctx.resolve(&expr) {
-            expr = replacement.clone();
-            return expr;
-        }
-
+    // The body of the last function tail-called, which replaces `expr`
+    let mut tail_body: Option<Arc<Expr>> = None;
+    let result = loop {
+        let expr = tail_body.as_deref().unwrap_or(expr);
+        ctx.limits.step();
         match expr {
+            Expr::Sym(s) => match ctx.resolve(s) {
+                Some(val) => break val,
+                None => break expr.clone(),
+            },
+            Expr::Local { depth, index, .. } => break ctx.local(*depth, *index),
             Expr::List(list) => {
                 if list.is_empty() {
-                    return Expr::List(vec![]);
+                    break Expr::List(Vector::new());
                 }
 
                 // Treat the list as a function application
-                let func_expr = list[0].clone();
-                let mut args = list[1..].to_vec();
-
-                match eval(func_expr, ctx) {
-                    Expr::Extern(f) => expr = f.call(&mut args, ctx),
-                    // Expr::Ref(func_expr) => {
-                    //     match &*func_expr.read().unwrap() {
-                    //         Expr::Extern(f) => {
-                    //             expr = f.call(&mut args, ctx);
-                    //             break;
-                    //         },
-                    //         Expr::Function {
-                    //             params,
-                    //             body,
-                    //             env,
-                    //             name,
-                    //         } => {
-                    //             is_in_new_env = true;
-                    //             if params.len() != args.len() {
-                    //                 panic!(
-                    //                     "Function {:?} expected {} arguments, got {}. Args: {:?}",
-                    //                     name,
-                    //                     params.len(),
-                    //                     args.len(),
-                    //                     args
-                    //                 );
-                    //             }
-
-                    //             let new_scope = Scope {
-                    //                 vars: RwLock::new(HashMap::new()),
-                    //                 parent: Some(env.scope.clone()),
-                    //             };
-
-                    //             let mut new_ctx = Context {
-                    //                 parsing: env.parsing.clone(),
-                    //                 scope: Arc::new(new_scope),
-                    //             };
-
-                    //             // If named, bind self to support recursion
-                    //             if let Some(fn_name) = &name {
-                    //                 let func_clone = Expr::Function {
-                    //                     params: params.clone(),
-                    //                     body: body.clone(),
-                    //                     env: env.clone(),
-                    //                     name: name.clone(),
-                    //                 };
-                    //                 new_ctx.define(fn_name.clone().into(), func_clone);
-                    //             }
-
-                    //             for (param, arg) in params.into_iter().zip(args.into_iter()) {
-                    //                 new_ctx.define(param.clone().into(), eval(arg, ctx));
-                    //             }
-
-                    //             expr = *body.clone();
-                    //             *ctx = new_ctx;
-                    //             continue;
-                    //         }
-                    //         _other => {}
-                    //     }
-                    //     expr = Expr::Ref(func_expr);
-                    // }
+                let args = form_args(list);
+                match eval_ref(&list[0], ctx) {
+                    Expr::Extern(f) => {
+                        let result = f.call(&args, ctx);
+                        ctx.limits.check_size(&result, f.short_desc());
+                        break result;
+                    }
+                    Expr::Keyword(key) => break keyword_get(&key, &args, ctx),
                     Expr::Function {
                         params,
                         body,
                         env,
                         name,
+                        receiver,
                     } => {
                         is_in_new_env = true;
-                        if params.len() != args.len() {
-                            panic!(
-                                "Function {:?} expected {} arguments, got {}. Args: {:?}",
-                                name,
-                                params.len(),
-                                args.len(),
-                                args
-                            );
-                        }
-
-                        let new_scope = Scope {
-                            vars: RwLock::new(HashMap::new()),
-                            parent: Some(env.scope.clone()),
-                        };
-
-                        let mut new_ctx = Context {
-                            parsing: env.parsing.clone(),
-                            scope: Arc::new(new_scope),
-                        };
-
-                        // If named, bind self to support recursion
-                        if let Some(fn_name) = &name {
-                            let func_clone = Expr::Function {
-                                params: params.clone(),
-                                body: body.clone(),
-                                env: env.clone(),
-                                name: name.clone(),
-                            };
-                            new_ctx.define(fn_name.clone().into(), func_clone);
-                        }
-
-                        for (param, arg) in params.into_iter().zip(args.into_iter()) {
-                            new_ctx.define(param.into(), eval(arg, ctx));
-                        }
-
-                        expr = *body;
+                        let new_ctx = bind_args(&params, &body, &env, &name, &receiver, args, ctx);
                         *ctx = new_ctx;
+                        tail_body = Some(body);
                         continue;
                     }
-                    other => {
-                        expr = other;
-                    }
+                    other => break other,
                 }
             }
 
             Expr::Map(m) => {
-                let mut evaluated_map = BTreeMap::new();
+                let mut evaluated_map = OrdMap::new();
                 for (k, v) in m {
-                    let eval_k = eval(k, ctx);
-                    let eval_v = eval(v, ctx);
+                    let eval_k = eval_ref(k, ctx);
+                    let eval_v = eval_ref(v, ctx);
                     evaluated_map.insert(eval_k, eval_v);
                 }
-                expr = Expr::Map(evaluated_map);
+                break Expr::Map(evaluated_map);
             }
             Expr::HashMap(m) => {
-                let mut evaluated_map = HashMap::new();
+                let mut evaluated_map = imbl::HashMap::new();
                 for (k, v) in m {
-                    let eval_k = eval(k, ctx);
-                    let eval_v = eval(v, ctx);
+                    let eval_k = eval_ref(k, ctx);
+                    let eval_v = eval_ref(v, ctx);
                     evaluated_map.insert(eval_k, eval_v);
                 }
-                expr = Expr::HashMap(evaluated_map);
+                break Expr::HashMap(evaluated_map);
             }
 
-            Expr::Quoted(q) => {
-                expr = *q;
-            }
+            Expr::Quoted(q) => break (**q).clone(),
             Expr::Ref(r) => {
-                let result = eval((*r.read().unwrap()).clone(), ctx);
-                r.write().unwrap().clone_from(&result);
-                expr = result;
+                let inner = r.read_unpoisoned().clone();
+                let result = eval_ref(&inner, ctx);
+                r.write_unpoisoned().clone_from(&result);
+                break result;
             }
 
-            _ => {
-                break;
-            }
+            other => break other.clone(),
         }
-
-        break;
-    }
+    };
     if is_in_new_env {
         *ctx = saved_ctx;
     }
 
-    expr
+    result
+}
+
+/// The arguments of a call form. Forms short enough to be stored in one chunk, which is
+/// nearly all of them, are borrowed rather than copied.
+fn form_args(list: &Vector<Expr>) -> Cow<'_, [Expr]> {
+    match list.leaves().next() {
+        Some(leaf) if leaf.len() == list.len() => Cow::Borrowed(&leaf[1..]),
+        _ => Cow::Owned(list.iter().skip(1).cloned().collect()),
+    }
+}
+
+/// Evaluate the arguments of a call to a function in the caller's context, and bind them
+/// in a new scope of the function's environment, along with `self` for a bound method.
+fn bind_args(
+    params: &Arc<[Symbol]>,
+    body: &Arc<Expr>,
+    env: &Context,
+    name: &Option<Symbol>,
+    receiver: &Option<Box<Expr>>,
+    args: Cow<'_, [Expr]>,
+    ctx: &mut Context,
+) -> Context {
+    let args = order_args(params, args, name);
+    if params.len() != args.len() {
+        crate::stop!(
+            "{} expected {} arguments, got {}",
+            name.as_ref().map_or("fun", |n| n.as_str()),
+            params.len(),
+            args.len()
+        );
+    }
+
+    let args = args.iter().map(|arg| eval_ref(arg, ctx)).collect();
+    let new_ctx = env.fork_frame(params.clone(), args);
+    if let Some(obj) = receiver {
+        new_ctx.define(Symbol::SELF, (**obj).clone());
+    }
+
+    // If named, bind self to support recursion, unless a parameter has the same name
+    if let Some(fn_name) = name
+        && !params.contains(fn_name)
+    {
+        let func_clone = Expr::Function {
+            params: params.clone(),
+            body: body.clone(),
+            env: env.clone(),
+            name: name.clone(),
+            receiver: receiver.clone(),
+        };
+        new_ctx.define(fn_name.clone(), func_clone);
+    }
+    new_ctx
+}
+
+/// Calling a keyword looks it up in a map or struct instance: `(:hp unit)`, or
+/// `(:hp unit 0)` to fall back to a default when the key is missing.
+fn keyword_get(key: &Symbol, args: &[Expr], ctx: &mut Context) -> Expr {
+    fn lookup(obj: &Expr, key: &Symbol) -> Option<Expr> {
+        let (keyword, sym) = (Expr::Keyword(key.clone()), Expr::Sym(key.clone()));
+        match obj {
+            Expr::Ref(r) => lookup(&r.read_unpoisoned(), key),
+            Expr::Map(m) => m.get(&keyword).or_else(|| m.get(&sym)).cloned(),
+            Expr::HashMap(m) => m.get(&keyword).or_else(|| m.get(&sym)).cloned(),
+            _ => None,
+        }
+    }
+
+    let obj = match args.first() {
+        Some(arg) => eval_ref(arg, ctx),
+        None => crate::stop!("Keyword :{} must be called with a map", key),
+    };
+    match lookup(&obj, key) {
+        Some(val) => val,
+        None => match args.get(1) {
+            Some(default) => eval_ref(default, ctx),
+            None => Expr::Nil,
+        },
+    }
+}
+
+/// Move named arguments such as `(spawn 1 :y 2)` into the position of the parameter
+/// they name. Positional arguments come first; calls without named arguments are unchanged.
+fn order_args<'a>(
+    params: &[Symbol],
+    args: Cow<'a, [Expr]>,
+    name: &Option<Symbol>,
+) -> Cow<'a, [Expr]> {
+    // A keyword only names a parameter when a value follows it, so `(f :x)` passes `:x`
+    let is_named =
+        |i: usize| i + 1 < args.len() && matches!(&args[i], Expr::Keyword(k) if params.contains(k));
+    let first_named = match (0..args.len()).find(|&i| is_named(i)) {
+        Some(i) => i,
+        None => return args,
+    };
+    let fn_name = name.as_ref().map_or("fun".to_string(), |n| n.to_string());
+    if first_named > params.len() {
+        crate::stop!(
+            "{} expected {} arguments, got {} before its named arguments",
+            fn_name,
+            params.len(),
+            first_named
+        );
+    }
+
+    let mut slots: Vec<Option<Expr>> = vec![None; params.len()];
+    let mut args = args.iter().cloned();
+    for (slot, arg) in slots.iter_mut().zip(args.by_ref().take(first_named)) {
+        *slot = Some(arg);
+    }
+    while let Some(arg) = args.next() {
+        let (key, i) = match &arg {
+            Expr::Keyword(k) => match params.iter().position(|p| p == k) {
+                Some(i) => (k, i),
+                None => crate::stop!("{} has no parameter named {}", fn_name, k),
+            },
+            other => crate::stop!(
+                "{}: positional argument {:?} after named arguments",
+                fn_name,
+                other
+            ),
+        };
+        if slots[i].is_some() {
+            crate::stop!("{}: argument {} given twice", fn_name, key);
+        }
+        match args.next() {
+            Some(val) => slots[i] = Some(val),
+            None => crate::stop!("{}: named argument :{} has no value", fn_name, key),
+        }
+    }
+
+    Cow::Owned(
+        slots
+            .into_iter()
+            .zip(params)
+            .map(|(slot, param)| match slot {
+                Some(val) => val,
+                None => crate::stop!("{}: missing argument {}", fn_name, param),
+            })
+            .collect(),
+    )
 }
 
 pub fn eval_in_place(expr: &mut Expr, ctx: &mut Context) {
-    *expr = eval(expr.clone(), ctx);
+    *expr = eval_ref(expr, ctx);
 }
 
 pub fn apply_in_place(func: &mut Expr, args: &mut [Expr], ctx: &mut Context) {
-    match eval(func.clone(), ctx) {
+    match eval_ref(func, ctx) {
         Expr::Extern(f) => {
             let result = f.call(args, ctx);
             *func = result;
         }
+        Expr::Keyword(key) => {
+            *func = keyword_get(&key, args, ctx);
+        }
         Expr::Function {
             params,
             body,
             env,
             name,
+            receiver,
         } => {
-            if params.len() != args.len() {
-                panic!(
-                    "Function {:?} expected {} arguments, got {}. Args: {:?}",
-                    name,
-                    params.len(),
-                    args.len(),
-                    args
-                );
-            }
-
-            let new_scope = Scope {
-                vars: RwLock::new(HashMap::new()),
-                parent: Some(env.scope.clone()),
-            };
-
-            let mut new_ctx = Context {
-                parsing: env.parsing.clone(),
-                scope: Arc::new(new_scope),
-            };
-
-            // If named, bind self to support recursion
-            if let Some(fn_name) = &name {
-                let func_clone = Expr::Function {
-                    params: params.clone(),
-                    body: body.clone(),
-                    env: env.clone(),
-                    name: name.clone(),
-                };
-                new_ctx.define(fn_name.clone().into(), func_clone);
-            }
-
-            for (param, arg) in params.into_iter().zip(args.iter_mut()) {
-                new_ctx.define(param.clone().into(), eval(arg.clone(), ctx));
-            }
-
-            let body_expr = *body.clone();
-            *func = eval(body_expr, &mut new_ctx);
-        }
-        _ => {
-            return;
+            let mut new_ctx = bind_args(
+                &params,
+                &body,
+                &env,
+                &name,
+                &receiver,
+                Cow::Borrowed(args),
+                ctx,
+            );
+            *func = eval_ref(&body, &mut new_ctx);
         }
+        _ => {}
     }
 }
 
@@ -360,7 +574,7 @@ pub fn apply_in_place(func: &mut Expr, args: &mut [Expr], ctx: &mut Context) {
 //                 }
 //             }
 //             Expr::Ref(r) => {
-//                 let mut inner = r.read().unwrap().clone();
+//                 let mut inner = r.read_unpoisoned().clone();
 //                 *expr = inner;
 //                 eval_in_place(expr, ctx);
 //             }
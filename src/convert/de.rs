@@ -0,0 +1,178 @@
+use super::ConvertError;
+use crate::error::RwLockExt;
+use crate::expr::Expr;
+use num_traits::ToPrimitive;
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+
+/// Read any `Deserialize` value out of an Onion value. Maps and struct instances fill in
+/// Rust structs, with symbol, keyword or string keys naming the fields.
+pub fn from_expr<T: DeserializeOwned>(expr: &Expr) -> Result<T, ConvertError> {
+    T::deserialize(Deserializer::new(expr.clone()))
+}
+
+impl de::Error for ConvertError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        ConvertError::new(msg.to_string())
+    }
+}
+
+/// A serde `Deserializer` reading from an `Expr`. Lists and maps are persistent, so
+/// holding the value instead of borrowing it costs nothing.
+pub struct Deserializer {
+    expr: Expr,
+}
+
+impl Deserializer {
+    pub fn new(expr: Expr) -> Self {
+        // Read through references and quotes, so a struct instance reads like its map
+        let expr = match expr {
+            Expr::Ref(r) => return Self::new(r.read_unpoisoned().clone()),
+            Expr::Quoted(inner) => return Self::new(*inner),
+            expr => expr,
+        };
+        Self { expr }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = ConvertError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ConvertError> {
+        match self.expr {
+            Expr::Nil => visitor.visit_unit(),
+            Expr::Int(n) => visitor.visit_i64(n),
+            Expr::BigInt(n) => match (n.to_i128(), n.to_u128()) {
+                (Some(n), _) => visitor.visit_i128(n),
+                (_, Some(n)) => visitor.visit_u128(n),
+                _ => Err(ConvertError::new(format!("{} is too large", n))),
+            },
+            Expr::Float(f) => visitor.visit_f64(f),
+            Expr::Str(s) => visitor.visit_string(s),
+            Expr::Sym(s) | Expr::Keyword(s) => visitor.visit_str(s.as_str()),
+            Expr::Bytes(b) => visitor.visit_byte_buf(b),
+            Expr::List(l) => visitor.visit_seq(de::value::SeqDeserializer::new(
+                l.into_iter().map(Deserializer::new),
+            )),
+            Expr::Map(m) => visitor.visit_map(de::value::MapDeserializer::new(
+                m.into_iter()
+                    .map(|(k, v)| (Deserializer::new(k), Deserializer::new(v))),
+            )),
+            Expr::HashMap(m) => visitor.visit_map(de::value::MapDeserializer::new(
+                m.into_iter()
+                    .map(|(k, v)| (Deserializer::new(k), Deserializer::new(v))),
+            )),
+            // A tagged value reads as a map from its tag to its value
+            Expr::Tagged { tag, value } => visitor.visit_map(de::value::MapDeserializer::new(
+                std::iter::once((Deserializer::new(Expr::Sym(tag)), Deserializer::new(*value))),
+            )),
+            other => Err(ConvertError::expected("data", &other)),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ConvertError> {
+        visitor.visit_bool(!matches!(self.expr, Expr::Nil | Expr::Int(0)))
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ConvertError> {
+        match self.expr {
+            Expr::Nil => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ConvertError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ConvertError> {
+        match self.expr {
+            Expr::Sym(s) | Expr::Keyword(s) => visitor.visit_enum(s.as_str().into_deserializer()),
+            Expr::Str(s) => visitor.visit_enum(s.into_deserializer()),
+            Expr::Tagged { tag, value } => visitor.visit_enum(Variant {
+                tag: Expr::Sym(tag),
+                value: *value,
+            }),
+            other => Err(ConvertError::expected("keyword or tagged value", &other)),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, ConvertError> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        unit unit_struct seq tuple tuple_struct map struct identifier
+    }
+}
+
+impl<'de> IntoDeserializer<'de, ConvertError> for Deserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// An enum variant holding data, read from a tagged value.
+struct Variant {
+    tag: Expr,
+    value: Expr,
+}
+
+impl<'de> de::EnumAccess<'de> for Variant {
+    type Error = ConvertError;
+    type Variant = Deserializer;
+
+    fn variant_seed<S: de::DeserializeSeed<'de>>(
+        self,
+        seed: S,
+    ) -> Result<(S::Value, Deserializer), ConvertError> {
+        let tag = seed.deserialize(Deserializer::new(self.tag))?;
+        Ok((tag, Deserializer::new(self.value)))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Deserializer {
+    type Error = ConvertError;
+
+    fn unit_variant(self) -> Result<(), ConvertError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<S: de::DeserializeSeed<'de>>(
+        self,
+        seed: S,
+    ) -> Result<S::Value, ConvertError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ConvertError> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ConvertError> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
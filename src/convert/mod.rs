@@ -0,0 +1,357 @@
+//! Moving data between Rust and Onion: the `FromExpr` and `IntoExpr` traits for common
+//! types, and a serde `Serializer`/`Deserializer` pair for everything else.
+//!
+//! Rust structs become maps keyed by symbols, like struct instances and `[x 10 y 20]`
+//! literals. Unit enum variants become keywords, and variants holding data become values
+//! tagged with the variant name. `bool` is `1` or `nil`, and `None` is `nil`.
+
+mod de;
+mod ser;
+
+pub use de::{Deserializer, from_expr};
+pub use ser::{Serializer, to_expr};
+
+use crate::error::RwLockExt;
+use crate::expr::Expr;
+use crate::stdlib::contracts::ANY;
+use crate::stdlib::reflect::type_of;
+use crate::symbol::Symbol;
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// A value that doesn't have the shape the Rust side asked for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConvertError {
+    pub message: String,
+}
+
+impl ConvertError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+
+    /// The error for finding `found` where a value of type `expected` was wanted.
+    pub fn expected(expected: &str, found: &Expr) -> Self {
+        Self::new(format!(
+            "expected {}, got {} {}",
+            expected,
+            type_of(found),
+            found
+        ))
+    }
+}
+
+impl std::fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ConvertError {}
+
+/// A Rust value that can be read from an Onion value.
+pub trait FromExpr: Sized {
+    /// The type name accepted, as written in signatures and annotations.
+    const TYPE: &'static str = ANY;
+
+    fn from_expr(expr: &Expr) -> Result<Self, ConvertError>;
+}
+
+/// A Rust value that can be handed to Onion code.
+pub trait IntoExpr {
+    /// The type name produced, as written in signatures and annotations.
+    const TYPE: &'static str = ANY;
+
+    fn into_expr(self) -> Expr;
+}
+
+/// Run `f` on the value behind any references, so struct instances read like maps.
+fn deref<T>(expr: &Expr, f: impl FnOnce(&Expr) -> T) -> T {
+    match expr {
+        Expr::Ref(r) => deref(&r.read_unpoisoned(), f),
+        Expr::Quoted(inner) => deref(inner, f),
+        other => f(other),
+    }
+}
+
+impl FromExpr for Expr {
+    fn from_expr(expr: &Expr) -> Result<Self, ConvertError> {
+        Ok(expr.clone())
+    }
+}
+
+impl IntoExpr for Expr {
+    fn into_expr(self) -> Expr {
+        self
+    }
+}
+
+macro_rules! int_conversions {
+    ($($ty:ty => $to:ident),*) => {$(
+        impl FromExpr for $ty {
+            const TYPE: &'static str = "int";
+
+            fn from_expr(expr: &Expr) -> Result<Self, ConvertError> {
+                deref(expr, |expr| {
+                    let n = match expr {
+                        Expr::Int(n) => <$ty>::try_from(*n).ok(),
+                        Expr::BigInt(n) => n.$to(),
+                        _ => return Err(ConvertError::expected(stringify!($ty), expr)),
+                    };
+                    n.ok_or_else(|| {
+                        ConvertError::new(format!("{} does not fit in {}", expr, stringify!($ty)))
+                    })
+                })
+            }
+        }
+
+        impl IntoExpr for $ty {
+            const TYPE: &'static str = "int";
+
+            fn into_expr(self) -> Expr {
+                match i64::try_from(self) {
+                    Ok(n) => Expr::Int(n),
+                    Err(_) => Expr::BigInt(BigInt::from(self)),
+                }
+            }
+        }
+    )*};
+}
+
+int_conversions!(
+    i8 => to_i8, i16 => to_i16, i32 => to_i32, i64 => to_i64, i128 => to_i128, isize => to_isize,
+    u8 => to_u8, u16 => to_u16, u32 => to_u32, u64 => to_u64, u128 => to_u128, usize => to_usize
+);
+
+impl FromExpr for f64 {
+    const TYPE: &'static str = "number";
+
+    fn from_expr(expr: &Expr) -> Result<Self, ConvertError> {
+        deref(expr, |expr| match expr {
+            Expr::Float(f) => Ok(*f),
+            Expr::Int(n) => Ok(*n as f64),
+            Expr::BigInt(n) => n
+                .to_f64()
+                .ok_or_else(|| ConvertError::expected("f64", expr)),
+            _ => Err(ConvertError::expected("f64", expr)),
+        })
+    }
+}
+
+impl IntoExpr for f64 {
+    const TYPE: &'static str = "float";
+
+    fn into_expr(self) -> Expr {
+        Expr::Float(self)
+    }
+}
+
+impl FromExpr for f32 {
+    const TYPE: &'static str = "number";
+
+    fn from_expr(expr: &Expr) -> Result<Self, ConvertError> {
+        f64::from_expr(expr).map(|f| f as f32)
+    }
+}
+
+impl IntoExpr for f32 {
+    const TYPE: &'static str = "float";
+
+    fn into_expr(self) -> Expr {
+        Expr::Float(self as f64)
+    }
+}
+
+impl IntoExpr for () {
+    const TYPE: &'static str = "nil";
+
+    fn into_expr(self) -> Expr {
+        Expr::Nil
+    }
+}
+
+impl FromExpr for bool {
+    fn from_expr(expr: &Expr) -> Result<Self, ConvertError> {
+        Ok(!matches!(expr, Expr::Nil | Expr::Int(0)))
+    }
+}
+
+impl IntoExpr for bool {
+    fn into_expr(self) -> Expr {
+        if self { Expr::Int(1) } else { Expr::Nil }
+    }
+}
+
+impl FromExpr for String {
+    const TYPE: &'static str = "string";
+
+    fn from_expr(expr: &Expr) -> Result<Self, ConvertError> {
+        deref(expr, |expr| match expr {
+            Expr::Str(s) => Ok(s.clone()),
+            Expr::Sym(s) | Expr::Keyword(s) => Ok(s.to_string()),
+            _ => Err(ConvertError::expected("string", expr)),
+        })
+    }
+}
+
+impl IntoExpr for String {
+    const TYPE: &'static str = "string";
+
+    fn into_expr(self) -> Expr {
+        Expr::Str(self)
+    }
+}
+
+impl IntoExpr for &str {
+    const TYPE: &'static str = "string";
+
+    fn into_expr(self) -> Expr {
+        Expr::Str(self.to_string())
+    }
+}
+
+impl FromExpr for Symbol {
+    const TYPE: &'static str = "symbol";
+
+    fn from_expr(expr: &Expr) -> Result<Self, ConvertError> {
+        deref(expr, |expr| match expr {
+            Expr::Sym(s) | Expr::Keyword(s) => Ok(s.clone()),
+            Expr::Str(s) => Ok(Symbol::new(s)),
+            _ => Err(ConvertError::expected("symbol", expr)),
+        })
+    }
+}
+
+impl IntoExpr for Symbol {
+    const TYPE: &'static str = "symbol";
+
+    fn into_expr(self) -> Expr {
+        Expr::Sym(self)
+    }
+}
+
+impl<T: FromExpr> FromExpr for Option<T> {
+    fn from_expr(expr: &Expr) -> Result<Self, ConvertError> {
+        match expr {
+            Expr::Nil => Ok(None),
+            other => T::from_expr(other).map(Some),
+        }
+    }
+}
+
+impl<T: IntoExpr> IntoExpr for Option<T> {
+    fn into_expr(self) -> Expr {
+        self.map_or(Expr::Nil, IntoExpr::into_expr)
+    }
+}
+
+impl<T: FromExpr> FromExpr for Vec<T> {
+    const TYPE: &'static str = "list";
+
+    fn from_expr(expr: &Expr) -> Result<Self, ConvertError> {
+        deref(expr, |expr| match expr {
+            Expr::List(l) => l.iter().map(T::from_expr).collect(),
+            Expr::Nil => Ok(Vec::new()),
+            _ => Err(ConvertError::expected("list", expr)),
+        })
+    }
+}
+
+impl<T: IntoExpr> IntoExpr for Vec<T> {
+    const TYPE: &'static str = "list";
+
+    fn into_expr(self) -> Expr {
+        Expr::List(self.into_iter().map(IntoExpr::into_expr).collect())
+    }
+}
+
+/// Convert each entry of a map, of either kind.
+fn map_entries<K: FromExpr, V: FromExpr, C: FromIterator<(K, V)>>(
+    expr: &Expr,
+) -> Result<C, ConvertError> {
+    deref(expr, |expr| {
+        let entry = |(k, v): (&Expr, &Expr)| Ok((K::from_expr(k)?, V::from_expr(v)?));
+        match expr {
+            Expr::Map(m) => m.iter().map(entry).collect(),
+            Expr::HashMap(m) => m.iter().map(entry).collect(),
+            Expr::Nil => std::iter::empty().collect(),
+            _ => Err(ConvertError::expected("map", expr)),
+        }
+    })
+}
+
+impl<K: FromExpr + Eq + Hash, V: FromExpr> FromExpr for HashMap<K, V> {
+    const TYPE: &'static str = "map";
+
+    fn from_expr(expr: &Expr) -> Result<Self, ConvertError> {
+        map_entries(expr)
+    }
+}
+
+impl<K: IntoExpr, V: IntoExpr> IntoExpr for HashMap<K, V> {
+    const TYPE: &'static str = "map";
+
+    fn into_expr(self) -> Expr {
+        Expr::HashMap(
+            self.into_iter()
+                .map(|(k, v)| (k.into_expr(), v.into_expr()))
+                .collect(),
+        )
+    }
+}
+
+impl<K: FromExpr + Ord, V: FromExpr> FromExpr for BTreeMap<K, V> {
+    const TYPE: &'static str = "map";
+
+    fn from_expr(expr: &Expr) -> Result<Self, ConvertError> {
+        map_entries(expr)
+    }
+}
+
+impl<K: IntoExpr, V: IntoExpr> IntoExpr for BTreeMap<K, V> {
+    const TYPE: &'static str = "map";
+
+    fn into_expr(self) -> Expr {
+        Expr::Map(
+            self.into_iter()
+                .map(|(k, v)| (k.into_expr(), v.into_expr()))
+                .collect(),
+        )
+    }
+}
+
+macro_rules! tuple_conversions {
+    ($($len:literal => ($($name:ident $index:tt),+)),*) => {$(
+        impl<$($name: FromExpr),+> FromExpr for ($($name,)+) {
+            const TYPE: &'static str = "list";
+
+            fn from_expr(expr: &Expr) -> Result<Self, ConvertError> {
+                deref(expr, |expr| match expr {
+                    Expr::List(l) if l.len() == $len => Ok(($($name::from_expr(&l[$index])?,)+)),
+                    _ => Err(ConvertError::expected(concat!("list of ", $len), expr)),
+                })
+            }
+        }
+
+        impl<$($name: IntoExpr),+> IntoExpr for ($($name,)+) {
+            const TYPE: &'static str = "list";
+
+            fn into_expr(self) -> Expr {
+                Expr::List(imbl::vector![$(self.$index.into_expr()),+])
+            }
+        }
+    )*};
+}
+
+tuple_conversions!(
+    1 => (A 0),
+    2 => (A 0, B 1),
+    3 => (A 0, B 1, C 2),
+    4 => (A 0, B 1, C 2, D 3),
+    5 => (A 0, B 1, C 2, D 3, E 4),
+    6 => (A 0, B 1, C 2, D 3, E 4, F 5)
+);
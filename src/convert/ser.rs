@@ -0,0 +1,362 @@
+use super::{ConvertError, IntoExpr};
+use crate::expr::Expr;
+use crate::symbol::Symbol;
+use imbl::{OrdMap, Vector};
+use serde::ser::{self, Serialize};
+
+/// Turn any `Serialize` value into an Onion value.
+pub fn to_expr<T: Serialize + ?Sized>(value: &T) -> Result<Expr, ConvertError> {
+    value.serialize(Serializer)
+}
+
+impl ser::Error for ConvertError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        ConvertError::new(msg.to_string())
+    }
+}
+
+/// A serde `Serializer` whose output is an `Expr`.
+pub struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = Expr;
+    type Error = ConvertError;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = SeqSerializer;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = MapSerializer;
+
+    fn serialize_bool(self, v: bool) -> Result<Expr, ConvertError> {
+        Ok(v.into_expr())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Expr, ConvertError> {
+        Ok(v.into_expr())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Expr, ConvertError> {
+        Ok(v.into_expr())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Expr, ConvertError> {
+        Ok(v.into_expr())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Expr, ConvertError> {
+        Ok(v.into_expr())
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Expr, ConvertError> {
+        Ok(v.into_expr())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Expr, ConvertError> {
+        Ok(v.into_expr())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Expr, ConvertError> {
+        Ok(v.into_expr())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Expr, ConvertError> {
+        Ok(v.into_expr())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Expr, ConvertError> {
+        Ok(v.into_expr())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Expr, ConvertError> {
+        Ok(v.into_expr())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Expr, ConvertError> {
+        Ok(v.into_expr())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Expr, ConvertError> {
+        Ok(v.into_expr())
+    }
+
+    fn serialize_char(self, v: char) -> Result<Expr, ConvertError> {
+        Ok(Expr::Str(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Expr, ConvertError> {
+        Ok(v.into_expr())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Expr, ConvertError> {
+        Ok(Expr::Bytes(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Expr, ConvertError> {
+        Ok(Expr::Nil)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Expr, ConvertError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Expr, ConvertError> {
+        Ok(Expr::Nil)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Expr, ConvertError> {
+        Ok(Expr::Nil)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Expr, ConvertError> {
+        Ok(Expr::keyword(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Expr, ConvertError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Expr, ConvertError> {
+        Ok(Expr::Tagged {
+            tag: Symbol::new(variant),
+            value: Box::new(value.serialize(self)?),
+        })
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<SeqSerializer, ConvertError> {
+        Ok(SeqSerializer {
+            tag: None,
+            items: Vector::new(),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, ConvertError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, ConvertError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SeqSerializer, ConvertError> {
+        Ok(SeqSerializer {
+            tag: Some(Symbol::new(variant)),
+            items: Vector::new(),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapSerializer, ConvertError> {
+        Ok(MapSerializer {
+            tag: None,
+            entries: OrdMap::new(),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<MapSerializer, ConvertError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<MapSerializer, ConvertError> {
+        Ok(MapSerializer {
+            tag: Some(Symbol::new(variant)),
+            entries: OrdMap::new(),
+            key: None,
+        })
+    }
+}
+
+/// Wrap the value of an enum variant in its tag.
+fn tagged(tag: Option<Symbol>, value: Expr) -> Expr {
+    match tag {
+        Some(tag) => Expr::Tagged {
+            tag,
+            value: Box::new(value),
+        },
+        None => value,
+    }
+}
+
+pub struct SeqSerializer {
+    tag: Option<Symbol>,
+    items: Vector<Expr>,
+}
+
+impl SeqSerializer {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        self.items.push_back(to_expr(value)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Expr, ConvertError> {
+        Ok(tagged(self.tag, Expr::List(self.items)))
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = Expr;
+    type Error = ConvertError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Expr, ConvertError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = Expr;
+    type Error = ConvertError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Expr, ConvertError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = Expr;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Expr, ConvertError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SeqSerializer {
+    type Ok = Expr;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Expr, ConvertError> {
+        self.finish()
+    }
+}
+
+pub struct MapSerializer {
+    tag: Option<Symbol>,
+    entries: OrdMap<Expr, Expr>,
+    /// The key of the entry whose value comes next.
+    key: Option<Expr>,
+}
+
+impl MapSerializer {
+    /// Struct fields are keyed by symbols, like the fields of a struct instance.
+    fn field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), ConvertError> {
+        self.entries.insert(Expr::sym(key), to_expr(value)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Expr, ConvertError> {
+        Ok(tagged(self.tag, Expr::Map(self.entries)))
+    }
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = Expr;
+    type Error = ConvertError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), ConvertError> {
+        self.key = Some(to_expr(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| ConvertError::new("map value serialized before its key"))?;
+        self.entries.insert(key, to_expr(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Expr, ConvertError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = Expr;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), ConvertError> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<Expr, ConvertError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for MapSerializer {
+    type Ok = Expr;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), ConvertError> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<Expr, ConvertError> {
+        self.finish()
+    }
+}
@@ -0,0 +1,130 @@
+use crate::symbol::Symbol;
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// An error raised with `stop!` while a `try` is running.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+thread_local! {
+    /// How many `catch` calls are running on this thread.
+    static CATCHING: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Report a runtime error. Inside `catch` the error unwinds back to it; otherwise the
+/// message is printed and the process exits.
+pub fn raise(message: String) -> ! {
+    if CATCHING.with(Cell::get) > 0 {
+        panic::resume_unwind(Box::new(RuntimeError { message }));
+    }
+    eprintln!("Runtime Error: {}", message);
+    // Print a backtrace if available
+    let bt = std::backtrace::Backtrace::capture();
+    if bt.status() == std::backtrace::BacktraceStatus::Captured {
+        eprintln!("{}", bt);
+    }
+    std::process::exit(1);
+}
+
+/// Run `f`, returning any runtime error it raises instead of exiting. Other panics are
+/// passed on unchanged.
+pub fn catch<T>(f: impl FnOnce() -> T) -> Result<T, RuntimeError> {
+    CATCHING.with(|c| c.set(c.get() + 1));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|c| c.set(c.get() - 1));
+    match result {
+        Ok(val) => Ok(val),
+        Err(payload) => match payload.downcast::<RuntimeError>() {
+            Ok(err) => Err(*err),
+            Err(payload) => panic::resume_unwind(payload),
+        },
+    }
+}
+
+/// Locking that ignores poisoning. A runtime error unwinding past a write guard poisons
+/// its lock, but `try` recovers from the error and the value behind the lock is still
+/// whole, so the interpreter locks with these rather than `unwrap`.
+pub trait RwLockExt<T: ?Sized> {
+    fn read_unpoisoned(&self) -> RwLockReadGuard<'_, T>;
+    fn write_unpoisoned(&self) -> RwLockWriteGuard<'_, T>;
+}
+
+impl<T: ?Sized> RwLockExt<T> for RwLock<T> {
+    fn read_unpoisoned(&self) -> RwLockReadGuard<'_, T> {
+        self.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_unpoisoned(&self) -> RwLockWriteGuard<'_, T> {
+        self.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// [`RwLockExt`] for a `Mutex`.
+pub trait MutexExt<T: ?Sized> {
+    fn lock_unpoisoned(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T: ?Sized> MutexExt<T> for Mutex<T> {
+    fn lock_unpoisoned(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// An error from running code through an [`Interpreter`](crate::interpreter::Interpreter).
+#[derive(Debug)]
+pub enum Error {
+    /// The source doesn't parse; the message points at the problem.
+    Parse(String),
+    /// The code raised an error while running.
+    Runtime(RuntimeError),
+    /// A source file couldn't be read.
+    Io(std::io::Error),
+    /// There is no global with this name.
+    Undefined(Symbol),
+    /// The global called is not a function.
+    NotCallable(Symbol),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Parse(message) => write!(f, "Parse error:\n{}", message),
+            Error::Runtime(err) => write!(f, "Runtime Error: {}", err),
+            Error::Io(err) => write!(f, "IO error: {}", err),
+            Error::Undefined(name) => write!(f, "{} is not defined", name),
+            Error::NotCallable(name) => write!(f, "{} is not a function", name),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+impl From<RuntimeError> for Error {
+    fn from(err: RuntimeError) -> Self {
+        Error::Runtime(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
@@ -1,30 +1,89 @@
 use super::Context;
 use super::Symbol;
+use crate::error::RwLockExt;
+use imbl::{OrdMap, Vector};
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
 use std::collections::{BTreeMap, HashMap};
 use std::sync::{Arc, RwLock};
 
+/// Parameter and return types of an extern, used by `onion check --types`. Types are the
+/// names `Type.of` gives, plus `number` for an int or a float and `any` for anything.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Signature {
+    pub params: Vec<Symbol>,
+    pub ret: Symbol,
+}
+
+/// A native function. It receives its arguments unevaluated and borrowed from the calling
+/// form, so natives evaluate the ones they need with `eval_ref`.
 #[derive(Clone)]
 pub struct ExternFunc {
-    func: Arc<dyn Fn(&mut [Expr], &mut Context) -> Expr + Send + Sync>,
+    func: Arc<dyn Fn(&[Expr], &mut Context) -> Expr + Send + Sync>,
     short_desc: String,
     long_desc: String,
+    signature: Option<Arc<Signature>>,
+    special: bool,
 }
 
 impl ExternFunc {
+    /// Wrap a native that takes its arguments as a mutable slice. The arguments are copied
+    /// into a fresh buffer on every call; prefer `ExternFunc::borrowed` for new natives.
     pub fn new<F, S1, S2>(func: F, short_desc: S1, long_desc: S2) -> Self
     where
         F: Fn(&mut [Expr], &mut Context) -> Expr + Send + Sync + 'static,
         S1: Into<String>,
         S2: Into<String>,
+    {
+        Self::borrowed(
+            move |args: &[Expr], ctx: &mut Context| func(&mut args.to_vec(), ctx),
+            short_desc,
+            long_desc,
+        )
+    }
+
+    /// Wrap a native that borrows its arguments from the calling form.
+    pub fn borrowed<F, S1, S2>(func: F, short_desc: S1, long_desc: S2) -> Self
+    where
+        F: Fn(&[Expr], &mut Context) -> Expr + Send + Sync + 'static,
+        S1: Into<String>,
+        S2: Into<String>,
     {
         Self {
             func: Arc::new(func),
             short_desc: short_desc.into(),
             long_desc: long_desc.into(),
+            signature: None,
+            special: false,
         }
     }
 
-    pub fn call(&self, args: &mut [Expr], ctx: &mut Context) -> Expr {
+    /// Declare the parameter and return types of this extern for the static checker.
+    pub fn with_signature(mut self, params: &[&str], ret: &str) -> Self {
+        self.signature = Some(Arc::new(Signature {
+            params: params.iter().map(|p| Symbol::new(p)).collect(),
+            ret: Symbol::new(ret),
+        }));
+        self
+    }
+
+    pub fn signature(&self) -> Option<&Signature> {
+        self.signature.as_deref()
+    }
+
+    /// Mark this extern as a special form, which reads some of its arguments as syntax
+    /// (names, parameter lists, ...) instead of evaluating them. The resolver leaves the
+    /// arguments of special forms alone.
+    pub fn special(mut self) -> Self {
+        self.special = true;
+        self
+    }
+
+    pub fn is_special(&self) -> bool {
+        self.special
+    }
+
+    pub fn call(&self, args: &[Expr], ctx: &mut Context) -> Expr {
         (self.func)(args, ctx)
     }
 
@@ -78,23 +137,41 @@ impl Ord for ExternFunc {
 pub enum Expr {
     Nil,
     Int(i64),
+    /// An integer too large for `Int`. Arithmetic returns `Int` whenever the result fits.
+    BigInt(BigInt),
     Float(f64),
     Str(String),
     Sym(Symbol),
-    List(Vec<Expr>),
-    Map(BTreeMap<Expr, Expr>),
-    HashMap(HashMap<Expr, Expr>),
+    /// A reference to parameter `index` of the function call `depth` frames up, which the
+    /// resolver puts in place of a symbol when a function is created.
+    Local {
+        name: Symbol,
+        depth: usize,
+        index: usize,
+    },
+    /// A self-evaluating name written `:name`, used for map keys and named arguments.
+    Keyword(Symbol),
+    /// Raw binary data, written `b"..."`.
+    Bytes(Vec<u8>),
+    /// Lists and maps are persistent: cloning one is O(1), and updated copies share
+    /// structure with the original.
+    List(Vector<Expr>),
+    Map(OrdMap<Expr, Expr>),
+    HashMap(imbl::HashMap<Expr, Expr>),
     Tagged {
         tag: Symbol,
         value: Box<Expr>,
     },
     Extern(ExternFunc),
     Quoted(Box<Expr>),
+    /// A closure. The body is shared, so copying a function never copies its code.
     Function {
-        params: Vec<Symbol>,
-        body: Box<Expr>,
+        params: Arc<[Symbol]>,
+        body: Arc<Expr>,
         env: Context,
         name: Option<Symbol>,
+        /// The instance a method was looked up on, bound to `self` when it is called.
+        receiver: Option<Box<Expr>>,
     },
     Ref(Arc<RwLock<Expr>>),
 }
@@ -111,16 +188,51 @@ impl Expr {
         Expr::Extern(ExternFunc::new(func, short_desc, long_desc))
     }
 
+    /// Like `extern_fun`, for a native that borrows its arguments.
+    pub fn extern_ref<F, S1, S2>(func: F, short_desc: S1, long_desc: S2) -> Self
+    where
+        F: Fn(&[Expr], &mut Context) -> Expr + Send + Sync + 'static,
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        Expr::Extern(ExternFunc::borrowed(func, short_desc, long_desc))
+    }
+
+    /// Declare the signature of an extern function; other values are returned unchanged.
+    pub fn with_signature(self, params: &[&str], ret: &str) -> Self {
+        match self {
+            Expr::Extern(f) => Expr::Extern(f.with_signature(params, ret)),
+            other => other,
+        }
+    }
+
+    /// Mark an extern function as a special form; other values are returned unchanged.
+    pub fn special(self) -> Self {
+        match self {
+            Expr::Extern(f) => Expr::Extern(f.special()),
+            other => other,
+        }
+    }
+
     pub fn sym<S: Into<Symbol>>(s: S) -> Self {
         Expr::Sym(s.into())
     }
 
+    pub fn keyword<S: Into<Symbol>>(s: S) -> Self {
+        Expr::Keyword(s.into())
+    }
+
     pub fn str<S: Into<String>>(s: S) -> Self {
         Expr::Str(s.into())
     }
 
+    /// A list of `items`, for natives building one from a `Vec` or an iterator.
+    pub fn list<I: IntoIterator<Item = Expr>>(items: I) -> Self {
+        Expr::List(items.into_iter().collect())
+    }
+
     pub fn is_int(&self) -> bool {
-        matches!(self, Expr::Int(_))
+        matches!(self, Expr::Int(_) | Expr::BigInt(_))
     }
 
     pub fn is_float(&self) -> bool {
@@ -139,6 +251,31 @@ impl Expr {
         matches!(self, Expr::Sym(_))
     }
 
+    pub fn is_keyword(&self) -> bool {
+        matches!(self, Expr::Keyword(_))
+    }
+
+    /// The order values are printed and sorted in: like `Ord`, except that symbols and
+    /// keywords compare by name rather than by ID, inside lists too.
+    pub fn cmp_by_name(&self, other: &Self) -> std::cmp::Ordering {
+        match (self, other) {
+            (Expr::Sym(a), Expr::Sym(b)) | (Expr::Keyword(a), Expr::Keyword(b)) => {
+                a.as_str().cmp(b.as_str())
+            }
+            (Expr::List(a), Expr::List(b)) => a
+                .iter()
+                .zip(b.iter())
+                .map(|(x, y)| x.cmp_by_name(y))
+                .find(|o| o.is_ne())
+                .unwrap_or_else(|| a.len().cmp(&b.len())),
+            _ => self.cmp(other),
+        }
+    }
+
+    pub fn is_bytes(&self) -> bool {
+        matches!(self, Expr::Bytes(_))
+    }
+
     pub fn is_list(&self) -> bool {
         matches!(self, Expr::List(_))
     }
@@ -169,6 +306,7 @@ impl Expr {
         match self {
             Expr::Float(f) => Some(*f),
             Expr::Int(i) => Some(*i as f64),
+            Expr::BigInt(b) => b.to_f64(),
             _ => None,
         }
     }
@@ -176,6 +314,7 @@ impl Expr {
     pub fn as_number(&self) -> Option<f64> {
         match self {
             Expr::Int(i) => Some(*i as f64),
+            Expr::BigInt(b) => b.to_f64(),
             Expr::Float(f) => Some(*f),
             _ => None,
         }
@@ -197,7 +336,23 @@ impl Expr {
         }
     }
 
-    pub fn as_list(&self) -> Option<&[Expr]> {
+    pub fn as_keyword(&self) -> Option<&Symbol> {
+        if let Expr::Keyword(k) = self {
+            Some(k)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        if let Expr::Bytes(b) = self {
+            Some(b)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&Vector<Expr>> {
         if let Expr::List(l) = self {
             Some(l)
         } else {
@@ -205,7 +360,13 @@ impl Expr {
         }
     }
 
-    pub fn as_map(&self) -> Option<&BTreeMap<Expr, Expr>> {
+    /// The items of a list, copied out for code that wants a slice to match on, as
+    /// `as_list` returned before lists became persistent.
+    pub fn list_to_vec(&self) -> Option<Vec<Expr>> {
+        self.as_list().map(|l| l.iter().cloned().collect())
+    }
+
+    pub fn as_map(&self) -> Option<&OrdMap<Expr, Expr>> {
         if let Expr::Map(m) = self {
             Some(m)
         } else {
@@ -216,7 +377,8 @@ impl Expr {
     fn discriminant(&self) -> u8 {
         match self {
             Expr::Nil => 0,
-            Expr::Int(_) => 1,
+            // Big integers sort among the Ints by value
+            Expr::Int(_) | Expr::BigInt(_) => 1,
             Expr::Float(_) => 2,
             Expr::Str(_) => 3,
             Expr::Sym(_) => 4,
@@ -228,6 +390,9 @@ impl Expr {
             Expr::Function { .. } => 10,
             Expr::Quoted(_) => 11,
             Expr::Ref(_) => 12,
+            Expr::Keyword(_) => 13,
+            Expr::Bytes(_) => 14,
+            Expr::Local { .. } => 15,
         }
     }
 }
@@ -240,15 +405,25 @@ impl std::hash::Hash for Expr {
             Expr::Int(i) => {
                 i.hash(state);
             }
+            Expr::BigInt(b) => {
+                b.hash(state);
+            }
             Expr::Float(f) => {
                 f.to_bits().hash(state);
             }
             Expr::Str(s) => {
                 s.hash(state);
             }
-            Expr::Sym(s) => {
+            Expr::Sym(s) | Expr::Keyword(s) => {
                 s.hash(state);
             }
+            Expr::Bytes(b) => {
+                b.hash(state);
+            }
+            Expr::Local { depth, index, .. } => {
+                depth.hash(state);
+                index.hash(state);
+            }
             Expr::List(l) => {
                 for item in l {
                     item.hash(state);
@@ -275,13 +450,8 @@ impl std::hash::Hash for Expr {
             Expr::Extern(f) => {
                 f.hash(state);
             }
-            Expr::Function {
-                params,
-                body,
-                env: _,
-                name: _,
-            } => {
-                for param in params {
+            Expr::Function { params, body, .. } => {
+                for param in params.iter() {
                     param.hash(state);
                 }
                 body.hash(state);
@@ -308,9 +478,26 @@ impl Ord for Expr {
         match (self, other) {
             (Expr::Nil, Expr::Nil) => Ordering::Equal,
             (Expr::Int(a), Expr::Int(b)) => a.cmp(b),
+            (Expr::BigInt(a), Expr::BigInt(b)) => a.cmp(b),
+            (Expr::Int(a), Expr::BigInt(b)) => BigInt::from(*a).cmp(b),
+            (Expr::BigInt(a), Expr::Int(b)) => a.cmp(&BigInt::from(*b)),
             (Expr::Float(a), Expr::Float(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
             (Expr::Str(a), Expr::Str(b)) => a.cmp(b),
             (Expr::Sym(a), Expr::Sym(b)) => a.cmp(b),
+            (Expr::Keyword(a), Expr::Keyword(b)) => a.cmp(b),
+            (Expr::Bytes(a), Expr::Bytes(b)) => a.cmp(b),
+            (
+                Expr::Local {
+                    depth: ad,
+                    index: ai,
+                    ..
+                },
+                Expr::Local {
+                    depth: bd,
+                    index: bi,
+                    ..
+                },
+            ) => (ad, ai).cmp(&(bd, bi)),
             (Expr::List(a), Expr::List(b)) => a.cmp(b),
             (Expr::Map(a), Expr::Map(b)) => a.cmp(b),
             (Expr::HashMap(a), Expr::HashMap(b)) => {
@@ -361,9 +548,24 @@ impl PartialEq for Expr {
         match (self, other) {
             (Expr::Nil, Expr::Nil) => true,
             (Expr::Int(a), Expr::Int(b)) => a == b,
+            (Expr::BigInt(a), Expr::BigInt(b)) => a == b,
             (Expr::Float(a), Expr::Float(b)) => a == b,
             (Expr::Str(a), Expr::Str(b)) => a == b,
             (Expr::Sym(a), Expr::Sym(b)) => a == b,
+            (Expr::Keyword(a), Expr::Keyword(b)) => a == b,
+            (Expr::Bytes(a), Expr::Bytes(b)) => a == b,
+            (
+                Expr::Local {
+                    depth: ad,
+                    index: ai,
+                    ..
+                },
+                Expr::Local {
+                    depth: bd,
+                    index: bi,
+                    ..
+                },
+            ) => ad == bd && ai == bi,
             (Expr::List(a), Expr::List(b)) => a == b,
             (Expr::Map(a), Expr::Map(b)) => a == b,
             (Expr::HashMap(a), Expr::HashMap(b)) => a == b,
@@ -403,6 +605,12 @@ impl From<i64> for Expr {
     }
 }
 
+impl From<BigInt> for Expr {
+    fn from(b: BigInt) -> Self {
+        crate::stdlib::bigint::normalize(b)
+    }
+}
+
 impl From<f64> for Expr {
     fn from(f: f64) -> Self {
         Expr::Float(f)
@@ -427,20 +635,68 @@ impl From<Symbol> for Expr {
     }
 }
 
+/// Scopes were keyed by `Expr` before they held symbols, so `ctx.define(Expr::sym("x"), ..)`
+/// still works. Strings name the symbol they contain; other values their printed form.
+impl From<&Expr> for Symbol {
+    fn from(e: &Expr) -> Self {
+        match e {
+            Expr::Sym(s) | Expr::Keyword(s) => s.clone(),
+            Expr::Str(s) => Symbol::new(s),
+            other => Symbol::new(&other.to_string()),
+        }
+    }
+}
+
+impl From<Expr> for Symbol {
+    fn from(e: Expr) -> Self {
+        Symbol::from(&e)
+    }
+}
+
+impl From<Vec<u8>> for Expr {
+    fn from(b: Vec<u8>) -> Self {
+        Expr::Bytes(b)
+    }
+}
+
 impl From<Vec<Expr>> for Expr {
     fn from(v: Vec<Expr>) -> Self {
+        Expr::List(v.into())
+    }
+}
+
+impl From<&[Expr]> for Expr {
+    fn from(items: &[Expr]) -> Self {
+        Expr::List(items.iter().cloned().collect())
+    }
+}
+
+impl From<Vector<Expr>> for Expr {
+    fn from(v: Vector<Expr>) -> Self {
         Expr::List(v)
     }
 }
 
 impl From<BTreeMap<Expr, Expr>> for Expr {
     fn from(m: BTreeMap<Expr, Expr>) -> Self {
+        Expr::Map(m.into_iter().collect())
+    }
+}
+
+impl From<OrdMap<Expr, Expr>> for Expr {
+    fn from(m: OrdMap<Expr, Expr>) -> Self {
         Expr::Map(m)
     }
 }
 
 impl From<HashMap<Expr, Expr>> for Expr {
     fn from(m: HashMap<Expr, Expr>) -> Self {
+        Expr::HashMap(m.into_iter().collect())
+    }
+}
+
+impl From<imbl::HashMap<Expr, Expr>> for Expr {
+    fn from(m: imbl::HashMap<Expr, Expr>) -> Self {
         Expr::HashMap(m)
     }
 }
@@ -450,9 +706,12 @@ impl std::fmt::Display for Expr {
         match self {
             Expr::Nil => write!(f, "nil"),
             Expr::Int(i) => write!(f, "{}", i),
+            Expr::BigInt(b) => write!(f, "{}", b),
             Expr::Float(fl) => write!(f, "{}", fl),
             Expr::Str(s) => write!(f, "{}", s),
-            Expr::Sym(s) => write!(f, "{}", s),
+            Expr::Sym(s) | Expr::Local { name: s, .. } => write!(f, "{}", s),
+            Expr::Keyword(k) => write!(f, ":{}", k),
+            Expr::Bytes(b) => write!(f, "b\"{}\"", b.escape_ascii()),
             Expr::List(l) => {
                 write!(f, "(")?;
                 let mut first = true;
@@ -468,7 +727,7 @@ impl std::fmt::Display for Expr {
             Expr::Map(m) => {
                 write!(f, "[")?;
                 let mut first = true;
-                for (k, v) in m {
+                for (k, v) in by_name(m) {
                     if !first {
                         write!(f, " ")?;
                     }
@@ -498,7 +757,7 @@ impl std::fmt::Display for Expr {
             Expr::Function { params, body, .. } => {
                 write!(f, "<function params: (")?;
                 let mut first = true;
-                for param in params {
+                for param in params.iter() {
                     if !first {
                         write!(f, " ")?;
                     }
@@ -512,20 +771,30 @@ impl std::fmt::Display for Expr {
             }
             Expr::Ref(inner) => {
                 // Try printing inner
-                write!(f, "{:?}", inner.read().unwrap())
+                write!(f, "{:?}", inner.read_unpoisoned())
             }
         }
     }
 }
 
+/// A map's entries in the order they are printed, with symbol and keyword keys by name.
+fn by_name(m: &OrdMap<Expr, Expr>) -> Vec<(&Expr, &Expr)> {
+    let mut entries: Vec<_> = m.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp_by_name(b.0));
+    entries
+}
+
 impl std::fmt::Debug for Expr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Expr::Nil => write!(f, "nil"),
             Expr::Int(i) => write!(f, "{}", i),
+            Expr::BigInt(b) => write!(f, "{}", b),
             Expr::Float(fl) => write!(f, "{}", fl),
             Expr::Str(s) => write!(f, "{:?}", s),
-            Expr::Sym(s) => write!(f, "{}", s),
+            Expr::Sym(s) | Expr::Local { name: s, .. } => write!(f, "{}", s),
+            Expr::Keyword(k) => write!(f, ":{}", k),
+            Expr::Bytes(b) => write!(f, "b\"{}\"", b.escape_ascii()),
             Expr::List(l) => {
                 write!(f, "(")?;
                 let mut first = true;
@@ -541,7 +810,7 @@ impl std::fmt::Debug for Expr {
             Expr::Map(m) => {
                 write!(f, "[")?;
                 let mut first = true;
-                for (k, v) in m {
+                for (k, v) in by_name(m) {
                     if !first {
                         write!(f, " ")?;
                     }
@@ -571,7 +840,7 @@ impl std::fmt::Debug for Expr {
             Expr::Function { params, body, .. } => {
                 write!(f, "<function params: (")?;
                 let mut first = true;
-                for param in params {
+                for param in params.iter() {
                     if !first {
                         write!(f, " ")?;
                     }
@@ -585,7 +854,7 @@ impl std::fmt::Debug for Expr {
             }
             Expr::Ref(inner) => {
                 // Try printing inner
-                write!(f, "{}", inner.read().unwrap())
+                write!(f, "{}", inner.read_unpoisoned())
             }
         }
     }
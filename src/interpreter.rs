@@ -0,0 +1,240 @@
+use crate::bundle::Bundle;
+use crate::context::{Context, eval};
+use crate::error::{self, Error};
+use crate::expr::Expr;
+use crate::limits::InterruptHandle;
+use crate::parser::{convert_error_to_string, parse_expr};
+use crate::stdlib::capabilities::Capabilities;
+use crate::stdlib::{self, Module};
+use crate::symbol::Symbol;
+use crate::vfs::{Overlay, RealFs, Vfs};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// An Onion interpreter for embedding in a Rust program. Globals defined by one call stay
+/// defined for the next, and errors are returned instead of ending the process.
+///
+/// ```
+/// use onion::{Expr, Interpreter};
+///
+/// let mut onion = Interpreter::new();
+/// onion.eval_str("(defun double (x) (x * 2))").unwrap();
+/// assert_eq!(onion.call("double", &[Expr::Int(21)]).unwrap(), Expr::Int(42));
+/// ```
+pub struct Interpreter {
+    ctx: Context,
+}
+
+/// Chooses what an [`Interpreter`] starts with. By default every stdlib module is installed.
+pub struct InterpreterBuilder {
+    modules: Vec<Module>,
+    caps: Capabilities,
+    fuel: Option<u64>,
+    timeout: Option<Duration>,
+    max_size: Option<usize>,
+    vfs: Option<Arc<dyn Vfs>>,
+    strict_ints: bool,
+    contracts: bool,
+}
+
+impl Default for InterpreterBuilder {
+    fn default() -> Self {
+        Self {
+            modules: Module::ALL.to_vec(),
+            caps: Capabilities::all(),
+            fuel: None,
+            timeout: None,
+            max_size: None,
+            vfs: None,
+            strict_ints: false,
+            contracts: true,
+        }
+    }
+}
+
+impl InterpreterBuilder {
+    /// Install exactly these stdlib modules.
+    pub fn modules(mut self, modules: &[Module]) -> Self {
+        self.modules = modules.to_vec();
+        self
+    }
+
+    /// Leave out a stdlib module.
+    pub fn without(mut self, module: Module) -> Self {
+        self.modules.retain(|m| *m != module);
+        self
+    }
+
+    /// Install a stdlib module left out earlier.
+    pub fn with(mut self, module: Module) -> Self {
+        if !self.modules.contains(&module) {
+            self.modules.push(module);
+        }
+        self
+    }
+
+    /// Limit what the stdlib may do outside the interpreter. Everything is allowed unless
+    /// this is called.
+    pub fn capabilities(mut self, caps: Capabilities) -> Self {
+        self.caps = caps;
+        self
+    }
+
+    /// Allow this many evaluation steps in total, over every call.
+    pub fn fuel(mut self, steps: u64) -> Self {
+        self.fuel = Some(steps);
+        self
+    }
+
+    /// Stop each call that runs longer than `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Stop code creating a list, string or map larger than about `bytes`.
+    pub fn max_size(mut self, bytes: usize) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// The filesystem scripts see. It is the disk unless this is called.
+    pub fn vfs(mut self, vfs: Arc<dyn Vfs>) -> Self {
+        self.vfs = Some(vfs);
+        self
+    }
+
+    /// Make integer overflow an error instead of promoting to a big integer.
+    pub fn strict_ints(mut self, strict: bool) -> Self {
+        self.strict_ints = strict;
+        self
+    }
+
+    /// Check type annotations, as is done unless this is turned off.
+    pub fn contracts(mut self, enabled: bool) -> Self {
+        self.contracts = enabled;
+        self
+    }
+
+    /// Serve file reads from `bundle` before the disk, as a bundled executable does.
+    pub fn bundle(self, bundle: Bundle) -> Self {
+        let disk = Arc::new(RealFs::new());
+        self.vfs(Arc::new(
+            Overlay::new(disk).mount("", Arc::new(bundle.into_archive())),
+        ))
+    }
+
+    pub fn build(self) -> Interpreter {
+        let mut ctx = stdlib::stdlib_sandboxed(&self.modules, self.caps);
+        if let Some(vfs) = self.vfs {
+            ctx.vfs = vfs;
+        }
+        ctx.limits.set_fuel(self.fuel);
+        ctx.limits.set_timeout(self.timeout);
+        ctx.limits.set_max_size(self.max_size);
+        ctx.settings.set_strict_ints(self.strict_ints);
+        ctx.settings.set_contracts(self.contracts);
+        Interpreter { ctx }
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    /// An interpreter with the whole standard library.
+    pub fn new() -> Self {
+        Self::builder().build()
+    }
+
+    pub fn builder() -> InterpreterBuilder {
+        InterpreterBuilder::default()
+    }
+
+    /// Run every form in `source`, returning the value of the last one.
+    pub fn eval_str(&mut self, source: &str) -> Result<Expr, Error> {
+        self.ctx.limits.start();
+        let mut input = source.trim_start();
+        let mut last = Expr::Nil;
+        while !input.is_empty() {
+            // Forms are parsed one at a time, since running one can define operators
+            // the next one uses
+            let (rest, expr) = match error::catch(|| parse_expr(input, &self.ctx))? {
+                Ok(parsed) => parsed,
+                Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+                    return Err(Error::Parse(convert_error_to_string(input, e)));
+                }
+                Err(nom::Err::Incomplete(_)) => {
+                    return Err(Error::Parse("Parse incomplete".to_string()));
+                }
+            };
+            let ctx = &mut self.ctx;
+            last = error::catch(move || eval(expr, ctx))?;
+            input = rest.trim_start();
+        }
+        Ok(last)
+    }
+
+    /// Read and run a source file, returning the value of its last form.
+    pub fn eval_file(&mut self, path: impl AsRef<Path>) -> Result<Expr, Error> {
+        let source = std::fs::read_to_string(path)?;
+        self.eval_str(&source)
+    }
+
+    /// Call the global function `name` with already evaluated arguments.
+    pub fn call(&mut self, name: &str, args: &[Expr]) -> Result<Expr, Error> {
+        self.ctx.limits.start();
+        let name = Symbol::new(name);
+        let func = match self.ctx.resolve(&name) {
+            Some(func @ (Expr::Function { .. } | Expr::Extern(_) | Expr::Keyword(_))) => func,
+            Some(_) => return Err(Error::NotCallable(name)),
+            None => return Err(Error::Undefined(name)),
+        };
+        let ctx = &mut self.ctx;
+        Ok(error::catch(move || {
+            stdlib::call_anon_fn(&func, args, ctx)
+        })?)
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Expr> {
+        self.ctx.resolve(&Symbol::new(name))
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Expr) {
+        self.ctx.define(name, value);
+    }
+
+    /// Bind a module of functions and values to the global `name`, the way the stdlib
+    /// binds `Math` or `IO`, so code can call `(name.member ...)`.
+    pub fn register_module<K: Into<Symbol>>(
+        &mut self,
+        name: &str,
+        members: impl IntoIterator<Item = (K, Expr)>,
+    ) {
+        let members = members
+            .into_iter()
+            .map(|(key, value)| (Expr::Sym(key.into()), value))
+            .collect();
+        let module = Expr::Ref(Arc::new(RwLock::new(Expr::Map(members))));
+        self.ctx.define(name, module);
+    }
+
+    /// A handle for stopping a running call from another thread, such as a Ctrl-C
+    /// handler. The call returns an `Interrupted` runtime error.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.ctx.limits.interrupt_handle()
+    }
+
+    /// The global scope, for anything the methods above don't cover.
+    pub fn context(&self) -> &Context {
+        &self.ctx
+    }
+
+    pub fn context_mut(&mut self) -> &mut Context {
+        &mut self.ctx
+    }
+}
@@ -1,16 +1,15 @@
 #[macro_export]
 macro_rules! stop {
-    ($($arg:tt)*) => {{
-        eprintln!("Runtime Error: {}", format!($($arg)*));
-        // Print a backtrace if available
-        let bt = std::backtrace::Backtrace::capture();
-        if bt.status() == std::backtrace::BacktraceStatus::Captured {
-             eprintln!("{}", bt);
-        }
-        std::process::exit(1);
-    }};
+    ($($arg:tt)*) => {
+        $crate::error::raise(format!($($arg)*))
+    };
 }
 
+// Lets code generated by `#[onion_fn]` name this crate as `::onion` from inside it too
+extern crate self as onion;
+
+pub mod error;
+
 mod symbol;
 pub use symbol::Symbol;
 pub mod parser;
@@ -21,4 +20,29 @@ pub use expr::Expr;
 pub mod context;
 pub use context::Context;
 
+pub mod resolve;
+
+pub mod limits;
+
+pub mod settings;
+
+pub mod vfs;
+
 pub mod stdlib;
+
+pub mod check;
+
+pub mod convert;
+pub use convert::{FromExpr, IntoExpr};
+
+pub mod native;
+pub use native::onion_fn;
+
+pub mod plugin;
+
+pub mod capi;
+
+pub mod bundle;
+
+pub mod interpreter;
+pub use interpreter::{Interpreter, InterpreterBuilder};
@@ -0,0 +1,173 @@
+//! Budgets for running untrusted or runaway code: a number of evaluation steps (fuel), a
+//! time limit, a cap on the size of any one list, string or map, and a handle to interrupt
+//! a running evaluation from another thread.
+//!
+//! Every interpreter has its own [`Limits`], shared by all of its scopes. None are set to
+//! begin with. A limit that runs out raises a runtime error; `try` can catch it, but the
+//! handler runs out too as soon as it takes a step, so the error reaches the host.
+
+use crate::error::MutexExt;
+use crate::expr::Expr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering::Relaxed};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How many steps pass between looks at the clock.
+const CLOCK_EVERY: u64 = 256;
+
+/// The limits of one interpreter.
+#[derive(Debug, Default)]
+pub struct Limits {
+    /// Set while anything below has to be checked on each step, so code without limits
+    /// only pays for this one load.
+    armed: AtomicBool,
+    interrupted: AtomicBool,
+    /// Steps left, when `fuel_limited`.
+    fuel: AtomicU64,
+    fuel_limited: AtomicBool,
+    steps: AtomicU64,
+    timeout: Mutex<Option<Duration>>,
+    deadline: Mutex<Option<Instant>>,
+    timed_out: AtomicBool,
+    /// The largest value allowed, in bytes, or 0 for no limit.
+    max_size: AtomicUsize,
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow this many more steps, or any number with `None`. A step is evaluating one
+    /// expression, so a loop spends at least one per iteration.
+    pub fn set_fuel(&self, fuel: Option<u64>) {
+        self.fuel.store(fuel.unwrap_or(0), Relaxed);
+        self.fuel_limited.store(fuel.is_some(), Relaxed);
+        self.rearm();
+    }
+
+    /// The steps left, if limited.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel_limited
+            .load(Relaxed)
+            .then(|| self.fuel.load(Relaxed))
+    }
+
+    /// Limit how long each call into the interpreter may run, counted from [`start`].
+    ///
+    /// [`start`]: Limits::start
+    pub fn set_timeout(&self, timeout: Option<Duration>) {
+        *self.timeout.lock_unpoisoned() = timeout;
+    }
+
+    /// Limit the size of any one list, string, map or byte string created, in bytes. The
+    /// size counts the value itself but not what its elements hold, so it is approximate.
+    pub fn set_max_size(&self, bytes: Option<usize>) {
+        self.max_size.store(bytes.unwrap_or(0), Relaxed);
+    }
+
+    /// Begin a call from the host: the timeout starts counting and any earlier interrupt
+    /// is forgotten.
+    pub fn start(&self) {
+        let deadline = self.timeout.lock_unpoisoned().map(|t| Instant::now() + t);
+        *self.deadline.lock_unpoisoned() = deadline;
+        self.timed_out.store(false, Relaxed);
+        self.interrupted.store(false, Relaxed);
+        self.rearm();
+    }
+
+    /// Stop the running evaluation at its next step.
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Relaxed);
+        self.armed.store(true, Relaxed);
+    }
+
+    fn rearm(&self) {
+        let armed = self.fuel_limited.load(Relaxed)
+            || self.deadline.lock_unpoisoned().is_some()
+            || self.interrupted.load(Relaxed);
+        self.armed.store(armed, Relaxed);
+    }
+
+    /// Take one step, raising an error if a limit has run out.
+    #[inline]
+    pub fn step(&self) {
+        if self.armed.load(Relaxed) {
+            self.check();
+        }
+    }
+
+    #[cold]
+    fn check(&self) {
+        if self.interrupted.load(Relaxed) {
+            crate::stop!("Interrupted");
+        }
+        if self.fuel_limited.load(Relaxed)
+            && self
+                .fuel
+                .fetch_update(Relaxed, Relaxed, |f| f.checked_sub(1))
+                .is_err()
+        {
+            crate::stop!("Out of fuel");
+        }
+        let steps = self.steps.fetch_add(1, Relaxed);
+        if self.timed_out.load(Relaxed) {
+            crate::stop!("Timed out");
+        }
+        if !steps.is_multiple_of(CLOCK_EVERY) {
+            return;
+        }
+        // Copied out, as the lock mustn't be held while the error unwinds
+        let deadline = *self.deadline.lock_unpoisoned();
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            self.timed_out.store(true, Relaxed);
+            crate::stop!("Timed out");
+        }
+    }
+
+    /// Raise an error if `bytes` is over the size limit. For natives that know how much
+    /// they are about to allocate, to fail before doing it.
+    pub fn reserve(&self, bytes: usize, func: &str) {
+        let max = self.max_size.load(Relaxed);
+        if max != 0 && bytes > max {
+            crate::stop!(
+                "{}: memory limit exceeded ({} bytes, limit is {})",
+                func,
+                bytes,
+                max
+            );
+        }
+    }
+
+    /// Raise an error if `value` is over the size limit.
+    pub fn check_size(&self, value: &Expr, func: &str) {
+        if self.max_size.load(Relaxed) == 0 {
+            return;
+        }
+        let entry = std::mem::size_of::<Expr>();
+        let bytes = match value {
+            Expr::Str(s) => s.len(),
+            Expr::Bytes(b) => b.len(),
+            Expr::List(l) => l.len() * entry,
+            Expr::Map(m) => m.len() * 2 * entry,
+            Expr::HashMap(m) => m.len() * 2 * entry,
+            _ => return,
+        };
+        self.reserve(bytes, func);
+    }
+
+    /// A handle that can interrupt evaluation from another thread.
+    pub fn interrupt_handle(self: &Arc<Self>) -> InterruptHandle {
+        InterruptHandle(self.clone())
+    }
+}
+
+/// Stops an interpreter's running evaluation, from any thread, with an `Interrupted` error.
+#[derive(Clone, Debug)]
+pub struct InterruptHandle(Arc<Limits>);
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.0.interrupt();
+    }
+}
@@ -1,40 +1,85 @@
-use clap::Parser;
-use onion::context::eval;
-use onion::parser::{convert_error_to_string, parse_expr};
-use onion::stdlib::stdlib;
+use clap::{Parser, Subcommand};
+use onion::Interpreter;
+use onion::bundle::Bundle;
+use onion::check::{check_syntax, check_types};
+use onion::context::Context;
+use onion::error::Error;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Optional file to run
     file: Option<PathBuf>,
+    /// Skip checking type annotations on functions and struct fields
+    #[arg(long)]
+    no_contracts: bool,
+    /// Make integer overflow an error instead of promoting to a big integer
+    #[arg(long)]
+    strict_ints: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check a file for errors without running it
+    Check {
+        file: PathBuf,
+        /// Also report likely type errors
+        #[arg(long)]
+        types: bool,
+    },
+    /// Make a single executable that runs a script, with the files it reads built in
+    Bundle {
+        file: PathBuf,
+        /// Where to write the executable
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Also bundle this file, or everything in this directory, for paths the script
+        /// builds at runtime
+        #[arg(long)]
+        include: Vec<PathBuf>,
+    },
 }
 
 fn main() {
+    // A bundled executable runs its script rather than taking commands
+    if let Some(bundle) = Bundle::current() {
+        run_bundle(bundle);
+        return;
+    }
+
     let cli = Cli::parse();
+    let mut onion = Interpreter::builder()
+        .strict_ints(cli.strict_ints)
+        .contracts(!cli.no_contracts)
+        .build();
 
-    let mut ctx = stdlib();
+    match cli.command {
+        Some(Command::Check { file, types }) => {
+            check(&file, types, onion.context());
+            return;
+        }
+        Some(Command::Bundle {
+            file,
+            output,
+            include,
+        }) => {
+            bundle(&file, &output, &include, onion.context());
+            return;
+        }
+        None => {}
+    }
 
     if let Some(file_path) = cli.file {
-        let content = fs::read_to_string(&file_path).expect("Failed to read file");
-        let mut input = content.as_str();
-
-        while !input.trim().is_empty() {
-            match parse_expr(input, &ctx) {
-                Ok((rest, expr)) => {
-                    eval(expr, &mut ctx);
-                    input = rest;
-                }
-                Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
-                    println!("Parse error:\n{}", convert_error_to_string(input, e));
-                    return;
-                }
-                Err(nom::Err::Incomplete(_)) => {
-                    println!("Parse incomplete");
-                    return;
-                }
+        match onion.eval_file(&file_path) {
+            Ok(_) => {}
+            Err(e @ Error::Parse(_)) => println!("{}", e),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
             }
         }
     } else {
@@ -42,23 +87,17 @@ fn main() {
         println!("(Press Ctrl+C to exit)");
 
         let mut rl = rustyline::DefaultEditor::new().unwrap();
+        // Ctrl+C while code runs stops it; at the prompt it still exits
+        let interrupt = onion.interrupt_handle();
+        let _ = ctrlc::set_handler(move || interrupt.interrupt());
 
         loop {
             match rl.readline(">> ") {
                 Ok(line) => {
                     let _ = rl.add_history_entry(line.as_str());
-                    let input = line.as_str();
-                    match parse_expr(input, &ctx) {
-                        Ok((_, expr)) => {
-                            let res = eval(expr, &mut ctx);
-                            println!("{}", res);
-                        }
-                        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
-                            println!("Parse error:\n{}", convert_error_to_string(input, e));
-                        }
-                        Err(nom::Err::Incomplete(_)) => {
-                            println!("Parse incomplete");
-                        }
+                    match onion.eval_str(&line) {
+                        Ok(res) => println!("{}", res),
+                        Err(e) => println!("{}", e),
                     }
                 }
                 Err(rustyline::error::ReadlineError::Interrupted) => {
@@ -77,3 +116,66 @@ fn main() {
         }
     }
 }
+
+fn run_bundle(bundle: Bundle) {
+    let source = match bundle.main_source() {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Broken bundle: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut onion = Interpreter::builder().bundle(bundle).build();
+    if let Err(e) = onion.eval_str(&source) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn bundle(file_path: &Path, output: &Path, include: &[PathBuf], ctx: &Context) {
+    let written = Bundle::collect(file_path, include, ctx).and_then(|bundle| {
+        let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+        bundle
+            .write_executable(&exe, output)
+            .map_err(|e| format!("can't write {}: {}", output.display(), e))?;
+        Ok(bundle)
+    });
+    match written {
+        Ok(bundle) => {
+            for name in bundle.names() {
+                println!("  {}", name);
+            }
+            println!("Wrote {}", output.display());
+        }
+        Err(e) => {
+            eprintln!("Bundle failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn check(file_path: &Path, types: bool, ctx: &Context) {
+    let content = fs::read_to_string(file_path).expect("Failed to read file");
+    let diagnostics = if types {
+        check_types(&content, ctx)
+    } else {
+        check_syntax(&content, ctx).map(|_| vec![])
+    };
+
+    match diagnostics {
+        Ok(diagnostics) if diagnostics.is_empty() => {
+            println!("{}: no problems found", file_path.display());
+        }
+        Ok(diagnostics) => {
+            for d in &diagnostics {
+                println!("{}:{}: {}", file_path.display(), d.line, d.message);
+            }
+            println!("{} problems found", diagnostics.len());
+            std::process::exit(1);
+        }
+        Err(e) => {
+            println!("Parse error:\n{}", e);
+            std::process::exit(1);
+        }
+    }
+}
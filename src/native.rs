@@ -0,0 +1,127 @@
+//! Support for native functions written with `#[onion_fn]`.
+//!
+//! ```
+//! use onion::{Interpreter, onion_fn};
+//!
+//! /// Keep a value between two bounds
+//! #[onion_fn(module = "Host")]
+//! fn bound(x: f64, lo: f64, hi: f64) -> f64 {
+//!     x.max(lo).min(hi)
+//! }
+//!
+//! let mut onion = Interpreter::new();
+//! bound_native().register(onion.context_mut());
+//! assert_eq!(onion.eval_str("(Host.bound 12 0 10)").unwrap(), onion::Expr::Float(10.0));
+//! ```
+//!
+//! The attribute keeps the function callable from Rust and adds `<name>_native()`, which
+//! returns a [`Native`]. Arguments are evaluated and converted with [`FromExpr`], so a wrong
+//! type or number of arguments is a runtime error naming the function. Trailing `Option`
+//! parameters may be left out, a `&mut Context` parameter receives the caller's context, and
+//! a function may return a `Result`, whose error is raised.
+
+use crate::context::{Context, eval_ref};
+use crate::convert::{FromExpr, IntoExpr};
+use crate::error::RwLockExt;
+use crate::expr::Expr;
+use std::sync::{Arc, RwLock};
+
+pub use onion_macros::onion_fn;
+
+/// A native function made by `#[onion_fn]`, ready to be installed.
+pub struct Native {
+    pub name: &'static str,
+    /// The module the function belongs to, or `None` for a global.
+    pub module: Option<&'static str>,
+    pub func: Expr,
+}
+
+impl Native {
+    /// Define the function in `ctx`, as a member of its module (created if it doesn't exist
+    /// yet) or as a global.
+    pub fn register(self, ctx: &Context) {
+        define_member(ctx, self.module, self.name, self.func);
+    }
+}
+
+/// Define `value` as `name` in `module`, creating the module if it doesn't exist yet, or as
+/// a global if there is no module.
+pub(crate) fn define_member(ctx: &Context, module: Option<&str>, name: &str, value: Expr) {
+    let Some(module) = module else {
+        ctx.define(name, value);
+        return;
+    };
+    match ctx.resolve(module) {
+        Some(Expr::Ref(r)) if matches!(*r.read_unpoisoned(), Expr::Map(_)) => {
+            if let Expr::Map(members) = &mut *r.write_unpoisoned() {
+                members.insert(Expr::sym(name), value);
+            }
+        }
+        _ => {
+            let members = imbl::OrdMap::unit(Expr::sym(name), value);
+            ctx.define(module, Expr::Ref(Arc::new(RwLock::new(Expr::Map(members)))));
+        }
+    }
+}
+
+/// Stop unless between `min` and `max` arguments were passed.
+pub fn check_arity(name: &str, args: &[Expr], min: usize, max: usize) {
+    if args.len() < min || args.len() > max {
+        let expected = if min == max {
+            min.to_string()
+        } else {
+            format!("{} to {}", min, max)
+        };
+        crate::stop!(
+            "{} expects {} arguments, got {}",
+            name,
+            expected,
+            args.len()
+        );
+    }
+}
+
+/// Evaluate argument `index` and convert it, treating a missing argument as `nil`.
+pub fn arg<T: FromExpr>(
+    name: &str,
+    param: &str,
+    args: &[Expr],
+    index: usize,
+    ctx: &mut Context,
+) -> T {
+    let val = match args.get(index) {
+        Some(arg) => eval_ref(arg, ctx),
+        None => Expr::Nil,
+    };
+    match T::from_expr(&val) {
+        Ok(v) => v,
+        Err(e) => crate::stop!("{}: argument {}: {}", name, param, e),
+    }
+}
+
+/// What a native function may return: any value with `IntoExpr`, or a `Result` whose error
+/// is raised as a runtime error.
+pub trait NativeReturn {
+    const TYPE: &'static str;
+
+    fn into_return(self, name: &str) -> Expr;
+}
+
+impl<T: IntoExpr> NativeReturn for T {
+    const TYPE: &'static str = T::TYPE;
+
+    fn into_return(self, _name: &str) -> Expr {
+        self.into_expr()
+    }
+}
+
+impl<T: IntoExpr, E: std::fmt::Display> NativeReturn for Result<T, E> {
+    const TYPE: &'static str = T::TYPE;
+
+    fn into_return(self, name: &str) -> Expr {
+        match self {
+            Ok(v) => v.into_expr(),
+            Err(e) => crate::stop!("{}: {}", name, e),
+        }
+    }
+}
@@ -1,13 +1,14 @@
 use crate::context::{Assoc, Context};
 use crate::expr::Expr;
 use crate::symbol::Symbol;
+use imbl::vector;
 use nom::{
     IResult,
     branch::alt,
     bytes::complete::{escaped_transform, tag, take_while1},
     character::complete::{char, digit1, multispace0, multispace1, none_of, not_line_ending},
     combinator::{cut, map, map_res, opt, recognize, value},
-    error::{VerboseError, context, convert_error},
+    error::{ErrorKind, ParseError, VerboseError, context, convert_error},
     multi::many0,
     sequence::{delimited, pair, preceded, terminated, tuple},
 };
@@ -48,7 +49,10 @@ fn parse_hex(input: &str) -> Res<Expr> {
 fn parse_int(input: &str) -> Res<Expr> {
     map_res(
         ws(recognize(pair(opt(alt((tag("-"), tag("+")))), digit1))),
-        |out: &str| out.parse::<i64>().map(Expr::Int),
+        |out: &str| {
+            crate::stdlib::bigint::parse(out.strip_prefix('+').unwrap_or(out))
+                .ok_or(ErrorKind::Digit)
+        },
     )(input)
 }
 
@@ -85,6 +89,11 @@ fn parse_str_lit(input: &str) -> Res<Expr> {
 }
 
 fn parse_symbol_str<'a>(input: &'a str, ctx: &Context) -> Res<'a, &'a str> {
+    // `->` introduces a return type annotation, so it must not be read as `-` followed by `>`
+    if let Some(rest) = input.strip_prefix("->") {
+        return Ok((rest, &input[..2]));
+    }
+
     // 1. Check if input matches a known operator in Context
     let ops = ctx.get_operator_keys();
     // Sort ops by length descending to match longest operator first
@@ -116,7 +125,19 @@ fn parse_symbol_str<'a>(input: &'a str, ctx: &Context) -> Res<'a, &'a str> {
     // Custom take_while since we need to respect delimiters
     let is_sym_char = |c: char| c.is_alphanumeric() || allowed_special.contains(c);
 
-    let (input, sym_str) = take_while1(is_sym_char)(input)?;
+    let (rest, sym_str) = take_while1(is_sym_char)(input)?;
+
+    // A trailing colon marks an annotated name, as in `(amount: int)`
+    let mut after_colon = rest.chars().skip(1);
+    if rest.starts_with(':')
+        && !after_colon
+            .next()
+            .is_some_and(|c| is_sym_char(c) || c == ':')
+    {
+        let len = sym_str.len() + 1;
+        return Ok((&input[len..], &input[..len]));
+    }
+    let input = rest;
 
     // Ensure it's not a number (if it starts with digit, it might have been parsed by int/float,
     // but if we are here, int/float failed or we are in pratt loop looking for token)
@@ -125,6 +146,76 @@ fn parse_symbol_str<'a>(input: &'a str, ctx: &Context) -> Res<'a, &'a str> {
     Ok((input, sym_str))
 }
 
+/// The contents of a `b"..."` literal, up to the closing quote. Besides plain characters
+/// (stored as UTF-8), it accepts the escapes `\\ \" \n \r \t \0` and `\xNN`.
+fn bytes_body(input: &str) -> Res<'_, Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        let byte = match c {
+            '"' => return Ok((&input[i..], bytes)),
+            '\\' => match chars.next() {
+                Some((_, '\\')) => b'\\',
+                Some((_, '"')) => b'"',
+                Some((_, 'n')) => b'\n',
+                Some((_, 'r')) => b'\r',
+                Some((_, 't')) => b'\t',
+                Some((_, '0')) => 0,
+                Some((j, 'x')) => {
+                    match input
+                        .get(j + 1..j + 3)
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    {
+                        Some(b) => {
+                            chars.nth(1);
+                            b
+                        }
+                        None => {
+                            return Err(nom::Err::Error(VerboseError::from_error_kind(
+                                &input[i..],
+                                ErrorKind::HexDigit,
+                            )));
+                        }
+                    }
+                }
+                _ => {
+                    return Err(nom::Err::Error(VerboseError::from_error_kind(
+                        &input[i..],
+                        ErrorKind::Escaped,
+                    )));
+                }
+            },
+            c => {
+                bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                continue;
+            }
+        };
+        bytes.push(byte);
+    }
+    Ok(("", bytes))
+}
+
+fn parse_bytes_lit(input: &str) -> Res<'_, Expr> {
+    context(
+        "bytes",
+        map(
+            preceded(pair(sp, tag("b\"")), cut(terminated(bytes_body, char('"')))),
+            Expr::Bytes,
+        ),
+    )(input)
+}
+
+fn parse_keyword(input: &str) -> Res<'_, Expr> {
+    let is_sym_char = |c: char| c.is_alphanumeric() || "+-*/<>=!?_".contains(c);
+    context(
+        "keyword",
+        map(
+            ws(preceded(char(':'), take_while1(is_sym_char))),
+            |s: &str| Expr::Keyword(Symbol::new(s)),
+        ),
+    )(input)
+}
+
 fn parse_sym<'a>(input: &'a str, ctx: &Context) -> Res<'a, Expr> {
     map(ws(|i| parse_symbol_str(i, ctx)), |s: &str| {
         Expr::Sym(Symbol::new(s))
@@ -141,7 +232,7 @@ fn parse_list<'a>(input: &'a str, ctx: &Context) -> Res<'a, Expr> {
                 ws(char('(')),
                 cut(terminated(many0(|i| parse_expr(i, ctx)), ws(char(')')))),
             ),
-            Expr::List,
+            |items| Expr::List(items.into()),
         ),
     )(input)
 }
@@ -155,7 +246,7 @@ fn parse_map<'a>(input: &'a str, ctx: &Context) -> Res<'a, Expr> {
                 cut(terminated(many0(|i| parse_expr(i, ctx)), ws(char(']')))),
             ),
             |items| {
-                let mut map = std::collections::BTreeMap::new();
+                let mut map = imbl::OrdMap::new();
                 for chunk in items.chunks(2) {
                     if chunk.len() == 2 {
                         map.insert(chunk[0].clone(), chunk[1].clone());
@@ -176,7 +267,7 @@ fn parse_hashmap<'a>(input: &'a str, ctx: &Context) -> Res<'a, Expr> {
                 cut(terminated(many0(|i| parse_expr(i, ctx)), ws(char(']')))),
             ),
             |items| {
-                let mut map = std::collections::HashMap::new();
+                let mut map = imbl::HashMap::new();
                 for chunk in items.chunks(2) {
                     if chunk.len() == 2 {
                         map.insert(chunk[0].clone(), chunk[1].clone());
@@ -207,9 +298,9 @@ fn parse_block<'a>(input: &'a str, ctx: &Context) -> Res<'a, Expr> {
                 cut(terminated(many0(|i| parse_expr(i, ctx)), ws(char('}')))),
             ),
             |exprs| {
-                let mut block = vec![Expr::Sym(Symbol::new("do"))];
+                let mut block = vec![Expr::Sym(Symbol::DO)];
                 block.extend(exprs);
-                Expr::List(block)
+                Expr::List(block.into())
             },
         ),
     )(input)
@@ -223,12 +314,14 @@ fn parse_atom<'a>(input: &'a str, ctx: &Context) -> Res<'a, Expr> {
         parse_hex,
         parse_float,
         parse_int,
+        parse_bytes_lit,
         parse_str_lit,
         |i| parse_quote(i, ctx),
         |i| parse_hashmap(i, ctx),
         |i| parse_map(i, ctx),
         |i| parse_block(i, ctx),
         |i| parse_list(i, ctx),
+        parse_keyword,
         |i| parse_sym(i, ctx),
     ))(input)
 }
@@ -244,7 +337,7 @@ fn pratt_parse<'a>(input: &'a str, ctx: &Context, min_bp: u8) -> Res<'a, Expr> {
                 let (next_input, rhs) = pratt_parse(input, ctx, op_info.precedence)?;
                 input = next_input;
                 // Prefix: (op rhs)
-                lhs = Expr::List(vec![lhs.clone(), rhs]);
+                lhs = Expr::List(vector![lhs.clone(), rhs]);
             }
         }
     }
@@ -279,7 +372,7 @@ fn pratt_parse<'a>(input: &'a str, ctx: &Context, min_bp: u8) -> Res<'a, Expr> {
                 input = next_input;
 
                 // Combine: (op lhs rhs)
-                lhs = Expr::List(vec![Expr::Sym(Symbol::new(op_sym)), lhs, rhs]);
+                lhs = Expr::List(vector![Expr::Sym(Symbol::new(op_sym)), lhs, rhs]);
                 continue;
             }
         }
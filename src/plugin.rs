@@ -0,0 +1,467 @@
+//! Native plugins: shared libraries that add functions to the interpreter at runtime with
+//! `(load_plugin "libmyext.so")`.
+//!
+//! Rust has no stable ABI, and a plugin built as its own library would carry its own copy
+//! of this crate's global state, so plugins talk to the interpreter through the C
+//! interface in `include/onion.h` instead. A plugin exports `onion_plugin_abi_version`,
+//! returning the [`ABI_VERSION`] it was built against, and `onion_register`, which is
+//! handed an [`OnionApi`] and defines functions the way `math::register` does:
+//!
+//! ```c
+//! #include "onion.h"
+//!
+//! static int twice(const OnionApi *api, OnionCall *call, void *user_data) {
+//!     OnionValue x;
+//!     if (api->arg(call, 0, &x) != 0 || x.tag != ONION_INT) {
+//!         api->raise(call, "expected an int");
+//!         return 1;
+//!     }
+//!     OnionValue result = {.tag = ONION_INT, .int_value = x.int_value * 2};
+//!     api->ret(call, &result);
+//!     return 0;
+//! }
+//!
+//! uint32_t onion_plugin_abi_version(void) { return ONION_PLUGIN_ABI_VERSION; }
+//!
+//! int onion_register(const OnionApi *api) {
+//!     return api->define(api, "Ext", "twice", "Double an int", twice, NULL);
+//! }
+//! ```
+//!
+//! The same can be written in Rust with `#[unsafe(no_mangle)] extern "C"` functions and the
+//! `#[repr(C)]` types of this module.
+
+use crate::context::{Context, eval_ref};
+use crate::error::RwLockExt;
+use crate::expr::Expr;
+use crate::stdlib::capabilities::Capabilities;
+use imbl::OrdMap;
+use std::ffi::{CStr, c_char, c_int, c_void};
+use std::path::Path;
+
+/// The version of the plugin interface, `ONION_PLUGIN_ABI_VERSION` in `include/onion.h`.
+pub const ABI_VERSION: u32 = 1;
+
+pub const ONION_NIL: u32 = 0;
+pub const ONION_INT: u32 = 1;
+pub const ONION_FLOAT: u32 = 2;
+pub const ONION_STRING: u32 = 3;
+pub const ONION_SYMBOL: u32 = 4;
+pub const ONION_KEYWORD: u32 = 5;
+pub const ONION_BYTES: u32 = 6;
+pub const ONION_LIST: u32 = 7;
+pub const ONION_MAP: u32 = 8;
+pub const ONION_OTHER: u32 = 9;
+
+/// An Onion value as C sees it; see `include/onion.h` for what `data` points to.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct OnionValue {
+    pub tag: u32,
+    pub int_value: i64,
+    pub float_value: f64,
+    pub data: *const c_void,
+    pub len: usize,
+}
+
+impl Default for OnionValue {
+    fn default() -> Self {
+        Self {
+            tag: ONION_NIL,
+            int_value: 0,
+            float_value: 0.0,
+            data: std::ptr::null(),
+            len: 0,
+        }
+    }
+}
+
+/// A native function defined by a plugin.
+pub type OnionFn = unsafe extern "C" fn(
+    api: *const OnionApi,
+    call: *mut OnionCall,
+    user_data: *mut c_void,
+) -> c_int;
+
+/// The functions a plugin calls back into the interpreter with.
+#[repr(C)]
+pub struct OnionApi {
+    pub abi_version: u32,
+    /// The `Context` being registered into, or null during a call.
+    pub host: *mut c_void,
+    pub define: unsafe extern "C" fn(
+        api: *const OnionApi,
+        module: *const c_char,
+        name: *const c_char,
+        doc: *const c_char,
+        func: Option<OnionFn>,
+        user_data: *mut c_void,
+    ) -> c_int,
+    pub define_value: unsafe extern "C" fn(
+        api: *const OnionApi,
+        module: *const c_char,
+        name: *const c_char,
+        value: *const OnionValue,
+    ) -> c_int,
+    pub arg_count: unsafe extern "C" fn(call: *const OnionCall) -> usize,
+    pub arg:
+        unsafe extern "C" fn(call: *mut OnionCall, index: usize, out: *mut OnionValue) -> c_int,
+    pub ret: unsafe extern "C" fn(call: *mut OnionCall, value: *const OnionValue),
+    pub raise: unsafe extern "C" fn(call: *mut OnionCall, message: *const c_char),
+}
+
+impl OnionApi {
+    /// The table handed to plugins, registering into `host` if it isn't null.
+    pub fn new(host: *const Context) -> Self {
+        Self {
+            abi_version: ABI_VERSION,
+            host: host as *mut c_void,
+            define: api_define,
+            define_value: api_define_value,
+            arg_count: api_arg_count,
+            arg: api_arg,
+            ret: api_ret,
+            raise: api_raise,
+        }
+    }
+}
+
+/// Keeps the text, bytes and lists handed to C alive until they are no longer needed.
+#[derive(Default)]
+pub struct Arena {
+    bytes: Vec<Box<[u8]>>,
+    values: Vec<Box<[OnionValue]>>,
+}
+
+impl Arena {
+    /// `expr` as C sees it, valid as long as the arena.
+    pub fn view(&mut self, expr: &Expr) -> OnionValue {
+        let value = |tag| OnionValue {
+            tag,
+            ..OnionValue::default()
+        };
+        match expr {
+            Expr::Ref(r) => self.view(&r.read_unpoisoned()),
+            Expr::Quoted(inner) => self.view(inner),
+            Expr::Nil => value(ONION_NIL),
+            Expr::Int(n) => OnionValue {
+                int_value: *n,
+                ..value(ONION_INT)
+            },
+            Expr::Float(f) => OnionValue {
+                float_value: *f,
+                ..value(ONION_FLOAT)
+            },
+            Expr::Str(s) => self.text(ONION_STRING, s),
+            Expr::Sym(s) => self.text(ONION_SYMBOL, s.as_str()),
+            Expr::Keyword(s) => self.text(ONION_KEYWORD, s.as_str()),
+            Expr::Bytes(b) => {
+                let data: Box<[u8]> = b.as_slice().into();
+                let view = OnionValue {
+                    data: data.as_ptr().cast(),
+                    len: data.len(),
+                    ..value(ONION_BYTES)
+                };
+                self.bytes.push(data);
+                view
+            }
+            Expr::List(l) => {
+                let items: Vec<OnionValue> = l.iter().map(|item| self.view(item)).collect();
+                self.values(ONION_LIST, items, l.len())
+            }
+            Expr::Map(m) => {
+                let items = m.iter().flat_map(|(k, v)| [k, v]).collect::<Vec<_>>();
+                let items = items.into_iter().map(|item| self.view(item)).collect();
+                self.values(ONION_MAP, items, m.len())
+            }
+            Expr::HashMap(m) => {
+                let items = m.iter().flat_map(|(k, v)| [k, v]).collect::<Vec<_>>();
+                let items = items.into_iter().map(|item| self.view(item)).collect();
+                self.values(ONION_MAP, items, m.len())
+            }
+            other => self.text(ONION_OTHER, &other.to_string()),
+        }
+    }
+
+    fn text(&mut self, tag: u32, text: &str) -> OnionValue {
+        let mut data = Vec::with_capacity(text.len() + 1);
+        data.extend_from_slice(text.as_bytes());
+        data.push(0);
+        let data = data.into_boxed_slice();
+        let view = OnionValue {
+            tag,
+            data: data.as_ptr().cast(),
+            len: text.len(),
+            ..OnionValue::default()
+        };
+        self.bytes.push(data);
+        view
+    }
+
+    fn values(&mut self, tag: u32, items: Vec<OnionValue>, len: usize) -> OnionValue {
+        let items = items.into_boxed_slice();
+        let view = OnionValue {
+            tag,
+            data: items.as_ptr().cast(),
+            len,
+            ..OnionValue::default()
+        };
+        self.values.push(items);
+        view
+    }
+}
+
+/// Copy a value from C into an `Expr`.
+///
+/// # Safety
+///
+/// `value.data` must point to what its tag says, as described in `include/onion.h`.
+pub unsafe fn to_expr(value: &OnionValue) -> Result<Expr, String> {
+    let slice = |len: usize| -> Result<&[u8], String> {
+        match (value.data.is_null(), len) {
+            (_, 0) => Ok(&[]),
+            (true, _) => Err("value has a length but no data".to_string()),
+            (false, _) => Ok(unsafe { std::slice::from_raw_parts(value.data.cast(), len) }),
+        }
+    };
+    let text = |len| slice(len).map(|b| String::from_utf8_lossy(b).into_owned());
+    let items = |count: usize| -> Result<Vec<Expr>, String> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        if value.data.is_null() {
+            return Err("value has a length but no data".to_string());
+        }
+        let items: &[OnionValue] = unsafe { std::slice::from_raw_parts(value.data.cast(), count) };
+        items.iter().map(|item| unsafe { to_expr(item) }).collect()
+    };
+    Ok(match value.tag {
+        ONION_NIL => Expr::Nil,
+        ONION_INT => Expr::Int(value.int_value),
+        ONION_FLOAT => Expr::Float(value.float_value),
+        ONION_STRING | ONION_OTHER => Expr::Str(text(value.len)?),
+        ONION_SYMBOL => Expr::sym(text(value.len)?.as_str()),
+        ONION_KEYWORD => Expr::keyword(text(value.len)?.as_str()),
+        ONION_BYTES => Expr::Bytes(slice(value.len)?.to_vec()),
+        ONION_LIST => Expr::List(items(value.len)?.into()),
+        ONION_MAP => {
+            let mut map = OrdMap::new();
+            let mut items = items(value.len * 2)?.into_iter();
+            while let (Some(k), Some(v)) = (items.next(), items.next()) {
+                map.insert(k, v);
+            }
+            Expr::Map(map)
+        }
+        tag => return Err(format!("unknown value tag {}", tag)),
+    })
+}
+
+/// One call of a plugin function: its evaluated arguments and what it returns.
+pub struct OnionCall {
+    args: Vec<Expr>,
+    arena: Arena,
+    result: Expr,
+    error: Option<String>,
+}
+
+impl OnionCall {
+    pub fn new(args: Vec<Expr>) -> Self {
+        Self {
+            args,
+            arena: Arena::default(),
+            result: Expr::Nil,
+            error: None,
+        }
+    }
+
+    /// The result, or the message of the error raised.
+    pub fn finish(self) -> Result<Expr, String> {
+        match self.error {
+            Some(message) => Err(message),
+            None => Ok(self.result),
+        }
+    }
+}
+
+/// A C string argument, or `None` for null.
+unsafe fn c_str<'a>(s: *const c_char) -> Option<std::borrow::Cow<'a, str>> {
+    (!s.is_null()).then(|| unsafe { CStr::from_ptr(s) }.to_string_lossy())
+}
+
+unsafe extern "C" fn api_define(
+    api: *const OnionApi,
+    module: *const c_char,
+    name: *const c_char,
+    doc: *const c_char,
+    func: Option<OnionFn>,
+    user_data: *mut c_void,
+) -> c_int {
+    let (Some(api), Some(name), Some(func)) =
+        (unsafe { api.as_ref() }, unsafe { c_str(name) }, func)
+    else {
+        return -1;
+    };
+    let Some(ctx) = (unsafe { api.host.cast::<Context>().as_ref() }) else {
+        return -1;
+    };
+    let module = unsafe { c_str(module) };
+    let full_name = match &module {
+        Some(module) => format!("{}.{}", module, name),
+        None => name.to_string(),
+    };
+    let doc = unsafe { c_str(doc) }.map_or_else(|| full_name.clone(), |d| d.into_owned());
+    let user_data = UserData(user_data);
+    let native = Expr::extern_ref(
+        move |args, ctx| {
+            let args = args.iter().map(|arg| eval_ref(arg, ctx)).collect();
+            call(&full_name, func, user_data.get(), args)
+        },
+        name.as_ref(),
+        doc,
+    );
+    crate::native::define_member(ctx, module.as_deref(), &name, native);
+    0
+}
+
+unsafe extern "C" fn api_define_value(
+    api: *const OnionApi,
+    module: *const c_char,
+    name: *const c_char,
+    value: *const OnionValue,
+) -> c_int {
+    let (Some(api), Some(name), Some(value)) =
+        (unsafe { api.as_ref() }, unsafe { c_str(name) }, unsafe {
+            value.as_ref()
+        })
+    else {
+        return -1;
+    };
+    let Some(ctx) = (unsafe { api.host.cast::<Context>().as_ref() }) else {
+        return -1;
+    };
+    match unsafe { to_expr(value) } {
+        Ok(value) => {
+            crate::native::define_member(ctx, unsafe { c_str(module) }.as_deref(), &name, value);
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+unsafe extern "C" fn api_arg_count(call: *const OnionCall) -> usize {
+    unsafe { call.as_ref() }.map_or(0, |call| call.args.len())
+}
+
+unsafe extern "C" fn api_arg(call: *mut OnionCall, index: usize, out: *mut OnionValue) -> c_int {
+    let (Some(call), Some(out)) = (unsafe { call.as_mut() }, unsafe { out.as_mut() }) else {
+        return -1;
+    };
+    match call.args.get(index) {
+        Some(arg) => {
+            *out = call.arena.view(arg);
+            0
+        }
+        None => -1,
+    }
+}
+
+unsafe extern "C" fn api_ret(call: *mut OnionCall, value: *const OnionValue) {
+    let Some(call) = (unsafe { call.as_mut() }) else {
+        return;
+    };
+    match unsafe { value.as_ref() }.map(|v| unsafe { to_expr(v) }) {
+        Some(Ok(value)) => call.result = value,
+        Some(Err(message)) => call.error = Some(message),
+        None => call.result = Expr::Nil,
+    }
+}
+
+unsafe extern "C" fn api_raise(call: *mut OnionCall, message: *const c_char) {
+    if let Some(call) = unsafe { call.as_mut() } {
+        let message = unsafe { c_str(message) }.unwrap_or("error".into());
+        call.error = Some(message.into_owned());
+    }
+}
+
+/// The `user_data` of a plugin function. Sharing it between threads is up to the plugin.
+#[derive(Clone, Copy)]
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+impl UserData {
+    fn get(self) -> *mut c_void {
+        self.0
+    }
+}
+
+/// Call a plugin function, raising any error it reports.
+fn call(name: &str, func: OnionFn, user_data: *mut c_void, args: Vec<Expr>) -> Expr {
+    let api = OnionApi::new(std::ptr::null());
+    let mut call = OnionCall::new(args);
+    let status = unsafe { func(&api, &mut call, user_data) };
+    match call.finish() {
+        Err(message) => crate::stop!("{}: {}", name, message),
+        Ok(_) if status != 0 => crate::stop!("{} failed with status {}", name, status),
+        Ok(result) => result,
+    }
+}
+
+/// Load the plugin at `path` and let it define its functions in `ctx`.
+pub fn load(path: &Path, ctx: &Context) -> Result<(), String> {
+    let shown = path.display();
+    let lib = unsafe { libloading::Library::new(path) }
+        .map_err(|e| format!("can't load {}: {}", shown, e))?;
+    let version = unsafe {
+        lib.get::<unsafe extern "C" fn() -> u32>(b"onion_plugin_abi_version\0")
+            .map_err(|_| format!("{} is not an Onion plugin", shown))?()
+    };
+    if version != ABI_VERSION {
+        return Err(format!(
+            "{} was built for plugin ABI version {}, but this interpreter uses version {}",
+            shown, version, ABI_VERSION
+        ));
+    }
+    let register = unsafe {
+        lib.get::<unsafe extern "C" fn(*const OnionApi) -> c_int>(b"onion_register\0")
+            .map_err(|_| format!("{} has no onion_register function", shown))?
+    };
+    let api = OnionApi::new(ctx);
+    let status = unsafe { register(&api) };
+    if status != 0 {
+        return Err(format!(
+            "{}: onion_register failed with status {}",
+            shown, status
+        ));
+    }
+    // The functions it defined point into the library, so it stays loaded for good
+    std::mem::forget(lib);
+    Ok(())
+}
+
+/// Install `load_plugin`.
+pub fn register(ctx: &mut Context) {
+    ctx.define(
+        "load_plugin",
+        Expr::extern_ref(
+            |args, ctx| {
+                Capabilities::require(ctx.caps.native, "load_plugin");
+                let path = match args {
+                    [path] => eval_ref(path, ctx),
+                    _ => crate::stop!("load_plugin expects a path"),
+                };
+                let Expr::Str(path) = path else {
+                    crate::stop!("load_plugin expects a path, got {}", path);
+                };
+                let path = ctx.caps.read_path("load_plugin", &path);
+                match load(&path, ctx) {
+                    Ok(()) => Expr::Int(1),
+                    Err(message) => crate::stop!("load_plugin: {}", message),
+                }
+            },
+            "load_plugin",
+            "Load a native plugin, a shared library that defines functions through the C interface in include/onion.h. (load_plugin \"libmyext.so\")",
+        ),
+    );
+}
@@ -0,0 +1,182 @@
+use crate::context::Context;
+use crate::expr::Expr;
+use crate::stdlib::contracts;
+use crate::symbol::Symbol;
+use imbl::Vector;
+use std::collections::HashSet;
+
+/// A function whose body is being resolved.
+struct Frame {
+    params: Vec<Symbol>,
+    /// Names the body may define at run time (with `def`, `=`, `for`, ...). Such a name
+    /// hides any variable of the same name further out, so it is always looked up by name.
+    defined: HashSet<Symbol>,
+}
+
+struct Resolver<'a> {
+    ctx: &'a Context,
+    frames: Vec<Frame>,
+}
+
+/// Replace references to the parameters of a function, and of any function written inside
+/// it, with `Expr::Local` slots, so calls don't look them up by name. Only places that are
+/// evaluated are rewritten: quoted code and the arguments of special forms are left as written.
+pub fn resolve_body(params: &[Symbol], name: Option<&Symbol>, body: Expr, ctx: &Context) -> Expr {
+    let mut resolver = Resolver {
+        ctx,
+        frames: Vec::new(),
+    };
+    resolver.push_frame(params.to_vec(), name, std::slice::from_ref(&body));
+    resolver.expr(&body)
+}
+
+impl Resolver<'_> {
+    fn push_frame(&mut self, params: Vec<Symbol>, name: Option<&Symbol>, body: &[Expr]) {
+        let mut defined = HashSet::new();
+        // A named function is bound to its name inside its own scope
+        defined.extend(name.cloned());
+        for expr in body {
+            collect_defined(expr, &mut defined);
+        }
+        self.frames.push(Frame { params, defined });
+    }
+
+    fn lookup(&self, name: &Symbol) -> Option<Expr> {
+        for (depth, frame) in self.frames.iter().rev().enumerate() {
+            if let Some(index) = frame.params.iter().position(|p| p == name) {
+                return Some(Expr::Local {
+                    name: name.clone(),
+                    depth,
+                    index,
+                });
+            }
+            if frame.defined.contains(name) {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Expr {
+        match expr {
+            Expr::Sym(s) => self.lookup(s).unwrap_or_else(|| expr.clone()),
+            Expr::List(l) if !l.is_empty() => self.form(l),
+            Expr::Map(m) => Expr::Map(
+                m.iter()
+                    .map(|(k, v)| (self.expr(k), self.expr(v)))
+                    .collect(),
+            ),
+            Expr::HashMap(m) => Expr::HashMap(
+                m.iter()
+                    .map(|(k, v)| (self.expr(k), self.expr(v)))
+                    .collect(),
+            ),
+            _ => expr.clone(),
+        }
+    }
+
+    fn form(&mut self, list: &Vector<Expr>) -> Expr {
+        let special = match &list[0] {
+            Expr::Sym(name) if self.lookup(name).is_none() => match self.ctx.resolve(name) {
+                Some(Expr::Extern(f)) if f.is_special() => Some(name.clone()),
+                _ => None,
+            },
+            Expr::Extern(f) if f.is_special() => return Expr::List(list.clone()),
+            _ => None,
+        };
+        match special {
+            Some(name) => self.special_form(&name, list),
+            None => Expr::List(list.iter().map(|e| self.expr(e)).collect()),
+        }
+    }
+
+    /// Resolve the parts of a special form that it evaluates in the current scope.
+    fn special_form(&mut self, name: &Symbol, list: &Vector<Expr>) -> Expr {
+        let keep = |n: usize| list.iter().take(n).cloned().collect::<Vector<_>>();
+        match (name.as_str(), list.len()) {
+            // (x = value) or (obj.key = value)
+            ("def" | "=", 3) => {
+                let target = match &list[1] {
+                    target @ Expr::List(_) => self.expr(target),
+                    other => other.clone(),
+                };
+                let value = self.expr(&list[2]);
+                Expr::List(Vector::from(vec![list[0].clone(), target, value]))
+            }
+            ("for", n) if n >= 3 => {
+                let mut out = keep(2);
+                out.extend(list.iter().skip(2).map(|e| self.expr(e)));
+                Expr::List(out)
+            }
+            // (obj . key) or (obj . key value): the key is a name, not a variable
+            (".", n) if n >= 3 => {
+                let mut out = keep(1);
+                out.push_back(self.expr(&list[1]));
+                out.push_back(list[2].clone());
+                out.extend(list.iter().skip(3).map(|e| self.expr(e)));
+                Expr::List(out)
+            }
+            ("fun", n) if n >= 3 => self.function(list, 1, None),
+            ("defun", n) if n >= 4 => match &list[1] {
+                Expr::Sym(fn_name) => self.function(list, 2, Some(fn_name)),
+                _ => Expr::List(list.clone()),
+            },
+            _ => Expr::List(list.clone()),
+        }
+    }
+
+    /// Resolve a function written inside the one being resolved, as a frame nested in it.
+    fn function(&mut self, list: &Vector<Expr>, params_at: usize, name: Option<&Symbol>) -> Expr {
+        let params = match &list[params_at] {
+            Expr::List(l) => contracts::parse_params(l),
+            Expr::Sym(s) => Some((vec![s.clone()], vec![None])),
+            Expr::Nil => Some((vec![], vec![])),
+            _ => None,
+        };
+        let Some((params, _)) = params else {
+            return Expr::List(list.clone());
+        };
+        let rest: Vec<Expr> = list.iter().skip(params_at + 1).cloned().collect();
+        let (_, body) = contracts::parse_return(&rest);
+        let header = list.len() - body.len();
+
+        self.push_frame(params, name, body);
+        let mut out: Vector<Expr> = list.iter().take(header).cloned().collect();
+        out.extend(body.iter().map(|e| self.expr(e)));
+        self.frames.pop();
+        Expr::List(out)
+    }
+}
+
+/// Add the names `expr` may define in the scope it runs in, leaving out the bodies of
+/// functions written inside it, which run in scopes of their own.
+fn collect_defined(expr: &Expr, out: &mut HashSet<Symbol>) {
+    match expr {
+        Expr::List(l) => {
+            if let Some(Expr::Sym(head)) = l.front() {
+                let defines = matches!(
+                    head.as_str(),
+                    "def" | "=" | "for" | "defun" | "struct" | "module" | "defgeneric"
+                );
+                if defines && let Some(Expr::Sym(name)) = l.get(1) {
+                    out.insert(name.clone());
+                }
+                if matches!(head.as_str(), "fun" | "defun") {
+                    return;
+                }
+            }
+            for item in l {
+                collect_defined(item, out);
+            }
+        }
+        Expr::Map(m) => m.iter().for_each(|(k, v)| {
+            collect_defined(k, out);
+            collect_defined(v, out);
+        }),
+        Expr::HashMap(m) => m.iter().for_each(|(k, v)| {
+            collect_defined(k, out);
+            collect_defined(v, out);
+        }),
+        _ => {}
+    }
+}
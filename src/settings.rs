@@ -0,0 +1,51 @@
+//! Switches that change how an interpreter runs code, rather than what it may do (see
+//! [`Capabilities`](crate::stdlib::capabilities::Capabilities)) or how long (see
+//! [`Limits`](crate::limits::Limits)).
+//!
+//! Every interpreter has its own [`Settings`], shared by all of its scopes, so turning one
+//! on in a script or with [`InterpreterBuilder`](crate::InterpreterBuilder) leaves other
+//! interpreters in the process alone.
+
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+
+/// The settings of one interpreter.
+#[derive(Debug)]
+pub struct Settings {
+    /// Whether integer overflow raises an error instead of promoting to a big integer.
+    strict_ints: AtomicBool,
+    /// Whether type annotations are checked.
+    contracts: AtomicBool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            strict_ints: AtomicBool::new(false),
+            contracts: AtomicBool::new(true),
+        }
+    }
+}
+
+impl Settings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn strict_ints(&self) -> bool {
+        self.strict_ints.load(Relaxed)
+    }
+
+    /// Turned on with `--strict-ints` or `Math.set_strict_ints`.
+    pub fn set_strict_ints(&self, strict: bool) {
+        self.strict_ints.store(strict, Relaxed);
+    }
+
+    pub fn contracts(&self) -> bool {
+        self.contracts.load(Relaxed)
+    }
+
+    /// Release runs can turn this off with `--no-contracts`, or `Type.set_contracts`.
+    pub fn set_contracts(&self, enabled: bool) {
+        self.contracts.store(enabled, Relaxed);
+    }
+}
@@ -1,7 +1,9 @@
 use crate::context::Context;
+use crate::error::RwLockExt;
 use crate::expr::Expr;
-use crate::stdlib::game::GAME_STATE; // Import the global state
+use crate::stdlib::game::GameState;
 use rand::Rng;
+use std::sync::RwLock;
 use std::time::Instant;
 
 // ------------------------------------------------------------------
@@ -289,10 +291,10 @@ fn generate_terrain(width: usize, height: usize) -> Vec<u32> {
     buffer
 }
 
-fn run_battle_simulation(config: BattleConfig) -> BattleResult {
-    // 1. Get dimensions from Global GameState
+fn run_battle_simulation(game: &RwLock<GameState>, config: BattleConfig) -> BattleResult {
+    // 1. Get dimensions from the GameState
     let (width, height) = {
-        let state = GAME_STATE.read().unwrap();
+        let state = game.read_unpoisoned();
         (state.width, state.height)
     };
 
@@ -441,7 +443,7 @@ fn run_battle_simulation(config: BattleConfig) -> BattleResult {
 
     while running {
         {
-            let state = GAME_STATE.read().unwrap();
+            let state = game.read_unpoisoned();
             if let Some(wrapper) = &state.window {
                 if !wrapper.0.is_open() {
                     running = false;
@@ -1109,7 +1111,7 @@ fn run_battle_simulation(config: BattleConfig) -> BattleResult {
 
         // 3. Update Window (Requires Lock)
         {
-            let mut state = GAME_STATE.write().unwrap();
+            let mut state = game.write_unpoisoned();
             if let Some(wrapper) = &mut state.window {
                 wrapper
                     .0
@@ -1127,12 +1129,12 @@ fn run_battle_simulation(config: BattleConfig) -> BattleResult {
 }
 
 pub fn register_sim_commands(game_exports: &mut std::collections::BTreeMap<Expr, Expr>) {
-    game_exports.insert(Expr::sym("simulate_battle"), Expr::extern_fun(|args: &mut [Expr], ctx: &mut Context| {
+    game_exports.insert(Expr::sym("simulate_battle"), Expr::extern_ref(|args: &[Expr], ctx: &mut Context| {
         if args.len() != 8 {
              crate::stop!("simulate_battle requires exactly 8 arguments (4 attacker counts, 4 defender counts), got {}", args.len());
         }
 
-        let evaluated_args: Vec<Expr> = args.iter().map(|a| crate::context::eval(a.clone(), ctx)).collect();
+        let evaluated_args: Vec<Expr> = args.iter().map(|a| crate::context::eval_ref(a, ctx)).collect();
         let get_int = |i: usize| -> usize {
             match evaluated_args[i] {
                 Expr::Int(n) => n as usize,
@@ -1140,7 +1142,7 @@ pub fn register_sim_commands(game_exports: &mut std::collections::BTreeMap<Expr,
                 ref other => crate::stop!("simulate_battle argument {} must be a number, got {:?}", i, other),
             }
         };
-        let result = run_battle_simulation(BattleConfig {
+        let result = run_battle_simulation(&ctx.game, BattleConfig {
             inf_a: get_int(0), arch_a: get_int(1), cav_a: get_int(2), art_a: get_int(3),
             inf_d: get_int(4), arch_d: get_int(5), cav_d: get_int(6), art_d: get_int(7),
         });
@@ -1148,7 +1150,7 @@ pub fn register_sim_commands(game_exports: &mut std::collections::BTreeMap<Expr,
 
         // Return the remaining units for both sides
         let remaining = result.remaining;
-        Expr::List(vec![
+        Expr::List(imbl::vector![
             Expr::Str(result.winner),
             Expr::Int(remaining.inf_a as i64),
             Expr::Int(remaining.arch_a as i64),
@@ -0,0 +1,281 @@
+use crate::context::Context;
+use crate::expr::Expr;
+use num_bigint::BigInt;
+use num_traits::{Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+
+/// The most bits a big integer built by `<<` or `Math.ipow` may have, 32 MiB worth. Those
+/// can ask for any size in one step, so they are refused before the memory is allocated.
+const MAX_BITS: u64 = 1 << 28;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl IntOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            IntOp::Add => "+",
+            IntOp::Sub => "-",
+            IntOp::Mul => "*",
+            IntOp::Div => "/",
+            IntOp::Rem => "%",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+}
+
+/// Turn a big integer back into an `Int` when it fits, so the two never overlap.
+pub fn normalize(n: BigInt) -> Expr {
+    match n.to_i64() {
+        Some(i) => Expr::Int(i),
+        None => Expr::BigInt(n),
+    }
+}
+
+/// Parse a decimal integer literal of any size.
+pub fn parse(digits: &str) -> Option<Expr> {
+    digits.parse::<BigInt>().ok().map(normalize)
+}
+
+/// Apply `op` to two Ints, promoting the result to a big integer if it overflows.
+/// The caller checks for division by zero.
+pub fn checked(op: IntOp, a: i64, b: i64, ctx: &Context) -> Expr {
+    let result = match op {
+        IntOp::Add => a.checked_add(b),
+        IntOp::Sub => a.checked_sub(b),
+        IntOp::Mul => a.checked_mul(b),
+        IntOp::Div => a.checked_div(b),
+        IntOp::Rem => a.checked_rem(b),
+    };
+    match result {
+        Some(n) => Expr::Int(n),
+        None => promote(ctx, big_op(op, BigInt::from(a), BigInt::from(b)), || {
+            format!("{} {} {}", a, op.symbol(), b)
+        }),
+    }
+}
+
+/// Apply `op` when either operand is a big integer. Returns `None` for any other operands,
+/// which the operator handles itself. A big integer with no left operand yet (the first
+/// argument of `+`, `*`, ...) is returned as is.
+pub fn arith(op: IntOp, a: &Expr, b: &Expr, ctx: &Context) -> Option<Expr> {
+    if !matches!(a, Expr::BigInt(_)) && !matches!(b, Expr::BigInt(_)) {
+        return None;
+    }
+    match (a, b) {
+        (Expr::Nil, Expr::BigInt(_)) => Some(b.clone()),
+        (Expr::Float(x), _) => Some(Expr::Float(float_op(op, *x, b.as_number()?))),
+        (_, Expr::Float(y)) => Some(Expr::Float(float_op(op, a.as_number()?, *y))),
+        _ => {
+            let (x, y) = (to_big(a)?, to_big(b)?);
+            if matches!(op, IntOp::Div | IntOp::Rem) && y.is_zero() {
+                crate::stop!("Division by zero");
+            }
+            Some(promote(ctx, big_op(op, x, y), || {
+                format!("{} {} {}", a, op.symbol(), b)
+            }))
+        }
+    }
+}
+
+/// Compare two numbers when either is a big integer.
+pub fn compare(a: &Expr, b: &Expr) -> Option<Ordering> {
+    match (a, b) {
+        (Expr::BigInt(_), Expr::Float(_)) | (Expr::Float(_), Expr::BigInt(_)) => {
+            a.as_number()?.partial_cmp(&b.as_number()?)
+        }
+        (Expr::BigInt(_), _) | (_, Expr::BigInt(_)) => Some(to_big(a)?.cmp(&to_big(b)?)),
+        _ => None,
+    }
+}
+
+/// Negate an Int or big integer.
+pub fn neg(n: &Expr, ctx: &Context) -> Option<Expr> {
+    match n {
+        Expr::Int(i) => Some(match i.checked_neg() {
+            Some(n) => Expr::Int(n),
+            None => promote(ctx, Expr::BigInt(-BigInt::from(*i)), || format!("-({})", i)),
+        }),
+        Expr::BigInt(b) => Some(promote(ctx, normalize(-b), || format!("-({})", b))),
+        _ => None,
+    }
+}
+
+/// Absolute value of an Int or big integer.
+pub fn abs(n: &Expr, ctx: &Context) -> Option<Expr> {
+    match n {
+        Expr::Int(i) if *i < 0 => neg(n, ctx),
+        Expr::Int(_) => Some(n.clone()),
+        Expr::BigInt(b) => Some(promote(ctx, normalize(b.abs()), || format!("abs({})", b))),
+        _ => None,
+    }
+}
+
+/// Raise an Int or big integer to a non-negative power exactly.
+pub fn pow(base: &Expr, exp: i64, ctx: &Context) -> Option<Expr> {
+    let exp = u32::try_from(exp).ok()?;
+    let big = match base {
+        Expr::Int(b) => match b.checked_pow(exp) {
+            Some(n) => return Some(Expr::Int(n)),
+            None => BigInt::from(*b),
+        },
+        Expr::BigInt(b) => b.clone(),
+        _ => return None,
+    };
+    // The result has at least this many bits; 0, 1 and -1 fit in an Int whatever the power
+    reserve_bits(
+        (big.bits() - 1).saturating_mul(exp as u64),
+        "Math.ipow",
+        ctx,
+    );
+    let result = big.pow(exp);
+    Some(promote(ctx, normalize(result), || {
+        format!("{} to the power of {}", base, exp)
+    }))
+}
+
+/// Apply a bitwise operator to two integers, treating negative numbers as two's complement.
+/// Returns `None` if either operand is not an integer.
+pub fn bit_op(op: BitOp, a: &Expr, b: &Expr, ctx: &Context) -> Option<Expr> {
+    if let (BitOp::Shl | BitOp::Shr, Expr::Int(n)) = (op, b)
+        && *n < 0
+    {
+        crate::stop!("Negative shift amount: {}", n);
+    }
+    match (a, b) {
+        (Expr::Int(x), Expr::Int(y)) => Some(match op {
+            BitOp::And => Expr::Int(x & y),
+            BitOp::Or => Expr::Int(x | y),
+            BitOp::Xor => Expr::Int(x ^ y),
+            BitOp::Shr => Expr::Int(x >> (*y).min(63)),
+            // Shifting out any bits other than copies of the sign bit overflows
+            BitOp::Shl => match u32::try_from(*y) {
+                Ok(n) if n < 64 && (x << n) >> n == *x => Expr::Int(x << n),
+                _ => shift_left(&BigInt::from(*x), *y, a, b, ctx),
+            },
+        }),
+        (Expr::BigInt(_), Expr::Int(_) | Expr::BigInt(_)) | (Expr::Int(_), Expr::BigInt(_)) => {
+            let (x, y) = (to_big(a)?, to_big(b)?);
+            Some(match op {
+                BitOp::And => normalize(x & y),
+                BitOp::Or => normalize(x | y),
+                BitOp::Xor => normalize(x ^ y),
+                BitOp::Shl | BitOp::Shr => {
+                    let n = match y.to_i64() {
+                        Some(n) if n >= 0 => n,
+                        _ => crate::stop!("Shift amount out of range: {}", y),
+                    };
+                    if op == BitOp::Shl {
+                        shift_left(&x, n, a, b, ctx)
+                    } else {
+                        normalize(x >> n)
+                    }
+                }
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Bitwise NOT of an Int or big integer.
+pub fn bit_not(n: &Expr) -> Option<Expr> {
+    match n {
+        Expr::Int(i) => Some(Expr::Int(!i)),
+        Expr::BigInt(b) => Some(normalize(!b)),
+        _ => None,
+    }
+}
+
+/// Whether bit `i` of an integer is set, counting negative numbers as two's complement.
+pub fn bit(n: &Expr, i: u64) -> Option<bool> {
+    match n {
+        Expr::Int(x) => Some((x >> i.min(63)) & 1 == 1),
+        Expr::BigInt(b) => Some(b.bit(i)),
+        _ => None,
+    }
+}
+
+/// The number of 1 bits in the absolute value of an integer.
+pub fn popcount(n: &Expr) -> Option<Expr> {
+    match n {
+        Expr::Int(x) => Some(Expr::Int(x.unsigned_abs().count_ones() as i64)),
+        Expr::BigInt(b) => Some(Expr::Int(b.magnitude().count_ones() as i64)),
+        _ => None,
+    }
+}
+
+fn shift_left(x: &BigInt, n: i64, a: &Expr, b: &Expr, ctx: &Context) -> Expr {
+    if x.is_zero() {
+        return Expr::Int(0);
+    }
+    reserve_bits(x.bits().saturating_add(n as u64), "<<", ctx);
+    let n = match usize::try_from(n) {
+        Ok(n) => n,
+        Err(_) => crate::stop!("Shift amount out of range: {}", n),
+    };
+    promote(ctx, normalize(x << n), || format!("{} << {}", a, b))
+}
+
+fn to_big(n: &Expr) -> Option<BigInt> {
+    match n {
+        Expr::Int(i) => Some(BigInt::from(*i)),
+        Expr::BigInt(b) => Some(b.clone()),
+        _ => None,
+    }
+}
+
+/// Raise an error before building a big integer of `bits` bits that is larger than
+/// [`MAX_BITS`], or than the interpreter's size limit.
+fn reserve_bits(bits: u64, func: &str, ctx: &Context) {
+    if bits > MAX_BITS {
+        crate::stop!(
+            "{}: integer too large ({} bits, limit is {})",
+            func,
+            bits,
+            MAX_BITS
+        );
+    }
+    ctx.limits.reserve((bits / 8) as usize, func);
+}
+
+/// Return a result that may not fit in an Int, or raise an error in strict mode if it doesn't.
+fn promote(ctx: &Context, result: Expr, what: impl FnOnce() -> String) -> Expr {
+    if ctx.settings.strict_ints() && matches!(result, Expr::BigInt(_)) {
+        crate::stop!("Integer overflow: {}", what());
+    }
+    result
+}
+
+fn big_op(op: IntOp, a: BigInt, b: BigInt) -> Expr {
+    normalize(match op {
+        IntOp::Add => a + b,
+        IntOp::Sub => a - b,
+        IntOp::Mul => a * b,
+        IntOp::Div => a / b,
+        IntOp::Rem => a % b,
+    })
+}
+
+fn float_op(op: IntOp, a: f64, b: f64) -> f64 {
+    match op {
+        IntOp::Add => a + b,
+        IntOp::Sub => a - b,
+        IntOp::Mul => a * b,
+        IntOp::Div => a / b,
+        IntOp::Rem => a % b,
+    }
+}
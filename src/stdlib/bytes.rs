@@ -0,0 +1,441 @@
+use crate::context::{Context, eval_ref};
+use crate::expr::Expr;
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+pub fn register(ctx: &mut Context) {
+    let mut bytes_exports = BTreeMap::new();
+
+    // Construction
+    bytes_exports.insert(
+        Expr::sym("make"),
+        Expr::extern_ref(
+            |args, ctx| {
+                let (len, fill) = match args {
+                    [len] => (eval_ref(len, ctx), Expr::Int(0)),
+                    [len, fill] => (eval_ref(len, ctx), eval_ref(fill, ctx)),
+                    _ => crate::stop!("Bytes.make expects a length and an optional fill byte"),
+                };
+                match (len, fill) {
+                    (Expr::Int(n), Expr::Int(b)) if n >= 0 => {
+                        ctx.limits.reserve(n as usize, "Bytes.make");
+                        Expr::Bytes(vec![to_byte(b, "Bytes.make"); n as usize])
+                    }
+                    (len, fill) => crate::stop!(
+                        "Bytes.make expected (Int, Int), got ({:?}, {:?})",
+                        len,
+                        fill
+                    ),
+                }
+            },
+            "make",
+            "Create n bytes, all set to fill (default 0)",
+        ),
+    );
+
+    bytes_exports.insert(
+        Expr::sym("from_list"),
+        Expr::extern_ref(
+            |args, ctx| match eval_first(args, ctx) {
+                Expr::List(l) => Expr::Bytes(
+                    l.iter()
+                        .map(|b| match b {
+                            Expr::Int(n) => to_byte(*n, "Bytes.from_list"),
+                            other => crate::stop!("Bytes.from_list expected Ints, got {:?}", other),
+                        })
+                        .collect(),
+                ),
+                other => crate::stop!("Bytes.from_list expected List, got {:?}", other),
+            },
+            "from_list",
+            "Create bytes from a list of Ints from 0 to 255",
+        )
+        .with_signature(&["list"], "bytes"),
+    );
+
+    bytes_exports.insert(
+        Expr::sym("to_list"),
+        Expr::extern_ref(
+            |args, ctx| {
+                let b = expect_bytes(eval_first(args, ctx), "Bytes.to_list");
+                Expr::List(b.into_iter().map(|b| Expr::Int(b as i64)).collect())
+            },
+            "to_list",
+            "List of the bytes as Ints",
+        )
+        .with_signature(&["bytes"], "list"),
+    );
+
+    // Text
+    bytes_exports.insert(
+        Expr::sym("from_str"),
+        Expr::extern_ref(
+            |args, ctx| {
+                let (s, encoding) = string_and_encoding(args, ctx, "Bytes.from_str");
+                let s = match s {
+                    Expr::Str(s) => s,
+                    other => crate::stop!("Bytes.from_str expected Str, got {:?}", other),
+                };
+                match encoding.as_str() {
+                    "utf8" => Expr::Bytes(s.into_bytes()),
+                    "ascii" | "latin1" => {
+                        let limit = if encoding == "ascii" { 0x7f } else { 0xff };
+                        Expr::Bytes(
+                            s.chars()
+                                .map(|c| match c as u32 {
+                                    n if n <= limit => n as u8,
+                                    _ => crate::stop!("Bytes.from_str: {:?} is not {}", c, encoding),
+                                })
+                                .collect(),
+                        )
+                    }
+                    "hex" => {
+                        let digits: Vec<char> =
+                            s.chars().filter(|c| !c.is_whitespace()).collect();
+                        if !digits.len().is_multiple_of(2) {
+                            crate::stop!("Bytes.from_str: hex string has an odd number of digits");
+                        }
+                        Expr::Bytes(
+                            digits
+                                .chunks(2)
+                                .map(|pair| {
+                                    let pair: String = pair.iter().collect();
+                                    u8::from_str_radix(&pair, 16).unwrap_or_else(|_| {
+                                        crate::stop!("Bytes.from_str: invalid hex {:?}", pair)
+                                    })
+                                })
+                                .collect(),
+                        )
+                    }
+                    other => unknown_encoding(other),
+                }
+            },
+            "from_str",
+            "Encode a string as bytes. (Bytes.from_str s [\"utf8\" | \"ascii\" | \"latin1\" | \"hex\"])",
+        ),
+    );
+
+    bytes_exports.insert(
+        Expr::sym("to_str"),
+        Expr::extern_ref(
+            |args, ctx| {
+                let (b, encoding) = string_and_encoding(args, ctx, "Bytes.to_str");
+                let b = expect_bytes(b, "Bytes.to_str");
+                match encoding.as_str() {
+                    "utf8" => match String::from_utf8(b) {
+                        Ok(s) => Expr::Str(s),
+                        Err(e) => crate::stop!("Bytes.to_str: invalid utf8: {}", e),
+                    },
+                    "ascii" => match b.iter().position(|b| !b.is_ascii()) {
+                        Some(i) => crate::stop!(
+                            "Bytes.to_str: byte {} at index {} is not ascii",
+                            b[i],
+                            i
+                        ),
+                        None => Expr::Str(b.into_iter().map(char::from).collect()),
+                    },
+                    "latin1" => Expr::Str(b.into_iter().map(char::from).collect()),
+                    "hex" => Expr::Str(b.iter().map(|b| format!("{:02x}", b)).collect()),
+                    other => unknown_encoding(other),
+                }
+            },
+            "to_str",
+            "Decode bytes as a string. (Bytes.to_str b [\"utf8\" | \"ascii\" | \"latin1\" | \"hex\"])",
+        ),
+    );
+
+    // Access
+    bytes_exports.insert(
+        Expr::sym("len"),
+        Expr::extern_ref(
+            |args, ctx| Expr::Int(expect_bytes(eval_first(args, ctx), "Bytes.len").len() as i64),
+            "len",
+            "Number of bytes",
+        )
+        .with_signature(&["bytes"], "int"),
+    );
+
+    bytes_exports.insert(
+        Expr::sym("slice"),
+        Expr::extern_ref(
+            |args, ctx| {
+                if args.len() < 2 || args.len() > 3 {
+                    crate::stop!("Bytes.slice expects bytes, a start and an optional end");
+                }
+                let b = expect_bytes(eval_ref(&args[0], ctx), "Bytes.slice");
+                let start = expect_index(eval_ref(&args[1], ctx), "Bytes.slice");
+                let end = match args.get(2) {
+                    Some(end) => expect_index(eval_ref(end, ctx), "Bytes.slice"),
+                    None => b.len(),
+                };
+                if start > end || end > b.len() {
+                    crate::stop!(
+                        "Bytes.slice: range {}..{} out of bounds for {} bytes",
+                        start,
+                        end,
+                        b.len()
+                    );
+                }
+                Expr::Bytes(b[start..end].to_vec())
+            },
+            "slice",
+            "Bytes from start up to (not including) end",
+        ),
+    );
+
+    bytes_exports.insert(
+        Expr::sym("set"),
+        Expr::extern_ref(
+            |args, ctx| {
+                if args.len() != 3 {
+                    crate::stop!("Bytes.set expects bytes, an index and a byte");
+                }
+                let mut b = expect_bytes(eval_ref(&args[0], ctx), "Bytes.set");
+                let i = expect_index(eval_ref(&args[1], ctx), "Bytes.set");
+                let val = match eval_ref(&args[2], ctx) {
+                    Expr::Int(n) => to_byte(n, "Bytes.set"),
+                    other => crate::stop!("Bytes.set expected an Int byte, got {:?}", other),
+                };
+                match b.get_mut(i) {
+                    Some(slot) => *slot = val,
+                    None => crate::stop!("Index out of bounds: {} for {} bytes", i, b.len()),
+                }
+                Expr::Bytes(b)
+            },
+            "set",
+            "Copy of the bytes with the byte at an index replaced",
+        )
+        .with_signature(&["bytes", "int", "int"], "bytes"),
+    );
+
+    bytes_exports.insert(
+        Expr::sym("concat"),
+        Expr::extern_ref(
+            |args, ctx| {
+                let mut res = Vec::new();
+                for arg in args.iter() {
+                    res.extend(expect_bytes(eval_ref(arg, ctx), "Bytes.concat"));
+                }
+                Expr::Bytes(res)
+            },
+            "concat",
+            "Join several byte strings together",
+        ),
+    );
+
+    // Binary formats
+    bytes_exports.insert(
+        Expr::sym("pack"),
+        Expr::extern_ref(
+            |args, ctx| {
+                let fmt = match args.first().map(|f| eval_ref(f, ctx)) {
+                    Some(Expr::Str(f)) => f,
+                    other => crate::stop!("Bytes.pack expected a format string, got {:?}", other),
+                };
+                let (big_endian, codes) = parse_format(&fmt);
+                if codes.len() != args.len() - 1 {
+                    crate::stop!(
+                        "Bytes.pack: format {:?} needs {} values, got {}",
+                        fmt,
+                        codes.len(),
+                        args.len() - 1
+                    );
+                }
+                let mut out = Vec::new();
+                for (code, arg) in codes.into_iter().zip(&args[1..]) {
+                    pack_value(code, eval_ref(arg, ctx), big_endian, &mut out);
+                }
+                Expr::Bytes(out)
+            },
+            "pack",
+            "Pack numbers into bytes. (Bytes.pack \"<hI\" -1 70000) packs a little-endian i16 and u32",
+        ),
+    );
+
+    bytes_exports.insert(
+        Expr::sym("unpack"),
+        Expr::extern_ref(
+            |args, ctx| {
+                if args.len() < 2 || args.len() > 3 {
+                    crate::stop!("Bytes.unpack expects a format, bytes and an optional offset");
+                }
+                let fmt = match eval_ref(&args[0], ctx) {
+                    Expr::Str(f) => f,
+                    other => crate::stop!("Bytes.unpack expected a format string, got {:?}", other),
+                };
+                let b = expect_bytes(eval_ref(&args[1], ctx), "Bytes.unpack");
+                let mut offset = match args.get(2) {
+                    Some(o) => expect_index(eval_ref(o, ctx), "Bytes.unpack"),
+                    None => 0,
+                };
+
+                let (big_endian, codes) = parse_format(&fmt);
+                let mut values = Vec::with_capacity(codes.len());
+                for code in codes {
+                    let size = code_size(code);
+                    let chunk = match b.get(offset..offset + size) {
+                        Some(chunk) => chunk,
+                        None => crate::stop!(
+                            "Bytes.unpack: format {:?} reads past the end of {} bytes",
+                            fmt,
+                            b.len()
+                        ),
+                    };
+                    values.push(unpack_value(code, chunk, big_endian));
+                    offset += size;
+                }
+                Expr::List(values.into())
+            },
+            "unpack",
+            "Unpack numbers from bytes. (Bytes.unpack \"<hI\" b [offset]) returns a list",
+        ),
+    );
+
+    let mod_val = Expr::Ref(Arc::new(RwLock::new(Expr::Map(bytes_exports.into()))));
+    ctx.define("Bytes", mod_val);
+}
+
+/// Split a pack format into its byte order and value codes. The format may start with `<`
+/// (little-endian, the default) or `>` (big-endian), followed by `b B h H i I q Q f d` for
+/// signed and unsigned 8, 16, 32 and 64 bit integers, and 32 and 64 bit floats.
+fn parse_format(fmt: &str) -> (bool, Vec<char>) {
+    let (big_endian, codes) = match fmt.trim_start().chars().next() {
+        Some('<') => (false, &fmt.trim_start()[1..]),
+        Some('>') => (true, &fmt.trim_start()[1..]),
+        _ => (false, fmt),
+    };
+    let codes: Vec<char> = codes.chars().filter(|c| !c.is_whitespace()).collect();
+    for c in &codes {
+        if !"bBhHiIqQfd".contains(*c) {
+            crate::stop!("Unknown code {:?} in pack format {:?}", c, fmt);
+        }
+    }
+    (big_endian, codes)
+}
+
+fn code_size(code: char) -> usize {
+    match code {
+        'b' | 'B' => 1,
+        'h' | 'H' => 2,
+        'i' | 'I' | 'f' => 4,
+        _ => 8,
+    }
+}
+
+fn pack_value(code: char, val: Expr, big_endian: bool, out: &mut Vec<u8>) {
+    macro_rules! put {
+        ($v:expr) => {
+            if big_endian {
+                out.extend_from_slice(&$v.to_be_bytes())
+            } else {
+                out.extend_from_slice(&$v.to_le_bytes())
+            }
+        };
+    }
+
+    if matches!(code, 'f' | 'd') {
+        let f = match val.as_number() {
+            Some(f) => f,
+            None => crate::stop!("Bytes.pack: {} expects a number, got {:?}", code, val),
+        };
+        if code == 'f' {
+            put!(f as f32)
+        } else {
+            put!(f)
+        }
+        return;
+    }
+
+    // Wide enough for every code, including a 'Q' above i64::MAX held in a big integer
+    let n: i128 = match &val {
+        Expr::Int(n) => *n as i128,
+        Expr::BigInt(b) => b.to_i128().unwrap_or(i128::MAX),
+        other => crate::stop!("Bytes.pack: {} expects an Int, got {:?}", code, other),
+    };
+    let out_of_range = || -> ! { crate::stop!("Bytes.pack: {} does not fit in {}", val, code) };
+    match code {
+        'b' => put!(i8::try_from(n).unwrap_or_else(|_| out_of_range())),
+        'B' => put!(u8::try_from(n).unwrap_or_else(|_| out_of_range())),
+        'h' => put!(i16::try_from(n).unwrap_or_else(|_| out_of_range())),
+        'H' => put!(u16::try_from(n).unwrap_or_else(|_| out_of_range())),
+        'i' => put!(i32::try_from(n).unwrap_or_else(|_| out_of_range())),
+        'I' => put!(u32::try_from(n).unwrap_or_else(|_| out_of_range())),
+        'q' => put!(i64::try_from(n).unwrap_or_else(|_| out_of_range())),
+        _ => put!(u64::try_from(n).unwrap_or_else(|_| out_of_range())),
+    }
+}
+
+fn unpack_value(code: char, chunk: &[u8], big_endian: bool) -> Expr {
+    macro_rules! get {
+        ($t:ty) => {{
+            let raw = chunk.try_into().unwrap();
+            if big_endian {
+                <$t>::from_be_bytes(raw)
+            } else {
+                <$t>::from_le_bytes(raw)
+            }
+        }};
+    }
+
+    match code {
+        'b' => Expr::Int(get!(i8) as i64),
+        'B' => Expr::Int(get!(u8) as i64),
+        'h' => Expr::Int(get!(i16) as i64),
+        'H' => Expr::Int(get!(u16) as i64),
+        'i' => Expr::Int(get!(i32) as i64),
+        'I' => Expr::Int(get!(u32) as i64),
+        'q' => Expr::Int(get!(i64)),
+        'Q' => super::bigint::normalize(BigInt::from(get!(u64))),
+        'f' => Expr::Float(get!(f32) as f64),
+        _ => Expr::Float(get!(f64)),
+    }
+}
+
+fn to_byte(n: i64, what: &str) -> u8 {
+    match u8::try_from(n) {
+        Ok(b) => b,
+        Err(_) => crate::stop!("{}: {} is not a byte (0 to 255)", what, n),
+    }
+}
+
+fn expect_bytes(val: Expr, what: &str) -> Vec<u8> {
+    match val {
+        Expr::Bytes(b) => b,
+        other => crate::stop!("{} expected Bytes, got {:?}", what, other),
+    }
+}
+
+fn expect_index(val: Expr, what: &str) -> usize {
+    match val {
+        Expr::Int(n) if n >= 0 => n as usize,
+        other => crate::stop!("{} expected a non-negative Int, got {:?}", what, other),
+    }
+}
+
+fn string_and_encoding(args: &[Expr], ctx: &mut Context, what: &str) -> (Expr, String) {
+    match args {
+        [val] => (eval_ref(val, ctx), "utf8".to_string()),
+        [val, encoding] => match eval_ref(encoding, ctx) {
+            Expr::Str(e) => (eval_ref(val, ctx), e.to_lowercase().replace('-', "")),
+            other => crate::stop!("{} expected an encoding name, got {:?}", what, other),
+        },
+        _ => crate::stop!("{} expects a value and an optional encoding", what),
+    }
+}
+
+fn unknown_encoding(encoding: &str) -> ! {
+    crate::stop!(
+        "Unknown encoding {:?}, expected utf8, ascii, latin1 or hex",
+        encoding
+    )
+}
+
+fn eval_first(args: &[Expr], ctx: &mut Context) -> Expr {
+    if args.len() != 1 {
+        Expr::Nil
+    } else {
+        eval_ref(&args[0], ctx)
+    }
+}
@@ -0,0 +1,198 @@
+//! What the stdlib lets a script do outside the interpreter: touch files, run programs,
+//! read and change the environment, exit the process, open a window or play sound, and
+//! load or call native code.
+//!
+//! A trusted script gets [`Capabilities::all`]. An untrusted one starts from
+//! [`Capabilities::none`] and is given what it needs:
+//!
+//! ```
+//! use onion::Interpreter;
+//! use onion::stdlib::capabilities::Capabilities;
+//!
+//! let caps = Capabilities::none().allow_read("levels");
+//! let mut onion = Interpreter::builder().capabilities(caps).build();
+//! let denied = onion.eval_str("(try (OS.exec \"rm\" \"-rf\" \"/\") (fun (msg) msg))");
+//! assert!(denied.unwrap().as_str().unwrap().starts_with("Permission denied"));
+//! ```
+//!
+//! Denied functions are still defined, and raise a runtime error starting with
+//! `Permission denied` that `try` can catch. File paths are resolved, following `..` and
+//! symbolic links, before being checked against the allowed directories.
+
+use std::path::{Component, Path, PathBuf};
+
+/// The start of the message of every permission error.
+pub const PERMISSION_DENIED: &str = "Permission denied";
+
+/// Where files may be read or written.
+#[derive(Clone, Debug, PartialEq)]
+enum Roots {
+    Anywhere,
+    /// Inside these directories, already resolved.
+    Only(Vec<PathBuf>),
+}
+
+impl Roots {
+    fn add(&mut self, dir: &Path) {
+        if let Roots::Only(dirs) = self {
+            dirs.push(resolve(dir).unwrap_or_else(|| dir.to_path_buf()));
+        }
+    }
+
+    fn allows(&self, path: &Path) -> bool {
+        match self {
+            Roots::Anywhere => true,
+            Roots::Only(dirs) => dirs.iter().any(|dir| path.starts_with(dir)),
+        }
+    }
+}
+
+/// The capabilities of one interpreter, chosen when its stdlib is built.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Capabilities {
+    read: Roots,
+    write: Roots,
+    /// `OS.exec`
+    pub process: bool,
+    /// `OS.env` and `OS.set_env`
+    pub env: bool,
+    /// `OS.exit`
+    pub exit: bool,
+    /// `Game.run` and `Game.play_sound`
+    pub window: bool,
+    /// `load_plugin` and the `FFI` module, which run native code and touch raw memory the
+    /// sandbox can't see into
+    pub native: bool,
+}
+
+impl Capabilities {
+    /// Everything allowed, as for a script the user runs themselves.
+    pub fn all() -> Self {
+        Self {
+            read: Roots::Anywhere,
+            write: Roots::Anywhere,
+            process: true,
+            env: true,
+            exit: true,
+            window: true,
+            native: true,
+        }
+    }
+
+    /// Nothing allowed.
+    pub fn none() -> Self {
+        Self {
+            read: Roots::Only(Vec::new()),
+            write: Roots::Only(Vec::new()),
+            process: false,
+            env: false,
+            exit: false,
+            window: false,
+            native: false,
+        }
+    }
+
+    /// Allow reading files inside `dir`.
+    pub fn allow_read(mut self, dir: impl AsRef<Path>) -> Self {
+        self.read.add(dir.as_ref());
+        self
+    }
+
+    /// Allow reading, writing and removing files inside `dir`.
+    pub fn allow_write(mut self, dir: impl AsRef<Path>) -> Self {
+        self.read.add(dir.as_ref());
+        self.write.add(dir.as_ref());
+        self
+    }
+
+    pub fn allow_process(mut self) -> Self {
+        self.process = true;
+        self
+    }
+
+    pub fn allow_env(mut self) -> Self {
+        self.env = true;
+        self
+    }
+
+    pub fn allow_exit(mut self) -> Self {
+        self.exit = true;
+        self
+    }
+
+    pub fn allow_window(mut self) -> Self {
+        self.window = true;
+        self
+    }
+
+    pub fn allow_native(mut self) -> Self {
+        self.native = true;
+        self
+    }
+
+    /// Raise a permission error for `func` unless `allowed`.
+    pub fn require(allowed: bool, func: &str) {
+        if !allowed {
+            crate::stop!("{}: {} is not allowed", PERMISSION_DENIED, func);
+        }
+    }
+
+    /// The path `func` may read, resolved, or a permission error.
+    pub fn read_path(&self, func: &str, path: &str) -> PathBuf {
+        Self::check_path(&self.read, func, "read", path)
+    }
+
+    /// The path `func` may write or remove, resolved, or a permission error.
+    pub fn write_path(&self, func: &str, path: &str) -> PathBuf {
+        Self::check_path(&self.write, func, "write", path)
+    }
+
+    fn check_path(roots: &Roots, func: &str, action: &str, path: &str) -> PathBuf {
+        if *roots == Roots::Anywhere {
+            return PathBuf::from(path);
+        }
+        match resolve(Path::new(path)) {
+            Some(resolved) if roots.allows(&resolved) => resolved,
+            _ => crate::stop!(
+                "{}: {} may not {} {}",
+                PERMISSION_DENIED,
+                func,
+                action,
+                path
+            ),
+        }
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+/// The absolute path `path` names, with links followed as far as the path exists. `None`
+/// if the part that doesn't exist yet climbs out with `..`, or is a dangling link, which
+/// a write would follow to wherever it points.
+fn resolve(path: &Path) -> Option<PathBuf> {
+    let path = std::path::absolute(path).ok()?;
+    let mut existing = path.as_path();
+    let mut rest = Vec::new();
+    loop {
+        if let Ok(resolved) = existing.canonicalize() {
+            let mut resolved = resolved;
+            for component in rest.iter().rev() {
+                match component {
+                    Component::Normal(name) => resolved.push(name),
+                    Component::CurDir => {}
+                    _ => return None,
+                }
+            }
+            return Some(resolved);
+        }
+        if existing.symlink_metadata().is_ok() {
+            return None;
+        }
+        rest.push(existing.components().next_back()?);
+        existing = existing.parent()?;
+    }
+}
@@ -1,5 +1,6 @@
-use crate::context::{Context, eval};
+use crate::context::{Context, eval_ref};
 use crate::expr::Expr;
+use imbl::{Vector, vector};
 use std::collections::BTreeMap;
 use std::sync::{Arc, RwLock};
 
@@ -9,17 +10,17 @@ pub fn register(ctx: &mut Context) {
     // List Operations
     col_exports.insert(
         Expr::sym("push"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     crate::stop!("push requires 2 arguments, got {}", args.len());
                 }
-                let list = crate::context::eval(args[0].clone(), ctx);
-                let val = crate::context::eval(args[1].clone(), ctx);
+                let list = crate::context::eval_ref(&args[0], ctx);
+                let val = crate::context::eval_ref(&args[1], ctx);
 
                 match list {
                     Expr::List(mut v) => {
-                        v.push(val);
+                        v.push_back(val);
                         Expr::List(v)
                     }
                     other => crate::stop!("push expected List as first argument, got {:?}", other),
@@ -32,10 +33,10 @@ pub fn register(ctx: &mut Context) {
 
     col_exports.insert(
         Expr::sym("pop"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::List(mut v) => {
-                    v.pop();
+                    v.pop_back();
                     Expr::List(v)
                 }
                 other => crate::stop!("pop expected List, got {:?}", other),
@@ -47,9 +48,9 @@ pub fn register(ctx: &mut Context) {
 
     col_exports.insert(
         Expr::sym("peek"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
-                Expr::List(v) => v.last().cloned().unwrap_or(Expr::Nil),
+                Expr::List(v) => v.back().cloned().unwrap_or(Expr::Nil),
                 other => crate::stop!("peek expected List, got {:?}", other),
             },
             "peek",
@@ -59,12 +60,9 @@ pub fn register(ctx: &mut Context) {
 
     col_exports.insert(
         Expr::sym("reverse"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
-                Expr::List(mut v) => {
-                    v.reverse();
-                    Expr::List(v)
-                }
+                Expr::List(v) => Expr::List(v.into_iter().rev().collect()),
                 other => crate::stop!("reverse expected List, got {:?}", other),
             },
             "reverse",
@@ -74,16 +72,16 @@ pub fn register(ctx: &mut Context) {
 
     col_exports.insert(
         Expr::sym("sort"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() < 1 {
                     crate::stop!("sort requires at least 1 argument");
                 }
-                let list_expr = crate::context::eval(args[0].clone(), ctx);
+                let list_expr = crate::context::eval_ref(&args[0], ctx);
 
                 match list_expr {
                     Expr::List(mut v) => {
-                        v.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+                        v.sort_by(|a, b| a.cmp_by_name(b));
                         Expr::List(v)
                     }
                     other => crate::stop!("sort expected List, got {:?}", other),
@@ -96,15 +94,15 @@ pub fn register(ctx: &mut Context) {
 
     col_exports.insert(
         Expr::sym("range"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() < 2 {
                     crate::stop!("range requires at least 2 arguments (start, end, [step])");
                 }
-                let start = crate::context::eval(args[0].clone(), ctx);
-                let end = crate::context::eval(args[1].clone(), ctx);
+                let start = crate::context::eval_ref(&args[0], ctx);
+                let end = crate::context::eval_ref(&args[1], ctx);
                 let step = if args.len() > 2 {
-                    crate::context::eval(args[2].clone(), ctx)
+                    crate::context::eval_ref(&args[2], ctx)
                 } else {
                     Expr::Int(1)
                 };
@@ -115,6 +113,9 @@ pub fn register(ctx: &mut Context) {
                         if st == 0 {
                             crate::stop!("range step cannot be 0");
                         }
+                        let len = ((e as i128 - s as i128) / st as i128).max(0) as usize;
+                        ctx.limits
+                            .reserve(len.saturating_mul(std::mem::size_of::<Expr>()), "range");
                         let mut i = s;
                         if st > 0 {
                             while i < e {
@@ -127,7 +128,7 @@ pub fn register(ctx: &mut Context) {
                                 i += st;
                             }
                         }
-                        Expr::List(res)
+                        Expr::List(res.into())
                     }
                     (s, e, st) => crate::stop!(
                         "range arguments must be integers, got start={:?}, end={:?}, step={:?}",
@@ -144,22 +145,22 @@ pub fn register(ctx: &mut Context) {
 
     col_exports.insert(
         Expr::sym("zip"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     crate::stop!("zip requires 2 arguments, got {}", args.len());
                 }
-                let l1 = crate::context::eval(args[0].clone(), ctx);
-                let l2 = crate::context::eval(args[1].clone(), ctx);
+                let l1 = crate::context::eval_ref(&args[0], ctx);
+                let l2 = crate::context::eval_ref(&args[1], ctx);
 
                 match (l1, l2) {
                     (Expr::List(v1), Expr::List(v2)) => {
                         let len = std::cmp::min(v1.len(), v2.len());
                         let mut res = Vec::with_capacity(len);
                         for i in 0..len {
-                            res.push(Expr::List(vec![v1[i].clone(), v2[i].clone()]));
+                            res.push(Expr::List(vector![v1[i].clone(), v2[i].clone()]));
                         }
-                        Expr::List(res)
+                        Expr::List(res.into())
                     }
                     (a, b) => crate::stop!("zip expected two Lists, got {:?} and {:?}", a, b),
                 }
@@ -171,10 +172,10 @@ pub fn register(ctx: &mut Context) {
 
     col_exports.insert(
         Expr::sym("flatten"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::List(v) => {
-                    fn flatten_recursively(list: Vec<Expr>, out: &mut Vec<Expr>) {
+                    fn flatten_recursively(list: Vector<Expr>, out: &mut Vec<Expr>) {
                         for item in list {
                             match item {
                                 Expr::List(nested) => flatten_recursively(nested, out),
@@ -184,7 +185,7 @@ pub fn register(ctx: &mut Context) {
                     }
                     let mut res = Vec::new();
                     flatten_recursively(v, &mut res);
-                    Expr::List(res)
+                    Expr::List(res.into())
                 }
                 other => crate::stop!("flatten expected List, got {:?}", other),
             },
@@ -195,7 +196,7 @@ pub fn register(ctx: &mut Context) {
 
     col_exports.insert(
         Expr::sym("dedup"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::List(v) => {
                     let mut res = Vec::new();
@@ -204,7 +205,7 @@ pub fn register(ctx: &mut Context) {
                             res.push(item);
                         }
                     }
-                    Expr::List(res)
+                    Expr::List(res.into())
                 }
                 other => crate::stop!("dedup expected List, got {:?}", other),
             },
@@ -216,7 +217,7 @@ pub fn register(ctx: &mut Context) {
     // Map Operations
     col_exports.insert(
         Expr::sym("keys"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Map(m) => Expr::List(m.keys().cloned().collect()),
                 Expr::HashMap(m) => Expr::List(m.keys().cloned().collect()),
@@ -229,7 +230,7 @@ pub fn register(ctx: &mut Context) {
 
     col_exports.insert(
         Expr::sym("values"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Map(m) => Expr::List(m.values().cloned().collect()),
                 Expr::HashMap(m) => Expr::List(m.values().cloned().collect()),
@@ -242,13 +243,13 @@ pub fn register(ctx: &mut Context) {
 
     col_exports.insert(
         Expr::sym("contains_key"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     crate::stop!("contains_key requires 2 arguments, got {}", args.len());
                 }
-                let map = crate::context::eval(args[0].clone(), ctx);
-                let key = crate::context::eval(args[1].clone(), ctx);
+                let map = crate::context::eval_ref(&args[0], ctx);
+                let key = crate::context::eval_ref(&args[1], ctx);
 
                 match map {
                     Expr::Map(m) => {
@@ -275,13 +276,13 @@ pub fn register(ctx: &mut Context) {
 
     col_exports.insert(
         Expr::sym("merge"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     crate::stop!("merge requires 2 arguments, got {}", args.len());
                 }
-                let map1 = crate::context::eval(args[0].clone(), ctx);
-                let map2 = crate::context::eval(args[1].clone(), ctx);
+                let map1 = crate::context::eval_ref(&args[0], ctx);
+                let map2 = crate::context::eval_ref(&args[1], ctx);
 
                 match (map1, map2) {
                     (Expr::Map(mut m1), Expr::Map(m2)) => {
@@ -307,26 +308,24 @@ pub fn register(ctx: &mut Context) {
     // Functional Helpers
     col_exports.insert(
         Expr::sym("map"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     crate::stop!("map requires 2 arguments (list, function)");
                 }
-                // let list = crate::context::eval(args[0].clone(), ctx);
-                crate::context::eval_in_place(&mut args[0], ctx);
-                crate::context::eval_in_place(&mut args[1], ctx);
-                // let func = crate::context::eval(args[1].clone(), ctx); // Func should optimize to self or extern
+                let list = eval_ref(&args[0], ctx);
+                let func = eval_ref(&args[1], ctx);
 
-                match args[0].clone() {
+                match super::structs::to_iter(list, ctx) {
                     Expr::List(v) => {
                         let mut res = Vec::with_capacity(v.len());
                         for item in v {
                             // Call function
                             let mut call_args = vec![item];
-                            let val = call_fn(&args[1], &mut call_args, ctx);
+                            let val = call_fn(&func, &mut call_args, ctx);
                             res.push(val);
                         }
-                        Expr::List(res)
+                        Expr::List(res.into())
                     }
                     other => crate::stop!("map expected List, got {:?}", other),
                 }
@@ -338,13 +337,14 @@ pub fn register(ctx: &mut Context) {
 
     col_exports.insert(
         Expr::sym("filter"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     crate::stop!("filter requires 2 arguments (list, function)");
                 }
-                let list = crate::context::eval(args[0].clone(), ctx);
-                let func = crate::context::eval(args[1].clone(), ctx);
+                let list = crate::context::eval_ref(&args[0], ctx);
+                let list = super::structs::to_iter(list, ctx);
+                let func = crate::context::eval_ref(&args[1], ctx);
                 match list {
                     Expr::List(v) => {
                         let mut res = Vec::new();
@@ -356,7 +356,7 @@ pub fn register(ctx: &mut Context) {
                                 res.push(item);
                             }
                         }
-                        Expr::List(res)
+                        Expr::List(res.into())
                     }
                     other => crate::stop!("filter expected List, got {:?}", other),
                 }
@@ -368,14 +368,15 @@ pub fn register(ctx: &mut Context) {
 
     col_exports.insert(
         Expr::sym("fold"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 3 {
                     crate::stop!("fold requires 3 arguments (list, init, function)");
                 }
-                let list = crate::context::eval(args[0].clone(), ctx);
-                let mut acc = crate::context::eval(args[1].clone(), ctx);
-                let func = crate::context::eval(args[2].clone(), ctx);
+                let list = crate::context::eval_ref(&args[0], ctx);
+                let list = super::structs::to_iter(list, ctx);
+                let mut acc = crate::context::eval_ref(&args[1], ctx);
+                let func = crate::context::eval_ref(&args[2], ctx);
 
                 match list {
                     Expr::List(v) => {
@@ -395,13 +396,14 @@ pub fn register(ctx: &mut Context) {
 
     col_exports.insert(
         Expr::sym("find"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     crate::stop!("find requires 2 arguments (list, function)");
                 }
-                let list = crate::context::eval(args[0].clone(), ctx);
-                let func = crate::context::eval(args[1].clone(), ctx);
+                let list = crate::context::eval_ref(&args[0], ctx);
+                let list = super::structs::to_iter(list, ctx);
+                let func = crate::context::eval_ref(&args[1], ctx);
 
                 match list {
                     Expr::List(v) => {
@@ -424,13 +426,14 @@ pub fn register(ctx: &mut Context) {
 
     col_exports.insert(
         Expr::sym("any"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     crate::stop!("any requires 2 arguments (list, function)");
                 }
-                let list = crate::context::eval(args[0].clone(), ctx);
-                let func = crate::context::eval(args[1].clone(), ctx);
+                let list = crate::context::eval_ref(&args[0], ctx);
+                let list = super::structs::to_iter(list, ctx);
+                let func = crate::context::eval_ref(&args[1], ctx);
 
                 match list {
                     Expr::List(v) => {
@@ -453,13 +456,14 @@ pub fn register(ctx: &mut Context) {
 
     col_exports.insert(
         Expr::sym("all"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     crate::stop!("all requires 2 arguments (list, function)");
                 }
-                let list = crate::context::eval(args[0].clone(), ctx);
-                let func = crate::context::eval(args[1].clone(), ctx);
+                let list = crate::context::eval_ref(&args[0], ctx);
+                let list = super::structs::to_iter(list, ctx);
+                let func = crate::context::eval_ref(&args[1], ctx);
 
                 match list {
                     Expr::List(v) => {
@@ -483,13 +487,13 @@ pub fn register(ctx: &mut Context) {
     // Access
     col_exports.insert(
         Expr::sym("get"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     crate::stop!("get requires 2 arguments (collection, key/index)");
                 }
-                let col = crate::context::eval(args[0].clone(), ctx);
-                let key = crate::context::eval(args[1].clone(), ctx);
+                let col = crate::context::eval_ref(&args[0], ctx);
+                let key = crate::context::eval_ref(&args[1], ctx);
 
                 match (col, key) {
                     (Expr::List(v), Expr::Int(i)) => {
@@ -534,20 +538,20 @@ pub fn register(ctx: &mut Context) {
 
     col_exports.insert(
         Expr::sym("enumerate"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 1 {
                     crate::stop!("enumerate requires 1 argument (list)");
                 }
-                let col = crate::context::eval(args[0].clone(), ctx);
+                let col = crate::context::eval_ref(&args[0], ctx);
 
-                match col {
+                match super::structs::to_iter(col, ctx) {
                     Expr::List(v) => {
                         let mut result = Vec::new();
                         for (i, item) in v.iter().enumerate() {
-                            result.push(Expr::List(vec![Expr::Int(i as i64), item.clone()]));
+                            result.push(Expr::List(vector![Expr::Int(i as i64), item.clone()]));
                         }
-                        Expr::List(result)
+                        Expr::List(result.into())
                     }
                     other => crate::stop!("enumerate expected List, got {:?}", other),
                 }
@@ -557,26 +561,21 @@ pub fn register(ctx: &mut Context) {
         ),
     );
 
-    let mod_val = Expr::Ref(Arc::new(RwLock::new(Expr::Map(col_exports))));
-    ctx.define(Expr::sym("Collections"), mod_val);
+    let mod_val = Expr::Ref(Arc::new(RwLock::new(Expr::Map(col_exports.into()))));
+    ctx.define("Collections", mod_val);
 }
 
 fn eval_first(args: &[Expr], ctx: &mut Context) -> Expr {
     if args.len() != 1 {
         crate::stop!("Expected exactly 1 argument, got {}", args.len());
     } else {
-        crate::context::eval(args[0].clone(), ctx)
+        crate::context::eval_ref(&args[0], ctx)
     }
 }
 
 fn call_fn(func: &Expr, args: &mut [Expr], ctx: &mut Context) -> Expr {
     match func {
-        Expr::Function {
-            params: _,
-            body: _,
-            env: _,
-            name: _,
-        } => {
+        Expr::Function { .. } | Expr::Keyword(_) => {
             // let mut call_list = Vec::new();
             // call_list.push(func.clone());
             // // for arg in args {
@@ -0,0 +1,128 @@
+use crate::context::{Context, eval_ref};
+use crate::expr::Expr;
+use crate::symbol::Symbol;
+use imbl::vector;
+
+/// Type that accepts every value, the same as leaving a name unannotated.
+pub const ANY: &str = "any";
+
+/// Split a parameter list such as `(attacker: Unit amount)` into names and their
+/// annotated types. Returns `None` if the list is malformed.
+pub fn parse_params<'a>(
+    list: impl IntoIterator<Item = &'a Expr>,
+) -> Option<(Vec<Symbol>, Vec<Option<Symbol>>)> {
+    let mut names = Vec::new();
+    let mut types = Vec::new();
+    let mut items = list.into_iter();
+    while let Some(item) = items.next() {
+        let name = match item {
+            Expr::Sym(s) => s,
+            _ => return None,
+        };
+        match name.as_str().strip_suffix(':') {
+            Some(stripped) => match items.next() {
+                Some(Expr::Sym(ty)) => {
+                    names.push(Symbol::new(stripped));
+                    types.push(Some(ty.clone()).filter(|t| t.as_str() != ANY));
+                }
+                _ => return None,
+            },
+            None => {
+                names.push(name.clone());
+                types.push(None);
+            }
+        }
+    }
+    Some((names, types))
+}
+
+/// Split an optional `-> Type` off the front of a function body.
+pub fn parse_return(body: &[Expr]) -> (Option<Symbol>, &[Expr]) {
+    match body {
+        [Expr::Sym(arrow), Expr::Sym(ty), rest @ ..] if arrow.as_str() == "->" => {
+            (Some(ty.clone()).filter(|t| t.as_str() != ANY), rest)
+        }
+        _ => (None, body),
+    }
+}
+
+/// Whether `val` has the type named `ty`: its `Type.of` name, its struct or one of its
+/// parents and interfaces, or its tag.
+pub fn has_type(val: &Expr, ty: &Symbol, ctx: &Context) -> bool {
+    ty.as_str() == ANY || super::generic::dispatch_types(val, ctx).contains(ty)
+}
+
+/// Describe a value for an error message, e.g. `int (5)`.
+pub fn describe(val: &Expr) -> String {
+    format!("{} ({})", super::reflect::type_of(val), val)
+}
+
+/// Wrap a function body so its annotated parameters are checked on entry and its result
+/// on return. Bodies without annotations are returned unchanged.
+pub fn wrap_body(
+    name: Option<&Symbol>,
+    params: &[Symbol],
+    types: &[Option<Symbol>],
+    ret: Option<Symbol>,
+    body: Expr,
+) -> Expr {
+    if ret.is_none() && types.iter().all(Option::is_none) {
+        return body;
+    }
+    let checks = params
+        .iter()
+        .zip(types)
+        .filter_map(|(p, t)| {
+            t.as_ref()
+                .map(|t| Expr::List(vector![Expr::Sym(p.clone()), Expr::Sym(t.clone())]))
+        })
+        .collect();
+    Expr::List(vector![
+        Expr::extern_ref(check_contract, "contract", "Check a function's annotations").special(),
+        Expr::Sym(name.cloned().unwrap_or_else(|| Symbol::new("fun"))),
+        Expr::List(checks),
+        ret.map(Expr::Sym).unwrap_or(Expr::Nil),
+        body,
+    ])
+}
+
+/// Runs inside the function's own scope: `(contract name ((param Type)...) ret body)`.
+fn check_contract(args: &[Expr], ctx: &mut Context) -> Expr {
+    let (name, checks, ret, body) = match args {
+        [Expr::Sym(name), Expr::List(checks), ret, body] => (name, checks, ret, body),
+        _ => crate::stop!("Malformed contract {:?}", args),
+    };
+    if !ctx.settings.contracts() {
+        return eval_ref(body, ctx);
+    }
+
+    for check in checks.iter() {
+        if let Expr::List(pair) = check
+            && let [Expr::Sym(param), Expr::Sym(ty)] = pair.iter().collect::<Vec<_>>()[..]
+        {
+            let val = ctx.resolve(param).unwrap_or(Expr::Nil);
+            if !has_type(&val, ty, ctx) {
+                crate::stop!(
+                    "{}: parameter {} expects {}, got {}",
+                    name,
+                    param,
+                    ty,
+                    describe(&val)
+                );
+            }
+        }
+    }
+
+    let result = eval_ref(body, ctx);
+    if let Expr::Sym(ty) = ret
+        && !has_type(&result, ty, ctx)
+    {
+        crate::stop!(
+            "{}: expected to return {}, got {}",
+            name,
+            ty,
+            describe(&result)
+        );
+    }
+    result
+}
@@ -0,0 +1,409 @@
+//! The `FFI` module: calling functions in C libraries without writing a plugin.
+//!
+//! ```text
+//! (def libc (FFI.open "libc.so.6"))
+//! (def strlen (FFI.fn libc "strlen" (list :string) :size))
+//! (strlen "onion") ; 5
+//! ```
+//!
+//! Argument and return types are keywords: the C integer types (`:char`, `:short`, `:int`,
+//! `:long`, `:longlong` and their `:u` versions, `:size`, `:ssize`, or `:i8` to `:u64`),
+//! `:float`, `:double`, `:pointer` (an address, as an int), `:string` (a NUL-terminated copy
+//! of a string, or the string a `char *` result points to) and `:bytes` (a pointer to a copy
+//! of a byte string). `:void` is for functions that return nothing.
+//!
+//! Buffers the C code writes into are made with `FFI.alloc` and read back with `FFI.read`.
+//! Nothing checks that a declaration matches the C function, so a wrong one can crash the
+//! process; sandboxed interpreters need the `native` capability to use this module.
+
+use crate::context::Context;
+use crate::convert::IntoExpr;
+use crate::error::MutexExt;
+use crate::expr::Expr;
+use crate::native::onion_fn;
+use crate::stdlib::capabilities::Capabilities;
+use crate::symbol::Symbol;
+use libffi::middle::{Arg, Cif, CodePtr, Type};
+use std::ffi::{CStr, CString, c_char, c_int, c_long, c_longlong, c_short, c_void};
+use std::sync::Arc;
+
+/// The C libraries an interpreter has opened, indexed by handle. Functions declared from a
+/// library hold on to it, so it is closed once they and the interpreter are gone.
+#[derive(Debug, Default)]
+pub struct Libraries(Vec<Arc<libloading::Library>>);
+
+pub fn register(ctx: &mut Context) {
+    open_native().register(ctx);
+    declare_native().register(ctx);
+    alloc_native().register(ctx);
+    free_native().register(ctx);
+    read_native().register(ctx);
+    write_native().register(ctx);
+    string_native().register(ctx);
+}
+
+/// A C type, as named in a declaration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CType {
+    Void,
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    I64,
+    U64,
+    F32,
+    F64,
+    Pointer,
+    String,
+    Bytes,
+}
+
+impl CType {
+    fn parse(name: Symbol) -> CType {
+        // The C names follow the sizes of the platform the interpreter was built for
+        let int = |size: usize, signed: bool| match (size, signed) {
+            (1, true) => CType::I8,
+            (1, false) => CType::U8,
+            (2, true) => CType::I16,
+            (2, false) => CType::U16,
+            (4, true) => CType::I32,
+            (4, false) => CType::U32,
+            (_, true) => CType::I64,
+            (_, false) => CType::U64,
+        };
+        use std::mem::size_of;
+        match name.as_str() {
+            "void" => CType::Void,
+            "i8" => CType::I8,
+            "u8" => CType::U8,
+            "i16" => CType::I16,
+            "u16" => CType::U16,
+            "i32" => CType::I32,
+            "u32" => CType::U32,
+            "i64" => CType::I64,
+            "u64" => CType::U64,
+            "char" => int(size_of::<c_char>(), true),
+            "uchar" => int(size_of::<c_char>(), false),
+            "short" => int(size_of::<c_short>(), true),
+            "ushort" => int(size_of::<c_short>(), false),
+            "int" => int(size_of::<c_int>(), true),
+            "uint" => int(size_of::<c_int>(), false),
+            "long" => int(size_of::<c_long>(), true),
+            "ulong" => int(size_of::<c_long>(), false),
+            "longlong" => int(size_of::<c_longlong>(), true),
+            "ulonglong" => int(size_of::<c_longlong>(), false),
+            "size" => int(size_of::<usize>(), false),
+            "ssize" => int(size_of::<isize>(), true),
+            "float" => CType::F32,
+            "double" => CType::F64,
+            "pointer" => CType::Pointer,
+            "string" => CType::String,
+            "bytes" => CType::Bytes,
+            other => crate::stop!("FFI: unknown C type :{}", other),
+        }
+    }
+
+    fn ffi_type(self) -> Type {
+        match self {
+            CType::Void => Type::void(),
+            CType::I8 => Type::i8(),
+            CType::U8 => Type::u8(),
+            CType::I16 => Type::i16(),
+            CType::U16 => Type::u16(),
+            CType::I32 => Type::i32(),
+            CType::U32 => Type::u32(),
+            CType::I64 => Type::i64(),
+            CType::U64 => Type::u64(),
+            CType::F32 => Type::f32(),
+            CType::F64 => Type::f64(),
+            CType::Pointer | CType::String | CType::Bytes => Type::pointer(),
+        }
+    }
+}
+
+/// An argument converted for C, holding what its pointer points to.
+enum CValue {
+    I8(i8),
+    U8(u8),
+    I16(i16),
+    U16(u16),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    Pointer(*const c_void),
+}
+
+impl CValue {
+    fn arg(&self) -> Arg {
+        match self {
+            CValue::I8(v) => Arg::new(v),
+            CValue::U8(v) => Arg::new(v),
+            CValue::I16(v) => Arg::new(v),
+            CValue::U16(v) => Arg::new(v),
+            CValue::I32(v) => Arg::new(v),
+            CValue::U32(v) => Arg::new(v),
+            CValue::I64(v) => Arg::new(v),
+            CValue::U64(v) => Arg::new(v),
+            CValue::F32(v) => Arg::new(v),
+            CValue::F64(v) => Arg::new(v),
+            CValue::Pointer(v) => Arg::new(v),
+        }
+    }
+}
+
+/// Convert argument `index` of `name` to `ty`. Strings and byte strings are copied into
+/// `buffers`, which must outlive the call.
+fn to_c(name: &str, index: usize, ty: CType, val: &Expr, buffers: &mut Vec<Vec<u8>>) -> CValue {
+    let int = || -> i128 {
+        match val {
+            Expr::Int(n) => *n as i128,
+            Expr::BigInt(n) => num_traits::ToPrimitive::to_i128(n).unwrap_or(i128::MAX),
+            _ => crate::stop!(
+                "{}: argument {} should be an int, got {}",
+                name,
+                index + 1,
+                val
+            ),
+        }
+    };
+    macro_rules! fit {
+        ($variant:ident, $ty:ty) => {
+            match <$ty>::try_from(int()) {
+                Ok(n) => CValue::$variant(n),
+                Err(_) => crate::stop!(
+                    "{}: argument {} doesn't fit in {}: {}",
+                    name,
+                    index + 1,
+                    stringify!($ty),
+                    val
+                ),
+            }
+        };
+    }
+    let float = || match val {
+        Expr::Float(f) => *f,
+        Expr::Int(n) => *n as f64,
+        _ => crate::stop!(
+            "{}: argument {} should be a number, got {}",
+            name,
+            index + 1,
+            val
+        ),
+    };
+    let mut buffer = |bytes: Vec<u8>| {
+        buffers.push(bytes);
+        CValue::Pointer(buffers.last().unwrap().as_ptr().cast())
+    };
+    match (ty, val) {
+        (CType::I8, _) => fit!(I8, i8),
+        (CType::U8, _) => fit!(U8, u8),
+        (CType::I16, _) => fit!(I16, i16),
+        (CType::U16, _) => fit!(U16, u16),
+        (CType::I32, _) => fit!(I32, i32),
+        (CType::U32, _) => fit!(U32, u32),
+        (CType::I64, _) => fit!(I64, i64),
+        (CType::U64, _) => fit!(U64, u64),
+        (CType::F32, _) => CValue::F32(float() as f32),
+        (CType::F64, _) => CValue::F64(float()),
+        (CType::Pointer | CType::String | CType::Bytes, Expr::Nil) => {
+            CValue::Pointer(std::ptr::null())
+        }
+        (CType::Pointer, _) => match usize::try_from(int()) {
+            Ok(address) => CValue::Pointer(address as *const c_void),
+            Err(_) => crate::stop!(
+                "{}: argument {} is not an address: {}",
+                name,
+                index + 1,
+                val
+            ),
+        },
+        (CType::String, Expr::Str(s)) => match CString::new(s.as_str()) {
+            Ok(s) => buffer(s.into_bytes_with_nul()),
+            Err(_) => crate::stop!("{}: argument {} contains a NUL byte", name, index + 1),
+        },
+        (CType::Bytes, Expr::Bytes(b)) => buffer(b.clone()),
+        (CType::String | CType::Bytes, _) => crate::stop!(
+            "{}: argument {} should be {}, got {}",
+            name,
+            index + 1,
+            if ty == CType::String {
+                "a string"
+            } else {
+                "bytes"
+            },
+            val
+        ),
+        (CType::Void, _) => crate::stop!("{}: an argument can't be :void", name),
+    }
+}
+
+/// Call the C function at `code`, declared as taking `params` and returning `ret`.
+fn call(name: &str, code: usize, params: &[CType], ret: CType, args: Vec<Expr>) -> Expr {
+    if args.len() != params.len() {
+        crate::stop!(
+            "{} expects {} arguments, got {}",
+            name,
+            params.len(),
+            args.len()
+        );
+    }
+    let mut buffers = Vec::new();
+    let values: Vec<CValue> = params
+        .iter()
+        .zip(&args)
+        .enumerate()
+        .map(|(i, (ty, val))| to_c(name, i, *ty, val, &mut buffers))
+        .collect();
+    let c_args: Vec<Arg> = values.iter().map(CValue::arg).collect();
+    let cif = Cif::new(params.iter().map(|ty| ty.ffi_type()), ret.ffi_type());
+    let code = CodePtr(code as *mut c_void);
+    unsafe {
+        match ret {
+            CType::Void => {
+                cif.call::<()>(code, &c_args);
+                Expr::Nil
+            }
+            CType::I8 => cif.call::<i8>(code, &c_args).into_expr(),
+            CType::U8 => cif.call::<u8>(code, &c_args).into_expr(),
+            CType::I16 => cif.call::<i16>(code, &c_args).into_expr(),
+            CType::U16 => cif.call::<u16>(code, &c_args).into_expr(),
+            CType::I32 => cif.call::<i32>(code, &c_args).into_expr(),
+            CType::U32 => cif.call::<u32>(code, &c_args).into_expr(),
+            CType::I64 => cif.call::<i64>(code, &c_args).into_expr(),
+            CType::U64 => cif.call::<u64>(code, &c_args).into_expr(),
+            CType::F32 => cif.call::<f32>(code, &c_args).into_expr(),
+            CType::F64 => cif.call::<f64>(code, &c_args).into_expr(),
+            CType::Pointer => (cif.call::<*const c_void>(code, &c_args) as usize).into_expr(),
+            CType::String => c_string(cif.call::<*const c_char>(code, &c_args)),
+            CType::Bytes => crate::stop!("{}: use :pointer to return bytes", name),
+        }
+    }
+}
+
+/// A copy of the C string at `ptr`, or nil for a null pointer.
+unsafe fn c_string(ptr: *const c_char) -> Expr {
+    if ptr.is_null() {
+        return Expr::Nil;
+    }
+    Expr::Str(
+        unsafe { CStr::from_ptr(ptr) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+/// Open a C library by path or name and return its handle. (FFI.open "libm.so.6")
+#[onion_fn(module = "FFI")]
+fn open(path: String, ctx: &mut Context) -> Result<i64, String> {
+    Capabilities::require(ctx.caps.native, "FFI.open");
+    let path = ctx.caps.read_path("FFI.open", &path);
+    let lib = unsafe { libloading::Library::new(&path) }
+        .map_err(|e| format!("can't open {}: {}", path.display(), e))?;
+    let mut libraries = ctx.libraries.lock_unpoisoned();
+    libraries.0.push(Arc::new(lib));
+    Ok(libraries.0.len() as i64 - 1)
+}
+
+/// Declare a C function of an open library. (FFI.fn lib "strlen" (list :string) :size)
+#[onion_fn(module = "FFI", name = "fn")]
+fn declare(
+    lib: usize,
+    name: String,
+    params: Vec<Symbol>,
+    ret: Symbol,
+    ctx: &mut Context,
+) -> Result<Expr, String> {
+    Capabilities::require(ctx.caps.native, "FFI.fn");
+    let params: Vec<CType> = params.into_iter().map(CType::parse).collect();
+    let ret = CType::parse(ret);
+    let library = ctx
+        .libraries
+        .lock_unpoisoned()
+        .0
+        .get(lib)
+        .cloned()
+        .ok_or_else(|| format!("no library with handle {}", lib))?;
+    let code = unsafe { library.get::<*const c_void>(name.as_bytes()) }
+        .map_err(|_| format!("no function {} in the library", name))?;
+    let code = *code as usize;
+    let desc = format!("The C function {}.", name);
+    Ok(Expr::extern_ref(
+        {
+            let name = name.clone();
+            move |args, ctx| {
+                // Keeps the library, and so `code`, loaded while the function exists
+                let _library = &library;
+                let args = args
+                    .iter()
+                    .map(|arg| crate::context::eval_ref(arg, ctx))
+                    .collect();
+                call(&name, code, &params, ret, args)
+            }
+        },
+        name,
+        desc,
+    ))
+}
+
+unsafe extern "C" {
+    fn calloc(count: usize, size: usize) -> *mut c_void;
+    #[link_name = "free"]
+    fn c_free(ptr: *mut c_void);
+}
+
+/// Allocate a zeroed buffer of n bytes for C code to fill, returning its address.
+#[onion_fn(module = "FFI")]
+fn alloc(size: usize, ctx: &mut Context) -> Result<usize, String> {
+    Capabilities::require(ctx.caps.native, "FFI.alloc");
+    ctx.limits.reserve(size, "FFI.alloc");
+    let ptr = unsafe { calloc(size.max(1), 1) };
+    if ptr.is_null() {
+        return Err(format!("can't allocate {} bytes", size));
+    }
+    Ok(ptr as usize)
+}
+
+/// Free a buffer from FFI.alloc, or anything else C allocated with malloc.
+#[onion_fn(module = "FFI")]
+fn free(ptr: usize, ctx: &mut Context) {
+    Capabilities::require(ctx.caps.native, "FFI.free");
+    unsafe { c_free(ptr as *mut c_void) }
+}
+
+/// Copy n bytes at an address into a byte string. (FFI.read ptr n)
+#[onion_fn(module = "FFI")]
+fn read(ptr: usize, len: usize, ctx: &mut Context) -> Result<Expr, String> {
+    Capabilities::require(ctx.caps.native, "FFI.read");
+    if ptr == 0 {
+        return Err("can't read from a null pointer".to_string());
+    }
+    let bytes = unsafe { std::slice::from_raw_parts(ptr as *const u8, len) };
+    Ok(Expr::Bytes(bytes.to_vec()))
+}
+
+/// Copy a byte string to an address. (FFI.write ptr bytes)
+#[onion_fn(module = "FFI")]
+fn write(ptr: usize, bytes: Expr, ctx: &mut Context) -> Result<(), String> {
+    Capabilities::require(ctx.caps.native, "FFI.write");
+    let Expr::Bytes(bytes) = bytes else {
+        return Err(format!("expected bytes, got {}", bytes));
+    };
+    if ptr == 0 {
+        return Err("can't write to a null pointer".to_string());
+    }
+    unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr as *mut u8, bytes.len()) };
+    Ok(())
+}
+
+/// Copy the NUL-terminated string at an address, or nil for a null pointer.
+#[onion_fn(module = "FFI")]
+fn string(ptr: usize, ctx: &mut Context) -> Expr {
+    Capabilities::require(ctx.caps.native, "FFI.string");
+    unsafe { c_string(ptr as *const c_char) }
+}
@@ -1,6 +1,9 @@
 use crate::context::Context;
+use crate::error::RwLockExt;
 use crate::expr::Expr;
+use crate::stdlib::capabilities::Capabilities;
 use crate::stdlib::game;
+use crate::symbol::Symbol;
 use font8x8::{BASIC_FONTS, UnicodeFonts};
 use image::GenericImageView;
 use image::io::Reader as ImageReader;
@@ -11,7 +14,7 @@ use std::io::Cursor;
 use std::sync::{Arc, RwLock};
 use std::time::Instant;
 
-// Wrapper to allow storing Window in a global static RwLock.
+// Wrapper to allow storing Window in the interpreter's shared, locked GameState.
 // SAFETY: We must ensure we only access the window through the RwLock guarantees.
 pub struct WindowWrapper(pub Window);
 unsafe impl Send for WindowWrapper {}
@@ -22,18 +25,19 @@ pub fn register(ctx: &mut Context) {
 
     game_exports.insert(
         Expr::sym("run"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
+                Capabilities::require(ctx.caps.window, "Game.run");
                 if args.len() != 3 {
                     return Expr::Nil;
                 }
-                let width = crate::context::eval(args[0].clone(), ctx)
+                let width = crate::context::eval_ref(&args[0], ctx)
                     .as_int()
                     .unwrap_or(800) as usize;
-                let height = crate::context::eval(args[1].clone(), ctx)
+                let height = crate::context::eval_ref(&args[1], ctx)
                     .as_int()
                     .unwrap_or(600) as usize;
-                let title = crate::context::eval(args[2].clone(), ctx)
+                let title = crate::context::eval_ref(&args[2], ctx)
                     .as_str()
                     .unwrap_or("Onion2D")
                     .to_string();
@@ -63,13 +67,13 @@ pub fn register(ctx: &mut Context) {
 
                 // Move window and handles into Global State
                 {
-                    let mut state = GAME_STATE.write().unwrap();
+                    let mut state = ctx.game.write_unpoisoned();
                     state.resize(width, height);
                     state.audio_handle = stream_handle.clone();
                     state.window = Some(WindowWrapper(window));
                 }
 
-                if let Some(load_fn) = ctx.resolve(&Expr::sym("load")) {
+                if let Some(load_fn) = ctx.resolve(&Symbol::LOAD) {
                     let call_args = vec![];
                     crate::stdlib::call_anon_fn(&load_fn, &call_args, ctx);
                 }
@@ -86,7 +90,7 @@ pub fn register(ctx: &mut Context) {
                     // 1. Update Input State (requires Lock)
                     // We do this in a block so we drop the lock before running user scripts
                     {
-                        let mut state = GAME_STATE.write().unwrap();
+                        let mut state = ctx.game.write_unpoisoned();
 
                         // Check if window is still open or Escape pressed
                         if let Some(wrapper) = &state.window {
@@ -103,18 +107,18 @@ pub fn register(ctx: &mut Context) {
                     }
 
                     // 2. Run User Scripts (No Lock Held)
-                    if let Some(update_fn) = ctx.resolve(&Expr::sym("update")) {
+                    if let Some(update_fn) = ctx.resolve(&Symbol::UPDATE) {
                         let call_args = vec![Expr::Float(dt_secs)];
                         crate::stdlib::call_anon_fn(&update_fn, &call_args, ctx);
                     }
 
-                    if let Some(draw_fn) = ctx.resolve(&Expr::sym("draw")) {
+                    if let Some(draw_fn) = ctx.resolve(&Symbol::DRAW) {
                         let call_args = vec![];
                         crate::stdlib::call_anon_fn(&draw_fn, &call_args, ctx);
                     }
 
                     {
-                        let mut state = GAME_STATE.write().unwrap();
+                        let mut state = ctx.game.write_unpoisoned();
 
                         let GameState {
                             window,
@@ -138,7 +142,7 @@ pub fn register(ctx: &mut Context) {
 
                 // Cleanup: Optionally remove window from state when done
                 {
-                    let mut state = GAME_STATE.write().unwrap();
+                    let mut state = ctx.game.write_unpoisoned();
                     state.window = None;
                 }
 
@@ -155,15 +159,15 @@ pub fn register(ctx: &mut Context) {
     // because we updated the GameState struct, the existing extern_fun works fine.
     game_exports.insert(
         Expr::sym("clear"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 1 {
                     return Expr::Nil;
                 }
-                let color = crate::context::eval(args[0].clone(), ctx)
+                let color = crate::context::eval_ref(&args[0], ctx)
                     .as_int()
                     .unwrap_or(0) as u32;
-                let mut state = GAME_STATE.write().unwrap();
+                let mut state = ctx.game.write_unpoisoned();
                 for p in state.buffer.iter_mut() {
                     *p = color;
                 }
@@ -178,9 +182,9 @@ pub fn register(ctx: &mut Context) {
     // because we updated the GameState struct, the existing extern_fun works fine.
     game_exports.insert(
         Expr::sym("present"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
-                let mut state = GAME_STATE.write().unwrap();
+                let mut state = ctx.game.write_unpoisoned();
                 let GameState {
                     window,
                     buffer,
@@ -207,29 +211,29 @@ pub fn register(ctx: &mut Context) {
 
     game_exports.insert(
         Expr::sym("rect"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 // x, y, w, h, color
                 if args.len() != 5 {
                     return Expr::Nil;
                 }
-                let x = crate::context::eval(args[0].clone(), ctx)
+                let x = crate::context::eval_ref(&args[0], ctx)
                     .as_int()
                     .unwrap_or(0) as i64;
-                let y = crate::context::eval(args[1].clone(), ctx)
+                let y = crate::context::eval_ref(&args[1], ctx)
                     .as_int()
                     .unwrap_or(0) as i64;
-                let w = crate::context::eval(args[2].clone(), ctx)
+                let w = crate::context::eval_ref(&args[2], ctx)
                     .as_int()
                     .unwrap_or(0) as i64;
-                let h = crate::context::eval(args[3].clone(), ctx)
+                let h = crate::context::eval_ref(&args[3], ctx)
                     .as_int()
                     .unwrap_or(0) as i64;
-                let color = crate::context::eval(args[4].clone(), ctx)
+                let color = crate::context::eval_ref(&args[4], ctx)
                     .as_int()
                     .unwrap_or(0xFFFFFF) as u32;
 
-                let mut state = GAME_STATE.write().unwrap();
+                let mut state = ctx.game.write_unpoisoned();
                 state.draw_rect(x, y, w, h, color);
                 Expr::Nil
             },
@@ -240,16 +244,16 @@ pub fn register(ctx: &mut Context) {
 
     game_exports.insert(
         Expr::sym("is_key_down"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 1 {
                     return Expr::Nil;
                 }
-                let key_str = crate::context::eval(args[0].clone(), ctx)
+                let key_str = crate::context::eval_ref(&args[0], ctx)
                     .as_str()
                     .unwrap_or("")
                     .to_string();
-                let state = GAME_STATE.read().unwrap();
+                let state = ctx.game.read_unpoisoned();
                 if state.is_key_down(&key_str) {
                     Expr::Int(1)
                 } else {
@@ -263,9 +267,9 @@ pub fn register(ctx: &mut Context) {
 
     game_exports.insert(
         Expr::sym("width"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
-                let state = GAME_STATE.read().unwrap();
+                let state = ctx.game.read_unpoisoned();
                 Expr::Int(state.width as i64)
             },
             "width",
@@ -275,9 +279,9 @@ pub fn register(ctx: &mut Context) {
 
     game_exports.insert(
         Expr::sym("height"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
-                let state = GAME_STATE.read().unwrap();
+                let state = ctx.game.read_unpoisoned();
                 Expr::Int(state.height as i64)
             },
             "height",
@@ -290,17 +294,20 @@ pub fn register(ctx: &mut Context) {
     // RE-INSERTING the rest for completeness of the file structure
     game_exports.insert(
         Expr::sym("load_image"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 1 {
                     return Expr::Nil;
                 }
-                let path = crate::context::eval(args[0].clone(), ctx)
+                let path = crate::context::eval_ref(&args[0], ctx)
                     .as_str()
                     .unwrap_or("")
                     .to_string();
 
-                let img = match ImageReader::open(&path) {
+                let data = crate::vfs::read(ctx, "Game.load_image", &path);
+                let reader =
+                    data.and_then(|d| ImageReader::new(Cursor::new(d)).with_guessed_format());
+                let img = match reader {
                     Ok(reader) => match reader.decode() {
                         Ok(i) => i,
                         Err(e) => {
@@ -327,7 +334,7 @@ pub fn register(ctx: &mut Context) {
                     pixels.push(color);
                 }
 
-                let mut state = GAME_STATE.write().unwrap();
+                let mut state = ctx.game.write_unpoisoned();
                 let id = state.next_id;
                 state.next_id += 1;
                 state.images.insert(
@@ -347,22 +354,22 @@ pub fn register(ctx: &mut Context) {
 
     game_exports.insert(
         Expr::sym("draw_image"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 3 {
                     return Expr::Nil;
                 }
-                let id = crate::context::eval(args[0].clone(), ctx)
+                let id = crate::context::eval_ref(&args[0], ctx)
                     .as_int()
                     .unwrap_or(0) as usize;
-                let x = crate::context::eval(args[1].clone(), ctx)
+                let x = crate::context::eval_ref(&args[1], ctx)
                     .as_int()
                     .unwrap_or(0) as i64;
-                let y = crate::context::eval(args[2].clone(), ctx)
+                let y = crate::context::eval_ref(&args[2], ctx)
                     .as_int()
                     .unwrap_or(0) as i64;
 
-                let mut state = GAME_STATE.write().unwrap();
+                let mut state = ctx.game.write_unpoisoned();
                 state.draw_image(id, x, y);
                 Expr::Nil
             },
@@ -373,34 +380,34 @@ pub fn register(ctx: &mut Context) {
 
     game_exports.insert(
         Expr::sym("draw_text"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() < 4 || args.len() > 5 {
                     return Expr::Nil;
                 }
-                let x = crate::context::eval(args[0].clone(), ctx)
+                let x = crate::context::eval_ref(&args[0], ctx)
                     .as_int()
                     .unwrap_or(0) as i64;
-                let y = crate::context::eval(args[1].clone(), ctx)
+                let y = crate::context::eval_ref(&args[1], ctx)
                     .as_int()
                     .unwrap_or(0) as i64;
-                let text = crate::context::eval(args[2].clone(), ctx)
+                let text = crate::context::eval_ref(&args[2], ctx)
                     .as_str()
                     .unwrap_or("")
                     .to_string();
-                let color = crate::context::eval(args[3].clone(), ctx)
+                let color = crate::context::eval_ref(&args[3], ctx)
                     .as_int()
                     .unwrap_or(0xFFFFFF) as u32;
 
                 let scale = if args.len() == 5 {
-                    crate::context::eval(args[4].clone(), ctx)
+                    crate::context::eval_ref(&args[4], ctx)
                         .as_int()
                         .unwrap_or(2) as i64
                 } else {
                     2
                 };
 
-                let mut state = GAME_STATE.write().unwrap();
+                let mut state = ctx.game.write_unpoisoned();
                 state.draw_text(x, y, &text, color, scale);
                 Expr::Nil
             },
@@ -411,17 +418,17 @@ pub fn register(ctx: &mut Context) {
 
     game_exports.insert(
         Expr::sym("load_sound"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 1 {
                     return Expr::Nil;
                 }
-                let path = crate::context::eval(args[0].clone(), ctx)
+                let path = crate::context::eval_ref(&args[0], ctx)
                     .as_str()
                     .unwrap_or("")
                     .to_string();
 
-                let data = match std::fs::read(&path) {
+                let data = match crate::vfs::read(ctx, "Game.load_sound", &path) {
                     Ok(d) => d,
                     Err(e) => {
                         println!("Failed to load sound {}: {:?}", path, e);
@@ -429,7 +436,7 @@ pub fn register(ctx: &mut Context) {
                     }
                 };
 
-                let mut state = GAME_STATE.write().unwrap();
+                let mut state = ctx.game.write_unpoisoned();
                 let id = state.next_id;
                 state.next_id += 1;
                 state.sounds.insert(id, GameSound { data });
@@ -442,16 +449,17 @@ pub fn register(ctx: &mut Context) {
 
     game_exports.insert(
         Expr::sym("play_sound"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 1 {
                     return Expr::Nil;
                 }
-                let id = crate::context::eval(args[0].clone(), ctx)
+                Capabilities::require(ctx.caps.window, "Game.play_sound");
+                let id = crate::context::eval_ref(&args[0], ctx)
                     .as_int()
                     .unwrap_or(0) as usize;
 
-                let state = GAME_STATE.read().unwrap();
+                let state = ctx.game.read_unpoisoned();
                 if let Some(sound) = state.sounds.get(&id) {
                     if let Some(handle) = &state.audio_handle {
                         let cursor = Cursor::new(sound.data.clone());
@@ -475,12 +483,8 @@ pub fn register(ctx: &mut Context) {
 
     super::battle::register_sim_commands(&mut game_exports);
 
-    let mod_val = Expr::Ref(Arc::new(RwLock::new(Expr::Map(game_exports))));
-    ctx.define(Expr::sym("Game"), mod_val);
-}
-
-lazy_static::lazy_static! {
-    pub static ref GAME_STATE: RwLock<GameState> = RwLock::new(GameState::new());
+    let mod_val = Expr::Ref(Arc::new(RwLock::new(Expr::Map(game_exports.into()))));
+    ctx.define("Game", mod_val);
 }
 
 struct GameImage {
@@ -501,13 +505,30 @@ pub struct GameState {
     images: BTreeMap<usize, GameImage>,
     sounds: BTreeMap<usize, GameSound>,
     audio_handle: Option<OutputStreamHandle>,
-    // Stored globally now
     pub window: Option<WindowWrapper>,
     next_id: usize,
 }
 
+impl Default for GameState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for GameState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GameState")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("images", &self.images.len())
+            .field("sounds", &self.sounds.len())
+            .field("window", &self.window.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
 impl GameState {
-    fn new() -> Self {
+    pub fn new() -> Self {
         Self {
             buffer: vec![],
             width: 0,
@@ -0,0 +1,249 @@
+use super::contracts::ANY;
+use crate::context::{Context, GenericDef, MethodDef, TypeDef, eval_ref};
+use crate::error::RwLockExt;
+use crate::expr::Expr;
+use crate::symbol::Symbol;
+use std::sync::{Arc, RwLock};
+
+pub fn register(ctx: &mut Context) {
+    ctx.define(
+        "defgeneric",
+        Expr::extern_ref(
+            |args, ctx| {
+                if args.len() != 2 {
+                    crate::stop!("defgeneric requires a name and a parameter list");
+                }
+                let name = match &args[0] {
+                    Expr::Sym(s) => s.clone(),
+                    other => crate::stop!("Generic function name must be a symbol, got {:?}", other),
+                };
+                let params = match &args[1] {
+                    Expr::List(l) => l
+                        .iter()
+                        .map(|p| match p {
+                            Expr::Sym(s) => s.clone(),
+                            other => crate::stop!(
+                                "Parameter of generic {} must be a symbol, got {:?}",
+                                name,
+                                other
+                            ),
+                        })
+                        .collect(),
+                    Expr::Nil => vec![],
+                    _ => crate::stop!("Generic parameters must be a list of symbols"),
+                };
+
+                let def = Arc::new(GenericDef {
+                    name: name.clone(),
+                    params,
+                    methods: RwLock::new(Vec::new()),
+                });
+                ctx.define_type(name.clone(), TypeDef::Generic(def.clone()));
+
+                let dispatcher = Expr::extern_ref(
+                    move |args, ctx| {
+                        let vals: Vec<Expr> =
+                            args.iter().map(|a| eval_ref(a, ctx)).collect();
+                        let method = select_method(&def, &vals, ctx);
+                        super::call_anon_fn(&method, &vals, ctx)
+                    },
+                    name.to_string(),
+                    "Generic function",
+                );
+                ctx.define(name, dispatcher);
+                Expr::Nil
+            },
+            "defgeneric",
+            "Declare a function that dispatches on the types of its arguments. (defgeneric name (params))",
+        )
+        .special(),
+    );
+
+    ctx.define(
+        "defmethod",
+        Expr::extern_ref(
+            define_method,
+            "defmethod",
+            "Add a method to a generic function. (defmethod name ((param Type) param...) body)",
+        )
+        .special(),
+    );
+}
+
+fn define_method(args: &[Expr], ctx: &mut Context) -> Expr {
+    if args.len() < 3 {
+        crate::stop!("defmethod requires a name, parameters, and a body");
+    }
+    let name = match &args[0] {
+        Expr::Sym(s) => s.clone(),
+        other => crate::stop!("Generic function name must be a symbol, got {:?}", other),
+    };
+    let def = match ctx.get_generic(&name) {
+        Some(def) => def,
+        None => crate::stop!(
+            "defmethod: {} is not a generic function, declare it with defgeneric first",
+            name
+        ),
+    };
+
+    let param_list = match &args[1] {
+        Expr::List(l) => l.clone(),
+        Expr::Nil => imbl::Vector::new(),
+        _ => crate::stop!("Method parameters must be a list"),
+    };
+    if param_list.len() != def.params.len() {
+        crate::stop!(
+            "Method of {} takes {} parameters, but the generic takes {} {:?}",
+            name,
+            param_list.len(),
+            def.params.len(),
+            def.params
+        );
+    }
+
+    let mut params = Vec::new();
+    let mut specializers = Vec::new();
+    for p in &param_list {
+        match p {
+            Expr::Sym(s) => {
+                params.push(s.clone());
+                specializers.push(None);
+            }
+            Expr::List(l) if l.len() == 2 && l.iter().all(|e| matches!(e, Expr::Sym(_))) => {
+                let (Expr::Sym(param), Expr::Sym(ty)) = (&l[0], &l[1]) else {
+                    unreachable!()
+                };
+                params.push(param.clone());
+                specializers.push(if ty.as_str() == ANY {
+                    None
+                } else {
+                    Some(ty.clone())
+                });
+            }
+            other => crate::stop!(
+                "Method parameter must be a symbol or (name Type), got {:?}",
+                other
+            ),
+        }
+    }
+
+    let body = if args.len() == 3 {
+        args[2].clone()
+    } else {
+        let mut do_block = vec![Expr::Sym(Symbol::DO)];
+        do_block.extend(args[2..].iter().cloned());
+        Expr::List(do_block.into())
+    };
+    let body = crate::resolve::resolve_body(&params, Some(&name), body, ctx);
+    let func = Expr::Function {
+        params: params.into(),
+        body: Arc::new(body),
+        env: ctx.clone(),
+        name: Some(name),
+        receiver: None,
+    };
+
+    // Redefining a method for the same types replaces it
+    let mut methods = def.methods.write_unpoisoned();
+    match methods.iter_mut().find(|m| m.specializers == specializers) {
+        Some(existing) => existing.func = func,
+        None => methods.push(MethodDef { specializers, func }),
+    }
+    Expr::Nil
+}
+
+/// The type names a value matches, most specific first: a struct instance matches its own
+/// struct, then each parent, then the interfaces they implement; a tagged value matches its
+/// tag; every value matches the name `Type.of` gives it.
+pub fn dispatch_types(val: &Expr, ctx: &Context) -> Vec<Symbol> {
+    let mut types = Vec::new();
+    match val {
+        Expr::Ref(r) => {
+            let mut current = super::structs::type_name_of(&r.read_unpoisoned());
+            let mut interfaces = Vec::new();
+            while let Some(name) = current {
+                let def = ctx.get_struct(&name);
+                types.push(name);
+                current = def.as_ref().and_then(|d| d.parent.clone());
+                if let Some(d) = def {
+                    interfaces.extend(d.interfaces.iter().cloned());
+                }
+            }
+            for iface in interfaces {
+                if !types.contains(&iface) {
+                    types.push(iface);
+                }
+            }
+        }
+        Expr::Tagged { tag, .. } => types.push(tag.clone()),
+        _ => {}
+    }
+    types.push(super::reflect::type_of(val).into());
+    types
+}
+
+/// Pick the most specific method that accepts `vals`. Methods are compared argument by
+/// argument from the left: the first argument whose specializer is closer to the value's own
+/// type decides, and an untyped parameter ranks below every type.
+fn select_method(def: &GenericDef, vals: &[Expr], ctx: &Context) -> Expr {
+    if vals.len() != def.params.len() {
+        crate::stop!(
+            "{} expects {} arguments {:?}, got {}",
+            def.name,
+            def.params.len(),
+            def.params,
+            vals.len()
+        );
+    }
+    let types: Vec<Vec<Symbol>> = vals.iter().map(|v| dispatch_types(v, ctx)).collect();
+
+    let methods = def.methods.read_unpoisoned();
+    let best = methods
+        .iter()
+        .filter_map(|m| {
+            let rank = m
+                .specializers
+                .iter()
+                .zip(&types)
+                .map(|(spec, candidates)| match spec {
+                    None => Some(candidates.len()),
+                    Some(ty) => candidates.iter().position(|c| c == ty),
+                })
+                .collect::<Option<Vec<usize>>>()?;
+            Some((rank, m))
+        })
+        .min_by(|(a, _), (b, _)| a.cmp(b));
+
+    match best {
+        Some((_, m)) => m.func.clone(),
+        None => {
+            let arg_types: Vec<String> = types.iter().map(|t| t[0].to_string()).collect();
+            let candidates: Vec<String> = methods
+                .iter()
+                .map(|m| format!("  {}", signature(&def.name, m)))
+                .collect();
+            crate::stop!(
+                "No method of {} matches argument types ({}). Candidates:\n{}",
+                def.name,
+                arg_types.join(", "),
+                if candidates.is_empty() {
+                    "  (none)".to_string()
+                } else {
+                    candidates.join("\n")
+                }
+            )
+        }
+    }
+}
+
+fn signature(name: &Symbol, method: &MethodDef) -> String {
+    let types: Vec<String> = method
+        .specializers
+        .iter()
+        .map(|s| match s {
+            Some(ty) => ty.to_string(),
+            None => ANY.to_string(),
+        })
+        .collect();
+    format!("({} {})", name, types.join(" "))
+}
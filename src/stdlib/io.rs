@@ -1,19 +1,19 @@
 use crate::context::Context;
+use crate::error::MutexExt;
 use crate::expr::Expr;
 use std::collections::BTreeMap;
-use std::fs;
-use std::io::Write;
-use std::sync::{Arc, RwLock};
+use std::io::{BufRead, Write};
+use std::sync::{Arc, Mutex, RwLock};
 
 pub fn register(ctx: &mut Context) {
     let mut io_exports = BTreeMap::new();
 
     io_exports.insert(
         Expr::sym("read_file"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
-                Expr::Str(path) => match fs::read_to_string(path) {
-                    Ok(content) => Expr::Str(content),
+                Expr::Str(path) => match crate::vfs::read(ctx, "IO.read_file", &path) {
+                    Ok(content) => String::from_utf8(content).map_or(Expr::Nil, Expr::Str),
                     Err(_) => Expr::Nil,
                 },
                 _ => Expr::Nil,
@@ -25,19 +25,21 @@ pub fn register(ctx: &mut Context) {
 
     io_exports.insert(
         Expr::sym("write_file"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     return Expr::Nil;
                 }
-                let path = crate::context::eval(args[0].clone(), ctx);
-                let content = crate::context::eval(args[1].clone(), ctx);
+                let path = crate::context::eval_ref(&args[0], ctx);
+                let content = crate::context::eval_ref(&args[1], ctx);
 
                 match (path, content) {
-                    (Expr::Str(p), Expr::Str(c)) => match fs::write(p, c) {
-                        Ok(_) => Expr::Int(1),
-                        Err(_) => Expr::Nil,
-                    },
+                    (Expr::Str(p), Expr::Str(c)) => {
+                        match crate::vfs::write(ctx, "IO.write_file", &p, c.as_bytes()) {
+                            Ok(_) => Expr::Int(1),
+                            Err(_) => Expr::Nil,
+                        }
+                    }
                     _ => Expr::Nil,
                 }
             },
@@ -46,31 +48,61 @@ pub fn register(ctx: &mut Context) {
         ),
     );
 
+    io_exports.insert(
+        Expr::sym("read_bytes"),
+        Expr::extern_ref(
+            |args, ctx| match eval_first(args, ctx) {
+                Expr::Str(path) => match crate::vfs::read(ctx, "IO.read_bytes", &path) {
+                    Ok(content) => Expr::Bytes(content),
+                    Err(_) => Expr::Nil,
+                },
+                _ => Expr::Nil,
+            },
+            "read_bytes",
+            "Read file content as bytes",
+        ),
+    );
+
+    io_exports.insert(
+        Expr::sym("write_bytes"),
+        Expr::extern_ref(
+            |args, ctx| {
+                if args.len() != 2 {
+                    return Expr::Nil;
+                }
+                let path = crate::context::eval_ref(&args[0], ctx);
+                let content = crate::context::eval_ref(&args[1], ctx);
+
+                match (path, content) {
+                    (Expr::Str(p), Expr::Bytes(b)) => {
+                        match crate::vfs::write(ctx, "IO.write_bytes", &p, &b) {
+                            Ok(_) => Expr::Int(1),
+                            Err(_) => Expr::Nil,
+                        }
+                    }
+                    _ => Expr::Nil,
+                }
+            },
+            "write_bytes",
+            "Write bytes to file",
+        ),
+    );
+
     // Append
     io_exports.insert(
         Expr::sym("append_file"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     return Expr::Nil;
                 }
-                let path = crate::context::eval(args[0].clone(), ctx);
-                let content = crate::context::eval(args[1].clone(), ctx);
+                let path = crate::context::eval_ref(&args[0], ctx);
+                let content = crate::context::eval_ref(&args[1], ctx);
 
                 match (path, content) {
                     (Expr::Str(p), Expr::Str(c)) => {
-                        match std::fs::OpenOptions::new()
-                            .append(true)
-                            .create(true)
-                            .open(p)
-                        {
-                            Ok(mut file) => {
-                                if let Ok(_) = write!(file, "{}", c) {
-                                    Expr::Int(1)
-                                } else {
-                                    Expr::Nil
-                                }
-                            }
+                        match crate::vfs::append(ctx, "IO.append_file", &p, c.as_bytes()) {
+                            Ok(_) => Expr::Int(1),
                             Err(_) => Expr::Nil,
                         }
                     }
@@ -84,10 +116,12 @@ pub fn register(ctx: &mut Context) {
 
     io_exports.insert(
         Expr::sym("read_line"),
-        Expr::extern_fun(
-            |_args, _ctx| {
+        Expr::extern_ref(
+            |_args, ctx| {
+                // Show any prompt printed without a newline before waiting
+                let _ = ctx.streams.stdout.lock_unpoisoned().flush();
                 let mut input = String::new();
-                match std::io::stdin().read_line(&mut input) {
+                match ctx.streams.stdin.lock_unpoisoned().read_line(&mut input) {
                     Ok(_) => Expr::Str(input.trim_end().to_string()),
                     Err(_) => Expr::Nil,
                 }
@@ -99,10 +133,10 @@ pub fn register(ctx: &mut Context) {
 
     io_exports.insert(
         Expr::sym("exists"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Str(p) => {
-                    if std::path::Path::new(&p).exists() {
+                    if crate::vfs::exists(ctx, "IO.exists", &p) {
                         Expr::Int(1)
                     } else {
                         Expr::Nil
@@ -117,9 +151,9 @@ pub fn register(ctx: &mut Context) {
 
     io_exports.insert(
         Expr::sym("remove_file"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
-                Expr::Str(p) => match std::fs::remove_file(p) {
+                Expr::Str(p) => match crate::vfs::remove(ctx, "IO.remove_file", &p) {
                     Ok(_) => Expr::Int(1),
                     Err(_) => Expr::Nil,
                 },
@@ -132,10 +166,10 @@ pub fn register(ctx: &mut Context) {
 
     io_exports.insert(
         Expr::sym("is_dir"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Str(p) => {
-                    if std::path::Path::new(&p).is_dir() {
+                    if crate::vfs::is_dir(ctx, "IO.is_dir", &p) {
                         Expr::Int(1)
                     } else {
                         Expr::Nil
@@ -150,10 +184,10 @@ pub fn register(ctx: &mut Context) {
 
     io_exports.insert(
         Expr::sym("is_file"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Str(p) => {
-                    if std::path::Path::new(&p).is_file() {
+                    if crate::vfs::is_file(ctx, "IO.is_file", &p) {
                         Expr::Int(1)
                     } else {
                         Expr::Nil
@@ -166,14 +200,89 @@ pub fn register(ctx: &mut Context) {
         ),
     );
 
-    let mod_val = Expr::Ref(Arc::new(RwLock::new(Expr::Map(io_exports))));
-    ctx.define(Expr::sym("IO"), mod_val);
+    let mod_val = Expr::Ref(Arc::new(RwLock::new(Expr::Map(io_exports.into()))));
+    ctx.define("IO", mod_val);
+}
+
+/// Where `print`, `eprint` and `IO.read_line` write and read. Each interpreter has its own,
+/// shared by every scope, starting out as the process's standard streams.
+pub struct Streams {
+    pub stdout: Mutex<Box<dyn Write + Send>>,
+    pub stderr: Mutex<Box<dyn Write + Send>>,
+    pub stdin: Mutex<Box<dyn BufRead + Send>>,
+}
+
+impl Streams {
+    pub fn new() -> Self {
+        Self {
+            stdout: Mutex::new(Box::new(std::io::stdout())),
+            stderr: Mutex::new(Box::new(std::io::stderr())),
+            stdin: Mutex::new(Box::new(std::io::BufReader::new(std::io::stdin()))),
+        }
+    }
+
+    pub fn set_stdout(&self, out: impl Write + Send + 'static) {
+        *self.stdout.lock_unpoisoned() = Box::new(out);
+    }
+
+    pub fn set_stderr(&self, out: impl Write + Send + 'static) {
+        *self.stderr.lock_unpoisoned() = Box::new(out);
+    }
+
+    pub fn set_stdin(&self, input: impl BufRead + Send + 'static) {
+        *self.stdin.lock_unpoisoned() = Box::new(input);
+    }
+}
+
+impl Default for Streams {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for Streams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Streams").finish_non_exhaustive()
+    }
+}
+
+/// An in-memory output stream. Clones share the same buffer, so one can be handed to
+/// `Streams::set_stdout` and the other kept to read what was written.
+#[derive(Clone, Debug, Default)]
+pub struct OutputBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl OutputBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything written so far.
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock_unpoisoned()).into_owned()
+    }
+
+    /// Everything written so far, emptying the buffer.
+    pub fn take(&self) -> String {
+        let bytes = std::mem::take(&mut *self.0.lock_unpoisoned());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock_unpoisoned().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
 }
 
 fn eval_first(args: &[Expr], ctx: &mut Context) -> Expr {
     if args.len() != 1 {
         Expr::Nil
     } else {
-        crate::context::eval(args[0].clone(), ctx)
+        crate::context::eval_ref(&args[0], ctx)
     }
 }
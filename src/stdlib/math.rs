@@ -1,5 +1,8 @@
+use super::bigint::BitOp;
 use crate::context::Context;
 use crate::expr::Expr;
+use crate::native::onion_fn;
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::sync::{Arc, RwLock};
 
@@ -16,20 +19,21 @@ pub fn register(ctx: &mut Context) {
     // Basic Functions
     math_exports.insert(
         Expr::sym("abs"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
-                Expr::Int(n) => Expr::Int(n.abs()),
+                n @ (Expr::Int(_) | Expr::BigInt(_)) => super::bigint::abs(&n, ctx).unwrap(),
                 Expr::Float(f) => Expr::Float(f.abs()),
                 _ => Expr::Nil,
             },
             "abs",
             "Absolute value",
-        ),
+        )
+        .with_signature(&["number"], "number"),
     );
 
     math_exports.insert(
         Expr::sym("ceil"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Int(n) => Expr::Int(n),
                 Expr::Float(f) => Expr::Int(f.ceil() as i64),
@@ -37,12 +41,13 @@ pub fn register(ctx: &mut Context) {
             },
             "ceil",
             "Ceiling",
-        ),
+        )
+        .with_signature(&["number"], "int"),
     );
 
     math_exports.insert(
         Expr::sym("floor"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Int(n) => Expr::Int(n),
                 Expr::Float(f) => Expr::Int(f.floor() as i64),
@@ -50,12 +55,13 @@ pub fn register(ctx: &mut Context) {
             },
             "floor",
             "Floor",
-        ),
+        )
+        .with_signature(&["number"], "int"),
     );
 
     math_exports.insert(
         Expr::sym("round"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Int(n) => Expr::Int(n),
                 Expr::Float(f) => Expr::Int(f.round() as i64),
@@ -63,92 +69,59 @@ pub fn register(ctx: &mut Context) {
             },
             "round",
             "Round to nearest integer",
-        ),
-    );
-
-    // Trigonometry
-    math_exports.insert(
-        Expr::sym("sin"),
-        Expr::extern_fun(
-            |args, ctx| match eval_first(args, ctx) {
-                Expr::Int(n) => Expr::Float((n as f64).sin()),
-                Expr::Float(f) => Expr::Float(f.sin()),
-                _ => Expr::Nil,
-            },
-            "sin",
-            "Sine",
-        ),
-    );
-
-    math_exports.insert(
-        Expr::sym("cos"),
-        Expr::extern_fun(
-            |args, ctx| match eval_first(args, ctx) {
-                Expr::Int(n) => Expr::Float((n as f64).cos()),
-                Expr::Float(f) => Expr::Float(f.cos()),
-                _ => Expr::Nil,
-            },
-            "cos",
-            "Cosine",
-        ),
-    );
-
-    math_exports.insert(
-        Expr::sym("tan"),
-        Expr::extern_fun(
-            |args, ctx| match eval_first(args, ctx) {
-                Expr::Int(n) => Expr::Float((n as f64).tan()),
-                Expr::Float(f) => Expr::Float(f.tan()),
-                _ => Expr::Nil,
-            },
-            "tan",
-            "Tangent",
-        ),
-    );
-
-    math_exports.insert(
-        Expr::sym("sqrt"),
-        Expr::extern_fun(
-            |args, ctx| match eval_first(args, ctx) {
-                Expr::Int(n) => Expr::Float((n as f64).sqrt()),
-                Expr::Float(f) => Expr::Float(f.sqrt()),
-                _ => Expr::Nil,
-            },
-            "sqrt",
-            "Square root",
-        ),
+        )
+        .with_signature(&["number"], "int"),
     );
 
     math_exports.insert(
         Expr::sym("pow"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     return Expr::Nil;
                 }
-                let base = crate::context::eval(args[0].clone(), ctx);
-                let exp = crate::context::eval(args[1].clone(), ctx);
-                match (base, exp) {
-                    (Expr::Int(b), Expr::Int(e)) => Expr::Float((b as f64).powf(e as f64)),
-                    (Expr::Float(b), Expr::Float(e)) => Expr::Float(b.powf(e)),
-                    (Expr::Int(b), Expr::Float(e)) => Expr::Float((b as f64).powf(e)),
-                    (Expr::Float(b), Expr::Int(e)) => Expr::Float(b.powf(e as f64)),
+                let base = crate::context::eval_ref(&args[0], ctx);
+                let exp = crate::context::eval_ref(&args[1], ctx);
+                match (base.as_number(), exp.as_number()) {
+                    (Some(b), Some(e)) => Expr::Float(b.powf(e)),
                     _ => Expr::Nil,
                 }
             },
             "pow",
             "Power",
-        ),
+        )
+        .with_signature(&["number", "number"], "float"),
+    );
+
+    math_exports.insert(
+        Expr::sym("ipow"),
+        Expr::extern_ref(
+            |args, ctx| {
+                if args.len() != 2 {
+                    return Expr::Nil;
+                }
+                let base = crate::context::eval_ref(&args[0], ctx);
+                let exp = match crate::context::eval_ref(&args[1], ctx) {
+                    Expr::Int(e) if e >= 0 => e,
+                    Expr::Int(e) => crate::stop!("Math.ipow: negative exponent {}", e),
+                    _ => return Expr::Nil,
+                };
+                super::bigint::pow(&base, exp, ctx).unwrap_or(Expr::Nil)
+            },
+            "ipow",
+            "Integer power, exact for any size",
+        )
+        .with_signature(&["int", "int"], "int"),
     );
 
     // Min/Max
     math_exports.insert(
         Expr::sym("min"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 let mut min_val = Expr::Nil;
                 for arg in args {
-                    let val = crate::context::eval(arg.clone(), ctx);
+                    let val = crate::context::eval_ref(arg, ctx);
                     if min_val == Expr::Nil {
                         min_val = val;
                     } else {
@@ -173,7 +146,11 @@ pub fn register(ctx: &mut Context) {
                                     min_val = val;
                                 }
                             }
-                            _ => {}
+                            (a, b) => {
+                                if super::bigint::compare(b, a) == Some(Ordering::Less) {
+                                    min_val = val;
+                                }
+                            }
                         }
                     }
                 }
@@ -186,11 +163,11 @@ pub fn register(ctx: &mut Context) {
 
     math_exports.insert(
         Expr::sym("max"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 let mut max_val = Expr::Nil;
                 for arg in args {
-                    let val = crate::context::eval(arg.clone(), ctx);
+                    let val = crate::context::eval_ref(arg, ctx);
                     if max_val == Expr::Nil {
                         max_val = val;
                     } else {
@@ -215,7 +192,11 @@ pub fn register(ctx: &mut Context) {
                                     max_val = val;
                                 }
                             }
-                            _ => {}
+                            (a, b) => {
+                                if super::bigint::compare(b, a) == Some(Ordering::Greater) {
+                                    max_val = val;
+                                }
+                            }
                         }
                     }
                 }
@@ -226,66 +207,108 @@ pub fn register(ctx: &mut Context) {
         ),
     );
 
-    // Logarithms
+    // Bits
     math_exports.insert(
-        Expr::sym("log"),
-        Expr::extern_fun(
+        Expr::sym("popcount"),
+        Expr::extern_ref(
             |args, ctx| {
-                if args.len() != 2 {
-                    return Expr::Nil;
+                let n = eval_first(args, ctx);
+                match super::bigint::popcount(&n) {
+                    Some(count) => count,
+                    None => crate::stop!("Math.popcount expected Int, got {:?}", n),
                 }
-                let n = crate::context::eval(args[0].clone(), ctx);
-                let base = crate::context::eval(args[1].clone(), ctx);
-                match (n.as_number(), base.as_number()) {
-                    (Some(n), Some(b)) => Expr::Float(n.log(b)),
-                    _ => Expr::Nil,
+            },
+            "popcount",
+            "Number of 1 bits in an integer",
+        )
+        .with_signature(&["int"], "int"),
+    );
+
+    math_exports.insert(
+        Expr::sym("bit_test"),
+        Expr::extern_ref(
+            |args, ctx| {
+                let (n, i) = int_and_bit(args, ctx, "Math.bit_test");
+                match super::bigint::bit(&n, i) {
+                    Some(true) => Expr::Int(1),
+                    _ => Expr::Int(0),
                 }
             },
-            "log",
-            "Logarithm of n base b",
-        ),
+            "bit_test",
+            "Whether bit i of n is set. (Math.bit_test n i)",
+        )
+        .with_signature(&["int", "int"], "int"),
     );
 
     math_exports.insert(
-        Expr::sym("ln"),
-        Expr::extern_fun(
-            |args, ctx| match eval_first(args, ctx).as_number() {
-                Some(n) => Expr::Float(n.ln()),
-                None => Expr::Nil,
+        Expr::sym("bit_set"),
+        Expr::extern_ref(
+            |args, ctx| {
+                let (n, i) = int_and_bit(args, ctx, "Math.bit_set");
+                let mask =
+                    super::bigint::bit_op(BitOp::Shl, &Expr::Int(1), &Expr::Int(i as i64), ctx);
+                super::bigint::bit_op(BitOp::Or, &n, &mask.unwrap(), ctx).unwrap()
             },
-            "ln",
-            "Natural logarithm",
-        ),
+            "bit_set",
+            "n with bit i set. (Math.bit_set n i)",
+        )
+        .with_signature(&["int", "int"], "int"),
     );
 
     math_exports.insert(
-        Expr::sym("log10"),
-        Expr::extern_fun(
-            |args, ctx| match eval_first(args, ctx).as_number() {
-                Some(n) => Expr::Float(n.log10()),
-                None => Expr::Nil,
+        Expr::sym("bit_clear"),
+        Expr::extern_ref(
+            |args, ctx| {
+                let (n, i) = int_and_bit(args, ctx, "Math.bit_clear");
+                let mask =
+                    super::bigint::bit_op(BitOp::Shl, &Expr::Int(1), &Expr::Int(i as i64), ctx);
+                let mask = super::bigint::bit_not(&mask.unwrap()).unwrap();
+                super::bigint::bit_op(BitOp::And, &n, &mask, ctx).unwrap()
             },
-            "log10",
-            "Base-10 logarithm",
-        ),
+            "bit_clear",
+            "n with bit i cleared. (Math.bit_clear n i)",
+        )
+        .with_signature(&["int", "int"], "int"),
     );
 
     math_exports.insert(
-        Expr::sym("exp"),
-        Expr::extern_fun(
-            |args, ctx| match eval_first(args, ctx).as_number() {
-                Some(n) => Expr::Float(n.exp()),
-                None => Expr::Nil,
+        Expr::sym("set_strict_ints"),
+        Expr::extern_ref(
+            |args, ctx| {
+                let on = !matches!(eval_first(args, ctx), Expr::Nil | Expr::Int(0));
+                ctx.settings.set_strict_ints(on);
+                Expr::Nil
             },
-            "exp",
-            "Exponential e^x",
+            "set_strict_ints",
+            "Make integer overflow an error instead of promoting to a big integer",
         ),
     );
 
+    // Logarithms
+    math_exports.insert(
+        Expr::sym("log"),
+        Expr::extern_ref(
+            |args, ctx| {
+                if args.len() != 2 {
+                    return Expr::Nil;
+                }
+                let n = crate::context::eval_ref(&args[0], ctx);
+                let base = crate::context::eval_ref(&args[1], ctx);
+                match (n.as_number(), base.as_number()) {
+                    (Some(n), Some(b)) => Expr::Float(n.log(b)),
+                    _ => Expr::Nil,
+                }
+            },
+            "log",
+            "Logarithm of n base b",
+        )
+        .with_signature(&["number", "number"], "float"),
+    );
+
     // Utility
     math_exports.insert(
         Expr::sym("sign"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx).as_number() {
                 Some(n) => {
                     if n > 0.0 {
@@ -300,68 +323,15 @@ pub fn register(ctx: &mut Context) {
             },
             "sign",
             "Sign of number (-1, 0, 1)",
-        ),
-    );
-
-    math_exports.insert(
-        Expr::sym("clamp"),
-        Expr::extern_fun(
-            |args, ctx| {
-                if args.len() != 3 {
-                    return Expr::Nil;
-                }
-                let val = crate::context::eval(args[0].clone(), ctx).as_number();
-                let min = crate::context::eval(args[1].clone(), ctx).as_number();
-                let max = crate::context::eval(args[2].clone(), ctx).as_number();
-
-                match (val, min, max) {
-                    (Some(v), Some(mn), Some(mx)) => {
-                        if v < mn {
-                            Expr::Float(mn)
-                        } else if v > mx {
-                            Expr::Float(mx)
-                        } else {
-                            Expr::Float(v)
-                        }
-                    }
-                    _ => Expr::Nil,
-                }
-            },
-            "clamp",
-            "Clamp value between min and max",
-        ),
-    );
-
-    // Degrees/Radians
-    math_exports.insert(
-        Expr::sym("to_radians"),
-        Expr::extern_fun(
-            |args, ctx| match eval_first(args, ctx).as_number() {
-                Some(n) => Expr::Float(n.to_radians()),
-                None => Expr::Nil,
-            },
-            "to_radians",
-            "Convert degrees to radians",
-        ),
-    );
-
-    math_exports.insert(
-        Expr::sym("to_degrees"),
-        Expr::extern_fun(
-            |args, ctx| match eval_first(args, ctx).as_number() {
-                Some(n) => Expr::Float(n.to_degrees()),
-                None => Expr::Nil,
-            },
-            "to_degrees",
-            "Convert radians to degrees",
-        ),
+        )
+        .with_signature(&["number"], "int"),
     );
 
     // Random
     // Randomness
     math_exports.insert(
         Expr::sym("rand"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |_args, _ctx| Expr::Float(rand::random::<f64>()),
             "rand",
             "Returns random float [0, 1)",
@@ -370,13 +340,13 @@ pub fn register(ctx: &mut Context) {
 
     math_exports.insert(
         Expr::sym("rand_int"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     return Expr::Nil;
                 }
-                let min = crate::context::eval(args[0].clone(), ctx);
-                let max = crate::context::eval(args[1].clone(), ctx);
+                let min = crate::context::eval_ref(&args[0], ctx);
+                let max = crate::context::eval_ref(&args[1], ctx);
 
                 match (min, max) {
                     (Expr::Int(min_val), Expr::Int(max_val)) => {
@@ -396,14 +366,107 @@ pub fn register(ctx: &mut Context) {
     );
 
     // Define 'Math' module in context
-    let mod_val = Expr::Ref(Arc::new(RwLock::new(Expr::Map(math_exports))));
-    ctx.define(Expr::sym("Math"), mod_val);
+    let mod_val = Expr::Ref(Arc::new(RwLock::new(Expr::Map(math_exports.into()))));
+    ctx.define("Math", mod_val);
+    for native in [
+        sin_native(),
+        cos_native(),
+        tan_native(),
+        sqrt_native(),
+        ln_native(),
+        log10_native(),
+        exp_native(),
+        clamp_native(),
+        to_radians_native(),
+        to_degrees_native(),
+    ] {
+        native.register(ctx);
+    }
+}
+
+/// Sine
+#[onion_fn(module = "Math")]
+fn sin(x: f64) -> f64 {
+    x.sin()
+}
+
+/// Cosine
+#[onion_fn(module = "Math")]
+fn cos(x: f64) -> f64 {
+    x.cos()
+}
+
+/// Tangent
+#[onion_fn(module = "Math")]
+fn tan(x: f64) -> f64 {
+    x.tan()
+}
+
+/// Square root
+#[onion_fn(module = "Math")]
+fn sqrt(x: f64) -> f64 {
+    x.sqrt()
+}
+
+/// Natural logarithm
+#[onion_fn(module = "Math")]
+fn ln(x: f64) -> f64 {
+    x.ln()
+}
+
+/// Base-10 logarithm
+#[onion_fn(module = "Math")]
+fn log10(x: f64) -> f64 {
+    x.log10()
+}
+
+/// Exponential e^x
+#[onion_fn(module = "Math")]
+fn exp(x: f64) -> f64 {
+    x.exp()
+}
+
+/// Clamp value between min and max
+#[onion_fn(module = "Math")]
+fn clamp(val: f64, min: f64, max: f64) -> f64 {
+    if val < min {
+        min
+    } else if val > max {
+        max
+    } else {
+        val
+    }
+}
+
+/// Convert degrees to radians
+#[onion_fn(module = "Math")]
+fn to_radians(degrees: f64) -> f64 {
+    degrees.to_radians()
+}
+
+/// Convert radians to degrees
+#[onion_fn(module = "Math")]
+fn to_degrees(radians: f64) -> f64 {
+    radians.to_degrees()
+}
+
+/// Evaluate the integer and bit index arguments of the `bit_*` functions.
+fn int_and_bit(args: &[Expr], ctx: &mut Context, what: &str) -> (Expr, u64) {
+    if args.len() != 2 {
+        crate::stop!("{} requires an integer and a bit index", what);
+    }
+    let n = crate::context::eval_ref(&args[0], ctx);
+    let i = crate::context::eval_ref(&args[1], ctx);
+    match (&n, i) {
+        (Expr::Int(_) | Expr::BigInt(_), Expr::Int(i)) if i >= 0 => (n, i as u64),
+        (n, i) => crate::stop!("{} expected (Int, Int >= 0), got ({:?}, {:?})", what, n, i),
+    }
 }
 
 fn eval_first(args: &[Expr], ctx: &mut Context) -> Expr {
     if args.len() != 1 {
         Expr::Nil
     } else {
-        crate::context::eval(args[0].clone(), ctx)
+        crate::context::eval_ref(&args[0], ctx)
     }
 }
@@ -1,33 +1,115 @@
 use super::context::{Assoc, Context, OpInfo};
 use super::*;
-use crate::context::eval;
+use crate::context::{eval, eval_ref};
+use crate::error::{MutexExt, RwLockExt};
 
-use crate::context::Scope;
-use std::collections::{BTreeMap, HashMap};
+use bigint::{BitOp, IntOp};
+use imbl::vector;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::sync::{Arc, RwLock};
 
 mod battle;
+pub mod bigint;
+pub mod bytes;
+pub mod capabilities;
 pub mod collections;
+pub mod contracts;
+pub mod ffi;
 pub mod game;
+pub mod generic;
 pub mod io;
 pub mod math;
 pub mod os;
 pub mod reflect;
 pub mod string;
+pub mod structs;
 pub mod time;
 
+/// A module of the standard library, bound to a global like `Math` or `IO`. The core
+/// language (operators, `fun`, `struct`, ...) is always installed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Module {
+    Math,
+    String,
+    Type,
+    Collections,
+    Time,
+    Os,
+    Io,
+    Bytes,
+    Game,
+    Ffi,
+}
+
+impl Module {
+    pub const ALL: [Module; 10] = [
+        Module::Math,
+        Module::String,
+        Module::Type,
+        Module::Collections,
+        Module::Time,
+        Module::Os,
+        Module::Io,
+        Module::Bytes,
+        Module::Game,
+        Module::Ffi,
+    ];
+
+    /// The global the module is bound to.
+    pub fn name(self) -> &'static str {
+        match self {
+            Module::Math => "Math",
+            Module::String => "String",
+            Module::Type => "Type",
+            Module::Collections => "Collections",
+            Module::Time => "Time",
+            Module::Os => "OS",
+            Module::Io => "IO",
+            Module::Bytes => "Bytes",
+            Module::Game => "Game",
+            Module::Ffi => "FFI",
+        }
+    }
+
+    fn register(self, ctx: &mut Context) {
+        match self {
+            Module::Math => math::register(ctx),
+            Module::String => string::register(ctx),
+            Module::Type => reflect::register(ctx),
+            Module::Collections => collections::register(ctx),
+            Module::Time => time::register(ctx),
+            Module::Os => os::register(ctx),
+            Module::Io => io::register(ctx),
+            Module::Bytes => bytes::register(ctx),
+            Module::Game => game::register(ctx),
+            Module::Ffi => ffi::register(ctx),
+        }
+    }
+}
+
 pub fn stdlib() -> Context {
+    stdlib_with(&Module::ALL)
+}
+
+/// The core language with only the given modules installed.
+pub fn stdlib_with(modules: &[Module]) -> Context {
+    stdlib_sandboxed(modules, capabilities::Capabilities::all())
+}
+
+/// The core language with the given modules, whose functions may only do what `caps`
+/// allows outside the interpreter.
+pub fn stdlib_sandboxed(modules: &[Module], caps: capabilities::Capabilities) -> Context {
     let mut ctx = Context::new();
+    ctx.caps = Arc::new(caps);
 
     // Register Modules
-    math::register(&mut ctx);
-    string::register(&mut ctx);
-    reflect::register(&mut ctx);
-    collections::register(&mut ctx);
-    time::register(&mut ctx);
-    os::register(&mut ctx);
-    io::register(&mut ctx);
-    game::register(&mut ctx);
+    for module in modules {
+        module.register(&mut ctx);
+    }
+    structs::register(&mut ctx);
+    generic::register(&mut ctx);
+    crate::plugin::register(&mut ctx);
 
     ctx.define_op(
         "+",
@@ -36,17 +118,29 @@ pub fn stdlib() -> Context {
             associativity: Assoc::Left,
             unary: false,
         },
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 let mut sum = Expr::Nil;
 
                 for arg in args {
-                    let val = eval(arg.clone(), ctx);
+                    let val = eval_ref(arg, ctx);
+                    if let Some(method) = structs::magic_method(&sum, "__add__", ctx) {
+                        sum = call_anon_fn(&method, &[val], ctx);
+                        continue;
+                    }
+                    if sum == Expr::Nil && matches!(val, Expr::Ref(_)) {
+                        sum = val;
+                        continue;
+                    }
+                    if let Some(res) = bigint::arith(IntOp::Add, &sum, &val, ctx) {
+                        sum = res;
+                        continue;
+                    }
                     match val {
                         Expr::Int(n) => {
                             sum = match sum {
                                 Expr::Nil => Expr::Int(n),
-                                Expr::Int(m) => Expr::Int(m + n),
+                                Expr::Int(m) => bigint::checked(IntOp::Add, m, n, ctx),
                                 Expr::Float(f) => Expr::Float(f + (n as f64)),
                                 other => {
                                     crate::stop!("Type error in +: cannot add Int to {:?}", other)
@@ -81,7 +175,7 @@ pub fn stdlib() -> Context {
                                 Expr::Nil => Expr::List(lst.clone()),
                                 Expr::List(existing) => {
                                     let mut new_list = existing.clone();
-                                    new_list.extend_from_slice(&lst);
+                                    new_list.append(lst);
                                     Expr::List(new_list)
                                 }
                                 other => {
@@ -157,17 +251,29 @@ pub fn stdlib() -> Context {
             associativity: Assoc::Left,
             unary: false,
         },
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 let mut sum = Expr::Nil;
 
                 for arg in args {
-                    let val = eval(arg.clone(), ctx);
+                    let val = eval_ref(arg, ctx);
+                    if let Some(method) = structs::magic_method(&sum, "__sub__", ctx) {
+                        sum = call_anon_fn(&method, &[val], ctx);
+                        continue;
+                    }
+                    if sum == Expr::Nil && matches!(val, Expr::Ref(_)) {
+                        sum = val;
+                        continue;
+                    }
+                    if let Some(res) = bigint::arith(IntOp::Sub, &sum, &val, ctx) {
+                        sum = res;
+                        continue;
+                    }
                     match val {
                         Expr::Int(n) => {
                             sum = match sum {
                                 Expr::Nil => Expr::Int(n),
-                                Expr::Int(m) => Expr::Int(m - n),
+                                Expr::Int(m) => bigint::checked(IntOp::Sub, m, n, ctx),
                                 Expr::Float(f) => Expr::Float(f - (n as f64)),
                                 other => crate::stop!(
                                     "Type error in -: cannot subtract Int from {:?}",
@@ -205,7 +311,7 @@ pub fn stdlib() -> Context {
             associativity: Assoc::Right,
             unary: false,
         },
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     return Expr::Nil;
@@ -219,15 +325,15 @@ pub fn stdlib() -> Context {
                         if let Expr::Sym(op) = &list[0] {
                             if op.as_str() == "." && list.len() >= 3 {
                                 let mut new_list = list.clone();
-                                new_list.push(rhs.clone());
+                                new_list.push_back(rhs.clone());
 
                                 return eval(Expr::List(new_list), ctx);
                             }
                         }
                     }
                 } else if let Expr::Sym(s) = lhs {
-                    let val = eval(rhs.clone(), ctx);
-                    ctx.define(Expr::Sym(s.clone()), val.clone());
+                    let val = eval_ref(rhs, ctx);
+                    ctx.define(s.clone(), val.clone());
                     return val;
                 }
 
@@ -235,7 +341,8 @@ pub fn stdlib() -> Context {
             },
             "=",
             "Assign value to property or variable. (x = 10) or (obj.key = 20)",
-        ),
+        )
+        .special(),
     );
 
     // Multiplication
@@ -246,16 +353,28 @@ pub fn stdlib() -> Context {
             associativity: Assoc::Left,
             unary: false,
         },
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 let mut prod = Expr::Nil;
                 for arg in args {
-                    let val = eval(arg.clone(), ctx);
+                    let val = eval_ref(arg, ctx);
+                    if let Some(method) = structs::magic_method(&prod, "__mul__", ctx) {
+                        prod = call_anon_fn(&method, &[val], ctx);
+                        continue;
+                    }
+                    if prod == Expr::Nil && matches!(val, Expr::Ref(_)) {
+                        prod = val;
+                        continue;
+                    }
+                    if let Some(res) = bigint::arith(IntOp::Mul, &prod, &val, ctx) {
+                        prod = res;
+                        continue;
+                    }
                     match val {
                         Expr::Int(n) => {
                             prod = match prod {
                                 Expr::Nil => Expr::Int(n),
-                                Expr::Int(m) => Expr::Int(m * n),
+                                Expr::Int(m) => bigint::checked(IntOp::Mul, m, n, ctx),
                                 Expr::Float(f) => Expr::Float(f * (n as f64)),
                                 other => crate::stop!(
                                     "Type error in *: cannot multiply Int with {:?}",
@@ -296,12 +415,26 @@ pub fn stdlib() -> Context {
             associativity: Assoc::Left,
             unary: false,
         },
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 let mut res = Expr::Nil;
                 let mut first = true;
                 for arg in args {
-                    let val = eval(arg.clone(), ctx);
+                    let val = eval_ref(arg, ctx);
+                    if first && matches!(val, Expr::Ref(_)) {
+                        res = val;
+                        first = false;
+                        continue;
+                    }
+                    if let Some(method) = structs::magic_method(&res, "__div__", ctx) {
+                        res = call_anon_fn(&method, &[val], ctx);
+                        continue;
+                    }
+                    if let Some(quot) = bigint::arith(IntOp::Div, &res, &val, ctx) {
+                        res = quot;
+                        first = false;
+                        continue;
+                    }
                     match val {
                         Expr::Int(n) => {
                             if first {
@@ -311,7 +444,7 @@ pub fn stdlib() -> Context {
                                 res = match res {
                                     Expr::Int(m) => {
                                         if n != 0 {
-                                            Expr::Int(m / n)
+                                            bigint::checked(IntOp::Div, m, n, ctx)
                                         } else {
                                             crate::stop!("Division by zero");
                                         }
@@ -368,19 +501,23 @@ pub fn stdlib() -> Context {
             associativity: Assoc::Left,
             unary: false,
         },
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     crate::stop!("Modulo % requires 2 arguments");
                 }
-                let first = eval(args[0].clone(), ctx);
-                let second = eval(args[1].clone(), ctx);
+                let first = eval_ref(&args[0], ctx);
+                let second = eval_ref(&args[1], ctx);
                 match (first, second) {
-                    (Expr::Int(a), Expr::Int(b)) => Expr::Int(a % b),
+                    (Expr::Int(_), Expr::Int(0)) => crate::stop!("Division by zero"),
+                    (Expr::Int(a), Expr::Int(b)) => bigint::checked(IntOp::Rem, a, b, ctx),
                     (Expr::Float(a), Expr::Int(b)) => Expr::Float(a % (b as f64)),
                     (Expr::Int(a), Expr::Float(b)) => Expr::Float((a as f64) % b),
                     (Expr::Float(a), Expr::Float(b)) => Expr::Float(a % b),
-                    (a, b) => crate::stop!("Invalid types for %: {:?} % {:?}", a, b),
+                    (a, b) => match bigint::arith(IntOp::Rem, &a, &b, ctx) {
+                        Some(rem) => rem,
+                        None => crate::stop!("Invalid types for %: {:?} % {:?}", a, b),
+                    },
                 }
             },
             "%",
@@ -396,14 +533,22 @@ pub fn stdlib() -> Context {
             associativity: Assoc::Left,
             unary: false,
         },
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() < 2 {
                     return Expr::Int(1); // True for 0 or 1 arg
                 }
-                let first = eval(args[0].clone(), ctx);
+                let first = eval_ref(&args[0], ctx);
                 for arg in &args[1..] {
-                    if eval(arg.clone(), ctx) != first {
+                    let other = eval_ref(arg, ctx);
+                    let equal = match structs::magic_method(&first, "__eq__", ctx) {
+                        Some(method) => !matches!(
+                            call_anon_fn(&method, &[other], ctx),
+                            Expr::Nil | Expr::Int(0)
+                        ),
+                        None => other == first,
+                    };
+                    if !equal {
                         return Expr::Int(0); // False
                     }
                 }
@@ -422,13 +567,19 @@ pub fn stdlib() -> Context {
             associativity: Assoc::Left,
             unary: false,
         },
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     return Expr::Int(1);
                 }
-                let first = eval(args[0].clone(), ctx);
-                let second = eval(args[1].clone(), ctx);
+                let first = eval_ref(&args[0], ctx);
+                let second = eval_ref(&args[1], ctx);
+                if let Some(method) = structs::magic_method(&first, "__eq__", ctx) {
+                    return match call_anon_fn(&method, &[second], ctx) {
+                        Expr::Nil | Expr::Int(0) => Expr::Int(1),
+                        _ => Expr::Int(0),
+                    };
+                }
                 if first != second {
                     Expr::Int(1)
                 } else {
@@ -448,14 +599,22 @@ pub fn stdlib() -> Context {
             associativity: Assoc::Left,
             unary: false,
         },
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() < 2 {
                     return Expr::Int(1);
                 }
-                let mut prev = eval(args[0].clone(), ctx);
+                let mut prev = eval_ref(&args[0], ctx);
                 for arg in &args[1..] {
-                    let curr = eval(arg.clone(), ctx);
+                    let curr = eval_ref(arg, ctx);
+                    if let Some(method) = structs::magic_method(&prev, "__lt__", ctx) {
+                        let less = call_anon_fn(&method, std::slice::from_ref(&curr), ctx);
+                        if matches!(less, Expr::Nil | Expr::Int(0)) {
+                            return Expr::Int(0);
+                        }
+                        prev = curr;
+                        continue;
+                    }
                     match (&prev, &curr) {
                         (Expr::Int(a), Expr::Int(b)) => {
                             if !(*a < *b) {
@@ -477,7 +636,11 @@ pub fn stdlib() -> Context {
                                 return Expr::Int(0);
                             }
                         }
-                        (a, b) => crate::stop!("Type mismatch in < : {:?} vs {:?}", a, b),
+                        (a, b) => match bigint::compare(a, b) {
+                            Some(Ordering::Less) => {}
+                            Some(_) => return Expr::Int(0),
+                            None => crate::stop!("Type mismatch in < : {:?} vs {:?}", a, b),
+                        },
                     }
                     prev = curr;
                 }
@@ -496,14 +659,22 @@ pub fn stdlib() -> Context {
             associativity: Assoc::Left,
             unary: false,
         },
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() < 2 {
                     return Expr::Int(1);
                 }
-                let mut prev = eval(args[0].clone(), ctx);
+                let mut prev = eval_ref(&args[0], ctx);
                 for arg in &args[1..] {
-                    let curr = eval(arg.clone(), ctx);
+                    let curr = eval_ref(arg, ctx);
+                    if let Some(method) = structs::magic_method(&curr, "__lt__", ctx) {
+                        let less = call_anon_fn(&method, std::slice::from_ref(&prev), ctx);
+                        if matches!(less, Expr::Nil | Expr::Int(0)) {
+                            return Expr::Int(0);
+                        }
+                        prev = curr;
+                        continue;
+                    }
                     match (&prev, &curr) {
                         (Expr::Int(a), Expr::Int(b)) => {
                             if !(*a > *b) {
@@ -525,7 +696,11 @@ pub fn stdlib() -> Context {
                                 return Expr::Int(0);
                             }
                         }
-                        (a, b) => crate::stop!("Type mismatch in > : {:?} vs {:?}", a, b),
+                        (a, b) => match bigint::compare(a, b) {
+                            Some(Ordering::Greater) => {}
+                            Some(_) => return Expr::Int(0),
+                            None => crate::stop!("Type mismatch in > : {:?} vs {:?}", a, b),
+                        },
                     }
                     prev = curr;
                 }
@@ -543,13 +718,19 @@ pub fn stdlib() -> Context {
             associativity: Assoc::Left,
             unary: false,
         },
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     stop!("<= operator requires exactly 2 arguments");
                 }
-                let left = eval(args[0].clone(), ctx);
-                let right = eval(args[1].clone(), ctx);
+                let left = eval_ref(&args[0], ctx);
+                let right = eval_ref(&args[1], ctx);
+                if let Some(method) = structs::magic_method(&right, "__lt__", ctx) {
+                    return match call_anon_fn(&method, &[left], ctx) {
+                        Expr::Nil | Expr::Int(0) => Expr::Int(1),
+                        _ => Expr::Int(0),
+                    };
+                }
                 match (left, right) {
                     (Expr::Int(a), Expr::Int(b)) => Expr::Int(if a <= b { 1 } else { 0 }),
                     (Expr::Float(a), Expr::Float(b)) => Expr::Int(if a <= b { 1 } else { 0 }),
@@ -559,7 +740,10 @@ pub fn stdlib() -> Context {
                     (Expr::Float(a), Expr::Int(b)) => {
                         Expr::Int(if a <= (b as f64) { 1 } else { 0 })
                     }
-                    (a, b) => crate::stop!("Type mismatch in <= : {:?} vs {:?}", a, b),
+                    (a, b) => match bigint::compare(&a, &b) {
+                        Some(ord) => Expr::Int(if ord.is_le() { 1 } else { 0 }),
+                        None => crate::stop!("Type mismatch in <= : {:?} vs {:?}", a, b),
+                    },
                 }
             },
             "<=",
@@ -574,13 +758,19 @@ pub fn stdlib() -> Context {
             associativity: Assoc::Left,
             unary: false,
         },
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     stop!(">= operator requires exactly 2 arguments");
                 }
-                let left = eval(args[0].clone(), ctx);
-                let right = eval(args[1].clone(), ctx);
+                let left = eval_ref(&args[0], ctx);
+                let right = eval_ref(&args[1], ctx);
+                if let Some(method) = structs::magic_method(&left, "__lt__", ctx) {
+                    return match call_anon_fn(&method, &[right], ctx) {
+                        Expr::Nil | Expr::Int(0) => Expr::Int(1),
+                        _ => Expr::Int(0),
+                    };
+                }
                 match (left, right) {
                     (Expr::Int(a), Expr::Int(b)) => Expr::Int(if a >= b { 1 } else { 0 }),
                     (Expr::Float(a), Expr::Float(b)) => Expr::Int(if a >= b { 1 } else { 0 }),
@@ -590,7 +780,10 @@ pub fn stdlib() -> Context {
                     (Expr::Float(a), Expr::Int(b)) => {
                         Expr::Int(if a >= (b as f64) { 1 } else { 0 })
                     }
-                    (a, b) => crate::stop!("Type mismatch in >= : {:?} vs {:?}", a, b),
+                    (a, b) => match bigint::compare(&a, &b) {
+                        Some(ord) => Expr::Int(if ord.is_ge() { 1 } else { 0 }),
+                        None => crate::stop!("Type mismatch in >= : {:?} vs {:?}", a, b),
+                    },
                 }
             },
             ">=",
@@ -606,14 +799,14 @@ pub fn stdlib() -> Context {
             associativity: Assoc::Right,
             unary: true,
         },
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 1 {
                     stop!("! operator requires exactly 1 argument, got {:?}", args);
                 }
-                match eval(args[0].clone(), ctx) {
-                    Expr::Int(n) => Expr::Int(-n),
+                match eval_ref(&args[0], ctx) {
                     Expr::Float(f) => Expr::Float(-f),
+                    n @ (Expr::Int(_) | Expr::BigInt(_)) => bigint::neg(&n, ctx).unwrap(),
                     other => crate::stop!("Invalid type for ! operator: {:?}", other),
                 }
             },
@@ -622,14 +815,91 @@ pub fn stdlib() -> Context {
         ),
     );
 
+    // Bitwise operators bind tighter than comparisons, so `(flags & 4) == 4` needs no parentheses
+    for (symbol, op, precedence, magic, desc) in [
+        ("|", BitOp::Or, 6, "__or__", "Bitwise OR."),
+        ("^", BitOp::Xor, 7, "__xor__", "Bitwise XOR."),
+        ("&", BitOp::And, 8, "__and__", "Bitwise AND."),
+        ("<<", BitOp::Shl, 9, "__lshift__", "Shift bits left."),
+        (
+            ">>",
+            BitOp::Shr,
+            9,
+            "__rshift__",
+            "Shift bits right, keeping the sign.",
+        ),
+    ] {
+        ctx.define_op(
+            symbol,
+            OpInfo {
+                precedence,
+                associativity: Assoc::Left,
+                unary: false,
+            },
+            Expr::extern_ref(
+                move |args, ctx| {
+                    if args.len() < 2 {
+                        stop!("{} operator requires at least 2 arguments", symbol);
+                    }
+                    let mut res = eval_ref(&args[0], ctx);
+                    for arg in &args[1..] {
+                        let val = eval_ref(arg, ctx);
+                        if let Some(method) = structs::magic_method(&res, magic, ctx) {
+                            res = call_anon_fn(&method, &[val], ctx);
+                            continue;
+                        }
+                        res = match bigint::bit_op(op, &res, &val, ctx) {
+                            Some(n) => n,
+                            None => crate::stop!(
+                                "Type error in {}: expected Int operands, got {:?} and {:?}",
+                                symbol,
+                                res,
+                                val
+                            ),
+                        };
+                    }
+                    res
+                },
+                symbol,
+                desc,
+            ),
+        );
+    }
+
+    ctx.define_op(
+        "~",
+        OpInfo {
+            precedence: 15,
+            associativity: Assoc::Right,
+            unary: true,
+        },
+        Expr::extern_ref(
+            |args, ctx| {
+                if args.len() != 1 {
+                    stop!("~ operator requires exactly 1 argument, got {:?}", args);
+                }
+                let val = eval_ref(&args[0], ctx);
+                if let Some(method) = structs::magic_method(&val, "__invert__", ctx) {
+                    return call_anon_fn(&method, &[], ctx);
+                }
+                match bigint::bit_not(&val) {
+                    Some(n) => n,
+                    None => crate::stop!("Invalid type for ~ operator: {:?}", val),
+                }
+            },
+            "~",
+            "Bitwise NOT.",
+        ),
+    );
+
     ctx.define(
-        Expr::sym("not"),
-        Expr::extern_fun(
+        "not",
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 1 {
                     stop!("not requires exactly 1 argument");
                 }
-                match eval(args[0].clone(), ctx) {
+                match eval_ref(&args[0], ctx) {
                     Expr::Int(0) => Expr::Int(1),
                     _ => Expr::Int(0),
                 }
@@ -639,6 +909,53 @@ pub fn stdlib() -> Context {
         ),
     );
 
+    ctx.define(
+        "try",
+        Expr::extern_ref(
+            |args, ctx| {
+                if args.is_empty() || args.len() > 2 {
+                    stop!("try requires a body and an optional handler");
+                }
+                let saved = ctx.clone();
+                let body = args[0].clone();
+                match crate::error::catch(|| eval(body, ctx)) {
+                    Ok(val) => val,
+                    Err(err) => {
+                        *ctx = saved;
+                        match args.get(1) {
+                            Some(handler) => {
+                                let handler = eval_ref(handler, ctx);
+                                call_anon_fn(&handler, &[Expr::Str(err.message)], ctx)
+                            }
+                            None => Expr::Nil,
+                        }
+                    }
+                }
+            },
+            "try",
+            "Evaluate an expression, catching runtime errors. The handler is called with the error message. (try body (fun (msg) ...))",
+        ),
+    );
+
+    ctx.define(
+        "raise",
+        Expr::extern_ref(
+            |args, ctx| {
+                let message = args
+                    .iter()
+                    .map(|a| match eval_ref(a, ctx) {
+                        Expr::Str(s) => s,
+                        other => other.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                stop!("{}", message)
+            },
+            "raise",
+            "Raise a runtime error with a message, which `try` can catch.",
+        ),
+    );
+
     // Logic AND (short-circuit)
     ctx.define_op(
         "and",
@@ -647,11 +964,11 @@ pub fn stdlib() -> Context {
             associativity: Assoc::Left,
             unary: false,
         },
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 let mut last = Expr::Int(1);
                 for arg in args {
-                    last = eval(arg.clone(), ctx);
+                    last = eval_ref(arg, ctx);
                     if matches!(last, Expr::Nil | Expr::Int(0)) {
                         return Expr::Int(0);
                     }
@@ -671,10 +988,10 @@ pub fn stdlib() -> Context {
             associativity: Assoc::Left,
             unary: false,
         },
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 for arg in args {
-                    let val = eval(arg.clone(), ctx);
+                    let val = eval_ref(arg, ctx);
                     if !matches!(val, Expr::Nil | Expr::Int(0)) {
                         return val;
                     }
@@ -688,14 +1005,14 @@ pub fn stdlib() -> Context {
 
     // List functions
     ctx.define(
-        Expr::sym("list"),
-        Expr::extern_fun(
+        "list",
+        Expr::extern_ref(
             |args, ctx| {
                 let mut vals = Vec::new();
                 for arg in args {
-                    vals.push(eval(arg.clone(), ctx));
+                    vals.push(eval_ref(arg, ctx));
                 }
-                Expr::List(vals)
+                Expr::List(vals.into())
             },
             "list",
             "Create a list.",
@@ -703,17 +1020,22 @@ pub fn stdlib() -> Context {
     );
 
     ctx.define(
-        Expr::sym("len"),
-        Expr::extern_fun(
+        "len",
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 1 {
                     return Expr::Nil;
                 }
-                match crate::context::eval(args[0].clone(), ctx) {
+                let val = crate::context::eval_ref(&args[0], ctx);
+                if let Some(n) = structs::call_magic(&val, "__len__", &[], ctx) {
+                    return n;
+                }
+                match val {
                     Expr::List(l) => Expr::Int(l.len() as i64),
                     Expr::Str(s) => Expr::Int(s.len() as i64),
                     Expr::Map(m) => Expr::Int(m.len() as i64),
                     Expr::HashMap(m) => Expr::Int(m.len() as i64),
+                    Expr::Bytes(b) => Expr::Int(b.len() as i64),
                     other => crate::stop!("len() expected List/Str/Map, got {:?}", other),
                 }
             },
@@ -723,14 +1045,35 @@ pub fn stdlib() -> Context {
     );
 
     ctx.define(
-        Expr::sym("first"),
-        Expr::extern_fun(
+        "hash",
+        Expr::extern_ref(
+            |args, ctx| {
+                if args.len() != 1 {
+                    stop!("hash requires exactly 1 argument");
+                }
+                let val = eval_ref(&args[0], ctx);
+                if let Some(h) = structs::call_magic(&val, "__hash__", &[], ctx) {
+                    return h;
+                }
+                use std::hash::{Hash, Hasher};
+                let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                val.hash(&mut hasher);
+                Expr::Int(hasher.finish() as i64)
+            },
+            "hash",
+            "Hash a value to an integer, calling __hash__ on struct instances.",
+        ),
+    );
+
+    ctx.define(
+        "first",
+        Expr::extern_ref(
             |args, ctx| {
                 if args.is_empty() {
                     stop!("first requires at least 1 argument");
                 }
-                match crate::context::eval(args[0].clone(), ctx) {
-                    Expr::List(l) => l.first().cloned().unwrap_or(Expr::Nil),
+                match crate::context::eval_ref(&args[0], ctx) {
+                    Expr::List(l) => l.front().cloned().unwrap_or(Expr::Nil),
                     other => crate::stop!("first expected List, got {:?}", other),
                 }
             },
@@ -740,20 +1083,14 @@ pub fn stdlib() -> Context {
     );
 
     ctx.define(
-        Expr::sym("rest"),
-        Expr::extern_fun(
+        "rest",
+        Expr::extern_ref(
             |args, ctx| {
                 if args.is_empty() {
                     stop!("rest requires at least 1 argument");
                 }
-                match eval(args[0].clone(), ctx) {
-                    Expr::List(l) => {
-                        if l.is_empty() {
-                            Expr::List(vec![])
-                        } else {
-                            Expr::List(l[1..].to_vec())
-                        }
-                    }
+                match eval_ref(&args[0], ctx) {
+                    Expr::List(l) => Expr::List(l.skip(1.min(l.len()))),
                     other => crate::stop!("rest() expected List, got {:?}", other),
                 }
             },
@@ -763,20 +1100,19 @@ pub fn stdlib() -> Context {
     );
 
     ctx.define(
-        Expr::sym("cons"),
-        Expr::extern_fun(
+        "cons",
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     stop!("cons requires exactly 2 arguments");
                 }
-                let head = eval(args[0].clone(), ctx);
-                match eval(args[1].clone(), ctx) {
-                    Expr::List(tail) => {
-                        let mut new_list = vec![head];
-                        new_list.extend(tail);
-                        Expr::List(new_list)
+                let head = eval_ref(&args[0], ctx);
+                match eval_ref(&args[1], ctx) {
+                    Expr::List(mut tail) => {
+                        tail.push_front(head);
+                        Expr::List(tail)
                     }
-                    Expr::Nil => Expr::List(vec![head]),
+                    Expr::Nil => Expr::List(vector![head]),
                     other => crate::stop!(
                         "cons second argument must be a List or Nil, got {:?}",
                         other
@@ -790,17 +1126,17 @@ pub fn stdlib() -> Context {
 
     // Control Flow
     ctx.define(
-        Expr::sym("if"),
-        Expr::extern_fun(
+        "if",
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() < 2 || args.len() > 3 {
                     stop!("if requires 2 or 3 arguments");
                 }
-                let cond = eval(args[0].clone(), ctx);
+                let cond = eval_ref(&args[0], ctx);
                 if !matches!(cond, Expr::Nil | Expr::Int(0)) {
-                    eval(args[1].clone(), ctx)
+                    eval_ref(&args[1], ctx)
                 } else if args.len() == 3 {
-                    eval(args[2].clone(), ctx)
+                    eval_ref(&args[2], ctx)
                 } else {
                     Expr::Nil
                 }
@@ -811,20 +1147,20 @@ pub fn stdlib() -> Context {
     );
 
     ctx.define(
-        Expr::sym("while"),
-        Expr::extern_fun(
+        "while",
+        Expr::extern_ref(
             |args, ctx| {
                 let cond = &args[0];
                 let body = &args[1..];
 
                 let mut last = Expr::Nil;
                 loop {
-                    let cond_val = eval(cond.clone(), ctx);
+                    let cond_val = eval_ref(cond, ctx);
                     if matches!(cond_val, Expr::Nil | Expr::Int(0)) {
                         break;
                     }
                     for expr in body {
-                        last = eval(expr.clone(), ctx);
+                        last = eval_ref(expr, ctx);
                     }
                 }
                 last
@@ -835,39 +1171,44 @@ pub fn stdlib() -> Context {
     );
 
     ctx.define(
-        Expr::sym("for"),
-        Expr::extern_fun(
+        "for",
+        Expr::extern_ref(
             |args, ctx| {
-                let var = &args[0];
+                let var = match &args[0] {
+                    Expr::Sym(s) => s,
+                    other => stop!("for loop variable must be a symbol, got {:?}", other),
+                };
                 let iterator = &args[1];
                 let body = &args[2..];
 
                 let mut last = Expr::Nil;
-                match eval(iterator.clone(), ctx) {
+                let iterable = eval_ref(iterator, ctx);
+                match structs::to_iter(iterable, ctx) {
                     Expr::List(lst) => {
                         for item in lst {
                             ctx.define(var.clone(), item.clone());
                             for expr in body {
-                                last = eval(expr.clone(), ctx);
+                                last = eval_ref(expr, ctx);
                             }
                         }
                     }
-                    _ => stop!("for loop iterator must be a List"),
+                    other => stop!("for loop iterator must be a List, got {:?}", other),
                 }
                 last
             },
             "for",
             "For loop: (for var iterator body).",
-        ),
+        )
+        .special(),
     );
 
     ctx.define(
-        Expr::sym("do"),
-        Expr::extern_fun(
+        "do",
+        Expr::extern_ref(
             |args, ctx| {
                 let mut result = Expr::Nil;
                 for expr in args {
-                    result = eval(expr.clone(), ctx);
+                    result = eval_ref(expr, ctx);
                 }
                 result
             },
@@ -876,40 +1217,61 @@ pub fn stdlib() -> Context {
         ),
     );
 
-    fn print(args: &mut [Expr], ctx: &mut Context) -> Expr {
+    /// Evaluate the arguments and write them, separated by spaces, to one of the streams.
+    fn print(
+        args: &[Expr],
+        ctx: &mut Context,
+        stream: fn(&io::Streams) -> &std::sync::Mutex<Box<dyn std::io::Write + Send>>,
+        end: &str,
+    ) -> Expr {
         let mut result = Expr::Nil;
-        let mut first = true;
-        for expr in args {
-            if !first {
-                print!(" ");
-            } else {
-                first = false;
+        let mut text = String::new();
+        for (i, expr) in args.iter().enumerate() {
+            if i > 0 {
+                text.push(' ');
             }
-            result = eval(expr.clone(), ctx);
-            print!("{}", result);
+            result = eval_ref(expr, ctx);
+            text.push_str(&structs::to_display(&result, ctx));
         }
+        text.push_str(end);
+        // Written only once everything is evaluated, as an argument may print too
+        let _ = stream(&ctx.streams)
+            .lock_unpoisoned()
+            .write_all(text.as_bytes());
         result
     }
     ctx.define(
-        Expr::sym("print"),
-        Expr::extern_fun(
-            print,
+        "print",
+        Expr::extern_ref(
+            |args, ctx| print(args, ctx, |s| &s.stdout, ""),
             "print",
             "Prints the given expressions to standard output without a newline.",
         ),
     );
     ctx.define(
-        Expr::sym("println"),
-        Expr::extern_fun(
-            |args, ctx| {
-                let result = print(args, ctx);
-                println!();
-                result
-            },
+        "println",
+        Expr::extern_ref(
+            |args, ctx| print(args, ctx, |s| &s.stdout, "\n"),
             "println",
             "Prints the given expressions to standard output with a newline.",
         ),
     );
+    ctx.define(
+        "eprint",
+        Expr::extern_ref(
+            |args, ctx| print(args, ctx, |s| &s.stderr, ""),
+            "eprint",
+            "Prints the given expressions to standard error without a newline.",
+        ),
+    );
+    ctx.define(
+        "eprintln",
+        Expr::extern_ref(
+            |args, ctx| print(args, ctx, |s| &s.stderr, "\n"),
+            "eprintln",
+            "Prints the given expressions to standard error with a newline.",
+        ),
+    );
 
     ctx.define_op(
         "new",
@@ -918,12 +1280,12 @@ pub fn stdlib() -> Context {
             associativity: Assoc::Right,
             unary: true,
         },
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 1 {
                     return Expr::Nil;
                 }
-                let val = eval(args[0].clone(), ctx);
+                let val = eval_ref(&args[0], ctx);
                 Expr::Ref(Arc::new(RwLock::new(val)))
             },
             "new",
@@ -938,14 +1300,18 @@ pub fn stdlib() -> Context {
             associativity: Assoc::Left,
             unary: false,
         },
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() < 2 || args.len() > 3 {
                     return Expr::Nil;
                 }
 
+                if args.len() == 2 && matches!(&args[0], Expr::Sym(s) if s.as_str() == "super") {
+                    return structs::super_method(&args[1], ctx);
+                }
+
                 // First arg must be a reference
-                let obj_expr = eval(args[0].clone(), ctx);
+                let obj_expr = eval_ref(&args[0], ctx);
                 let obj_ref = if let Expr::Ref(r) = &obj_expr {
                     r
                 } else {
@@ -963,37 +1329,24 @@ pub fn stdlib() -> Context {
                     };
 
                     if let Some(key) = &key_sym {
-                        let guard = obj_ref.read().unwrap();
+                        let guard = obj_ref.read_unpoisoned();
                         let found = match &*guard {
                             Expr::Map(m) => m.get(&Expr::Sym(key.clone())).cloned(),
                             Expr::HashMap(m) => m.get(&Expr::Sym(key.clone())).cloned(),
                             _ => None,
                         };
                         if let Some(v) = found {
-                            match v {
-                                Expr::Function {
-                                    params,
-                                    body,
-                                    env,
-                                    name,
-                                } => {
-                                    let mut new_ctx = env.fork();
-                                    new_ctx.define(Expr::sym("self"), obj_expr.clone());
-                                    return Expr::Function {
-                                        params,
-                                        body,
-                                        env: new_ctx,
-                                        name,
-                                    };
-                                }
-                                _ => return v,
-                            }
+                            return structs::bind_self(v, &obj_expr);
+                        }
+                        if let Some(method) = structs::instance_method(&guard, &obj_expr, key, ctx)
+                        {
+                            return method;
                         }
                     }
 
-                    let attr_expr = eval(args[1].clone(), ctx);
+                    let attr_expr = eval_ref(&args[1], ctx);
                     let val_opt = {
-                        let guard = obj_ref.read().unwrap();
+                        let guard = obj_ref.read_unpoisoned();
                         match &*guard {
                             Expr::Map(m) => m.get(&attr_expr).cloned(),
                             Expr::HashMap(m) => m.get(&attr_expr).cloned(),
@@ -1002,24 +1355,7 @@ pub fn stdlib() -> Context {
                     };
 
                     if let Some(val) = val_opt {
-                        match val {
-                            Expr::Function {
-                                params,
-                                body,
-                                env,
-                                name,
-                            } => {
-                                let mut new_ctx = env.fork();
-                                new_ctx.define(Expr::sym("self"), obj_expr.clone());
-                                Expr::Function {
-                                    params,
-                                    body,
-                                    env: new_ctx,
-                                    name,
-                                }
-                            }
-                            _ => val,
-                        }
+                        structs::bind_self(val, &obj_expr)
                     } else {
                         crate::stop!(
                             "Attribute {} not found on expression {}",
@@ -1031,27 +1367,32 @@ pub fn stdlib() -> Context {
                     let attr_expr = if let Expr::Sym(s) = &args[1] {
                         Expr::Sym(s.clone())
                     } else {
-                        eval(args[1].clone(), ctx)
+                        eval_ref(&args[1], ctx)
                     };
-                    let val_expr = eval(args[2].clone(), ctx);
+                    let val_expr = eval_ref(&args[2], ctx);
 
-                    let mut guard = obj_ref.write().unwrap();
-                    match &mut *guard {
+                    let error = match &mut *obj_ref.write_unpoisoned() {
                         Expr::Map(m) => {
                             m.insert(attr_expr, val_expr.clone());
+                            None
                         }
                         Expr::HashMap(m) => {
                             m.insert(attr_expr, val_expr.clone());
+                            None
                         }
-                        _ => crate::stop!("Type error: cannot set property on {:?}", *guard),
+                        other => Some(format!("Type error: cannot set property on {:?}", other)),
                     };
+                    if let Some(message) = error {
+                        crate::stop!("{}", message);
+                    }
                     val_expr
                 };
                 result
             },
             ".",
             "Access or set properties of a reference. (obj.attr) or (obj.attr val).",
-        ),
+        )
+        .special(),
     );
 
     ctx.define_op(
@@ -1061,24 +1402,27 @@ pub fn stdlib() -> Context {
             associativity: Assoc::Left,
             unary: false,
         },
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() < 2 || args.len() > 3 {
                     return Expr::Nil;
                 }
-                let obj = eval(args[0].clone(), ctx);
-                let key = eval(args[1].clone(), ctx);
+                let obj = eval_ref(&args[0], ctx);
+                let key = eval_ref(&args[1], ctx);
 
                 if args.len() == 3 {
-                    let val = eval(args[2].clone(), ctx);
+                    let val = eval_ref(&args[2], ctx);
                     if let Expr::Ref(r) = obj {
-                        let mut guard = r.write().unwrap();
-                        match &mut *guard {
+                        // The guard is dropped before raising, so the value stays usable
+                        // after `try` catches the error
+                        let error = match &mut *r.write_unpoisoned() {
                             Expr::Map(m) => {
                                 m.insert(key, val.clone());
+                                None
                             }
                             Expr::HashMap(m) => {
                                 m.insert(key, val.clone());
+                                None
                             }
                             Expr::List(l) => {
                                 if let Expr::Int(i) = key {
@@ -1086,13 +1430,39 @@ pub fn stdlib() -> Context {
                                         l[i as usize] = val.clone();
                                     }
                                 }
+                                None
                             }
-                            _ => crate::stop!("Type error: cannot index into {:?}", *guard),
+                            Expr::Bytes(b) => match (key, &val) {
+                                (Expr::Int(i), Expr::Int(n))
+                                    if i >= 0 && (i as usize) < b.len() =>
+                                {
+                                    match u8::try_from(*n) {
+                                        Ok(byte) => {
+                                            b[i as usize] = byte;
+                                            None
+                                        }
+                                        Err(_) => Some(format!("{} is not a byte (0 to 255)", n)),
+                                    }
+                                }
+                                (key, val) => Some(format!(
+                                    "Type error: cannot set bytes index {:?} to {:?}",
+                                    key, val
+                                )),
+                            },
+                            other => Some(format!("Type error: cannot index into {:?}", other)),
+                        };
+                        if let Some(message) = error {
+                            crate::stop!("{}", message);
                         }
                         return val;
                     }
                     crate::stop!("Type error: cannot index into non-reference {:?}", obj);
                 } else {
+                    if let Some(val) =
+                        structs::call_magic(&obj, "__index__", std::slice::from_ref(&key), ctx)
+                    {
+                        return val;
+                    }
                     match obj {
                         Expr::List(l) => {
                             if let Expr::Int(i) = key {
@@ -1112,8 +1482,9 @@ pub fn stdlib() -> Context {
                         }
                         Expr::Map(m) => m.get(&key).cloned().unwrap_or(Expr::Nil),
                         Expr::HashMap(m) => m.get(&key).cloned().unwrap_or(Expr::Nil),
+                        Expr::Bytes(b) => index_bytes(&b, &key),
                         Expr::Ref(r) => {
-                            let guard = r.read().unwrap();
+                            let guard = r.read_unpoisoned();
                             match &*guard {
                                 Expr::List(l) => {
                                     if let Expr::Int(i) = key {
@@ -1137,6 +1508,7 @@ pub fn stdlib() -> Context {
                                 Expr::HashMap(m) => m.get(&key).cloned().unwrap_or_else(|| {
                                     crate::stop!("Key {:?} not found in HashMap", key)
                                 }),
+                                Expr::Bytes(b) => index_bytes(b, &key),
                                 _ => Expr::Nil,
                             }
                         }
@@ -1156,73 +1528,73 @@ pub fn stdlib() -> Context {
             associativity: Assoc::Right,
             unary: false,
         },
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     return Expr::Nil;
                 }
-                let sym = &args[0];
-                let val = eval(args[1].clone(), ctx);
+                let sym = match &args[0] {
+                    Expr::Sym(s) => s,
+                    other => stop!("def expects a symbol to define, got {:?}", other),
+                };
+                let val = eval_ref(&args[1], ctx);
                 ctx.define(sym.clone(), val.clone());
                 val
             },
             "def",
             "Define a value in the current context.",
-        ),
+        )
+        .special(),
     );
 
     // Define (fun (params) body)
     ctx.define(
-        Expr::sym("fun"),
-        Expr::extern_fun(
+        "fun",
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() < 2 {
                     return Expr::Nil;
                 }
                 let params_expr = &args[0];
+                let (ret, body_exprs) = contracts::parse_return(&args[1..]);
 
-                let body = Box::new(if args.len() == 2 {
-                    args[1].clone()
+                let body = if body_exprs.len() == 1 {
+                    body_exprs[0].clone()
                 } else {
-                    let mut do_block = vec![Expr::sym("do")];
-                    for i in 1..args.len() {
-                        do_block.push(args[i].clone());
-                    }
-                    Expr::List(do_block)
-                });
+                    let mut do_block = vec![Expr::Sym(Symbol::DO)];
+                    do_block.extend(body_exprs.iter().cloned());
+                    Expr::List(do_block.into())
+                };
 
-                let params = match params_expr {
-                    Expr::List(lst) => {
-                        let mut syms = Vec::new();
-                        for item in lst {
-                            if let Expr::Sym(s) = item {
-                                syms.push(s.clone());
-                            } else {
-                                return Expr::Nil;
-                            }
-                        }
-                        syms
-                    }
-                    Expr::Sym(s) => vec![s.clone()],
+                let (params, types) = match params_expr {
+                    Expr::List(lst) => match contracts::parse_params(lst) {
+                        Some(parsed) => parsed,
+                        None => return Expr::Nil,
+                    },
+                    Expr::Sym(s) => (vec![s.clone()], vec![None]),
                     _ => return Expr::Nil,
                 };
+                let body = crate::resolve::resolve_body(&params, None, body, ctx);
+                let body = contracts::wrap_body(None, &params, &types, ret, body);
 
                 Expr::Function {
-                    params,
-                    body,
+                    params: params.into(),
+                    body: Arc::new(body),
                     env: ctx.clone(),
                     name: None,
+                    receiver: None,
                 }
             },
             "fun",
             "Create a function (closure).",
-        ),
+        )
+        .special(),
     );
 
     // Module Definition
     ctx.define(
-        Expr::sym("module"),
-        Expr::extern_fun(
+        "module",
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() < 2 {
                     crate::stop!("module requires at least a name and one expression");
@@ -1232,24 +1604,16 @@ pub fn stdlib() -> Context {
                     _ => crate::stop!("module name must be a symbol"),
                 };
 
-                let module_scope = Scope {
-                    vars: RwLock::new(HashMap::new()),
-                    parent: Some(ctx.scope.clone()),
-                };
-
-                let mut module_ctx = Context {
-                    parsing: ctx.parsing.clone(),
-                    scope: Arc::new(module_scope),
-                };
+                let mut module_ctx = ctx.fork();
 
                 let mut last = Expr::Nil;
                 for expr in &args[1..] {
-                    last = eval(expr.clone(), &mut module_ctx);
+                    last = eval_ref(expr, &mut module_ctx);
                 }
 
                 let mut map = BTreeMap::new();
                 {
-                    let vars = module_ctx.scope.vars.read().unwrap();
+                    let vars = module_ctx.scope.vars.read_unpoisoned();
                     println!("Harvesting module {}: found {} vars", name_sym, vars.len());
                     for (k, v) in vars.iter() {
                         println!("Exporting: {:?}", k);
@@ -1257,246 +1621,137 @@ pub fn stdlib() -> Context {
                     }
                 }
 
-                let mod_val = Expr::Ref(Arc::new(RwLock::new(Expr::Map(map))));
+                let mod_val = Expr::Ref(Arc::new(RwLock::new(Expr::Map(map.into()))));
 
-                ctx.define(Expr::Sym(name_sym), mod_val.clone());
+                ctx.define(name_sym, mod_val.clone());
 
                 mod_val
             },
             "module",
             "Define a new module with its own scope. (module Name (def x 1)...)",
-        ),
+        )
+        .special(),
     );
 
     ctx.define(
-        Expr::sym("defun"),
-        Expr::extern_fun(
+        "defun",
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() < 3 {
                     crate::stop!("defun requires at least a name, parameters, and a body");
                 }
                 let name_expr = &args[0];
                 let params_expr = &args[1];
-                let body_exprs = &args[2..];
+                let (ret, body_exprs) = contracts::parse_return(&args[2..]);
+                if body_exprs.is_empty() {
+                    crate::stop!("defun requires a body after the return type");
+                }
 
                 let fn_name_sym = match name_expr {
                     Expr::Sym(s) => s.clone(),
                     _ => crate::stop!("Function name must be a symbol"),
                 };
 
-                let params = match params_expr {
-                    Expr::List(lst) => {
-                        let mut syms = Vec::new();
-                        for item in lst {
-                            if let Expr::Sym(s) = item {
-                                syms.push(s.clone());
-                            } else {
-                                return Expr::Nil;
-                            }
-                        }
-                        syms
-                    }
-                    Expr::Sym(s) => vec![s.clone()],
-                    Expr::Nil => vec![],
+                let (params, types) = match params_expr {
+                    Expr::List(lst) => match contracts::parse_params(lst) {
+                        Some(parsed) => parsed,
+                        None => return Expr::Nil,
+                    },
+                    Expr::Sym(s) => (vec![s.clone()], vec![None]),
+                    Expr::Nil => (vec![], vec![]),
                     _ => crate::stop!("Function parameters must be a list or symbol"),
                 };
 
                 let body = if body_exprs.len() == 1 {
                     body_exprs[0].clone()
                 } else {
-                    let mut do_block = vec![Expr::sym("do")];
+                    let mut do_block = vec![Expr::Sym(Symbol::DO)];
                     do_block.extend(body_exprs.iter().cloned());
-                    Expr::List(do_block)
+                    Expr::List(do_block.into())
                 };
 
+                let body = crate::resolve::resolve_body(&params, Some(&fn_name_sym), body, ctx);
+                let body = contracts::wrap_body(Some(&fn_name_sym), &params, &types, ret, body);
+
                 let func = Expr::Function {
-                    params,
-                    body: Box::new(body),
+                    params: params.into(),
+                    body: Arc::new(body),
                     env: ctx.clone(),
                     name: Some(fn_name_sym.clone()),
+                    receiver: None,
                 };
 
-                ctx.define(fn_name_sym.into(), func.clone());
+                ctx.define(fn_name_sym, func.clone());
                 func
             },
             "defun",
             "Define a recursive function.",
-        ),
-    );
-
-    ctx.define(
-        Expr::sym("struct"),
-        Expr::extern_fun(
-            |args, ctx| {
-                if args.len() < 2 {
-                    return Expr::Nil;
-                }
-                let name_expr = &args[0];
-                let fields_expr = &args[1];
-                let method_defs = &args[2..];
-
-                let struct_name = match name_expr {
-                    Expr::Sym(s) => s.clone(),
-                    _ => crate::stop!("Struct name must be a symbol"),
-                };
-
-                let fields: Vec<crate::symbol::Symbol> = match fields_expr {
-                    Expr::List(lst) => {
-                        let mut syms = Vec::new();
-                        for item in lst {
-                            if let Expr::Sym(s) = item {
-                                syms.push(s.clone());
-                            } else {
-                                return Expr::Nil;
-                            }
-                        }
-                        syms
-                    }
-                    Expr::Nil => vec![],
-                    _ => crate::stop!("Struct fields must be a list of symbols"),
-                };
-
-                let mut methods = BTreeMap::new();
-                for method_def in method_defs {
-                    if let Expr::List(l) = method_def {
-                        if l.len() < 3 {
-                            crate::stop!(
-                                "Method definition requires a name, parameters, and a body"
-                            );
-                        } // Name, Params, Body
-                        let m_name = match &l[0] {
-                            Expr::Sym(s) => s.clone(),
-                            _ => crate::stop!("Method name must be a symbol"),
-                        };
-
-                        let m_params = match &l[1] {
-                            Expr::List(pl) => {
-                                let mut ps = Vec::new();
-                                for p in pl {
-                                    if let Expr::Sym(s) = p {
-                                        ps.push(s.clone());
-                                    } else {
-                                        return Expr::Nil;
-                                    }
-                                }
-                                ps
-                            }
-                            Expr::Nil => vec![],
-                            _ => crate::stop!("Method parameters must be a list of symbols"),
-                        };
-
-                        let m_body = if l.len() == 3 {
-                            Box::new(l[2].clone())
-                        } else {
-                            Box::new(Expr::List(l[2..].iter().cloned().map(|e| e).collect()))
-                        };
-
-                        let method_func = Expr::Function {
-                            params: m_params,
-                            body: m_body,
-                            env: ctx.clone(),
-                            name: Some(m_name.clone()),
-                        };
-                        methods.insert(m_name, method_func);
-                    } else {
-                        crate::stop!("Method definition must be a list");
-                    }
-                }
-
-                let fields_clone = fields.clone();
-                let methods_clone = methods.clone();
-
-                let constructor = Expr::extern_fun(
-                    move |ctor_args, _ctx| {
-                        if ctor_args.len() != fields_clone.len() {
-                            return Expr::Nil;
-                        }
-
-                        let mut obj_map = BTreeMap::new();
-
-                        for (i, field) in fields_clone.iter().enumerate() {
-                            obj_map.insert(field.clone().into(), eval(ctor_args[i].clone(), _ctx));
-                        }
-
-                        for (i, field) in fields_clone.iter().enumerate() {
-                            obj_map.insert(field.clone().into(), eval(ctor_args[i].clone(), _ctx));
-                        }
-
-                        for (m_name, m_func) in &methods_clone {
-                            obj_map.insert(Expr::Sym(m_name.clone()), m_func.clone());
-                        }
-
-                        Expr::Ref(Arc::new(RwLock::new(Expr::Map(obj_map))))
-                    },
-                    struct_name.to_string(),
-                    "Struct Constructor",
-                );
-
-                ctx.define(struct_name.into(), constructor);
-                Expr::Nil
-            },
-            "struct",
-            "Define a struct with fields and methods.",
-        ),
+        )
+        .special(),
     );
 
     use std::f64::consts::{E, PI};
-    ctx.define(Expr::sym("PI"), Expr::Float(PI));
-    ctx.define(Expr::sym("E"), Expr::Float(E));
+    ctx.define("PI", Expr::Float(PI));
+    ctx.define("E", Expr::Float(E));
 
     ctx.define(
-        Expr::sym("sqrt"),
-        Expr::extern_fun(
+        "sqrt",
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 1 {
                     crate::stop!("sqrt requires exactly one argument");
                 }
-                match eval(args[0].clone(), ctx) {
+                match eval_ref(&args[0], ctx) {
                     Expr::Int(n) => Expr::Float((n as f64).sqrt()),
                     Expr::Float(f) => Expr::Float(f.sqrt()),
+                    n @ Expr::BigInt(_) => Expr::Float(n.as_number().unwrap_or(f64::NAN).sqrt()),
                     _ => crate::stop!("sqrt expected Int or Float"),
                 }
             },
             "sqrt",
             "Calculate square root.",
-        ),
+        )
+        .with_signature(&["number"], "float"),
     );
 
     ctx.define(
-        Expr::sym("pow"),
-        Expr::extern_fun(
+        "pow",
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     crate::stop!("pow requires exactly two arguments");
                 }
-                let base = eval(args[0].clone(), ctx);
-                let exp = eval(args[1].clone(), ctx);
-                match (base, exp) {
-                    (Expr::Int(b), Expr::Int(e)) => Expr::Float((b as f64).powf(e as f64)),
-                    (Expr::Float(b), Expr::Float(e)) => Expr::Float(b.powf(e)),
-                    (Expr::Int(b), Expr::Float(e)) => Expr::Float((b as f64).powf(e)),
-                    (Expr::Float(b), Expr::Int(e)) => Expr::Float(b.powf(e as f64)),
+                let base = eval_ref(&args[0], ctx);
+                let exp = eval_ref(&args[1], ctx);
+                match (base.as_number(), exp.as_number()) {
+                    (Some(b), Some(e)) => Expr::Float(b.powf(e)),
                     _ => crate::stop!("pow expected Int or Float arguments"),
                 }
             },
             "pow",
             "Calculate power (base^exponent).",
-        ),
+        )
+        .with_signature(&["number", "number"], "float"),
     );
 
     ctx.define(
-        Expr::sym("length"),
-        Expr::extern_fun(
+        "length",
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 1 {
                     crate::stop!("length requires exactly one argument");
                 }
-                match eval(args[0].clone(), ctx) {
+                let val = eval_ref(&args[0], ctx);
+                if let Some(n) = structs::call_magic(&val, "__len__", &[], ctx) {
+                    return n;
+                }
+                match val {
                     Expr::List(l) => Expr::Int(l.len() as i64),
                     Expr::Str(s) => Expr::Int(s.len() as i64),
                     Expr::Map(m) => Expr::Int(m.len() as i64),
                     Expr::HashMap(hm) => Expr::Int(hm.len() as i64),
-                    _ => crate::stop!("length expected List, Str, Map, or HashMap"),
+                    Expr::Bytes(b) => Expr::Int(b.len() as i64),
+                    _ => crate::stop!("length expected List, Str, Map, HashMap, or Bytes"),
                 }
             },
             "length",
@@ -1504,18 +1759,18 @@ pub fn stdlib() -> Context {
         ),
     );
 
-    let len_fn = ctx.resolve(&Expr::sym("length")).unwrap();
-    ctx.define(Expr::sym("len"), len_fn);
+    let len_fn = ctx.resolve(&Symbol::new("length")).unwrap();
+    ctx.define("len", len_fn);
 
     ctx.define(
-        Expr::sym("nth"),
-        Expr::extern_fun(
+        "nth",
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     return Expr::Nil;
                 }
-                let idx_expr = eval(args[0].clone(), ctx);
-                let list_expr = eval(args[1].clone(), ctx);
+                let idx_expr = eval_ref(&args[0], ctx);
+                let list_expr = eval_ref(&args[1], ctx);
 
                 match (idx_expr, list_expr) {
                     (Expr::Int(idx), Expr::List(l)) => {
@@ -1538,20 +1793,19 @@ pub fn stdlib() -> Context {
     );
 
     ctx.define(
-        Expr::sym("take"),
-        Expr::extern_fun(
+        "take",
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     return Expr::Nil;
                 }
-                let n_expr = eval(args[0].clone(), ctx);
-                let list_expr = eval(args[1].clone(), ctx);
+                let n_expr = eval_ref(&args[0], ctx);
+                let list_expr = eval_ref(&args[1], ctx);
 
                 match (n_expr, list_expr) {
                     (Expr::Int(n), Expr::List(l)) => {
                         let n = n.max(0) as usize;
-                        let count = n.min(l.len());
-                        Expr::List(l[0..count].to_vec())
+                        Expr::List(l.take(n.min(l.len())))
                     }
                     _ => crate::stop!("take expected (Int, List)"),
                 }
@@ -1562,23 +1816,19 @@ pub fn stdlib() -> Context {
     );
 
     ctx.define(
-        Expr::sym("drop"),
-        Expr::extern_fun(
+        "drop",
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     return Expr::Nil;
                 }
-                let n_expr = eval(args[0].clone(), ctx);
-                let list_expr = eval(args[1].clone(), ctx);
+                let n_expr = eval_ref(&args[0], ctx);
+                let list_expr = eval_ref(&args[1], ctx);
 
                 match (n_expr, list_expr) {
                     (Expr::Int(n), Expr::List(l)) => {
                         let n = n.max(0) as usize;
-                        if n >= l.len() {
-                            Expr::List(vec![])
-                        } else {
-                            Expr::List(l[n..].to_vec())
-                        }
+                        Expr::List(l.skip(n.min(l.len())))
                     }
                     _ => crate::stop!("drop expected (Int, List)"),
                 }
@@ -1593,30 +1843,33 @@ pub fn stdlib() -> Context {
 
 pub fn call_anon_fn(func: &Expr, args: &[Expr], ctx: &mut Context) -> Expr {
     match func {
-        Expr::Function {
-            params: _,
-            body: _,
-            env: _,
-            name: _,
-        } => {
+        Expr::Function { .. } | Expr::Keyword(_) => {
             let mut call_list = Vec::new();
             call_list.push(func.clone());
             for arg in args {
                 call_list.push(Expr::Quoted(Box::new(arg.clone())));
             }
-            crate::context::eval(Expr::List(call_list), ctx)
+            crate::context::eval(Expr::List(call_list.into()), ctx)
         }
         Expr::Extern(ext) => {
             let mut call_args = Vec::new();
             for arg in args {
                 call_args.push(Expr::Quoted(Box::new(arg.clone())));
             }
-            ext.call(&mut call_args, ctx)
+            ext.call(&call_args, ctx)
         }
         _ => Expr::Nil,
     }
 }
 
+fn index_bytes(b: &[u8], key: &Expr) -> Expr {
+    match key {
+        Expr::Int(i) if *i >= 0 && (*i as usize) < b.len() => Expr::Int(b[*i as usize] as i64),
+        Expr::Int(i) => crate::stop!("Index out of bounds: {} for {} bytes", i, b.len()),
+        _ => crate::stop!("Type error: bytes index must be an integer, got {:?}", key),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
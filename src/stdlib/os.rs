@@ -1,5 +1,6 @@
 use crate::context::Context;
 use crate::expr::Expr;
+use crate::stdlib::capabilities::Capabilities;
 use std::collections::BTreeMap;
 use std::env;
 use std::sync::{Arc, RwLock};
@@ -9,10 +10,10 @@ pub fn register(ctx: &mut Context) {
 
     os_exports.insert(
         Expr::sym("args"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |_args, _ctx| {
                 let args: Vec<Expr> = env::args().map(Expr::Str).collect();
-                Expr::List(args)
+                Expr::List(args.into())
             },
             "args",
             "Command line arguments",
@@ -21,13 +22,16 @@ pub fn register(ctx: &mut Context) {
 
     os_exports.insert(
         Expr::sym("env"),
-        Expr::extern_fun(
-            |args, ctx| match eval_first(args, ctx) {
-                Expr::Str(key) => match env::var(key) {
-                    Ok(val) => Expr::Str(val),
-                    Err(_) => Expr::Nil,
-                },
-                _ => Expr::Nil,
+        Expr::extern_ref(
+            |args, ctx| {
+                Capabilities::require(ctx.caps.env, "OS.env");
+                match eval_first(args, ctx) {
+                    Expr::Str(key) => match env::var(key) {
+                        Ok(val) => Expr::Str(val),
+                        Err(_) => Expr::Nil,
+                    },
+                    _ => Expr::Nil,
+                }
             },
             "env",
             "Get environment variable",
@@ -36,8 +40,9 @@ pub fn register(ctx: &mut Context) {
 
     os_exports.insert(
         Expr::sym("exit"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
+                Capabilities::require(ctx.caps.exit, "OS.exit");
                 let code = match eval_first(args, ctx) {
                     Expr::Int(n) => n as i32,
                     _ => 0,
@@ -52,15 +57,16 @@ pub fn register(ctx: &mut Context) {
     // Command execution
     os_exports.insert(
         Expr::sym("exec"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
+                Capabilities::require(ctx.caps.process, "OS.exec");
                 if args.is_empty() {
                     return Expr::Nil;
                 }
 
                 let mut cmd_args = Vec::new();
                 for arg in args {
-                    match crate::context::eval(arg.clone(), ctx) {
+                    match crate::context::eval_ref(arg, ctx) {
                         Expr::Str(s) => cmd_args.push(s),
                         _ => return Expr::Nil,
                     }
@@ -91,13 +97,14 @@ pub fn register(ctx: &mut Context) {
 
     os_exports.insert(
         Expr::sym("set_env"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
+                Capabilities::require(ctx.caps.env, "OS.set_env");
                 if args.len() != 2 {
                     return Expr::Nil;
                 }
-                let key = crate::context::eval(args[0].clone(), ctx);
-                let val = crate::context::eval(args[1].clone(), ctx);
+                let key = crate::context::eval_ref(&args[0], ctx);
+                let val = crate::context::eval_ref(&args[1], ctx);
 
                 match (key, val) {
                     (Expr::Str(k), Expr::Str(v)) => {
@@ -116,7 +123,7 @@ pub fn register(ctx: &mut Context) {
 
     os_exports.insert(
         Expr::sym("cwd"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |_args, _ctx| match env::current_dir() {
                 Ok(p) => Expr::Str(p.to_string_lossy().to_string()),
                 Err(_) => Expr::Nil,
@@ -126,14 +133,14 @@ pub fn register(ctx: &mut Context) {
         ),
     );
 
-    let mod_val = Expr::Ref(Arc::new(RwLock::new(Expr::Map(os_exports))));
-    ctx.define(Expr::sym("OS"), mod_val);
+    let mod_val = Expr::Ref(Arc::new(RwLock::new(Expr::Map(os_exports.into()))));
+    ctx.define("OS", mod_val);
 }
 
 fn eval_first(args: &[Expr], ctx: &mut Context) -> Expr {
     if args.len() != 1 {
         Expr::Nil
     } else {
-        crate::context::eval(args[0].clone(), ctx)
+        crate::context::eval_ref(&args[0], ctx)
     }
 }
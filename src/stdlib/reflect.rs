@@ -9,31 +9,68 @@ pub fn register(ctx: &mut Context) {
     // Type inspection
     reflect_exports.insert(
         Expr::sym("of"),
-        Expr::extern_fun(
-            |args, ctx| match eval_first(args, ctx) {
-                Expr::Int(_) => Expr::Str("int".to_string()),
-                Expr::Float(_) => Expr::Str("float".to_string()),
-                Expr::Str(_) => Expr::Str("string".to_string()),
-                Expr::Sym(_) => Expr::Str("symbol".to_string()),
-                Expr::List(_) => Expr::Str("list".to_string()),
-                Expr::Map(_) | Expr::HashMap(_) => Expr::Str("map".to_string()),
-                Expr::Function { .. } | Expr::Extern { .. } => Expr::Str("fun".to_string()),
-                Expr::Nil => Expr::Str("nil".to_string()),
-                Expr::Ref(_) => Expr::Str("ref".to_string()),
-                Expr::Tagged { .. } => Expr::Str("tagged".to_string()),
-                Expr::Quoted(_) => Expr::Str("quoted".to_string()),
-            },
+        Expr::extern_ref(
+            |args, ctx| Expr::Str(type_of(&eval_first(args, ctx)).to_string()),
             "of",
             "Get type name",
         ),
     );
 
+    reflect_exports.insert(
+        Expr::sym("tag"),
+        Expr::extern_ref(
+            |args, ctx| {
+                if args.len() != 2 {
+                    return Expr::Nil;
+                }
+                match crate::context::eval_ref(&args[0], ctx) {
+                    Expr::Sym(tag) => Expr::Tagged {
+                        tag,
+                        value: Box::new(crate::context::eval_ref(&args[1], ctx)),
+                    },
+                    Expr::Str(s) => Expr::Tagged {
+                        tag: s.as_str().into(),
+                        value: Box::new(crate::context::eval_ref(&args[1], ctx)),
+                    },
+                    _ => Expr::Nil,
+                }
+            },
+            "tag",
+            "Wrap a value in a tag",
+        ),
+    );
+
+    reflect_exports.insert(
+        Expr::sym("untag"),
+        Expr::extern_ref(
+            |args, ctx| match eval_first(args, ctx) {
+                Expr::Tagged { value, .. } => *value,
+                other => other,
+            },
+            "untag",
+            "Get the value inside a tag",
+        ),
+    );
+
+    reflect_exports.insert(
+        Expr::sym("set_contracts"),
+        Expr::extern_ref(
+            |args, ctx| {
+                let on = !matches!(eval_first(args, ctx), Expr::Nil | Expr::Int(0));
+                ctx.settings.set_contracts(on);
+                Expr::Nil
+            },
+            "set_contracts",
+            "Turn checking of type annotations on or off",
+        ),
+    );
+
     // Type Checks
     reflect_exports.insert(
         Expr::sym("is_int"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
-                Expr::Int(_) => Expr::Int(1),
+                Expr::Int(_) | Expr::BigInt(_) => Expr::Int(1),
                 _ => Expr::Nil,
             },
             "is_int",
@@ -43,7 +80,7 @@ pub fn register(ctx: &mut Context) {
 
     reflect_exports.insert(
         Expr::sym("is_float"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Float(_) => Expr::Int(1),
                 _ => Expr::Nil,
@@ -55,7 +92,7 @@ pub fn register(ctx: &mut Context) {
 
     reflect_exports.insert(
         Expr::sym("is_string"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Str(_) => Expr::Int(1),
                 _ => Expr::Nil,
@@ -65,9 +102,21 @@ pub fn register(ctx: &mut Context) {
         ),
     );
 
+    reflect_exports.insert(
+        Expr::sym("is_keyword"),
+        Expr::extern_ref(
+            |args, ctx| match eval_first(args, ctx) {
+                Expr::Keyword(_) => Expr::Int(1),
+                _ => Expr::Nil,
+            },
+            "is_keyword",
+            "Is keyword?",
+        ),
+    );
+
     reflect_exports.insert(
         Expr::sym("is_list"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::List(_) => Expr::Int(1),
                 _ => Expr::Nil,
@@ -79,7 +128,7 @@ pub fn register(ctx: &mut Context) {
 
     reflect_exports.insert(
         Expr::sym("is_map"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Map(_) | Expr::HashMap(_) => Expr::Int(1),
                 _ => Expr::Nil,
@@ -91,7 +140,7 @@ pub fn register(ctx: &mut Context) {
 
     reflect_exports.insert(
         Expr::sym("is_nil"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Nil => Expr::Int(1),
                 _ => Expr::Nil,
@@ -104,7 +153,7 @@ pub fn register(ctx: &mut Context) {
     // Conversions
     reflect_exports.insert(
         Expr::sym("to_int"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Int(n) => Expr::Int(n),
                 Expr::Float(f) => Expr::Int(f as i64),
@@ -121,7 +170,7 @@ pub fn register(ctx: &mut Context) {
 
     reflect_exports.insert(
         Expr::sym("to_float"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Int(n) => Expr::Float(n as f64),
                 Expr::Float(f) => Expr::Float(f),
@@ -138,8 +187,11 @@ pub fn register(ctx: &mut Context) {
 
     reflect_exports.insert(
         Expr::sym("to_str"),
-        Expr::extern_fun(
-            |args, ctx| Expr::Str(eval_first(args, ctx).to_string()),
+        Expr::extern_ref(
+            |args, ctx| {
+                let val = eval_first(args, ctx);
+                Expr::Str(super::structs::to_display(&val, ctx))
+            },
             "to_str",
             "Convert to string",
         ),
@@ -147,10 +199,10 @@ pub fn register(ctx: &mut Context) {
 
     reflect_exports.insert(
         Expr::sym("to_sym"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Str(s) => Expr::sym(s.as_str()),
-                Expr::Sym(s) => Expr::sym(s.as_str()),
+                Expr::Sym(s) | Expr::Keyword(s) => Expr::Sym(s),
                 _ => Expr::Nil,
             },
             "to_sym",
@@ -158,14 +210,59 @@ pub fn register(ctx: &mut Context) {
         ),
     );
 
-    let mod_val = Expr::Ref(Arc::new(RwLock::new(Expr::Map(reflect_exports))));
-    ctx.define(Expr::sym("Type"), mod_val);
+    reflect_exports.insert(
+        Expr::sym("to_keyword"),
+        Expr::extern_ref(
+            |args, ctx| match eval_first(args, ctx) {
+                Expr::Str(s) => Expr::keyword(s.as_str()),
+                Expr::Sym(s) | Expr::Keyword(s) => Expr::Keyword(s),
+                _ => Expr::Nil,
+            },
+            "to_keyword",
+            "Convert to keyword",
+        ),
+    );
+
+    reflect_exports.insert(
+        Expr::sym("name"),
+        Expr::extern_ref(
+            |args, ctx| match eval_first(args, ctx) {
+                Expr::Sym(s) | Expr::Keyword(s) => Expr::Str(s.to_string()),
+                Expr::Str(s) => Expr::Str(s),
+                _ => Expr::Nil,
+            },
+            "name",
+            "Name of a keyword or symbol, without the colon",
+        ),
+    );
+
+    let mod_val = Expr::Ref(Arc::new(RwLock::new(Expr::Map(reflect_exports.into()))));
+    ctx.define("Type", mod_val);
+}
+
+/// The name `Type.of` gives the type of a value.
+pub fn type_of(val: &Expr) -> &'static str {
+    match val {
+        Expr::Int(_) | Expr::BigInt(_) => "int",
+        Expr::Float(_) => "float",
+        Expr::Str(_) => "string",
+        Expr::Sym(_) | Expr::Local { .. } => "symbol",
+        Expr::Keyword(_) => "keyword",
+        Expr::Bytes(_) => "bytes",
+        Expr::List(_) => "list",
+        Expr::Map(_) | Expr::HashMap(_) => "map",
+        Expr::Function { .. } | Expr::Extern { .. } => "fun",
+        Expr::Nil => "nil",
+        Expr::Ref(_) => "ref",
+        Expr::Tagged { .. } => "tagged",
+        Expr::Quoted(_) => "quoted",
+    }
 }
 
 fn eval_first(args: &[Expr], ctx: &mut Context) -> Expr {
     if args.len() != 1 {
         Expr::Nil
     } else {
-        crate::context::eval(args[0].clone(), ctx)
+        crate::context::eval_ref(&args[0], ctx)
     }
 }
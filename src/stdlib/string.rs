@@ -9,7 +9,7 @@ pub fn register(ctx: &mut Context) {
     // Inspection
     string_exports.insert(
         Expr::sym("len"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Str(s) => Expr::Int(s.len() as i64),
                 Expr::Nil => Expr::Int(0),
@@ -17,12 +17,13 @@ pub fn register(ctx: &mut Context) {
             },
             "len",
             "Length of string",
-        ),
+        )
+        .with_signature(&["string"], "int"),
     );
 
     string_exports.insert(
         Expr::sym("is_empty"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Str(s) => {
                     if s.is_empty() {
@@ -42,49 +43,52 @@ pub fn register(ctx: &mut Context) {
     // Manipulation
     string_exports.insert(
         Expr::sym("trim"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Str(s) => Expr::Str(s.trim().to_string()),
                 _ => Expr::Nil,
             },
             "trim",
             "Trim whitespace",
-        ),
+        )
+        .with_signature(&["string"], "string"),
     );
 
     string_exports.insert(
         Expr::sym("to_upper"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Str(s) => Expr::Str(s.to_uppercase()),
                 _ => Expr::Nil,
             },
             "to_upper",
             "To Uppercase",
-        ),
+        )
+        .with_signature(&["string"], "string"),
     );
 
     string_exports.insert(
         Expr::sym("to_lower"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Str(s) => Expr::Str(s.to_lowercase()),
                 _ => Expr::Nil,
             },
             "to_lower",
             "To Lowercase",
-        ),
+        )
+        .with_signature(&["string"], "string"),
     );
 
     string_exports.insert(
         Expr::sym("split"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     return Expr::Nil;
                 }
-                let s_expr = crate::context::eval(args[0].clone(), ctx);
-                let sep_expr = crate::context::eval(args[1].clone(), ctx);
+                let s_expr = crate::context::eval_ref(&args[0], ctx);
+                let sep_expr = crate::context::eval_ref(&args[1], ctx);
 
                 match (s_expr, sep_expr) {
                     (Expr::Str(s), Expr::Str(sep)) => Expr::List(
@@ -97,18 +101,19 @@ pub fn register(ctx: &mut Context) {
             },
             "split",
             "Split string by separator",
-        ),
+        )
+        .with_signature(&["string", "string"], "list"),
     );
 
     string_exports.insert(
         Expr::sym("join"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     return Expr::Nil;
                 }
-                let list_expr = crate::context::eval(args[0].clone(), ctx);
-                let sep_expr = crate::context::eval(args[1].clone(), ctx);
+                let list_expr = crate::context::eval_ref(&args[0], ctx);
+                let sep_expr = crate::context::eval_ref(&args[1], ctx);
 
                 match (list_expr, sep_expr) {
                     (Expr::List(l), Expr::Str(sep)) => {
@@ -126,19 +131,20 @@ pub fn register(ctx: &mut Context) {
             },
             "join",
             "Join list of strings with separator",
-        ),
+        )
+        .with_signature(&["list", "string"], "string"),
     );
 
     string_exports.insert(
         Expr::sym("replace"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 3 {
                     return Expr::Nil;
                 }
-                let s = crate::context::eval(args[0].clone(), ctx);
-                let old = crate::context::eval(args[1].clone(), ctx);
-                let new = crate::context::eval(args[2].clone(), ctx);
+                let s = crate::context::eval_ref(&args[0], ctx);
+                let old = crate::context::eval_ref(&args[1], ctx);
+                let new = crate::context::eval_ref(&args[2], ctx);
 
                 match (s, old, new) {
                     (Expr::Str(s), Expr::Str(o), Expr::Str(n)) => Expr::Str(s.replace(&o, &n)),
@@ -147,20 +153,21 @@ pub fn register(ctx: &mut Context) {
             },
             "replace",
             "Replace occurrences",
-        ),
+        )
+        .with_signature(&["string", "string", "string"], "string"),
     );
 
     string_exports.insert(
         Expr::sym("substring"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() < 2 {
                     return Expr::Nil;
                 }
-                let s = crate::context::eval(args[0].clone(), ctx);
-                let start = crate::context::eval(args[1].clone(), ctx);
+                let s = crate::context::eval_ref(&args[0], ctx);
+                let start = crate::context::eval_ref(&args[1], ctx);
                 let len_opt = if args.len() > 2 {
-                    Some(crate::context::eval(args[2].clone(), ctx))
+                    Some(crate::context::eval_ref(&args[2], ctx))
                 } else {
                     None
                 };
@@ -190,50 +197,54 @@ pub fn register(ctx: &mut Context) {
 
     string_exports.insert(
         Expr::sym("chars"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Str(s) => {
                     let chars: Vec<Expr> = s.chars().map(|c| Expr::Str(c.to_string())).collect();
-                    Expr::List(chars)
+                    Expr::List(chars.into())
                 }
                 _ => Expr::Nil,
             },
             "chars",
             "Split string into list of characters",
-        ),
+        )
+        .with_signature(&["string"], "list"),
     );
 
     string_exports.insert(
         Expr::sym("lines"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Str(s) => {
                     let lines: Vec<Expr> =
                         s.lines().map(|line| Expr::Str(line.to_string())).collect();
-                    Expr::List(lines)
+                    Expr::List(lines.into())
                 }
                 _ => Expr::Nil,
             },
             "lines",
             "Split string into lines",
-        ),
+        )
+        .with_signature(&["string"], "list"),
     );
 
     // Manipulation
     string_exports.insert(
         Expr::sym("repeat"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     return Expr::Nil;
                 }
-                let s = crate::context::eval(args[0].clone(), ctx);
-                let n = crate::context::eval(args[1].clone(), ctx);
+                let s = crate::context::eval_ref(&args[0], ctx);
+                let n = crate::context::eval_ref(&args[1], ctx);
                 match (s, n) {
                     (Expr::Str(s), Expr::Int(n)) => {
                         if n <= 0 {
                             Expr::Str("".to_string())
                         } else {
+                            ctx.limits
+                                .reserve(s.len().saturating_mul(n as usize), "repeat");
                             Expr::Str(s.repeat(n as usize))
                         }
                     }
@@ -242,25 +253,26 @@ pub fn register(ctx: &mut Context) {
             },
             "repeat",
             "Repeat string n times",
-        ),
+        )
+        .with_signature(&["string", "int"], "string"),
     );
 
     string_exports.insert(
         Expr::sym("pad_left"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() < 2 {
                     return Expr::Nil;
                 }
-                let s = crate::context::eval(args[0].clone(), ctx)
+                let s = crate::context::eval_ref(&args[0], ctx)
                     .as_str()
                     .unwrap_or("")
                     .to_string();
-                let width = crate::context::eval(args[1].clone(), ctx)
+                let width = crate::context::eval_ref(&args[1], ctx)
                     .as_int()
                     .unwrap_or(0) as usize;
                 let pad_char = if args.len() > 2 {
-                    crate::context::eval(args[2].clone(), ctx)
+                    crate::context::eval_ref(&args[2], ctx)
                         .as_str()
                         .unwrap_or(" ")
                         .chars()
@@ -289,20 +301,20 @@ pub fn register(ctx: &mut Context) {
 
     string_exports.insert(
         Expr::sym("pad_right"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() < 2 {
                     return Expr::Nil;
                 }
-                let s = crate::context::eval(args[0].clone(), ctx)
+                let s = crate::context::eval_ref(&args[0], ctx)
                     .as_str()
                     .unwrap_or("")
                     .to_string();
-                let width = crate::context::eval(args[1].clone(), ctx)
+                let width = crate::context::eval_ref(&args[1], ctx)
                     .as_int()
                     .unwrap_or(0) as usize;
                 let pad_char = if args.len() > 2 {
-                    crate::context::eval(args[2].clone(), ctx)
+                    crate::context::eval_ref(&args[2], ctx)
                         .as_str()
                         .unwrap_or(" ")
                         .chars()
@@ -331,13 +343,13 @@ pub fn register(ctx: &mut Context) {
     // Predicates
     string_exports.insert(
         Expr::sym("starts_with"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     return Expr::Nil;
                 }
-                let s = crate::context::eval(args[0].clone(), ctx);
-                let prefix = crate::context::eval(args[1].clone(), ctx);
+                let s = crate::context::eval_ref(&args[0], ctx);
+                let prefix = crate::context::eval_ref(&args[1], ctx);
                 match (s, prefix) {
                     (Expr::Str(s), Expr::Str(p)) => {
                         if s.starts_with(&p) {
@@ -351,18 +363,19 @@ pub fn register(ctx: &mut Context) {
             },
             "starts_with",
             "Check prefix",
-        ),
+        )
+        .with_signature(&["string", "string"], "any"),
     );
 
     string_exports.insert(
         Expr::sym("ends_with"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     return Expr::Nil;
                 }
-                let s = crate::context::eval(args[0].clone(), ctx);
-                let suffix = crate::context::eval(args[1].clone(), ctx);
+                let s = crate::context::eval_ref(&args[0], ctx);
+                let suffix = crate::context::eval_ref(&args[1], ctx);
                 match (s, suffix) {
                     (Expr::Str(s), Expr::Str(p)) => {
                         if s.ends_with(&p) {
@@ -376,18 +389,19 @@ pub fn register(ctx: &mut Context) {
             },
             "ends_with",
             "Check suffix",
-        ),
+        )
+        .with_signature(&["string", "string"], "any"),
     );
 
     string_exports.insert(
         Expr::sym("contains"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.len() != 2 {
                     return Expr::Nil;
                 }
-                let s = crate::context::eval(args[0].clone(), ctx);
-                let sub = crate::context::eval(args[1].clone(), ctx);
+                let s = crate::context::eval_ref(&args[0], ctx);
+                let sub = crate::context::eval_ref(&args[1], ctx);
                 match (s, sub) {
                     (Expr::Str(s), Expr::Str(sub)) => {
                         if s.contains(&sub) {
@@ -401,18 +415,19 @@ pub fn register(ctx: &mut Context) {
             },
             "contains",
             "Check contains",
-        ),
+        )
+        .with_signature(&["string", "string"], "any"),
     );
 
     // Formatting
     string_exports.insert(
         Expr::sym("fmt"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| {
                 if args.is_empty() {
                     return Expr::Nil;
                 }
-                let template_val = crate::context::eval(args[0].clone(), ctx);
+                let template_val = crate::context::eval_ref(&args[0], ctx);
                 let template = match template_val {
                     Expr::Str(s) => s,
                     _ => return Expr::Nil,
@@ -427,10 +442,10 @@ pub fn register(ctx: &mut Context) {
                         if let Some(&'}') = chars.peek() {
                             chars.next(); // Consume '}'
                             if arg_idx < args.len() {
-                                let val = crate::context::eval(args[arg_idx].clone(), ctx);
+                                let val = crate::context::eval_ref(&args[arg_idx], ctx);
                                 match val {
                                     Expr::Str(s) => result.push_str(&s),
-                                    _ => result.push_str(&val.to_string()),
+                                    _ => result.push_str(&super::structs::to_display(&val, ctx)),
                                 }
                                 arg_idx += 1;
                             } else {
@@ -450,14 +465,14 @@ pub fn register(ctx: &mut Context) {
         ),
     );
 
-    let mod_val = Expr::Ref(Arc::new(RwLock::new(Expr::Map(string_exports))));
-    ctx.define(Expr::sym("String"), mod_val);
+    let mod_val = Expr::Ref(Arc::new(RwLock::new(Expr::Map(string_exports.into()))));
+    ctx.define("String", mod_val);
 }
 
 fn eval_first(args: &[Expr], ctx: &mut Context) -> Expr {
     if args.len() != 1 {
         Expr::Nil
     } else {
-        crate::context::eval(args[0].clone(), ctx)
+        crate::context::eval_ref(&args[0], ctx)
     }
 }
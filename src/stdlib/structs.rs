@@ -0,0 +1,396 @@
+use super::contracts;
+use crate::context::{Context, InterfaceDef, StructDef, TypeDef, eval_ref};
+use crate::error::RwLockExt;
+use crate::expr::Expr;
+use crate::symbol::Symbol;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+/// Field under which every struct instance records the name of its type.
+pub const TYPE_KEY: &str = "__type__";
+/// Variable bound in a method's environment to the struct that defined it, used by `super`.
+const CLASS_KEY: &str = "__class__";
+
+pub fn register(ctx: &mut Context) {
+    ctx.define(
+        "struct",
+        Expr::extern_ref(
+            define_struct,
+            "struct",
+            "Define a struct with fields and methods. (struct Name [extends Parent] [implements Iface...] (fields) (method (params) body)...)",
+        )
+        .special(),
+    );
+
+    ctx.define(
+        "interface",
+        Expr::extern_ref(
+            |args, ctx| {
+                if args.is_empty() {
+                    crate::stop!("interface requires a name");
+                }
+                let name = match &args[0] {
+                    Expr::Sym(s) => s.clone(),
+                    other => crate::stop!("Interface name must be a symbol, got {:?}", other),
+                };
+
+                let mut methods = Vec::new();
+                for sig in &args[1..] {
+                    let (m_name, params) = match sig {
+                        Expr::List(l) if !l.is_empty() => (&l[0], l.len() - 1),
+                        Expr::Sym(_) => (sig, 0),
+                        other => crate::stop!(
+                            "Interface {} method signature must be (name params...), got {:?}",
+                            name,
+                            other
+                        ),
+                    };
+                    match m_name {
+                        Expr::Sym(s) => methods.push((s.clone(), params)),
+                        other => crate::stop!("Method name must be a symbol, got {:?}", other),
+                    }
+                }
+
+                ctx.define_type(
+                    name.clone(),
+                    TypeDef::Interface(Arc::new(InterfaceDef { name, methods })),
+                );
+                Expr::Nil
+            },
+            "interface",
+            "Declare the methods a struct must implement. (interface Name (method params...)...)",
+        )
+        .special(),
+    );
+}
+
+fn define_struct(args: &[Expr], ctx: &mut Context) -> Expr {
+    if args.len() < 2 {
+        crate::stop!("struct requires a name and a list of fields");
+    }
+    let struct_name = match &args[0] {
+        Expr::Sym(s) => s.clone(),
+        _ => crate::stop!("Struct name must be a symbol"),
+    };
+
+    // Header: optional `extends Parent` and `implements A B ...` before the field list
+    let mut parent = None;
+    let mut interfaces = Vec::new();
+    let mut i = 1;
+    while let Some(Expr::Sym(s)) = args.get(i) {
+        match s.as_str() {
+            "extends" => match args.get(i + 1) {
+                Some(Expr::Sym(p)) => {
+                    parent = Some(p.clone());
+                    i += 2;
+                }
+                other => crate::stop!("extends expects a struct name, got {:?}", other),
+            },
+            "implements" => {
+                i += 1;
+                while let Some(Expr::Sym(iface)) = args.get(i) {
+                    if matches!(iface.as_str(), "extends" | "implements") {
+                        break;
+                    }
+                    interfaces.push(iface.clone());
+                    i += 1;
+                }
+            }
+            other => crate::stop!(
+                "Unexpected {} in definition of struct {}",
+                other,
+                struct_name
+            ),
+        }
+    }
+
+    let parent_def = parent.as_ref().map(|p| match ctx.get_struct(p) {
+        Some(def) => def,
+        None => crate::stop!("Struct {} cannot extend unknown struct {}", struct_name, p),
+    });
+
+    let (mut fields, mut field_types) = parent_def
+        .as_ref()
+        .map(|def| (def.fields.clone(), def.field_types.clone()))
+        .unwrap_or_default();
+    match args.get(i) {
+        Some(Expr::List(lst)) => {
+            let (names, types) = match contracts::parse_params(lst) {
+                Some(parsed) => parsed,
+                None => crate::stop!(
+                    "Struct {} fields must be symbols, optionally annotated as (name: Type), got {:?}",
+                    struct_name,
+                    lst
+                ),
+            };
+            for (name, ty) in names.into_iter().zip(types) {
+                if fields.contains(&name) {
+                    crate::stop!("Struct {} declares field {} twice", struct_name, name)
+                }
+                fields.push(name);
+                field_types.push(ty);
+            }
+        }
+        Some(Expr::Nil) => {}
+        _ => crate::stop!("Struct fields must be a list of symbols"),
+    }
+
+    // Methods close over a scope that knows which struct they belong to, so `super` can find the parent
+    let class_ctx = ctx.fork();
+    class_ctx.define(CLASS_KEY, Expr::Sym(struct_name.clone()));
+
+    let mut methods = BTreeMap::new();
+    for method_def in &args[i + 1..] {
+        let l = match method_def {
+            Expr::List(l) if l.len() >= 3 => l,
+            Expr::List(_) => {
+                crate::stop!("Method definition requires a name, parameters, and a body")
+            }
+            _ => crate::stop!("Method definition must be a list"),
+        };
+        let m_name = match &l[0] {
+            Expr::Sym(s) => s.clone(),
+            _ => crate::stop!("Method name must be a symbol"),
+        };
+
+        let (m_params, m_types) = match &l[1] {
+            Expr::List(pl) => match contracts::parse_params(pl) {
+                Some(parsed) => parsed,
+                None => crate::stop!(
+                    "Parameters of method {}.{} must be symbols, optionally annotated as (name: Type), got {:?}",
+                    struct_name,
+                    m_name,
+                    pl
+                ),
+            },
+            Expr::Nil => (vec![], vec![]),
+            _ => crate::stop!("Method parameters must be a list of symbols"),
+        };
+
+        let rest: Vec<Expr> = l.iter().skip(2).cloned().collect();
+        let (m_ret, body_exprs) = contracts::parse_return(&rest);
+        let m_body = match body_exprs {
+            [] => crate::stop!("Method {}.{} requires a body", struct_name, m_name),
+            [single] => single.clone(),
+            _ => {
+                let mut do_block = vec![Expr::Sym(Symbol::DO)];
+                do_block.extend(body_exprs.iter().cloned());
+                Expr::List(do_block.into())
+            }
+        };
+        let m_body = crate::resolve::resolve_body(&m_params, Some(&m_name), m_body, &class_ctx);
+        let m_body = contracts::wrap_body(Some(&m_name), &m_params, &m_types, m_ret, m_body);
+
+        methods.insert(
+            m_name.clone(),
+            Expr::Function {
+                params: m_params.into(),
+                body: Arc::new(m_body),
+                env: class_ctx.clone(),
+                name: Some(m_name),
+                receiver: None,
+            },
+        );
+    }
+
+    let def = StructDef {
+        name: struct_name.clone(),
+        parent,
+        fields: fields.clone(),
+        field_types: field_types.clone(),
+        methods,
+        interfaces: interfaces.clone(),
+    };
+    // Checked before the struct is registered, so a failing definition leaves no trace
+    for iface in &interfaces {
+        check_interface(&def, iface, ctx);
+    }
+    ctx.define_type(struct_name.clone(), TypeDef::Struct(Arc::new(def)));
+
+    let type_name = Expr::Sym(struct_name.clone());
+    let constructor = Expr::extern_ref(
+        move |ctor_args, ctx| {
+            if ctor_args.len() != fields.len() {
+                crate::stop!(
+                    "{} expects {} fields {:?}, got {} arguments",
+                    type_name,
+                    fields.len(),
+                    fields,
+                    ctor_args.len()
+                );
+            }
+
+            let mut obj_map = BTreeMap::new();
+            for ((field, ty), arg) in fields.iter().zip(&field_types).zip(ctor_args.iter()) {
+                let val = eval_ref(arg, ctx);
+                if let Some(ty) = ty
+                    && ctx.settings.contracts()
+                    && !contracts::has_type(&val, ty, ctx)
+                {
+                    crate::stop!(
+                        "{}: field {} expects {}, got {}",
+                        type_name,
+                        field,
+                        ty,
+                        contracts::describe(&val)
+                    );
+                }
+                obj_map.insert(Expr::Sym(field.clone()), val);
+            }
+            obj_map.insert(Expr::sym(TYPE_KEY), type_name.clone());
+
+            Expr::Ref(Arc::new(RwLock::new(Expr::Map(obj_map.into()))))
+        },
+        struct_name.to_string(),
+        "Struct Constructor",
+    );
+
+    ctx.define(struct_name, constructor);
+    Expr::Nil
+}
+
+fn check_interface(def: &StructDef, iface: &Symbol, ctx: &Context) {
+    let struct_name = &def.name;
+    let find_method = |m_name: &Symbol| match def.methods.get(m_name) {
+        Some(m) => Some((struct_name.clone(), m.clone())),
+        None => def.parent.as_ref().and_then(|p| ctx.find_method(p, m_name)),
+    };
+    let iface_def = match ctx.get_type(iface) {
+        Some(TypeDef::Interface(def)) => def,
+        _ => crate::stop!(
+            "Struct {} implements unknown interface {}",
+            struct_name,
+            iface
+        ),
+    };
+    for (m_name, arity) in &iface_def.methods {
+        match find_method(m_name) {
+            Some((_, Expr::Function { params, .. })) if params.len() == *arity => {}
+            Some((owner, Expr::Function { params, .. })) => crate::stop!(
+                "Struct {} does not satisfy interface {}: method {}.{} takes {} parameters, expected {}",
+                struct_name,
+                iface,
+                owner,
+                m_name,
+                params.len(),
+                arity
+            ),
+            _ => crate::stop!(
+                "Struct {} does not satisfy interface {}: missing method {}",
+                struct_name,
+                iface,
+                m_name
+            ),
+        }
+    }
+}
+
+/// Get the struct type name recorded on an instance, if the value is one.
+pub fn type_name_of(obj: &Expr) -> Option<Symbol> {
+    match obj {
+        Expr::Map(m) => match m.get(&Expr::sym(TYPE_KEY)) {
+            Some(Expr::Sym(s)) => Some(s.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Find a method in the type of a struct instance and bind `self` to the instance.
+/// `inner` is the map behind the reference `obj`.
+pub fn instance_method(inner: &Expr, obj: &Expr, method: &Symbol, ctx: &Context) -> Option<Expr> {
+    let type_name = type_name_of(inner)?;
+    let (_, func) = ctx.find_method(&type_name, method)?;
+    Some(bind_self(func, obj))
+}
+
+/// Resolve `super.method` inside a method body: look the method up starting from the
+/// parent of the struct that defined the running method, bound to the same `self`.
+pub fn super_method(method: &Expr, ctx: &Context) -> Expr {
+    let method = match method {
+        Expr::Sym(s) => s,
+        other => crate::stop!("super expects a method name, got {:?}", other),
+    };
+    let (class, obj) = match (
+        ctx.resolve(&Symbol::new(CLASS_KEY)),
+        ctx.resolve(&Symbol::SELF),
+    ) {
+        (Some(Expr::Sym(class)), Some(obj)) => (class, obj),
+        _ => crate::stop!("super.{} used outside of a struct method", method),
+    };
+    let parent = ctx.get_struct(&class).and_then(|def| def.parent.clone());
+    let parent = match parent {
+        Some(p) => p,
+        None => crate::stop!("super.{}: struct {} has no parent", method, class),
+    };
+    match ctx.find_method(&parent, method) {
+        Some((_, func)) => bind_self(func, &obj),
+        None => crate::stop!(
+            "super.{}: no method {} above struct {}",
+            method,
+            method,
+            class
+        ),
+    }
+}
+
+/// Bind a method to `obj`, which becomes `self` in the scope of each call to it.
+pub fn bind_self(func: Expr, obj: &Expr) -> Expr {
+    match func {
+        Expr::Function {
+            params,
+            body,
+            env,
+            name,
+            ..
+        } => Expr::Function {
+            params,
+            body,
+            env,
+            name,
+            receiver: Some(Box::new(obj.clone())),
+        },
+        other => other,
+    }
+}
+
+/// Find a magic method such as `__add__` on a struct instance or object reference, bound to
+/// `self`. Returns `None` for values that aren't references to maps, so callers can fall back
+/// to the built-in behavior.
+pub fn magic_method(obj: &Expr, name: &str, ctx: &Context) -> Option<Expr> {
+    let r = match obj {
+        Expr::Ref(r) => r,
+        _ => return None,
+    };
+    let guard = r.read_unpoisoned();
+    let key = Symbol::new(name);
+    let own = match &*guard {
+        Expr::Map(m) => m.get(&Expr::Sym(key.clone())),
+        Expr::HashMap(m) => m.get(&Expr::Sym(key.clone())),
+        _ => return None,
+    };
+    if let Some(func @ Expr::Function { .. }) = own {
+        return Some(bind_self(func.clone(), obj));
+    }
+    instance_method(&guard, obj, &key, ctx)
+}
+
+/// Call a magic method on `obj` if it defines one.
+pub fn call_magic(obj: &Expr, name: &str, args: &[Expr], ctx: &mut Context) -> Option<Expr> {
+    let method = magic_method(obj, name, ctx)?;
+    Some(super::call_anon_fn(&method, args, ctx))
+}
+
+/// The text `print` and `String.fmt` show for a value, using `__str__` when it is defined.
+pub fn to_display(val: &Expr, ctx: &mut Context) -> String {
+    match call_magic(val, "__str__", &[], ctx) {
+        Some(Expr::Str(s)) => s,
+        Some(other) => other.to_string(),
+        None => val.to_string(),
+    }
+}
+
+/// The list to loop over for a value, using `__iter__` when it is defined.
+pub fn to_iter(val: Expr, ctx: &mut Context) -> Expr {
+    call_magic(&val, "__iter__", &[], ctx).unwrap_or(val)
+}
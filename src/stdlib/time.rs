@@ -9,7 +9,7 @@ pub fn register(ctx: &mut Context) {
 
     time_exports.insert(
         Expr::sym("now"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |_args, _ctx| {
                 let start = SystemTime::now();
                 let since_the_epoch = start
@@ -24,7 +24,7 @@ pub fn register(ctx: &mut Context) {
 
     time_exports.insert(
         Expr::sym("sleep"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Int(n) => {
                     std::thread::sleep(Duration::from_millis(n as u64));
@@ -43,7 +43,7 @@ pub fn register(ctx: &mut Context) {
 
     time_exports.insert(
         Expr::sym("format"),
-        Expr::extern_fun(
+        Expr::extern_ref(
             |args, ctx| match eval_first(args, ctx) {
                 Expr::Float(ts) => Expr::Str(format!("{}", ts)),
                 _ => Expr::Nil,
@@ -53,14 +53,14 @@ pub fn register(ctx: &mut Context) {
         ),
     );
 
-    let mod_val = Expr::Ref(Arc::new(RwLock::new(Expr::Map(time_exports))));
-    ctx.define(Expr::sym("Time"), mod_val);
+    let mod_val = Expr::Ref(Arc::new(RwLock::new(Expr::Map(time_exports.into()))));
+    ctx.define("Time", mod_val);
 }
 
 fn eval_first(args: &[Expr], ctx: &mut Context) -> Expr {
     if args.len() != 1 {
         Expr::Nil
     } else {
-        crate::context::eval(args[0].clone(), ctx)
+        crate::context::eval_ref(&args[0], ctx)
     }
 }
@@ -1,60 +1,121 @@
-use lazy_static::lazy_static;
-use std::sync::Arc;
-
-/// An interned symbol.
-#[derive(Clone, Eq, Hash, PartialOrd, Ord)]
-pub struct Symbol(Arc<str>);
+use crate::error::MutexExt;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex, OnceLock};
+
+/// An interned symbol: a small integer ID, so equality, hashing and ordering never look
+/// at the string. Symbols order by when they were first interned; use
+/// [`Expr::cmp_by_name`](crate::expr::Expr::cmp_by_name) to put them in alphabetical order.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol(u32);
+
+/// Names interned before anything else, so their IDs are known at compile time.
+const WELL_KNOWN: [&str; 5] = ["self", "do", "load", "update", "draw"];
+
+/// Maps names to IDs. Only taken the first time a thread sees a name.
+static INTERNER: LazyLock<Mutex<HashMap<&'static str, u32>>> = LazyLock::new(|| {
+    Mutex::new(
+        WELL_KNOWN
+            .iter()
+            .enumerate()
+            .map(|(id, name)| (*name, id as u32))
+            .collect(),
+    )
+});
+
+/// Maps IDs back to names. Slots are written once, under the interner lock, and read
+/// without locking. Bucket `b` holds `FIRST_BUCKET << b` names, so it never has to move.
+const FIRST_BUCKET: usize = 64;
+const BUCKETS: usize = 26;
+type Bucket = Box<[OnceLock<&'static str>]>;
+static NAMES: [OnceLock<Bucket>; BUCKETS] = [const { OnceLock::new() }; BUCKETS];
+
+thread_local! {
+    static CACHE: RefCell<HashMap<&'static str, u32>> = RefCell::new(HashMap::new());
+}
 
-lazy_static! {
-    static ref SYMBOL_TABLE: std::sync::RwLock<std::collections::HashSet<Arc<str>>> =
-        std::sync::RwLock::new(std::collections::HashSet::new());
+/// The bucket and offset in `NAMES` of an ID.
+fn slot(id: u32) -> (usize, usize) {
+    let n = id as usize + FIRST_BUCKET;
+    let bucket = n.ilog2() as usize - FIRST_BUCKET.ilog2() as usize;
+    (bucket, n - (FIRST_BUCKET << bucket))
 }
 
-fn get_interned(s: &str) -> Arc<str> {
-    let existing = {
-        let table = SYMBOL_TABLE.read().unwrap();
-        table.get(s).cloned()
-    };
-    if let Some(existing) = existing {
-        existing
-    } else {
-        let mut table = SYMBOL_TABLE.write().unwrap();
-        if let Some(existing) = table.get(s) {
-            return existing.clone();
+fn intern(s: &str) -> u32 {
+    if let Some(id) = CACHE.with(|cache| cache.borrow().get(s).copied()) {
+        return id;
+    }
+    let (name, id) = {
+        let mut table = INTERNER.lock_unpoisoned();
+        match table.get_key_value(s) {
+            Some((name, id)) => (*name, *id),
+            None => {
+                let id = table.len() as u32;
+                let name: &'static str = Box::leak(s.into());
+                let (bucket, offset) = slot(id);
+                let names = NAMES
+                    .get(bucket)
+                    .expect("too many symbols")
+                    .get_or_init(|| {
+                        (0..FIRST_BUCKET << bucket)
+                            .map(|_| OnceLock::new())
+                            .collect()
+                    });
+                let _ = names[offset].set(name);
+                table.insert(name, id);
+                (name, id)
+            }
         }
-        let arc_str: Arc<str> = Arc::from(s);
-        table.insert(arc_str.clone());
-        arc_str
-    }
+    };
+    CACHE.with(|cache| cache.borrow_mut().insert(name, id));
+    id
 }
 
 impl Symbol {
+    pub const SELF: Symbol = Symbol(0);
+    pub const DO: Symbol = Symbol(1);
+    pub const LOAD: Symbol = Symbol(2);
+    pub const UPDATE: Symbol = Symbol(3);
+    pub const DRAW: Symbol = Symbol(4);
+
     pub fn new(s: &str) -> Self {
-        Self(get_interned(s))
+        Self(intern(s))
     }
 
-    pub fn as_str(&self) -> &str {
-        &self.0
+    /// The ID of the symbol, unique for the life of the process.
+    pub fn id(&self) -> u32 {
+        self.0
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        if let Some(name) = WELL_KNOWN.get(self.0 as usize) {
+            return name;
+        }
+        let (bucket, offset) = slot(self.0);
+        NAMES[bucket]
+            .get()
+            .and_then(|names| names[offset].get())
+            .expect("symbol was interned")
     }
 
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.as_str().len()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.as_str().is_empty()
     }
 }
 
 impl std::fmt::Display for Symbol {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", &self.0)
+        write!(f, "{}", self.as_str())
     }
 }
 
 impl std::fmt::Debug for Symbol {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", &self.0)
+        write!(f, "{:?}", self.as_str())
     }
 }
 
@@ -70,9 +131,15 @@ impl From<String> for Symbol {
     }
 }
 
+impl From<&Symbol> for Symbol {
+    fn from(value: &Symbol) -> Self {
+        value.clone()
+    }
+}
+
 impl AsRef<str> for Symbol {
     fn as_ref(&self) -> &str {
-        &self.0
+        self.as_str()
     }
 }
 
@@ -80,19 +147,7 @@ impl std::ops::Deref for Symbol {
     type Target = str;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
-
-impl std::borrow::Borrow<str> for Symbol {
-    fn borrow(&self) -> &str {
-        &self.0
-    }
-}
-
-impl PartialEq for Symbol {
-    fn eq(&self, other: &Self) -> bool {
-        Arc::ptr_eq(&self.0, &other.0)
+        self.as_str()
     }
 }
 
@@ -104,11 +159,34 @@ mod tests {
         let sym1 = Symbol::new("example");
         let sym2 = Symbol::new("example");
         assert!(sym1 == sym2);
-        assert!(std::ptr::eq(&*sym1.0, &*sym2.0));
+        assert_eq!(sym1.id(), sym2.id());
         let sym3 = Symbol::new("different");
         assert!(sym1 != sym3);
     }
 
+    #[test]
+    fn test_symbol_well_known() {
+        assert_eq!(Symbol::new("self"), Symbol::SELF);
+        assert_eq!(Symbol::new("draw"), Symbol::DRAW);
+        assert_eq!(Symbol::DO.as_str(), "do");
+    }
+
+    #[test]
+    fn test_symbol_across_threads() {
+        let ids: Vec<u32> = (0..4)
+            .map(|i| std::thread::spawn(move || Symbol::new(&format!("threaded_{}", i % 2)).id()))
+            .map(|t| t.join().unwrap())
+            .collect();
+        assert_eq!(ids[0], ids[2]);
+        assert_eq!(ids[1], ids[3]);
+        assert_ne!(ids[0], ids[1]);
+        // Names beyond the first bucket are stored and read back
+        for i in 0..200 {
+            let name = format!("many_{}", i);
+            assert_eq!(Symbol::new(&name).as_str(), name);
+        }
+    }
+
     #[test]
     fn test_symbol_display() {
         let sym = Symbol::new("display_test");
@@ -0,0 +1,523 @@
+//! The filesystem scripts see through `IO` and the `Game` asset loaders. Every interpreter
+//! has one, shared by all of its scopes, and it is the disk unless the host picks another:
+//!
+//! ```
+//! use onion::{Expr, Interpreter};
+//! use onion::vfs::MemoryFs;
+//! use std::sync::Arc;
+//!
+//! let files = Arc::new(MemoryFs::new().with_file("level.txt", "###"));
+//! let mut onion = Interpreter::builder().vfs(files.clone()).build();
+//! onion.eval_str("(IO.write_file \"save.txt\" (IO.read_file \"level.txt\"))").unwrap();
+//! assert_eq!(files.get("save.txt").unwrap(), b"###");
+//! ```
+//!
+//! [`RealFs`] is the disk, [`MemoryFs`] a set of files in memory, [`Archive`] read-only
+//! files packed into one blob, as bundled executables carry, and [`Overlay`] stacks them,
+//! each mounted at a directory. Capabilities are only checked for paths that end up on
+//! the disk.
+
+use crate::context::Context;
+use crate::error::MutexExt;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::io::{self, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A filesystem. Paths are as scripts write them; each implementation decides what they
+/// are relative to.
+pub trait Vfs: Debug + Send + Sync {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Create or replace a file.
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// Add to the end of a file, creating it if needed.
+    fn append(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut file = match self.read(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        file.extend_from_slice(contents);
+        self.write(path, &file)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    fn is_file(&self, path: &Path) -> bool;
+
+    fn is_dir(&self, path: &Path) -> bool;
+
+    fn exists(&self, path: &Path) -> bool {
+        self.is_file(path) || self.is_dir(path)
+    }
+
+    /// Whether every write fails, so an [`Overlay`] passes writes to the layer below.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Where `path` is on the disk, if it is, for the capability checks. `write` asks
+    /// where it would be written rather than read.
+    fn disk_path(&self, _path: &Path, _write: bool) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// The disk, as the process sees it or inside one directory.
+#[derive(Clone, Debug, Default)]
+pub struct RealFs {
+    root: Option<PathBuf>,
+}
+
+impl RealFs {
+    /// Paths are used as given.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Paths are inside `dir`, and can't climb out of it with `..`.
+    pub fn rooted(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            root: Some(dir.into()),
+        }
+    }
+
+    fn path(&self, path: &Path) -> PathBuf {
+        match &self.root {
+            None => path.to_path_buf(),
+            Some(root) => root.join(key(path).trim_start_matches('/')),
+        }
+    }
+}
+
+impl Vfs for RealFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(self.path(path))
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        std::fs::write(self.path(path), contents)
+    }
+
+    fn append(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        use std::io::Write;
+        std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(self.path(path))?
+            .write_all(contents)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        std::fs::remove_file(self.path(path))
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.path(path).is_file()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.path(path).is_dir()
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.path(path).exists()
+    }
+
+    fn disk_path(&self, path: &Path, _write: bool) -> Option<PathBuf> {
+        Some(self.path(path))
+    }
+}
+
+/// Files kept in memory, for tests and sandboxes. Directories exist while they hold files.
+#[derive(Debug, Default)]
+pub struct MemoryFs {
+    files: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start out with a file.
+    pub fn with_file(self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) -> Self {
+        self.insert(path, contents);
+        self
+    }
+
+    pub fn insert(&self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) {
+        let mut files = self.files.lock_unpoisoned();
+        files.insert(key(path.as_ref()), contents.into());
+    }
+
+    /// A copy of a file's contents.
+    pub fn get(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        self.files
+            .lock_unpoisoned()
+            .get(&key(path.as_ref()))
+            .cloned()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.files.lock_unpoisoned().keys().cloned().collect()
+    }
+}
+
+impl Vfs for MemoryFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.get(path).ok_or_else(|| not_found(path))
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        if self.is_dir(path) {
+            return Err(io::Error::new(
+                ErrorKind::IsADirectory,
+                format!("{} is a directory", path.display()),
+            ));
+        }
+        self.insert(path, contents);
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let mut files = self.files.lock_unpoisoned();
+        files
+            .remove(&key(path))
+            .map(|_| ())
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.files.lock_unpoisoned().contains_key(&key(path))
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        is_dir_in(self.files.lock_unpoisoned().keys(), path)
+    }
+}
+
+/// Read-only files packed into one blob: a little-endian `u64` count of files, then each
+/// file's name and contents, each preceded by its length as a little-endian `u64`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Archive {
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl Archive {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file while building the archive.
+    pub fn insert(&mut self, path: impl AsRef<Path>, contents: Vec<u8>) {
+        self.files.insert(key(path.as_ref()), contents);
+    }
+
+    pub fn get(&self, path: impl AsRef<Path>) -> Option<&[u8]> {
+        self.files.get(&key(path.as_ref())).map(Vec::as_slice)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(String::as_str)
+    }
+
+    /// Read an archive file written with [`to_bytes`].
+    ///
+    /// [`to_bytes`]: Archive::to_bytes
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&(self.files.len() as u64).to_le_bytes());
+        for (name, contents) in &self.files {
+            put(&mut out, name.as_bytes());
+            put(&mut out, contents);
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        Self::read_from(&mut Table(bytes))
+    }
+
+    pub(crate) fn read_from(table: &mut Table) -> io::Result<Self> {
+        let mut files = BTreeMap::new();
+        for _ in 0..table.number()? {
+            let name = table.text()?;
+            files.insert(name, table.bytes()?.to_vec());
+        }
+        Ok(Self { files })
+    }
+}
+
+impl Vfs for Archive {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.get(path)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn write(&self, path: &Path, _contents: &[u8]) -> io::Result<()> {
+        Err(read_only(path))
+    }
+
+    fn append(&self, path: &Path, _contents: &[u8]) -> io::Result<()> {
+        Err(read_only(path))
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        Err(read_only(path))
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.files.contains_key(&key(path))
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        is_dir_in(self.files.keys(), path)
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
+
+/// Filesystems stacked on each other, each mounted at a directory. A read is served by the
+/// highest layer mounted above the path that has the file, and a write goes to the highest
+/// one that isn't read-only.
+#[derive(Debug)]
+pub struct Overlay {
+    /// Mount points and filesystems, lowest first.
+    layers: Vec<(String, Arc<dyn Vfs>)>,
+}
+
+impl Overlay {
+    /// An overlay with `base` mounted at the root.
+    pub fn new(base: Arc<dyn Vfs>) -> Self {
+        Self {
+            layers: vec![(String::new(), base)],
+        }
+    }
+
+    /// Mount `fs` at `dir`, above every layer so far. Paths inside `dir` are passed to it
+    /// relative to `dir`.
+    pub fn mount(mut self, dir: impl AsRef<Path>, fs: Arc<dyn Vfs>) -> Self {
+        self.layers.push((key(dir.as_ref()), fs));
+        self
+    }
+
+    /// The layers mounted above `path`, highest first, with the path inside each.
+    fn matching(&self, path: &Path) -> impl Iterator<Item = (&dyn Vfs, PathBuf)> {
+        let path = key(path);
+        self.layers.iter().rev().filter_map(move |(dir, fs)| {
+            let inner = match (dir.as_str(), path.strip_prefix(dir.as_str())?) {
+                ("", rest) | (_, rest @ "") => rest,
+                (_, rest) => rest.strip_prefix('/')?,
+            };
+            Some((fs.as_ref(), PathBuf::from(inner)))
+        })
+    }
+
+    /// The layer that reads `path`, or that writes it when `write`.
+    fn layer(&self, path: &Path, write: bool) -> Option<(&dyn Vfs, PathBuf)> {
+        let mut layers = self.matching(path);
+        match write {
+            true => layers.find(|(fs, _)| !fs.is_read_only()),
+            // A read of a missing file fails where it would be written, so whether it
+            // exists on a disk the capabilities hide can't be told from the error
+            false => layers
+                .find(|(fs, inner)| fs.exists(inner))
+                .or_else(|| self.layer(path, true))
+                .or_else(|| self.matching(path).next()),
+        }
+    }
+
+    fn read_layer(&self, path: &Path) -> io::Result<(&dyn Vfs, PathBuf)> {
+        self.layer(path, false).ok_or_else(|| not_found(path))
+    }
+
+    fn write_layer(&self, path: &Path) -> io::Result<(&dyn Vfs, PathBuf)> {
+        self.layer(path, true).ok_or_else(|| read_only(path))
+    }
+}
+
+impl Vfs for Overlay {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let (fs, inner) = self.read_layer(path)?;
+        fs.read(&inner)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let (fs, inner) = self.write_layer(path)?;
+        fs.write(&inner, contents)
+    }
+
+    fn append(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let (fs, inner) = self.write_layer(path)?;
+        // A file from a lower layer is copied up before it is added to
+        if !fs.exists(&inner)
+            && let Ok(below) = self.read(path)
+        {
+            fs.write(&inner, &below)?;
+        }
+        fs.append(&inner, contents)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let (fs, inner) = self.write_layer(path)?;
+        fs.remove(&inner)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.matching(path).any(|(fs, inner)| fs.is_file(&inner))
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.matching(path).any(|(fs, inner)| fs.is_dir(&inner))
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.layers.iter().all(|(_, fs)| fs.is_read_only())
+    }
+
+    fn disk_path(&self, path: &Path, write: bool) -> Option<PathBuf> {
+        let (fs, inner) = self.layer(path, write)?;
+        fs.disk_path(&inner, write)
+    }
+}
+
+/// The name a file is kept under in memory and in archives: the path's parts joined by
+/// `/`, with `.` and `..` applied, so `./a/../b.png` and `b.png` are the same file.
+pub(crate) fn key(path: &Path) -> String {
+    let mut root = false;
+    let mut parts: Vec<String> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::RootDir | Component::Prefix(_) => root = true,
+            Component::CurDir => {}
+            Component::ParentDir => {
+                parts.pop();
+            }
+            Component::Normal(part) => parts.push(part.to_string_lossy().replace('\\', "/")),
+        }
+    }
+    let joined = parts.join("/");
+    if root { format!("/{}", joined) } else { joined }
+}
+
+fn is_dir_in<'a>(mut names: impl Iterator<Item = &'a String>, path: &Path) -> bool {
+    let dir = key(path);
+    match dir.as_str() {
+        "" | "/" => true,
+        dir => names.any(|name| {
+            name.strip_prefix(dir)
+                .is_some_and(|rest| rest.starts_with('/'))
+        }),
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(ErrorKind::NotFound, format!("{} not found", path.display()))
+}
+
+fn read_only(path: &Path) -> io::Error {
+    io::Error::new(
+        ErrorKind::ReadOnlyFilesystem,
+        format!("{} is read-only", path.display()),
+    )
+}
+
+/// Append `bytes` with its length before it.
+pub(crate) fn put(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+/// What is left of packed data being read.
+pub(crate) struct Table<'a>(pub(crate) &'a [u8]);
+
+impl<'a> Table<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(invalid("the archive is truncated".to_string()));
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    pub(crate) fn number(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.number()?;
+        self.take(usize::try_from(len).unwrap_or(usize::MAX))
+    }
+
+    pub(crate) fn text(&mut self) -> io::Result<String> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|e| invalid(e.to_string()))
+    }
+}
+
+pub(crate) fn invalid(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Run `op` on the file a script passed to `func`. A file on the disk is checked against
+/// the capabilities and then opened by the path the check resolved, with its links
+/// already followed, so a link swapped in after the check can't lead somewhere else.
+fn checked<T>(
+    ctx: &Context,
+    func: &str,
+    path: &str,
+    write: bool,
+    op: impl FnOnce(&dyn Vfs, &Path) -> T,
+) -> T {
+    match ctx.vfs.disk_path(Path::new(path), write) {
+        Some(disk) => {
+            let disk = disk.to_string_lossy();
+            let resolved = match write {
+                true => ctx.caps.write_path(func, &disk),
+                false => ctx.caps.read_path(func, &disk),
+            };
+            op(&RealFs::new(), &resolved)
+        }
+        None => op(&*ctx.vfs, Path::new(path)),
+    }
+}
+
+/// Read the file a script asked `func` for, through the interpreter's filesystem.
+pub fn read(ctx: &Context, func: &str, path: &str) -> io::Result<Vec<u8>> {
+    checked(ctx, func, path, false, |fs, path| fs.read(path))
+}
+
+pub fn write(ctx: &Context, func: &str, path: &str, contents: &[u8]) -> io::Result<()> {
+    checked(ctx, func, path, true, |fs, path| fs.write(path, contents))
+}
+
+pub fn append(ctx: &Context, func: &str, path: &str, contents: &[u8]) -> io::Result<()> {
+    checked(ctx, func, path, true, |fs, path| fs.append(path, contents))
+}
+
+pub fn remove(ctx: &Context, func: &str, path: &str) -> io::Result<()> {
+    checked(ctx, func, path, true, |fs, path| fs.remove(path))
+}
+
+pub fn exists(ctx: &Context, func: &str, path: &str) -> bool {
+    checked(ctx, func, path, false, |fs, path| fs.exists(path))
+}
+
+pub fn is_file(ctx: &Context, func: &str, path: &str) -> bool {
+    checked(ctx, func, path, false, |fs, path| fs.is_file(path))
+}
+
+pub fn is_dir(ctx: &Context, func: &str, path: &str) -> bool {
+    checked(ctx, func, path, false, |fs, path| fs.is_dir(path))
+}
@@ -0,0 +1,88 @@
+/* A program embedding Onion through the C interface, built and run by tests/stdlib_tests.rs. */
+#include <stdio.h>
+#include <string.h>
+
+#include "onion.h"
+
+static int failures = 0;
+
+#define CHECK(cond)                                                                  \
+    do {                                                                             \
+        if (!(cond)) {                                                               \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #cond); \
+            failures++;                                                              \
+        }                                                                            \
+    } while (0)
+
+/* Adds two ints, plus the offset passed as user data. */
+static int add(const OnionApi *api, OnionCall *call, void *user_data) {
+    OnionValue a, b;
+    if (api->arg_count(call) != 2 || api->arg(call, 0, &a) != 0 || api->arg(call, 1, &b) != 0 ||
+        a.tag != ONION_INT || b.tag != ONION_INT) {
+        api->raise(call, "Host.add expects two ints");
+        return 1;
+    }
+    OnionValue result = {.tag = ONION_INT,
+                         .int_value = a.int_value + b.int_value + *(int64_t *)user_data};
+    api->ret(call, &result);
+    return 0;
+}
+
+int main(void) {
+    OnionInterpreter *onion = onion_new();
+    OnionValue result;
+    CHECK(onion != NULL);
+
+    CHECK(onion_eval(onion, "(1 + 2)", &result) == 0);
+    CHECK(result.tag == ONION_INT && result.int_value == 3);
+    CHECK(onion_error(onion) == NULL);
+
+    CHECK(onion_eval(onion, "(String.repeat \"on\" 2)", &result) == 0);
+    CHECK(result.tag == ONION_STRING && result.len == 4);
+    CHECK(strcmp((const char *)result.data, "onon") == 0);
+
+    CHECK(onion_eval(onion, "(list 1 2.5 :k)", &result) == 0);
+    CHECK(result.tag == ONION_LIST && result.len == 3);
+    const OnionValue *items = result.data;
+    CHECK(items[1].tag == ONION_FLOAT && items[1].float_value == 2.5);
+    CHECK(items[2].tag == ONION_KEYWORD && strcmp((const char *)items[2].data, "k") == 0);
+    CHECK(strcmp(onion_result_json(onion), "[1,2.5,\"k\"]") == 0);
+
+    CHECK(onion_eval(onion, "[x 10 y (list nil \"s\")]", NULL) == 0);
+    CHECK(strcmp(onion_result_json(onion), "{\"x\":10,\"y\":[null,\"s\"]}") == 0);
+
+    /* Definitions persist between evals */
+    CHECK(onion_eval(onion, "(def double (fun (n) (n * 2)))", NULL) == 0);
+    OnionValue arg = {.tag = ONION_INT, .int_value = 21};
+    CHECK(onion_call(onion, "double", &arg, 1, &result) == 0);
+    CHECK(result.tag == ONION_INT && result.int_value == 42);
+
+    int64_t offset = 100;
+    CHECK(onion_define(onion, "Host", "add", "Add two ints and the offset", add, &offset) == 0);
+    CHECK(onion_eval(onion, "(Host.add 1 2)", &result) == 0);
+    CHECK(result.tag == ONION_INT && result.int_value == 103);
+
+    OnionValue name = {.tag = ONION_STRING, .data = "editor", .len = 6};
+    CHECK(onion_define_value(onion, NULL, "host_name", &name) == 0);
+    CHECK(onion_eval(onion, "host_name", &result) == 0);
+    CHECK(result.tag == ONION_STRING && strcmp((const char *)result.data, "editor") == 0);
+
+    /* Errors are reported, and the callback's can be caught by the script */
+    CHECK(onion_eval(onion, "(Host.add 1 \"x\")", &result) != 0);
+    CHECK(onion_error(onion) != NULL && strstr(onion_error(onion), "expects two ints") != NULL);
+    CHECK(onion_eval(onion, "(try (Host.add 1) (fun (msg) msg))", &result) == 0);
+    CHECK(result.tag == ONION_STRING);
+    CHECK(onion_eval(onion, "(1 + ", &result) != 0);
+    CHECK(onion_error(onion) != NULL);
+    CHECK(onion_eval(onion, NULL, &result) != 0);
+    CHECK(onion_eval(NULL, "1", &result) == -1);
+
+    CHECK(onion_eval(onion, "(1 + 1)", &result) == 0);
+    CHECK(onion_error(onion) == NULL);
+
+    onion_free(onion);
+    onion_free(NULL);
+    if (failures == 0)
+        printf("ok\n");
+    return failures != 0;
+}
@@ -0,0 +1,89 @@
+/* A plugin for the load_plugin tests, built by tests/stdlib_tests.rs. */
+#include <stdio.h>
+#include <string.h>
+
+#include "onion.h"
+
+#ifndef PLUGIN_ABI_VERSION
+#define PLUGIN_ABI_VERSION ONION_PLUGIN_ABI_VERSION
+#endif
+
+static int twice(const OnionApi *api, OnionCall *call, void *user_data) {
+    OnionValue x;
+    if (api->arg(call, 0, &x) != 0 || x.tag != ONION_INT) {
+        api->raise(call, "expected an int");
+        return 1;
+    }
+    OnionValue result = {.tag = ONION_INT, .int_value = x.int_value * 2};
+    api->ret(call, &result);
+    return 0;
+}
+
+static int greet(const OnionApi *api, OnionCall *call, void *user_data) {
+    OnionValue name;
+    char text[64];
+    if (api->arg(call, 0, &name) != 0 || name.tag != ONION_STRING) {
+        api->raise(call, "expected a string");
+        return 1;
+    }
+    snprintf(text, sizeof text, "%s, %s", (const char *)user_data, (const char *)name.data);
+    OnionValue result = {.tag = ONION_STRING, .data = text, .len = strlen(text)};
+    api->ret(call, &result);
+    return 0;
+}
+
+/* The sum of a list of numbers, as a float. */
+static int sum(const OnionApi *api, OnionCall *call, void *user_data) {
+    OnionValue list;
+    double total = 0;
+    if (api->arg(call, 0, &list) != 0 || list.tag != ONION_LIST) {
+        api->raise(call, "expected a list");
+        return 1;
+    }
+    const OnionValue *items = list.data;
+    for (size_t i = 0; i < list.len; i++) {
+        total += items[i].tag == ONION_INT ? (double)items[i].int_value : items[i].float_value;
+    }
+    OnionValue result = {.tag = ONION_FLOAT, .float_value = total};
+    api->ret(call, &result);
+    return 0;
+}
+
+/* A map of the number of arguments to the arguments. */
+static int args(const OnionApi *api, OnionCall *call, void *user_data) {
+    OnionValue items[8];
+    size_t count = api->arg_count(call);
+    if (count > 8) {
+        count = 8;
+    }
+    for (size_t i = 0; i < count; i++) {
+        api->arg(call, i, &items[i]);
+    }
+    OnionValue entry[2] = {
+        {.tag = ONION_KEYWORD, .data = "count", .len = 5},
+        {.tag = ONION_LIST, .data = items, .len = count},
+    };
+    OnionValue result = {.tag = ONION_MAP, .data = entry, .len = 1};
+    api->ret(call, &result);
+    return 0;
+}
+
+static int fail(const OnionApi *api, OnionCall *call, void *user_data) {
+    return 7;
+}
+
+uint32_t onion_plugin_abi_version(void) {
+    return PLUGIN_ABI_VERSION;
+}
+
+int onion_register(const OnionApi *api) {
+    OnionValue version = {.tag = ONION_INT, .int_value = 3};
+    if (api->define_value(api, "Ext", "version", &version) != 0) {
+        return 1;
+    }
+    return api->define(api, "Ext", "twice", "Double an int", twice, NULL)
+        || api->define(api, "Ext", "greet", "Greet someone", greet, "hello")
+        || api->define(api, "Ext", "sum", NULL, sum, NULL)
+        || api->define(api, "Ext", "args", NULL, args, NULL)
+        || api->define(api, NULL, "ext_fail", NULL, fail, NULL);
+}
@@ -1,23 +1,41 @@
-use onion::context::eval;
+use onion::Symbol;
+use onion::bundle::Bundle;
+use onion::check::check_types;
+use onion::context::{eval, eval_ref};
 use onion::expr::Expr;
 use onion::parser::parse_expr;
+use onion::stdlib::capabilities::Capabilities;
+use onion::stdlib::io::OutputBuffer;
 use onion::stdlib::stdlib;
+use onion::vfs::{Archive, MemoryFs, Overlay, RealFs};
+use onion::{Interpreter, error::Error, stdlib::Module};
+use std::sync::Arc;
 
 fn run_code(code: &str) -> Expr {
-    let mut ctx = stdlib();
-    let mut input = code.trim();
-    let mut last_res = Expr::Nil;
-
-    while !input.is_empty() {
-        match parse_expr(input, &ctx) {
-            Ok((rest, expr)) => {
-                last_res = eval(expr, &mut ctx);
-                input = rest.trim();
-            }
-            Err(e) => panic!("Parse error at '{}': {:?}", input, e),
-        }
+    match Interpreter::new().eval_str(code) {
+        Ok(val) => val,
+        Err(e) => panic!("{} in code: {}", e, code),
+    }
+}
+
+/// Run code and return what it printed to stdout.
+fn run_output(code: &str) -> String {
+    let onion = Interpreter::new();
+    let out = OutputBuffer::new();
+    onion.context().streams.set_stdout(out.clone());
+    run_with(onion, code);
+    out.take()
+}
+
+fn run_with(mut onion: Interpreter, code: &str) -> Expr {
+    match onion.eval_str(code) {
+        Ok(val) => val,
+        Err(e) => panic!("{} in code: {}", e, code),
     }
-    last_res
+}
+
+fn assert_output(code: &str, expected: &str) {
+    assert_eq!(run_output(code), expected, "Code: {}", code);
 }
 
 fn assert_int(code: &str, expected: i64) {
@@ -117,6 +135,18 @@ fn test_collections_module() {
     ";
     assert_int(code, 1);
 
+    // Symbols sort by name, not by when they were first seen
+    assert_output(
+        "(def zebra 'zebra) (println (Collections.sort (list zebra 'apple 'mango)))",
+        "(apple mango zebra)\n",
+    );
+    assert_output("(println [zzz 1 aaa 2 mmm 3])", "[aaa 2 mmm 3 zzz 1]\n");
+    assert_output("(println [:zz 1 :aa 2])", "[:aa 2 :zz 1]\n");
+    assert_output(
+        "(println (Collections.sort (list '(zz 1) '(aa 2))))",
+        "((aa 2) (zz 1))\n",
+    );
+
     // Map
     let code = "
     (def m #[ a 1 b 2 ])
@@ -236,3 +266,1399 @@ fn test_phase_2_extensions() {
     ";
     assert_int(code, 1);
 }
+
+#[test]
+fn test_struct_inheritance() {
+    let code = "
+    (struct Unit (hp)
+        (describe () (String.fmt \"unit {}\" self.hp))
+        (attack () 1))
+    (struct Archer extends Unit (range)
+        (attack () (super.attack) + self.range))
+    (def a (Archer 10 4))
+    (list (a.attack) (a.describe) a.hp)
+    ";
+    match run_code(code) {
+        Expr::List(l) => {
+            assert_eq!(l[0], Expr::Int(5));
+            assert_eq!(l[1], Expr::str("unit 10"));
+            assert_eq!(l[2], Expr::Int(10));
+        }
+        val => panic!("Expected list, got {:?}", val),
+    }
+
+    // Methods live on the type, not on each instance
+    let code = "
+    (struct Counter (count)
+        (inc () (self.count = self.count + 1)))
+    (def c (Counter 0))
+    (c.inc)
+    (c.inc)
+    c
+    ";
+    match run_code(code) {
+        Expr::Ref(r) => match &*r.read().unwrap() {
+            Expr::Map(m) => {
+                assert_eq!(m.len(), 2);
+                assert_eq!(m.get(&Expr::sym("count")), Some(&Expr::Int(2)));
+            }
+            val => panic!("Expected map, got {:?}", val),
+        },
+        val => panic!("Expected reference, got {:?}", val),
+    }
+}
+
+#[test]
+fn test_struct_interfaces() {
+    let code = "
+    (interface Drawable (draw) (update dt))
+    (struct Base (x)
+        (draw () self.x))
+    (struct Sprite extends Base implements Drawable (y)
+        (update (dt) (self.y = self.y + dt)))
+    (def s (Sprite 1 2))
+    (s.update 3)
+    (s.draw) + s.y
+    ";
+    assert_int(code, 6);
+
+    // `self` is bound when a method is called, so a method taken off one instance keeps
+    // it, and closures made inside the call see it
+    let code = "
+    (struct Counter (n)
+        (get () self.n)
+        (adder () (fun (k) (self.n + k))))
+    (def a (Counter 1))
+    (def b (Counter 10))
+    (def get_a a.get)
+    (def add_b (b.adder))
+    (get_a) + (add_b 5) + (b.get)
+    ";
+    assert_int(code, 26);
+
+    // A struct missing a method is rejected and never registered
+    let code = "
+    (interface Drawable (draw))
+    (def msg (try (struct Blob implements Drawable (x)) (fun (m) m)))
+    (try (struct Child extends Blob (y)) (fun (m) (String.fmt \"{}|{}\" msg m)))
+    ";
+    assert_str(
+        code,
+        "Struct Blob does not satisfy interface Drawable: missing method draw|Struct Child cannot extend unknown struct Blob",
+    );
+}
+
+#[test]
+fn test_struct_magic_methods() {
+    let prelude = "
+    (struct Vec2 (x y)
+        (__add__ (o) (Vec2 (self.x + o.x) (self.y + o.y)))
+        (__eq__ (o) (and (self.x == o.x) (self.y == o.y)))
+        (__lt__ (o) (self.x < o.x))
+        (__len__ () 2)
+        (__index__ (i) (if (i == 0) self.x self.y))
+        (__str__ () (String.fmt \"<{}, {}>\" self.x self.y))
+        (__iter__ () (list self.x self.y)))
+    (def a (Vec2 1 2))
+    (def b (Vec2 3 4))
+    ";
+    assert_int(&format!("{prelude} (def c (a + b)) c.x * 10 + c.y"), 46);
+    assert_int(&format!("{prelude} (if ((a + b) == (Vec2 4 6)) 1 0)"), 1);
+    assert_int(&format!("{prelude} (if (a < b) (if (b > a) 1 0) 0)"), 1);
+    assert_int(&format!("{prelude} (if (a >= b) 0 (if (a <= b) 1 0))"), 1);
+    assert_int(&format!("{prelude} (len a) + (a ? 1)"), 4);
+    assert_str(&format!("{prelude} (String.fmt \"v={{}}\" b)"), "v=<3, 4>");
+    assert_int(&format!("{prelude} (def s 0) (for v b (s = s + v)) s"), 7);
+}
+
+#[test]
+fn test_multimethods() {
+    let prelude = "
+    (interface Ranged (range))
+    (struct Unit (hp))
+    (struct Archer extends Unit implements Ranged () (range () 3))
+    (struct Knight extends Unit ())
+    (defgeneric damage (attacker target))
+    (defmethod damage (a t) 1)
+    (defmethod damage ((a Unit) (t Unit)) 2)
+    (defmethod damage ((a Archer) t) 10)
+    (defmethod damage ((a Archer) (t Knight)) 20)
+    (defmethod damage ((a Ranged) (t int)) 30)
+    (defmethod damage ((a int) (t int)) (a + t))
+    (defmethod damage ((a Shield) t) (Type.untag a))
+    ";
+    assert_int(&format!("{prelude} (damage (Knight 5) (Archer 5))"), 2);
+    assert_int(&format!("{prelude} (damage (Archer 5) (Knight 5))"), 20);
+    assert_int(&format!("{prelude} (damage (Archer 5) (Unit 5))"), 10);
+    assert_int(&format!("{prelude} (damage (Archer 5) 7)"), 10);
+    assert_int(&format!("{prelude} (damage 3 4)"), 7);
+    assert_int(&format!("{prelude} (damage \"a\" 4)"), 1);
+    assert_int(&format!("{prelude} (damage (Type.tag Shield 9) nil)"), 9);
+    // Redefining a method for the same types replaces it
+    assert_int(
+        &format!("{prelude} (defmethod damage ((a int) (t int)) 0) (damage 3 4)"),
+        0,
+    );
+}
+
+#[test]
+fn test_type_annotations() {
+    let prelude = "
+    (struct Unit (hp: int name))
+    (struct Archer extends Unit (range: int))
+    (defun damage (attacker: Unit amount: int) -> float (attacker.hp * amount * 1.5))
+    (defun label (x: any) -> string (Type.of x))
+    ";
+    assert_float(&format!("{prelude} (damage (Unit 2 \"u\") 3)"), 9.0);
+    assert_float(&format!("{prelude} (damage (Archer 2 \"a\" 4) 1)"), 3.0);
+    assert_str(&format!("{prelude} (label nil)"), "nil");
+    assert_int("((fun (x: int y) -> int (x + y)) 1 2)", 3);
+
+    // A violation is a runtime error naming the parameter and what it got, which try catches
+    assert_str(
+        &format!("{prelude} (try (damage (Unit 2 \"u\") 1.5) (fun (msg) msg))"),
+        "damage: parameter amount expects int, got float (1.5)",
+    );
+    assert_str(
+        &format!("{prelude} (try (damage 7 1) (fun (msg) msg))"),
+        "damage: parameter attacker expects Unit, got int (7)",
+    );
+    assert_str(
+        &format!("{prelude} (try (Unit \"full\" \"u\") (fun (msg) msg))"),
+        "Unit: field hp expects int, got string (full)",
+    );
+    assert_str(
+        "(defun bad (x) -> int \"oops\") (try (bad 1) (fun (msg) msg))",
+        "bad: expected to return int, got string (oops)",
+    );
+
+    // Checks are skipped while contracts are off
+    assert_str(
+        "(defun bad (x) -> int \"oops\") (Type.set_contracts 0) (def r (bad 1)) (Type.set_contracts 1) r",
+        "oops",
+    );
+    let mut unchecked = Interpreter::builder().contracts(false).build();
+    let bad = "(defun bad (x) -> int \"oops\") (bad 1)";
+    assert!(matches!(unchecked.eval_str(bad), Ok(Expr::Str(s)) if s == "oops"));
+    assert!(Interpreter::new().eval_str(bad).is_err());
+}
+
+#[test]
+fn test_static_type_check() {
+    let code = "
+    (struct Unit (hp: int)
+        (attack () 1)
+        (heal (n: int) -> int (self.hp + n)))
+    (def u (Unit 5))
+    (u.attack 1)
+    (u.heal \"x\")
+    (Math.sqrt \"16\")
+    (if (u.attack < 3) 1 0)
+    (defun f (x: int) -> string (x + 1))
+    (u.heal (u.attack))
+    (Math.sqrt u.hp)
+    ";
+    let lines: Vec<usize> = check_types(code, &stdlib())
+        .unwrap()
+        .iter()
+        .map(|d| d.line)
+        .collect();
+    assert_eq!(lines, vec![6, 7, 8, 9, 10]);
+    assert!(check_types("(def x", &stdlib()).is_err());
+}
+
+#[test]
+fn test_keywords() {
+    assert_str("(Type.of :hp)", "keyword");
+    assert_int("(def m [:hp 10 :name \"archer\"]) (:hp m)", 10);
+    assert_int("(:missing [:hp 10] 5)", 5);
+    assert_nil("(:missing [:hp 10])");
+    assert_int("(struct Unit (hp)) (:hp (Unit 7))", 7);
+    assert_int("(nth 1 (Collections.map (list [:hp 1] [:hp 2]) :hp))", 2);
+    assert_int("(if (== :a :a) 1 0)", 1);
+    assert_int("(if (== :a (Type.to_keyword \"a\")) 1 0)", 1);
+    assert_str("(Type.name :hp)", "hp");
+
+    // Named arguments
+    let code = "(defun spawn (x y kind) (x * 100 + y * 10 + kind))";
+    assert_int(&format!("{code} (spawn 1 :kind 3 :y 2)"), 123);
+    assert_int(&format!("{code} (spawn :kind 3 :x 1 :y 2)"), 123);
+    assert_int(&format!("{code} (spawn 1 2 3)"), 123);
+    // A keyword with no value after it is passed as is, even if it names a parameter
+    assert_str("(defun f (x) (Type.name x)) (f :x)", "x");
+    assert_str("(defun g (a x) (Type.name x)) (g 1 :x)", "x");
+}
+
+#[test]
+fn test_bytes() {
+    assert_str("(Type.of b\"abc\")", "bytes");
+    assert_int("(len b\"a\\x00\\xff\")", 3);
+    assert_int("(b\"a\\x00\\xff\" ? 2)", 255);
+    assert_int("(def r (new b\"abc\")) (? r 0 65) (r ? 0)", 65);
+    assert_str("(Bytes.to_str (Bytes.from_str \"héllo\"))", "héllo");
+    assert_str("(Bytes.to_str (Bytes.from_str \"CAFE\" \"hex\") \"hex\")", "cafe");
+    assert_str("(Bytes.to_str (Bytes.from_str \"é\" \"latin1\") \"latin1\")", "é");
+    assert_int("(len (Bytes.from_str \"é\" \"latin1\"))", 1);
+    assert_str(
+        "(Bytes.to_str (Bytes.concat (Bytes.slice b\"xabx\" 1 3) (Bytes.from_list (list 99))))",
+        "abc",
+    );
+    assert_int("(nth 2 (Bytes.to_list (Bytes.make 3 7)))", 7);
+
+    // pack and unpack
+    assert_str(
+        "(Bytes.to_str (Bytes.pack \">hI\" (- 0 2) 70000) \"hex\")",
+        "fffe00011170",
+    );
+    assert_str("(Bytes.to_str (Bytes.pack \"<H\" 1) \"hex\")", "0100");
+    assert_int("(nth 0 (Bytes.unpack \">hI\" (Bytes.pack \">hI\" (- 0 2) 70000)))", -2);
+    assert_int("(nth 1 (Bytes.unpack \">hI\" (Bytes.pack \">hI\" (- 0 2) 70000)))", 70000);
+    assert_float("(nth 0 (Bytes.unpack \"<d\" (Bytes.pack \"<d\" 1.5)))", 1.5);
+    assert_int("(nth 0 (Bytes.unpack \"B\" b\"\\x01\\x02\" 1))", 2);
+    // Unsigned 64-bit values above the Int range come back as big integers, and pack again
+    let max = "(def max (Bytes.unpack \"<Q\" b\"\\xff\\xff\\xff\\xff\\xff\\xff\\xff\\xff\"))";
+    assert_str(
+        &format!("{max} (String.fmt \"{{}}\" (nth 0 max))"),
+        "18446744073709551615",
+    );
+    assert_str(
+        &format!("{max} (Bytes.to_str (Bytes.pack \"<Q\" (nth 0 max)) \"hex\")"),
+        "ffffffffffffffff",
+    );
+    assert_str(
+        "(try (Bytes.pack \"<Q\" (1 << 64)) (fun (msg) msg))",
+        "Bytes.pack: 18446744073709551616 does not fit in Q",
+    );
+
+    // files
+    let path = std::env::temp_dir().join("onion_test_bytes.bin");
+    let path = path.to_str().unwrap();
+    assert_int(&format!("(IO.write_bytes \"{path}\" b\"\\x00\\x01\")"), 1);
+    assert_int(&format!("(len (IO.read_bytes \"{path}\"))"), 2);
+}
+
+#[test]
+fn test_big_integers() {
+    let factorial = "(defun factorial (n) (if (<= n 1) 1 n * (factorial n - 1)))";
+    assert_str(
+        &format!("{factorial} (String.fmt \"{{}}\" (factorial 25))"),
+        "15511210043330985984000000",
+    );
+    assert_str(&format!("{factorial} (Type.of (factorial 25))"), "int");
+    assert_str(
+        "(String.fmt \"{}\" (9223372036854775807 + 1))",
+        "9223372036854775808",
+    );
+    assert_str(
+        "(String.fmt \"{}\" (Math.ipow 2 100))",
+        "1267650600228229401496703205376",
+    );
+    assert_int("(Math.ipow 2 10)", 1024);
+    assert_int("(Math.ipow (0 - 3) 3)", -27);
+    assert_nil("(Math.ipow 2 0.5)");
+    assert_str(
+        "(try (Math.ipow 2 (0 - 1)) (fun (msg) msg))",
+        "Math.ipow: negative exponent -1",
+    );
+    // Math.pow stays a float function
+    assert_float("(Math.pow 2 10)", 1024.0);
+    assert_float("(Math.pow 4 0.5)", 2.0);
+
+    // Results that fit come back as plain Ints
+    assert_int("(99999999999999999999999 - 99999999999999999999998)", 1);
+    assert_int(&format!("{factorial} ((factorial 30) / (factorial 28))"), 870);
+    assert_int(&format!("{factorial} ((factorial 30) % 7)"), 0);
+    assert_int(&format!("{factorial} (if ((factorial 22) > (factorial 21)) 1 0)"), 1);
+    assert_int(&format!("{factorial} (if ((factorial 22) < 5.0) 1 0)"), 0);
+    assert_int(&format!("{factorial} (== (factorial 25) (factorial 25))"), 1);
+    assert_int(
+        &format!("{factorial} (== (hash (factorial 25)) (hash (factorial 25)))"),
+        1,
+    );
+
+    // Strict mode makes overflow a catchable error
+    assert_str(
+        "(Math.set_strict_ints 1) (try (9223372036854775807 + 1) (fun (msg) msg))",
+        "Integer overflow: 9223372036854775807 + 1",
+    );
+    assert_int("(Math.set_strict_ints 0) (try (raise \"no\") (fun (msg) 7))", 7);
+    // and is set per interpreter
+    let mut strict = Interpreter::builder().strict_ints(true).build();
+    let mut lenient = Interpreter::new();
+    assert!(strict.eval_str("(9223372036854775807 + 1)").is_err());
+    assert!(lenient.eval_str("(9223372036854775807 + 1)").is_ok());
+    strict.eval_str("(Math.set_strict_ints 0)").unwrap();
+    lenient.eval_str("(Math.set_strict_ints 1)").unwrap();
+    assert!(strict.eval_str("(9223372036854775807 + 1)").is_ok());
+    assert!(lenient.eval_str("(9223372036854775807 + 1)").is_err());
+    assert_int("(try (1 + 2))", 3);
+    assert_nil("(try (raise \"no\"))");
+
+    // A value whose update failed is still usable after the error is caught
+    assert_int(
+        "(def r (new b\"abc\")) (try (? r 0 300) (fun (m) m)) (r ? 0)",
+        97,
+    );
+    let mut onion = Interpreter::new();
+    onion.eval_str("(def r (new b\"abc\"))").unwrap();
+    assert!(onion.eval_str("(? r 0 300)").is_err());
+    assert_eq!(onion.eval_str("(r ? 1)").unwrap(), Expr::Int(98));
+}
+
+#[test]
+fn test_bitwise_operators() {
+    let color = "(def color 0x3366CC)";
+    assert_int(&format!("{color} (color >> 16 & 0xFF)"), 0x33);
+    assert_int(&format!("{color} (color >> 8 & 0xFF)"), 0x66);
+    assert_int("((0x33 << 16) | (0x66 << 8) | 0xCC)", 0x3366CC);
+    assert_int(&format!("{color} (if (color & 0xFF == 0xCC) 1 0)"), 1);
+    assert_int("(5 ^ 3)", 6);
+    assert_int("(~ 5)", -6);
+    assert_int("(1 + 2 << 1)", 6);
+    assert_int("((0 - 16) >> 2)", -4);
+    assert_int("((1 << 70) >> 69)", 2);
+    assert_str("(String.fmt \"{}\" (1 << 64))", "18446744073709551616");
+
+    // Huge results are refused before they are allocated
+    assert_str(
+        "(try (1 << 10000000000) (fun (msg) msg))",
+        "<<: integer too large (10000000001 bits, limit is 268435456)",
+    );
+    assert_int("(0 << 10000000000)", 0);
+    assert_str(
+        "(try (Math.ipow 3 4000000000) (fun (msg) msg))",
+        "Math.ipow: integer too large (4000000000 bits, limit is 268435456)",
+    );
+    assert_int("(Math.ipow 1 4000000000)", 1);
+    let mut small = Interpreter::builder().max_size(1000).build();
+    assert!(small.eval_str("(1 << 7000)").is_ok());
+    let err = small.eval_str("(1 << 100000)").unwrap_err().to_string();
+    assert!(err.contains("<<: memory limit exceeded"), "{}", err);
+
+    assert_int("(Math.popcount 255)", 8);
+    assert_int("(Math.bit_test 5 2)", 1);
+    assert_int("(Math.bit_test 5 1)", 0);
+    assert_int("(Math.bit_set 0 3)", 8);
+    assert_int("(Math.bit_clear 15 0)", 14);
+
+    assert_str(
+        "(try (1.5 & 1) (fun (msg) msg))",
+        "Type error in &: expected Int operands, got 1.5 and 1",
+    );
+    let lines: Vec<usize> = check_types("(def a (1 & 2))\n(def b (1.5 | 2))", &stdlib())
+        .unwrap()
+        .iter()
+        .map(|d| d.line)
+        .collect();
+    assert_eq!(lines, vec![2]);
+}
+
+#[test]
+fn test_persistent_collections() {
+    let big = "(def big (Collections.range 0 10000)) (def bigger (Collections.push big 10000))";
+    assert_int(&format!("{big} (len big)"), 10000);
+    assert_int(&format!("{big} (len bigger)"), 10001);
+    assert_int(&format!("{big} (bigger ? 10000)"), 10000);
+    assert_int(&format!("{big} (len (big + bigger))"), 20001);
+    assert_int(&format!("{big} (first (rest (Collections.pop bigger)))"), 1);
+    assert_int("(first (cons 0 '(1 2)))", 0);
+    assert_int("(len (take 2 '(1 2 3)))", 2);
+    assert_int("(first (drop 2 '(1 2 3)))", 3);
+    assert_int("(first (Collections.reverse '(1 2 3)))", 3);
+
+    let maps = "(def m [a 1]) (def n (Collections.merge m [b 2]))";
+    assert_nil(&format!("{maps} (m ? 'b)"));
+    assert_int(&format!("{maps} (n ? 'b)"), 2);
+    assert_int(&format!("{maps} (len #[a 1 b 2])"), 2);
+
+    // Natives written against Vec-backed lists still have a way in and out
+    let list = Expr::list(vec![Expr::Int(1), Expr::Int(2)]);
+    assert_eq!(list, Expr::from(vec![Expr::Int(1), Expr::Int(2)]));
+    assert_eq!(list, Expr::from(&[Expr::Int(1), Expr::Int(2)][..]));
+    match list.list_to_vec().as_deref() {
+        Some([Expr::Int(1), Expr::Int(2)]) => {}
+        other => panic!("Expected [1, 2], got {:?}", other),
+    }
+    assert_eq!(Expr::Int(1).list_to_vec(), None);
+}
+
+#[test]
+fn test_borrowed_natives() {
+    let mut ctx = stdlib();
+    ctx.define(
+        Expr::sym("second"),
+        Expr::extern_ref(|args, ctx| eval_ref(&args[1], ctx), "second", "Second argument"),
+    );
+    // Natives written against the old `&mut [Expr]` signature still work
+    ctx.define(
+        Expr::sym("count"),
+        Expr::extern_fun(
+            |args: &mut [Expr], _ctx| {
+                args.reverse();
+                Expr::Int(args.len() as i64)
+            },
+            "count",
+            "Number of arguments",
+        ),
+    );
+    let (_, expr) = parse_expr("(defun add (a b) (a + b))", &ctx).unwrap();
+    eval(expr, &mut ctx);
+
+    let (_, expr) = parse_expr("(second (count 1 2 3) (add 2 3))", &ctx).unwrap();
+    assert_eq!(eval_ref(&expr, &mut ctx), Expr::Int(5));
+    let (_, expr) = parse_expr("(count 1 2 3)", &ctx).unwrap();
+    assert_eq!(eval_ref(&expr, &mut ctx), Expr::Int(3));
+
+    // Copies of a function share its body
+    let (a, b) = (ctx.resolve(&Expr::sym("add")), ctx.resolve(&Expr::sym("add")));
+    match (a, b) {
+        (Some(Expr::Function { body: a, .. }), Some(Expr::Function { body: b, .. })) => {
+            assert!(std::sync::Arc::ptr_eq(&a, &b))
+        }
+        other => panic!("Expected two functions, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_lexical_addressing() {
+    // Parameters, including those of enclosing functions, are read from frame slots
+    let mut ctx = stdlib();
+    let (_, expr) = parse_expr("(defun outer (a b) (fun (c) (a + c)))", &ctx).unwrap();
+    eval(expr, &mut ctx);
+    let (_, expr) = parse_expr("((outer 1 2) 10)", &ctx).unwrap();
+    assert_eq!(eval_ref(&expr, &mut ctx), Expr::Int(11));
+    match ctx.resolve(&Symbol::new("outer")) {
+        Some(Expr::Function { body, .. }) => {
+            let Expr::List(l) = &*body else {
+                panic!("Expected list body, got {:?}", body)
+            };
+            // (fun (c) ((+ a c)))
+            let inner = match &l[2] {
+                Expr::List(body) => match &body[0] {
+                    Expr::List(inner) => inner.clone(),
+                    other => panic!("Expected call, got {:?}", other),
+                },
+                other => panic!("Expected inner body, got {:?}", other),
+            };
+            assert!(matches!(
+                inner[1],
+                Expr::Local {
+                    depth: 1,
+                    index: 0,
+                    ..
+                }
+            ));
+            assert!(matches!(
+                inner[2],
+                Expr::Local {
+                    depth: 0,
+                    index: 0,
+                    ..
+                }
+            ));
+        }
+        other => panic!("Expected function, got {:?}", other),
+    }
+
+    // Assigning to a parameter updates its slot
+    assert_int("(defun bump (n) (n = n + 1) n) (bump 4)", 5);
+    // A name the body defines hides the one further out
+    assert_int("(def x 1) (defun f (x) (for x (list 7 8) x) x) (f 3)", 8);
+    // Recursion through the function's own name
+    assert_int(
+        "(defun fact (n) (if (n <= 1) 1 (n * (fact (n - 1))))) (fact 5)",
+        120,
+    );
+    // Closures keep their captured parameters
+    assert_int(
+        "(defun adder (n) (fun (x) (x + n))) (def add2 (adder 2)) (def add5 (adder 5)) (add2 1) + (add5 1)",
+        9,
+    );
+    // Methods see their parameters alongside self
+    assert_int(
+        "(struct Acc (total) (add (n) (self.total = self.total + n))) (def a (Acc 1)) (a.add 4) a.total",
+        5,
+    );
+}
+
+#[test]
+fn test_interpreter_api() {
+    let mut onion = Interpreter::new();
+    onion
+        .eval_str("(defun area (w h) (w * h)) (def scale 3)")
+        .unwrap();
+    assert_eq!(
+        onion.call("area", &[Expr::Int(4), Expr::Int(5)]).unwrap(),
+        Expr::Int(20)
+    );
+    assert_eq!(onion.get_global("scale"), Some(Expr::Int(3)));
+    onion.set_global("scale", Expr::Int(10));
+    assert_eq!(onion.eval_str("scale * 2").unwrap(), Expr::Int(20));
+
+    onion.register_module(
+        "Host",
+        [
+            (
+                "twice",
+                Expr::extern_ref(
+                    |args, ctx| match eval_ref(&args[0], ctx) {
+                        Expr::Int(n) => Expr::Int(n * 2),
+                        other => other,
+                    },
+                    "twice",
+                    "Double a number",
+                ),
+            ),
+            ("version", Expr::Int(7)),
+        ],
+    );
+    assert_eq!(
+        onion.eval_str("(Host.twice Host.version)").unwrap(),
+        Expr::Int(14)
+    );
+
+    // Errors are returned and leave the interpreter usable
+    match onion.eval_str("(raise \"boom\")") {
+        Err(Error::Runtime(e)) => assert!(e.message.contains("boom"), "{}", e),
+        other => panic!("Expected runtime error, got {:?}", other),
+    }
+    assert!(matches!(onion.eval_str("(1 2"), Err(Error::Parse(_))));
+    assert!(matches!(
+        onion.call("missing", &[]),
+        Err(Error::Undefined(_))
+    ));
+    assert!(matches!(
+        onion.call("scale", &[]),
+        Err(Error::NotCallable(_))
+    ));
+    assert!(matches!(
+        onion.eval_file("no/such/file.onion"),
+        Err(Error::Io(_))
+    ));
+    assert_eq!(
+        onion.call("area", &[Expr::Int(2), Expr::Int(2)]).unwrap(),
+        Expr::Int(4)
+    );
+
+    // A wrong argument count is an error too, which try can catch
+    match onion.eval_str("(area 1 2 3)") {
+        Err(Error::Runtime(e)) => assert_eq!(e.message, "area expected 2 arguments, got 3"),
+        other => panic!("Expected runtime error, got {:?}", other),
+    }
+    assert!(matches!(onion.call("area", &[]), Err(Error::Runtime(_))));
+    assert_eq!(
+        onion.eval_str("(try (area 1) (fun (m) m))").unwrap(),
+        Expr::Str("area expected 2 arguments, got 1".to_string())
+    );
+
+    // Only the chosen stdlib modules are installed
+    let mut small = Interpreter::builder()
+        .modules(&[Module::Math])
+        .with(Module::String)
+        .without(Module::Math)
+        .build();
+    assert_eq!(small.get_global("Math"), None);
+    assert!(small.get_global("String").is_some());
+    assert_eq!(small.eval_str("1 + 2").unwrap(), Expr::Int(3));
+}
+
+#[test]
+fn test_expr_conversions() {
+    use onion::convert::{from_expr, to_expr};
+    use onion::{FromExpr, IntoExpr};
+    use serde::{Deserialize, Serialize};
+    use std::collections::{BTreeMap, HashMap};
+
+    assert_eq!(i32::from_expr(&Expr::Int(7)), Ok(7));
+    assert!(u8::from_expr(&Expr::Int(300)).is_err());
+    assert!(i64::from_expr(&Expr::str("7")).is_err());
+    assert_eq!(u64::MAX.into_expr(), run_code("18446744073709551615"));
+    assert_eq!(
+        Vec::<(String, bool)>::from_expr(&run_code("(list (list \"a\" 1) (list \"b\" nil))")),
+        Ok(vec![("a".to_string(), true), ("b".to_string(), false)])
+    );
+    assert_eq!(Option::<f64>::from_expr(&Expr::Nil), Ok(None));
+    assert_eq!(Option::<f64>::from_expr(&Expr::Int(2)), Ok(Some(2.0)));
+    let scores = HashMap::<String, i64>::from_expr(&run_code("#[\"ann\" 3 \"bo\" 5]")).unwrap();
+    assert_eq!(scores["bo"], 5);
+    let tree = BTreeMap::from([(1, "one"), (2, "two")]).into_expr();
+    assert_eq!(
+        BTreeMap::<i32, String>::from_expr(&tree).unwrap()[&2],
+        "two"
+    );
+    assert_eq!((1, "x").into_expr(), run_code("(list 1 \"x\")"));
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Mode {
+        Fast,
+        Limited(u32),
+        Window { start: i64, end: i64 },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Service {
+        name: String,
+        port: u16,
+        tags: Vec<String>,
+        mode: Mode,
+        backup: Option<Box<Service>>,
+        limits: HashMap<String, i64>,
+        verbose: bool,
+    }
+
+    // Onion source works as a config file
+    let config = run_code(
+        "[name \"api\" port 8080 tags (list \"web\" \"public\") mode :Fast verbose 1
+          limits #[\"rps\" 100]
+          backup [:name \"spare\" :port 8081 :tags (list) :mode (Type.tag 'Limited 3)
+                  :limits #[] :verbose nil]]",
+    );
+    let service: Service = from_expr(&config).unwrap();
+    assert_eq!(service.name, "api");
+    assert_eq!(service.port, 8080);
+    assert_eq!(service.tags, vec!["web", "public"]);
+    assert_eq!(service.mode, Mode::Fast);
+    assert_eq!(service.limits["rps"], 100);
+    assert!(service.verbose);
+    let backup = service.backup.as_ref().unwrap();
+    assert_eq!(backup.mode, Mode::Limited(3));
+    assert!(!backup.verbose);
+
+    // Rust values round-trip through Expr::Map
+    let window = Service {
+        mode: Mode::Window { start: 1, end: 9 },
+        ..service
+    };
+    let expr = to_expr(&window).unwrap();
+    assert!(matches!(expr, Expr::Map(_)));
+    assert_eq!(from_expr::<Service>(&expr).unwrap(), window);
+
+    // Struct instances read like maps
+    let mut onion = Interpreter::new();
+    onion
+        .eval_str("(struct Point (x y)) (def p (Point 3 4))")
+        .unwrap();
+    #[derive(Deserialize)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+    let p: Point = from_expr(&onion.get_global("p").unwrap()).unwrap();
+    assert_eq!((p.x, p.y), (3, 4));
+
+    let err = from_expr::<Service>(&Expr::Int(1)).unwrap_err();
+    assert!(err.message.contains("invalid type"), "{}", err);
+}
+
+/// Repeat a string, with an optional separator
+#[onion::onion_fn(module = "Text")]
+fn repeat(s: String, times: usize, sep: Option<String>) -> String {
+    vec![s; times].join(sep.as_deref().unwrap_or(""))
+}
+
+#[onion::onion_fn(name = "safe-div", doc = "Divide, failing on zero")]
+fn safe_div(a: i64, b: i64) -> Result<i64, String> {
+    if b == 0 {
+        return Err("division by zero".to_string());
+    }
+    Ok(a / b)
+}
+
+#[onion::onion_fn]
+fn lookup(ctx: &mut onion::Context, name: String) -> Option<Expr> {
+    ctx.resolve(&Symbol::new(&name))
+}
+
+#[test]
+fn test_onion_fn_macro() {
+    let mut onion = Interpreter::new();
+    for native in [repeat_native(), safe_div_native(), lookup_native()] {
+        native.register(onion.context());
+    }
+    assert_eq!(
+        onion.eval_str("(Text.repeat \"ab\" 3)").unwrap(),
+        Expr::str("ababab")
+    );
+    assert_eq!(
+        onion.eval_str("(Text.repeat \"ab\" 2 \", \")").unwrap(),
+        Expr::str("ab, ab")
+    );
+    assert_eq!(onion.eval_str("(safe-div 7 2)").unwrap(), Expr::Int(3));
+    assert_eq!(
+        onion.eval_str("(def x 5) (lookup \"x\")").unwrap(),
+        Expr::Int(5)
+    );
+    assert_eq!(onion.eval_str("(lookup \"nope\")").unwrap(), Expr::Nil);
+    // The Rust function is still there
+    assert_eq!(repeat("x".to_string(), 2, None), "xx");
+
+    let mut error = |code: &str| match onion.eval_str(code) {
+        Err(Error::Runtime(e)) => e.message,
+        other => panic!("Expected runtime error, got {:?}", other),
+    };
+    assert_eq!(error("(safe-div 1 0)"), "safe-div: division by zero");
+    assert_eq!(
+        error("(Text.repeat \"a\")"),
+        "Text.repeat expects 2 to 3 arguments, got 1"
+    );
+    assert!(
+        error("(Text.repeat \"a\" \"b\")").starts_with("Text.repeat: argument times: expected"),
+    );
+    assert!(error("(Math.sqrt \"16\")").starts_with("Math.sqrt: argument x"));
+
+    // Descriptions and signatures are filled in
+    match onion.get_global("safe-div") {
+        Some(Expr::Extern(f)) => {
+            assert_eq!(f.short_desc(), "safe-div");
+            assert_eq!(f.long_desc(), "Divide, failing on zero");
+            let sig = f.signature().unwrap();
+            assert_eq!(sig.ret.as_str(), "int");
+        }
+        other => panic!("Expected extern, got {:?}", other),
+    }
+    assert_float("(Math.clamp 12 0 10)", 10.0);
+    assert_float("(Math.to_degrees Math.PI)", 180.0);
+}
+
+#[test]
+fn test_game_state_per_interpreter() {
+    let path = std::env::temp_dir().join(format!("onion_game_{}.png", std::process::id()));
+    image::RgbaImage::from_pixel(2, 2, image::Rgba([255, 0, 0, 255]))
+        .save(&path)
+        .unwrap();
+    let load = format!("(Game.load_image \"{}\")", path.display());
+
+    // Interpreters on different threads each get their own images and ids
+    let handles: Vec<_> = (1..=3)
+        .map(|loads| {
+            let load = load.clone();
+            std::thread::spawn(move || {
+                let mut onion = Interpreter::new();
+                let mut last = Expr::Nil;
+                for _ in 0..loads {
+                    last = onion.eval_str(&load).unwrap();
+                }
+                onion
+                    .eval_str("(Game.clear 0) (Game.rect 0 0 1 1 255)")
+                    .unwrap();
+                (last, onion.eval_str("(Game.width)").unwrap())
+            })
+        })
+        .collect();
+    let results: Vec<(Expr, Expr)> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    for (i, (id, width)) in results.into_iter().enumerate() {
+        assert_eq!(id, Expr::Int(i as i64 + 1));
+        assert_eq!(width, Expr::Int(0));
+    }
+
+    // Every scope of one interpreter sees the same state
+    let mut onion = Interpreter::new();
+    onion.eval_str(&load).unwrap();
+    let inner = onion.context().fork();
+    assert!(std::sync::Arc::ptr_eq(&inner.game, &onion.context().game));
+    assert!(!std::sync::Arc::ptr_eq(
+        &Interpreter::new().context().game,
+        &onion.context().game
+    ));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_streams() {
+    assert_output("(print \"a\" 1) (println \"b\")", "a 1b\n");
+    assert_output("(println (list 1 2))", "(1 2)\n");
+    assert_output("(eprintln \"hidden\")", "");
+
+    let onion = Interpreter::new();
+    let (out, err) = (OutputBuffer::new(), OutputBuffer::new());
+    let streams = &onion.context().streams;
+    streams.set_stdout(out.clone());
+    streams.set_stderr(err.clone());
+    streams.set_stdin(std::io::Cursor::new("Ada\nBabbage\n"));
+    let code = r#"
+        (print "name?")
+        (def first (IO.read_line))
+        (def second (IO.read_line))
+        (eprint "warning:")
+        (eprintln "-" second)
+        first
+    "#;
+    assert_eq!(run_with(onion, code), Expr::Str("Ada".to_string()));
+    assert_eq!(out.contents(), "name?");
+    assert_eq!(err.contents(), "warning:- Babbage\n");
+
+    // Each interpreter has its own streams
+    let other = Interpreter::new();
+    assert!(!std::sync::Arc::ptr_eq(
+        &other.context().streams,
+        &Interpreter::new().context().streams
+    ));
+}
+
+#[test]
+fn test_capabilities() {
+    let root = std::env::temp_dir().join(format!("onion_caps_{}", std::process::id()));
+    let (mods, outside) = (root.join("mods"), root.join("outside"));
+    std::fs::create_dir_all(&mods).unwrap();
+    std::fs::create_dir_all(&outside).unwrap();
+    std::fs::write(outside.join("secret.txt"), "hunter2").unwrap();
+
+    let caps = Capabilities::none().allow_write(&mods);
+    let mut onion = Interpreter::builder().capabilities(caps).build();
+    onion.set_global("mods", Expr::Str(mods.display().to_string()));
+    onion.set_global("outside", Expr::Str(outside.display().to_string()));
+    let denied = |onion: &mut Interpreter, call: &str| {
+        let code = format!("(try {} (fun (msg) msg))", call);
+        match onion.eval_str(&code).unwrap() {
+            Expr::Str(msg) => assert!(msg.starts_with("Permission denied"), "{}", msg),
+            other => panic!("{} was allowed: {:?}", call, other),
+        }
+    };
+
+    assert_eq!(
+        onion
+            .eval_str(r#"(IO.write_file (mods + "/save.txt") "lvl 3") (IO.read_file (mods + "/save.txt"))"#)
+            .unwrap(),
+        Expr::Str("lvl 3".to_string())
+    );
+    denied(&mut onion, r#"(IO.read_file (outside + "/secret.txt"))"#);
+    denied(&mut onion, r#"(IO.write_file (outside + "/new.txt") "x")"#);
+    denied(
+        &mut onion,
+        r#"(IO.read_file (mods + "/../outside/secret.txt"))"#,
+    );
+    denied(&mut onion, r#"(IO.remove_file (outside + "/secret.txt"))"#);
+    denied(&mut onion, r#"(IO.exists "/")"#);
+    denied(&mut onion, r#"(OS.exec "echo" "hi")"#);
+    denied(&mut onion, r#"(OS.env "HOME")"#);
+    denied(&mut onion, r#"(OS.set_env "ONION" "1")"#);
+    denied(&mut onion, "(OS.exit 3)");
+    denied(&mut onion, r#"(Game.load_image (outside + "/secret.txt"))"#);
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(&outside, mods.join("link")).unwrap();
+        denied(&mut onion, r#"(IO.read_file (mods + "/link/secret.txt"))"#);
+        // A dangling link would be followed out of the sandbox by the write creating it
+        std::os::unix::fs::symlink(outside.join("escaped.txt"), mods.join("dangling")).unwrap();
+        denied(&mut onion, r#"(IO.write_file (mods + "/dangling") "x")"#);
+        denied(&mut onion, r#"(IO.append_file (mods + "/dangling") "x")"#);
+        assert!(!outside.join("escaped.txt").exists());
+    }
+    assert!(outside.join("secret.txt").exists());
+    assert!(!outside.join("new.txt").exists());
+
+    // Read-only access, and everything allowed by default
+    let caps = Capabilities::none().allow_read(&outside).allow_env();
+    let mut reader = Interpreter::builder().capabilities(caps).build();
+    reader.set_global("outside", Expr::Str(outside.display().to_string()));
+    assert_eq!(
+        reader
+            .eval_str(r#"(IO.read_file (outside + "/secret.txt"))"#)
+            .unwrap(),
+        Expr::Str("hunter2".to_string())
+    );
+    denied(
+        &mut reader,
+        r#"(IO.write_file (outside + "/secret.txt") "x")"#,
+    );
+    assert!(reader.eval_str(r#"(OS.env "PATH")"#).is_ok());
+    assert_int("(IO.exists \"/\")", 1);
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_limits() {
+    let runtime_error = |result: Result<Expr, Error>| match result {
+        Err(Error::Runtime(err)) => err.message,
+        other => panic!("expected a runtime error, got {:?}", other),
+    };
+
+    // Fuel is spent over every call, and `try` can't keep running once it's gone
+    let mut onion = Interpreter::builder().fuel(10_000).build();
+    assert_eq!(onion.eval_str("(1 + 2)").unwrap(), Expr::Int(3));
+    let left = onion.context().limits.fuel().unwrap();
+    assert!(left < 10_000 && left > 9_000, "{}", left);
+    assert_eq!(runtime_error(onion.eval_str("(while 1 1)")), "Out of fuel");
+    assert_eq!(
+        runtime_error(onion.eval_str("(try (while 1 1) (fun (msg) 5))")),
+        "Out of fuel"
+    );
+    onion.context().limits.set_fuel(None);
+    assert_eq!(onion.eval_str("(1 + 2)").unwrap(), Expr::Int(3));
+
+    // The timeout starts again with each call
+    let mut onion = Interpreter::builder()
+        .timeout(std::time::Duration::from_millis(50))
+        .build();
+    let started = std::time::Instant::now();
+    assert_eq!(runtime_error(onion.eval_str("(while 1 1)")), "Timed out");
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    assert_eq!(onion.eval_str("(1 + 2)").unwrap(), Expr::Int(3));
+
+    let mut onion = Interpreter::builder().max_size(1000).build();
+    assert_eq!(
+        onion
+            .eval_str("(String.len (String.repeat \"ab\" 100))")
+            .unwrap(),
+        Expr::Int(200)
+    );
+    for code in [
+        "(String.repeat \"ab\" 1000)",
+        "(Collections.range 0 1000000000)",
+        "(Bytes.make 5000)",
+        "(def s \"abcd\") (while 1 (def s (s + s)))",
+    ] {
+        let message = runtime_error(onion.eval_str(code));
+        assert!(message.contains("memory limit exceeded"), "{}", message);
+    }
+
+    // Interrupting from another thread
+    let mut onion = Interpreter::new();
+    let handle = onion.interrupt_handle();
+    let interrupter = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        handle.interrupt();
+    });
+    assert_eq!(runtime_error(onion.eval_str("(while 1 1)")), "Interrupted");
+    interrupter.join().unwrap();
+    assert_eq!(onion.eval_str("(1 + 2)").unwrap(), Expr::Int(3));
+}
+
+/// Compile `tests/plugin/<name>.c` into a shared library, or `None` without a C compiler.
+fn build_plugin(name: &str, output: &str, flags: &[&str]) -> Option<std::path::PathBuf> {
+    let dir = std::env::temp_dir().join(format!("onion_{}_{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let lib = dir.join(output);
+    let root = env!("CARGO_MANIFEST_DIR");
+    let status = std::process::Command::new(std::env::var("CC").unwrap_or("cc".to_string()))
+        .args(["-shared", "-fPIC", "-o"])
+        .arg(&lib)
+        .arg(format!("-I{}/include", root))
+        .arg(format!("{}/tests/plugin/{}.c", root, name))
+        .args(flags)
+        .status();
+    match status {
+        Ok(status) if status.success() => Some(lib),
+        _ => {
+            eprintln!("skipping: no C compiler to build {}", name);
+            None
+        }
+    }
+}
+
+#[test]
+fn test_load_plugin() {
+    let Some(lib) = build_plugin("test_plugin", "libtest_plugin.so", &[]) else {
+        return;
+    };
+    let mut onion = Interpreter::new();
+    onion.set_global("lib", Expr::Str(lib.display().to_string()));
+    let eval = |onion: &mut Interpreter, code: &str| onion.eval_str(code).unwrap();
+
+    assert_eq!(eval(&mut onion, "(load_plugin lib)"), Expr::Int(1));
+    assert_eq!(eval(&mut onion, "(Ext.twice (20 + 1))"), Expr::Int(42));
+    assert_eq!(eval(&mut onion, "Ext.version"), Expr::Int(3));
+    assert_eq!(
+        eval(&mut onion, "(Ext.greet \"Ada\")"),
+        Expr::Str("hello, Ada".to_string())
+    );
+    assert_eq!(
+        eval(&mut onion, "(Ext.sum (list 1 2.5 3))"),
+        Expr::Float(6.5)
+    );
+    assert_eq!(
+        eval(&mut onion, "(:count (Ext.args 1 \"two\" :three (list 4)))"),
+        run_code("(list 1 \"two\" :three (list 4))")
+    );
+    let error = |onion: &mut Interpreter, code: &str| match onion.eval_str(code) {
+        Err(Error::Runtime(err)) => err.message,
+        other => panic!("expected an error from {}, got {:?}", code, other),
+    };
+    assert_eq!(
+        error(&mut onion, "(Ext.twice \"x\")"),
+        "Ext.twice: expected an int"
+    );
+    assert_eq!(
+        error(&mut onion, "(ext_fail)"),
+        "ext_fail failed with status 7"
+    );
+    assert_eq!(
+        eval(&mut onion, "(try (Ext.twice nil) (fun (msg) 0))"),
+        Expr::Int(0)
+    );
+
+    // Loading refuses a plugin built for another ABI, or something that isn't a plugin
+    let old = build_plugin(
+        "test_plugin",
+        "libold_plugin.so",
+        &["-DPLUGIN_ABI_VERSION=99"],
+    )
+    .unwrap();
+    onion.set_global("old", Expr::Str(old.display().to_string()));
+    let message = error(&mut onion, "(load_plugin old)");
+    assert!(
+        message.contains("built for plugin ABI version 99"),
+        "{}",
+        message
+    );
+    let message = error(&mut onion, "(load_plugin \"/nonexistent/libnone.so\")");
+    assert!(
+        message.starts_with("load_plugin: can't load"),
+        "{}",
+        message
+    );
+
+    // Native code gets around the sandbox, so it needs its own capability
+    let caps = Capabilities::none().allow_read(lib.parent().unwrap());
+    let mut sandbox = Interpreter::builder().capabilities(caps).build();
+    sandbox.set_global("lib", Expr::Str(lib.display().to_string()));
+    assert_eq!(
+        error(&mut sandbox, "(load_plugin lib)"),
+        "Permission denied: load_plugin is not allowed"
+    );
+    std::fs::remove_dir_all(lib.parent().unwrap()).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_ffi() {
+    let mut onion = Interpreter::new();
+    let eval = |onion: &mut Interpreter, code: &str| match onion.eval_str(code) {
+        Ok(val) => val,
+        Err(e) => panic!("{} in code: {}", e, code),
+    };
+    eval(
+        &mut onion,
+        r#"
+        (def libc (FFI.open "libc.so.6"))
+        (def libm (FFI.open "libm.so.6"))
+        (def strlen (FFI.fn libc "strlen" (list :string) :size))
+        (def abs (FFI.fn libc "abs" (list :int) :int))
+        (def pow (FFI.fn libm "pow" (list :double :double) :double))
+        (def powf (FFI.fn libm "powf" (list :float :float) :float))
+        (def memset (FFI.fn libc "memset" (list :pointer :int :size) :pointer))
+        (def memcpy (FFI.fn libc "memcpy" (list :pointer :bytes :size) :pointer))
+        (def strdup (FFI.fn libc "strdup" (list :string) :pointer))
+        (def strchr (FFI.fn libc "strchr" (list :string :int) :string))
+        "#,
+    );
+    assert_eq!(eval(&mut onion, "(strlen \"onion\")"), Expr::Int(5));
+    assert_eq!(eval(&mut onion, "(abs (0 - 42))"), Expr::Int(42));
+    assert_eq!(eval(&mut onion, "(pow 2 10)"), Expr::Float(1024.0));
+    assert_eq!(eval(&mut onion, "(powf 1.5 2)"), Expr::Float(2.25));
+    assert_eq!(
+        eval(&mut onion, "(strchr \"layers\" 121)"),
+        Expr::Str("yers".to_string())
+    );
+    assert_eq!(eval(&mut onion, "(strchr \"layers\" 122)"), Expr::Nil);
+
+    // Buffers for C to write into
+    assert_eq!(
+        eval(
+            &mut onion,
+            r#"
+            (def buf (FFI.alloc 6))
+            (memset buf 97 3)
+            (memcpy (buf + 3) (Bytes.from_str "xyz") 3)
+            (def out (FFI.read buf 6))
+            (FFI.free buf)
+            (Bytes.to_str out)
+            "#
+        ),
+        Expr::Str("aaaxyz".to_string())
+    );
+    assert_eq!(
+        eval(
+            &mut onion,
+            r#"(def copy (strdup "skin")) (def s (FFI.string copy)) (FFI.free copy) s"#
+        ),
+        Expr::Str("skin".to_string())
+    );
+
+    let error = |onion: &mut Interpreter, code: &str| match onion.eval_str(code) {
+        Err(Error::Runtime(err)) => err.message,
+        other => panic!("expected an error from {}, got {:?}", code, other),
+    };
+    assert_eq!(
+        error(&mut onion, "(abs 1 2)"),
+        "abs expects 1 arguments, got 2"
+    );
+    assert_eq!(
+        error(&mut onion, "(abs 9999999999)"),
+        "abs: argument 1 doesn't fit in i32: 9999999999"
+    );
+    assert_eq!(
+        error(&mut onion, "(strlen 5)"),
+        "strlen: argument 1 should be a string, got 5"
+    );
+    assert!(error(&mut onion, "(FFI.fn libc \"no_such_fn\" (list) :void)").contains("no function"));
+    assert!(
+        error(&mut onion, "(FFI.fn libc \"abs\" (list :complex) :int)").contains("unknown C type")
+    );
+
+    // Handles belong to the interpreter that opened the library
+    let mut other = Interpreter::new();
+    assert_eq!(
+        error(&mut other, "(FFI.fn 0 \"abs\" (list :int) :int)"),
+        "FFI.fn: no library with handle 0"
+    );
+
+    let mut sandbox = Interpreter::builder()
+        .capabilities(Capabilities::none())
+        .build();
+    assert_eq!(
+        error(&mut sandbox, "(FFI.open \"libc.so.6\")"),
+        "Permission denied: FFI.open is not allowed"
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_c_api() {
+    // Cargo builds the cdylib next to the test binary, in target/<profile>/deps. Linux
+    // builds are expected to have a C compiler, so a missing one fails rather than skips.
+    let exe = std::env::current_exe().unwrap();
+    let deps = exe.parent().unwrap();
+    assert!(
+        deps.join("libonion.so").exists(),
+        "libonion.so wasn't built in {}",
+        deps.display()
+    );
+    let dir = std::env::temp_dir().join(format!("onion_embed_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let program = dir.join("embed");
+    let root = env!("CARGO_MANIFEST_DIR");
+    let cc = std::env::var("CC").unwrap_or("cc".to_string());
+    let status = std::process::Command::new(&cc)
+        .arg(format!("{}/tests/capi/embed.c", root))
+        .arg(format!("-I{}/include", root))
+        .arg(format!("-L{}", deps.display()))
+        .arg(format!("-Wl,-rpath,{}", deps.display()))
+        .args(["-lonion", "-o"])
+        .arg(&program)
+        .status()
+        .unwrap_or_else(|e| panic!("can't run the C compiler {} (set CC): {}", cc, e));
+    assert!(status.success(), "{} failed to build embed.c", cc);
+    let output = std::process::Command::new(&program).output().unwrap();
+    assert!(
+        output.status.success(),
+        "embed.c failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}
+
+#[test]
+fn test_bundle() {
+    let dir = std::env::temp_dir().join(format!("onion_bundle_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("assets")).unwrap();
+    let script = dir.join("main.onion");
+    std::fs::write(
+        &script,
+        "(def img (Game.load_image \"assets/hero.png\"))\n\
+         (defun level (n) (IO.read_file \"./level.txt\"))\n\
+         (IO.read_bytes \"made_at_runtime.bin\")",
+    )
+    .unwrap();
+    std::fs::write(dir.join("assets/hero.png"), b"not really a png").unwrap();
+    std::fs::write(dir.join("level.txt"), "###").unwrap();
+    std::fs::write(dir.join("notes.txt"), "extra").unwrap();
+
+    let onion = Interpreter::new();
+    let bundle = Bundle::collect(&script, &[dir.join("notes.txt")], onion.context()).unwrap();
+    let mut names: Vec<&str> = bundle.names().collect();
+    names.sort();
+    let notes = dir.join("notes.txt").display().to_string();
+    let mut expected = vec!["assets/hero.png", "level.txt", "main.onion", notes.as_str()];
+    expected.sort();
+    assert_eq!(names, expected);
+    assert_eq!(bundle.get("./level.txt"), Some(&b"###"[..]));
+    assert!(bundle.main_source().unwrap().starts_with("(def img"));
+    assert_eq!(Bundle::from_bytes(&bundle.to_bytes()).unwrap(), bundle);
+    assert!(Bundle::from_bytes(&bundle.to_bytes()[..20]).is_err());
+
+    // Reads are served from the bundle, even where the sandbox allows no disk access
+    let mut onion = Interpreter::builder()
+        .capabilities(Capabilities::none())
+        .bundle(bundle)
+        .build();
+    std::fs::remove_file(dir.join("level.txt")).unwrap();
+    assert_eq!(
+        onion.eval_str("(IO.read_file \"level.txt\")").unwrap(),
+        Expr::Str("###".to_string())
+    );
+    assert_eq!(
+        onion.eval_str("(IO.exists \"assets/hero.png\")").unwrap(),
+        Expr::Int(1)
+    );
+    let denied = onion
+        .eval_str("(try (IO.read_file \"notes.txt\") (fun (msg) msg))")
+        .unwrap();
+    assert!(denied.as_str().unwrap().starts_with("Permission denied"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_bundle_executable() {
+    let dir = std::env::temp_dir().join(format!("onion_bundle_exe_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("main.onion"),
+        "(println (IO.read_file \"greeting.txt\"))",
+    )
+    .unwrap();
+    std::fs::write(dir.join("greeting.txt"), "hello from the bundle").unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_onion"))
+        .args(["bundle", "main.onion", "-o", "game"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // The bundled program still runs once the files it was made from are gone
+    std::fs::remove_file(dir.join("greeting.txt")).unwrap();
+    std::fs::remove_file(dir.join("main.onion")).unwrap();
+    let output = std::process::Command::new(dir.join("game"))
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "hello from the bundle\n"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_vfs() {
+    // In memory, nothing touches the disk, and an empty sandbox doesn't get in the way
+    let files = Arc::new(MemoryFs::new().with_file("levels/1.txt", "###"));
+    let mut onion = Interpreter::builder()
+        .capabilities(Capabilities::none())
+        .vfs(files.clone())
+        .build();
+    let mut eval = |code: &str| onion.eval_str(code).unwrap();
+    assert_eq!(
+        eval("(IO.read_file \"./levels/../levels/1.txt\")"),
+        Expr::Str("###".to_string())
+    );
+    assert_eq!(eval("(IO.write_file \"save.txt\" \"a\")"), Expr::Int(1));
+    assert_eq!(eval("(IO.append_file \"save.txt\" \"b\")"), Expr::Int(1));
+    assert_eq!(files.get("save.txt").unwrap(), b"ab");
+    assert_eq!(eval("(IO.is_dir \"levels\")"), Expr::Int(1));
+    assert_eq!(eval("(IO.is_file \"levels\")"), Expr::Nil);
+    assert_eq!(eval("(IO.remove_file \"save.txt\")"), Expr::Int(1));
+    assert_eq!(eval("(IO.exists \"save.txt\")"), Expr::Nil);
+    assert_eq!(eval("(IO.read_file \"save.txt\")"), Expr::Nil);
+
+    // Assets load from the filesystem too
+    let mut png = std::io::Cursor::new(Vec::new());
+    image::RgbaImage::new(2, 2)
+        .write_to(&mut png, image::ImageFormat::Png)
+        .unwrap();
+    files.insert("hero.png", png.into_inner());
+    assert!(matches!(
+        eval("(Game.load_image \"hero.png\")"),
+        Expr::Int(_)
+    ));
+
+    // An archive is read-only, and survives packing
+    let mut archive = Archive::new();
+    archive.insert("a.txt", b"packed".to_vec());
+    archive.insert("log.txt", b"1".to_vec());
+    let archive = Archive::from_bytes(&archive.to_bytes()).unwrap();
+    assert_eq!(archive.get("a.txt"), Some(&b"packed"[..]));
+    assert!(Archive::from_bytes(&archive.to_bytes()[..12]).is_err());
+
+    // Overlays: the archive mounted at assets over memory, and a directory on disk at disk
+    let dir = std::env::temp_dir().join(format!("onion_vfs_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let below = Arc::new(MemoryFs::new().with_file("assets/a.txt", "hidden"));
+    let overlay = Overlay::new(below.clone())
+        .mount("assets", Arc::new(archive))
+        .mount("disk", Arc::new(RealFs::rooted(&dir)));
+    let mut onion = Interpreter::builder().vfs(Arc::new(overlay)).build();
+    let mut eval = |code: &str| onion.eval_str(code).unwrap();
+    assert_eq!(
+        eval("(IO.read_file \"assets/a.txt\")"),
+        Expr::Str("packed".to_string())
+    );
+    // Writes pass the read-only archive, and appends copy its file up first
+    assert_eq!(
+        eval("(IO.write_file \"assets/b.txt\" \"new\")"),
+        Expr::Int(1)
+    );
+    assert_eq!(below.get("assets/b.txt").unwrap(), b"new");
+    assert_eq!(
+        eval("(IO.append_file \"assets/log.txt\" \"2\")"),
+        Expr::Int(1)
+    );
+    assert_eq!(below.get("assets/log.txt").unwrap(), b"12");
+    assert_eq!(
+        eval("(IO.write_file \"disk/out.txt\" \"real\")"),
+        Expr::Int(1)
+    );
+    assert_eq!(
+        std::fs::read_to_string(dir.join("out.txt")).unwrap(),
+        "real"
+    );
+    assert_eq!(eval("(IO.exists \"disk/../disk/out.txt\")"), Expr::Int(1));
+
+    // Capabilities still guard the parts on disk
+    let overlay =
+        Overlay::new(Arc::new(MemoryFs::new())).mount("disk", Arc::new(RealFs::rooted(&dir)));
+    let mut sandbox = Interpreter::builder()
+        .capabilities(Capabilities::none())
+        .vfs(Arc::new(overlay))
+        .build();
+    let denied = sandbox
+        .eval_str("(try (IO.read_file \"disk/out.txt\") (fun (msg) msg))")
+        .unwrap();
+    assert!(denied.as_str().unwrap().starts_with("Permission denied"));
+    assert_eq!(
+        sandbox
+            .eval_str("(IO.write_file \"mem.txt\" \"ok\")")
+            .unwrap(),
+        Expr::Int(1)
+    );
+
+    // A link inside an allowed root that points out of it doesn't get past the check
+    #[cfg(unix)]
+    {
+        let outside = dir.join("outside");
+        let allowed = dir.join("allowed");
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::create_dir_all(&allowed).unwrap();
+        std::fs::write(outside.join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(&outside, allowed.join("link")).unwrap();
+        let mut sandbox = Interpreter::builder()
+            .capabilities(Capabilities::none().allow_write(&allowed))
+            .vfs(Arc::new(RealFs::rooted(&allowed)))
+            .build();
+        let mut denied = |code: &str| {
+            let msg = sandbox.eval_str(&format!("(try {} (fun (msg) msg))", code));
+            let msg = msg.unwrap();
+            assert!(msg.as_str().unwrap().starts_with("Permission denied"));
+        };
+        denied("(IO.read_file \"link/secret.txt\")");
+        denied("(IO.write_file \"link/secret.txt\" \"gone\")");
+        denied("(IO.write_file \"link/new.txt\" \"new\")");
+        assert_eq!(
+            std::fs::read_to_string(outside.join("secret.txt")).unwrap(),
+            "secret"
+        );
+        assert!(!outside.join("new.txt").exists());
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}